@@ -1,10 +1,13 @@
-use clap::{crate_version, App, Arg};
+use clap::{crate_version, App, AppSettings, Arg};
 use solana_rbpf::{
     aligned_memory::AlignedMemory,
     assembler::assemble,
+    coverage::{disassembly_line_table, Coverage},
     ebpf,
     elf::Executable,
+    elf_report::ElfReport,
     memory_region::{MemoryMapping, MemoryRegion},
+    profiler::Profile,
     static_analysis::Analysis,
     verifier::{RequisiteVerifier, TautologyVerifier},
     vm::{BuiltInProgram, Config, DynamicAnalysis, EbpfVm, TestContextObject},
@@ -16,6 +19,26 @@ fn main() {
         .version(crate_version!())
         .author("Solana Maintainers <maintainers@solana.foundation>")
         .about("CLI to test and analyze eBPF programs")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            App::new("readelf")
+                .about("Display what the loader sees of an ELF, even if loading fails")
+                .arg(
+                    Arg::new("elf")
+                        .about("ELF to inspect")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("format")
+                        .about("Output format")
+                        .short('f')
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "json"])
+                        .default_value("text"),
+                ),
+        )
         .arg(
             Arg::new("assembler")
                 .about("Assemble and load eBPF executable")
@@ -86,17 +109,48 @@ fn main() {
         )
         .arg(
             Arg::new("profile")
-                .about("Display profile using tracing instrumentation")
+                .about("Write profile.dot, profile.folded and callgrind.out using tracing instrumentation")
                 .short('p')
                 .long("prof"),
         )
+        .arg(
+            Arg::new("coverage")
+                .about("Write coverage report using tracing instrumentation")
+                .short('c')
+                .long("cov")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["lcov", "cobertura"]),
+        )
         .get_matches();
 
     let loader = Arc::new(BuiltInProgram::new_loader(Config {
-        enable_instruction_tracing: matches.is_present("trace") || matches.is_present("profile"),
+        enable_instruction_tracing: matches.is_present("trace")
+            || matches.is_present("profile")
+            || matches.is_present("coverage"),
         enable_symbol_and_section_labels: true,
+        enable_backtrace: true,
         ..Config::default()
     }));
+    if let Some(matches) = matches.subcommand_matches("readelf") {
+        let mut file = File::open(Path::new(matches.value_of("elf").unwrap())).unwrap();
+        let mut elf = Vec::new();
+        file.read_to_end(&mut elf).unwrap();
+        let report = match ElfReport::new(&elf, loader) {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("Failed to parse ELF: {err}");
+                std::process::exit(1);
+            }
+        };
+        let stdout = std::io::stdout();
+        if matches.value_of("format") == Some("json") {
+            report.write_json(&mut stdout.lock()).unwrap();
+        } else {
+            report.write_text(&mut stdout.lock()).unwrap();
+        }
+        return;
+    }
     let executable = match matches.value_of("assembler") {
         Some(asm_file_name) => {
             let mut file = File::open(Path::new(asm_file_name)).unwrap();
@@ -148,6 +202,7 @@ fn main() {
             .parse::<usize>()
             .unwrap(),
     );
+    let mut data = AlignedMemory::<{ ebpf::HOST_ALIGN }>::with_capacity(0);
     let regions: Vec<MemoryRegion> = vec![
         verified_executable.get_ro_region(),
         MemoryRegion::new_writable_gapped(
@@ -161,7 +216,10 @@ fn main() {
         ),
         MemoryRegion::new_writable(heap.as_slice_mut(), ebpf::MM_HEAP_START),
         MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START),
-    ];
+    ]
+    .into_iter()
+    .chain(verified_executable.get_data_region(&mut data))
+    .collect();
 
     let memory_mapping = MemoryMapping::new(regions, config).unwrap();
 
@@ -176,6 +234,7 @@ fn main() {
         || matches.value_of("use") == Some("disassembler")
         || matches.is_present("trace")
         || matches.is_present("profile")
+        || matches.is_present("coverage")
     {
         Some(Analysis::from_executable(&verified_executable).unwrap())
     } else {
@@ -209,6 +268,9 @@ fn main() {
     let (instruction_count, result) = vm.execute_program(matches.value_of("use").unwrap() != "jit");
     println!("Result: {result:?}");
     println!("Instruction Count: {instruction_count}");
+    if let Some(backtrace) = &vm.env.backtrace {
+        println!("Backtrace:\n{backtrace}");
+    }
     if matches.is_present("trace") {
         println!("Trace:\n");
         let stdout = std::io::stdout();
@@ -229,5 +291,39 @@ fn main() {
             .unwrap()
             .visualize_graphically(&mut file, Some(&dynamic_analysis))
             .unwrap();
+        let mut profile = Profile::default();
+        profile.add_trace_log(
+            analysis.as_ref().unwrap(),
+            &vm.env.context_object_pointer.trace_log,
+        );
+        let mut file = File::create("profile.folded").unwrap();
+        profile.write_folded(&mut file).unwrap();
+        let mut file = File::create("callgrind.out").unwrap();
+        profile.write_callgrind(&mut file).unwrap();
+    }
+    if let Some(format) = matches.value_of("coverage") {
+        let analysis = analysis.as_ref().unwrap();
+        let mut coverage = Coverage::default();
+        coverage.add_trace_log(analysis, &vm.env.context_object_pointer.trace_log);
+        // The report refers to the source files, or else to the lines of the disassembly
+        let line_table = match verified_executable.get_debug_info() {
+            Some(debug_info) => debug_info.line_table(),
+            None => {
+                let mut file = File::create("coverage.s").unwrap();
+                analysis.disassemble(&mut file).unwrap();
+                disassembly_line_table(analysis, "coverage.s")
+            }
+        };
+        if format == "lcov" {
+            let mut file = File::create("coverage.info").unwrap();
+            coverage
+                .write_lcov(&mut file, analysis, &line_table)
+                .unwrap();
+        } else {
+            let mut file = File::create("coverage.xml").unwrap();
+            coverage
+                .write_cobertura(&mut file, analysis, &line_table)
+                .unwrap();
+        }
     }
 }
@@ -17,6 +17,7 @@ pub struct ConfigTemplate {
     dynamic_stack_frames: bool,
     enable_sdiv: bool,
     optimize_rodata: bool,
+    optimize_jit_code: bool,
 }
 
 impl<'a> Arbitrary<'a> for ConfigTemplate {
@@ -34,6 +35,7 @@ impl<'a> Arbitrary<'a> for ConfigTemplate {
             dynamic_stack_frames: bools & (1 << 7) != 0,
             enable_sdiv: bools & (1 << 8) != 0,
             optimize_rodata: bools & (1 << 9) != 0,
+            optimize_jit_code: bools & (1 << 10) != 0,
         })
     }
 
@@ -60,6 +62,7 @@ impl From<ConfigTemplate> for Config {
                 dynamic_stack_frames,
                 enable_sdiv,
                 optimize_rodata,
+                optimize_jit_code,
             } => Config {
                 max_call_depth,
                 enable_stack_frame_gaps,
@@ -77,6 +80,7 @@ impl From<ConfigTemplate> for Config {
                 dynamic_stack_frames,
                 enable_sdiv,
                 optimize_rodata,
+                optimize_jit_code,
                 ..Default::default()
             },
         }
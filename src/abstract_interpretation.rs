@@ -0,0 +1,1066 @@
+//! Abstract interpretation of register value ranges and pointer provenance
+//!
+//! Similar to the Linux kernel verifier, every function is interpreted over the control-flow
+//! graph of [Analysis] until the abstract states of its basic blocks reach a fixpoint.
+//! The result is used by [AbstractInterpretationVerifier] to prove that memory accesses
+//! through pointers derived from the frame pointer (r10) and the input pointer (r1) stay in
+//! bounds, and to reject programs which fault on every execution of an instruction. As the
+//! length of the input is not known when a program is loaded, the end of the input region is
+//! only checked by `AbstractInterpretation::check()` if the caller passes it.
+
+use crate::{
+    ebpf,
+    elf::Executable,
+    error::EbpfError,
+    static_analysis::Analysis,
+    verifier::{RequisiteVerifier, TautologyVerifier, Verifier, VerifierError},
+    vm::{Config, ContextObject, FunctionRegistry},
+};
+use std::collections::BTreeMap;
+
+/// How often a backward edge is followed before changing bounds are widened to infinity
+const WIDENING_THRESHOLD: usize = 8;
+
+/// Memory region a pointer was derived from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Provenance {
+    /// Frame pointer (r10) of the current function
+    Stack,
+    /// Start of the input region (r1 at the entrypoint)
+    Input,
+}
+
+impl Provenance {
+    fn name(self) -> &'static str {
+        match self {
+            Provenance::Stack => "stack",
+            Provenance::Input => "input",
+        }
+    }
+}
+
+/// Abstraction of the value of a register or stack slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbstractValue {
+    /// Number in an unsigned range
+    Scalar {
+        /// Lower bound (inclusive)
+        min: u64,
+        /// Upper bound (inclusive)
+        max: u64,
+    },
+    /// Address at an offset range from the start of a memory region
+    Pointer {
+        /// Region the address was derived from
+        provenance: Provenance,
+        /// Lower bound of the offset (inclusive)
+        min: i64,
+        /// Upper bound of the offset (inclusive)
+        max: i64,
+    },
+    /// Any value, including pointers
+    Unknown,
+}
+
+impl AbstractValue {
+    const ANY_SCALAR: Self = AbstractValue::Scalar {
+        min: 0,
+        max: u64::MAX,
+    };
+
+    /// A number which is known exactly
+    pub fn exact(value: u64) -> Self {
+        AbstractValue::Scalar {
+            min: value,
+            max: value,
+        }
+    }
+
+    /// The number if it is known exactly
+    pub fn as_exact(&self) -> Option<u64> {
+        match self {
+            AbstractValue::Scalar { min, max } if min == max => Some(*min),
+            _ => None,
+        }
+    }
+
+    fn pointer(provenance: Provenance, min: Option<i64>, max: Option<i64>) -> Self {
+        match (min, max) {
+            (Some(min), Some(max)) => AbstractValue::Pointer {
+                provenance,
+                min,
+                max,
+            },
+            _ => AbstractValue::Unknown,
+        }
+    }
+
+    fn scalar_below(limit: u64) -> Self {
+        AbstractValue::Scalar { min: 0, max: limit }
+    }
+
+    /// Smallest value which contains both
+    pub fn join(self, other: Self) -> Self {
+        match (self, other) {
+            (
+                AbstractValue::Scalar { min, max },
+                AbstractValue::Scalar {
+                    min: other_min,
+                    max: other_max,
+                },
+            ) => AbstractValue::Scalar {
+                min: min.min(other_min),
+                max: max.max(other_max),
+            },
+            (
+                AbstractValue::Pointer {
+                    provenance,
+                    min,
+                    max,
+                },
+                AbstractValue::Pointer {
+                    provenance: other_provenance,
+                    min: other_min,
+                    max: other_max,
+                },
+            ) if provenance == other_provenance => AbstractValue::Pointer {
+                provenance,
+                min: min.min(other_min),
+                max: max.max(other_max),
+            },
+            _ => AbstractValue::Unknown,
+        }
+    }
+
+    /// Moves the bounds of `self` which grow in `next` to infinity
+    fn widen(self, next: Self) -> Self {
+        match (self, next) {
+            (
+                AbstractValue::Scalar { min, max },
+                AbstractValue::Scalar {
+                    min: next_min,
+                    max: next_max,
+                },
+            ) => AbstractValue::Scalar {
+                min: if next_min < min { 0 } else { min },
+                max: if next_max > max { u64::MAX } else { max },
+            },
+            (
+                AbstractValue::Pointer {
+                    provenance,
+                    min,
+                    max,
+                },
+                AbstractValue::Pointer {
+                    provenance: next_provenance,
+                    min: next_min,
+                    max: next_max,
+                },
+            ) if provenance == next_provenance => AbstractValue::Pointer {
+                provenance,
+                min: if next_min < min { i64::MIN } else { min },
+                max: if next_max > max { i64::MAX } else { max },
+            },
+            _ => next,
+        }
+    }
+
+    /// Adds a signed offset to a pointer or scalar
+    fn offset_by(self, offset: i64) -> Self {
+        match self {
+            AbstractValue::Pointer {
+                provenance,
+                min,
+                max,
+            } => Self::pointer(provenance, min.checked_add(offset), max.checked_add(offset)),
+            AbstractValue::Scalar { min, max } => {
+                match (
+                    min.checked_add_signed(offset),
+                    max.checked_add_signed(offset),
+                ) {
+                    (Some(min), Some(max)) => AbstractValue::Scalar { min, max },
+                    _ => Self::ANY_SCALAR,
+                }
+            }
+            AbstractValue::Unknown => AbstractValue::Unknown,
+        }
+    }
+}
+
+/// Abstract registers and spilled stack slots at the start of a basic block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AbstractState {
+    /// Registers r0 to r10
+    pub registers: [AbstractValue; 11],
+    /// Values of 64 bit stores to exact offsets from the frame pointer
+    pub stack_slots: BTreeMap<i64, AbstractValue>,
+}
+
+impl AbstractState {
+    /// State at the start of the entrypoint, see `EbpfVm::execute_program()`
+    fn entrypoint() -> Self {
+        let mut registers = [AbstractValue::exact(0); 11];
+        registers[1] = AbstractValue::pointer(Provenance::Input, Some(0), Some(0));
+        registers[ebpf::FRAME_PTR_REG] =
+            AbstractValue::pointer(Provenance::Stack, Some(0), Some(0));
+        Self {
+            registers,
+            stack_slots: BTreeMap::new(),
+        }
+    }
+
+    /// State at the start of a function which is called
+    fn function() -> Self {
+        let mut registers = [AbstractValue::Unknown; 11];
+        registers[ebpf::FRAME_PTR_REG] =
+            AbstractValue::pointer(Provenance::Stack, Some(0), Some(0));
+        Self {
+            registers,
+            stack_slots: BTreeMap::new(),
+        }
+    }
+
+    fn merge(
+        &self,
+        other: &Self,
+        combine: fn(AbstractValue, AbstractValue) -> AbstractValue,
+    ) -> Self {
+        let mut registers = self.registers;
+        for (register, other_register) in registers.iter_mut().zip(other.registers.iter()) {
+            *register = combine(*register, *other_register);
+        }
+        let stack_slots = self
+            .stack_slots
+            .iter()
+            .filter_map(|(offset, value)| {
+                other
+                    .stack_slots
+                    .get(offset)
+                    .map(|other_value| (*offset, combine(*value, *other_value)))
+            })
+            .collect();
+        Self {
+            registers,
+            stack_slots,
+        }
+    }
+
+    /// Smallest state which contains both
+    pub fn join(&self, other: &Self) -> Self {
+        self.merge(other, AbstractValue::join)
+    }
+
+    fn widen(&self, next: &Self) -> Self {
+        self.merge(next, AbstractValue::widen)
+    }
+
+    /// Forgets the stack slots which overlap `[start, end)`
+    fn clobber_stack_slots(&mut self, start: i64, end: i64) {
+        self.stack_slots
+            .retain(|offset, _value| offset.saturating_add(8) <= start || *offset >= end);
+    }
+
+    /// Registers and stack slots which a call can change
+    fn clobber_call(&mut self) {
+        for register in self.registers.iter_mut().take(6) {
+            *register = AbstractValue::Unknown;
+        }
+        self.stack_slots.clear();
+    }
+}
+
+/// Fixpoint of the abstract interpretation of all functions
+#[derive(Debug)]
+pub struct AbstractInterpretation {
+    /// Abstract state at the start of every reachable basic block
+    pub states: BTreeMap<usize, AbstractState>,
+}
+
+impl AbstractInterpretation {
+    /// Interprets every function of the analysis
+    pub fn new(analysis: &Analysis, config: &Config) -> Self {
+        let mut result = Self {
+            states: BTreeMap::new(),
+        };
+        let entrypoint_is_called = analysis.instructions.iter().any(|insn| {
+            insn.opc == ebpf::CALL_IMM
+                && analysis
+                    .functions
+                    .get(&analysis.entrypoint)
+                    .is_some_and(|(key, _name)| *key == insn.imm as u32)
+        });
+        for function in analysis.functions.keys() {
+            let state = if *function == analysis.entrypoint && !entrypoint_is_called {
+                AbstractState::entrypoint()
+            } else {
+                AbstractState::function()
+            };
+            result.interpret_function(analysis, config, *function, state);
+        }
+        result
+    }
+
+    fn interpret_function(
+        &mut self,
+        analysis: &Analysis,
+        config: &Config,
+        function: usize,
+        state: AbstractState,
+    ) {
+        let mut visits = BTreeMap::<usize, usize>::new();
+        let mut worklist = vec![function];
+        self.update(&mut visits, &mut worklist, function, state, false);
+        while let Some(cfg_node_start) = worklist.pop() {
+            let mut state = self.states[&cfg_node_start].clone();
+            for (destination, destination_state) in
+                transfer_cfg_node(analysis, config, None, cfg_node_start, &mut state, false)
+                    .unwrap_or_default()
+            {
+                // Every cycle contains a backward edge, so widening there suffices to terminate
+                let backward_edge = destination <= cfg_node_start;
+                self.update(
+                    &mut visits,
+                    &mut worklist,
+                    destination,
+                    destination_state,
+                    backward_edge,
+                );
+            }
+        }
+    }
+
+    fn update(
+        &mut self,
+        visits: &mut BTreeMap<usize, usize>,
+        worklist: &mut Vec<usize>,
+        cfg_node_start: usize,
+        state: AbstractState,
+        backward_edge: bool,
+    ) {
+        let visit_count = visits.entry(cfg_node_start).or_insert(0);
+        if backward_edge {
+            *visit_count = visit_count.saturating_add(1);
+        }
+        let next_state = match self.states.get(&cfg_node_start) {
+            Some(previous_state) => {
+                let joined_state = previous_state.join(&state);
+                let next_state = if *visit_count > WIDENING_THRESHOLD {
+                    previous_state.widen(&joined_state)
+                } else {
+                    joined_state
+                };
+                if &next_state == previous_state {
+                    return;
+                }
+                next_state
+            }
+            None => state,
+        };
+        self.states.insert(cfg_node_start, next_state);
+        if !worklist.contains(&cfg_node_start) {
+            worklist.push(cfg_node_start);
+        }
+    }
+
+    /// Checks every reachable instruction against its abstract state
+    ///
+    /// Accesses through the input pointer are only checked against the end of the input if
+    /// `input_len` is known, otherwise only against its start.
+    pub fn check(
+        &self,
+        analysis: &Analysis,
+        config: &Config,
+        input_len: Option<u64>,
+    ) -> Result<(), VerifierError> {
+        for (cfg_node_start, state) in self.states.iter() {
+            transfer_cfg_node(
+                analysis,
+                config,
+                input_len,
+                *cfg_node_start,
+                &mut state.clone(),
+                true,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Interprets a basic block and returns the states of the control-flow edges leaving it
+fn transfer_cfg_node(
+    analysis: &Analysis,
+    config: &Config,
+    input_len: Option<u64>,
+    cfg_node_start: usize,
+    state: &mut AbstractState,
+    check: bool,
+) -> Result<Vec<(usize, AbstractState)>, VerifierError> {
+    let cfg_node = &analysis.cfg_nodes[&cfg_node_start];
+    let instructions = &analysis.instructions[cfg_node.instructions.clone()];
+    for insn in instructions.iter() {
+        transfer_instruction(config, input_len, insn, state, check)?;
+    }
+    let last_insn = match instructions.last() {
+        Some(insn) => insn,
+        None => return Ok(Vec::new()),
+    };
+    // The successors are derived from the last instruction, as the control-flow graph has no
+    // edges out of blocks which end at a function boundary
+    let fallthrough = analysis
+        .instructions
+        .get(cfg_node.instructions.end)
+        .map(|insn| insn.ptr);
+    let target = (last_insn.ptr as isize)
+        .saturating_add(last_insn.off as isize)
+        .saturating_add(1) as usize;
+    let mut edges = Vec::new();
+    let mut add_edge = |destination: Option<usize>, state: Option<AbstractState>| {
+        if let (Some(destination), Some(state)) = (destination, state) {
+            if analysis.cfg_nodes.contains_key(&destination) {
+                edges.push((destination, state));
+            }
+        }
+    };
+    match last_insn.opc {
+        ebpf::EXIT => {}
+        ebpf::JA => add_edge(Some(target), Some(state.clone())),
+        opc if opc & 0x07 == ebpf::BPF_JMP && !matches!(opc, ebpf::CALL_IMM | ebpf::CALL_REG) => {
+            if Some(target) == fallthrough {
+                add_edge(Some(target), Some(state.clone()));
+            } else {
+                add_edge(Some(target), refine_branch(state, last_insn, true));
+                add_edge(fallthrough, refine_branch(state, last_insn, false));
+            }
+        }
+        _ => add_edge(fallthrough, Some(state.clone())),
+    }
+    Ok(edges)
+}
+
+/// Narrows the state to the values for which a conditional branch goes the given way
+///
+/// Returns `None` if the branch can never go that way.
+fn refine_branch(state: &AbstractState, insn: &ebpf::Insn, taken: bool) -> Option<AbstractState> {
+    let operand = if insn.opc & ebpf::BPF_X == ebpf::BPF_X {
+        state.registers[insn.src as usize].as_exact()
+    } else {
+        Some(insn.imm as u64)
+    };
+    let (constant, (min, max)) = match (operand, state.registers[insn.dst as usize]) {
+        (Some(constant), AbstractValue::Scalar { min, max }) => (constant, (min, max)),
+        (Some(constant), AbstractValue::Unknown) => (constant, (0, u64::MAX)),
+        _ => return Some(state.clone()),
+    };
+    // Signed comparisons are only refined if signed and unsigned order agree
+    let signed_as_unsigned = max <= i64::MAX as u64 && constant <= i64::MAX as u64;
+    let mut opc = insn.opc & !ebpf::BPF_X;
+    if signed_as_unsigned {
+        opc = match opc {
+            ebpf::JSGT_IMM => ebpf::JGT_IMM,
+            ebpf::JSGE_IMM => ebpf::JGE_IMM,
+            ebpf::JSLT_IMM => ebpf::JLT_IMM,
+            ebpf::JSLE_IMM => ebpf::JLE_IMM,
+            _ => opc,
+        };
+    }
+    // Express everything as "dst == constant", "dst != constant", "dst > constant" or "dst <= constant"
+    let (opc, constant) = match (opc, taken) {
+        (ebpf::JGE_IMM, _) | (ebpf::JLT_IMM, _) => match constant.checked_sub(1) {
+            Some(constant) => (opc ^ ebpf::JGE_IMM ^ ebpf::JGT_IMM, constant),
+            // Always greater or equal to 0
+            None => {
+                return (opc == ebpf::JGE_IMM && taken || opc == ebpf::JLT_IMM && !taken)
+                    .then(|| state.clone())
+            }
+        },
+        _ => (opc, constant),
+    };
+    let (lower, upper) = match (opc, taken) {
+        (ebpf::JEQ_IMM, true) | (ebpf::JNE_IMM, false) => (constant, constant),
+        (ebpf::JEQ_IMM, false) | (ebpf::JNE_IMM, true) => {
+            if min == max && min == constant {
+                return None;
+            }
+            if min == constant {
+                (constant.saturating_add(1), max)
+            } else if max == constant {
+                (min, constant.saturating_sub(1))
+            } else {
+                (min, max)
+            }
+        }
+        // dst > constant
+        (ebpf::JGT_IMM, true) | (ebpf::JLT_IMM, false) | (ebpf::JLE_IMM, false) => {
+            (constant.checked_add(1)?, u64::MAX)
+        }
+        // dst <= constant
+        (ebpf::JGT_IMM, false) | (ebpf::JLT_IMM, true) | (ebpf::JLE_IMM, true) => (0, constant),
+        _ => return Some(state.clone()),
+    };
+    let (min, max) = (min.max(lower), max.min(upper));
+    if min > max {
+        return None;
+    }
+    let mut state = state.clone();
+    let refined = AbstractValue::Scalar { min, max };
+    // Unknown values might be pointers and are only replaced if they are known exactly now
+    if matches!(
+        state.registers[insn.dst as usize],
+        AbstractValue::Scalar { .. }
+    ) || refined.as_exact().is_some()
+    {
+        state.registers[insn.dst as usize] = refined;
+    }
+    Some(state)
+}
+
+fn memory_access_size(opc: u8) -> u64 {
+    match opc & 0x18 {
+        ebpf::BPF_B => 1,
+        ebpf::BPF_H => 2,
+        ebpf::BPF_W => 4,
+        _ => 8,
+    }
+}
+
+/// Checks that a memory access is in bounds or at least does not fault on every execution
+fn check_memory_access(
+    config: &Config,
+    input_len: Option<u64>,
+    address: AbstractValue,
+    len: u64,
+    store: bool,
+    insn_ptr: usize,
+) -> Result<(), VerifierError> {
+    if !config.enable_address_translation {
+        return Ok(());
+    }
+    match address {
+        AbstractValue::Pointer {
+            provenance,
+            min,
+            max,
+        } => {
+            let (lower, upper) = match provenance {
+                Provenance::Stack => {
+                    let frame_size = if config.dynamic_stack_frames {
+                        config.stack_size()
+                    } else {
+                        config.stack_frame_size
+                    };
+                    ((frame_size as i64).saturating_neg(), 0)
+                }
+                Provenance::Input => (
+                    0,
+                    input_len.map_or(i64::MAX, |input_len| input_len.min(i64::MAX as u64) as i64),
+                ),
+            };
+            if min < lower || max.saturating_add(len as i64) > upper {
+                return Err(VerifierError::MemoryAccessOutOfBounds(
+                    adj_insn_ptr(insn_ptr),
+                    provenance.name(),
+                ));
+            }
+        }
+        AbstractValue::Scalar { min, max } => {
+            let end = max.saturating_add(len);
+            if end <= ebpf::MM_PROGRAM_START {
+                return Err(VerifierError::AlwaysFaultingMemoryAccess(
+                    adj_insn_ptr(insn_ptr),
+                    "unknown",
+                ));
+            }
+            if store && min >= ebpf::MM_PROGRAM_START && end <= ebpf::MM_STACK_START {
+                return Err(VerifierError::AlwaysFaultingMemoryAccess(
+                    adj_insn_ptr(insn_ptr),
+                    "program",
+                ));
+            }
+        }
+        AbstractValue::Unknown => {}
+    }
+    Ok(())
+}
+
+fn adj_insn_ptr(insn_ptr: usize) -> usize {
+    insn_ptr.saturating_add(ebpf::ELF_INSN_DUMP_OFFSET)
+}
+
+/// Applies the effect of a store to the stack slots
+fn store_stack_slots(
+    state: &mut AbstractState,
+    address: AbstractValue,
+    len: u64,
+    value: Option<AbstractValue>,
+) {
+    match address {
+        AbstractValue::Pointer {
+            provenance: Provenance::Stack,
+            min,
+            max,
+        } => {
+            state.clobber_stack_slots(min, max.saturating_add(len as i64));
+            if let (true, Some(value)) = (min == max && len == 8, value) {
+                state.stack_slots.insert(min, value);
+            }
+        }
+        AbstractValue::Pointer {
+            provenance: Provenance::Input,
+            ..
+        } => {}
+        AbstractValue::Scalar { min, max }
+            if max < ebpf::MM_STACK_START || min >= ebpf::MM_HEAP_START => {}
+        _ => state.stack_slots.clear(),
+    }
+}
+
+/// Interprets a single instruction, `check` reports the errors it would cause
+fn transfer_instruction(
+    config: &Config,
+    input_len: Option<u64>,
+    insn: &ebpf::Insn,
+    state: &mut AbstractState,
+    check: bool,
+) -> Result<(), VerifierError> {
+    let dst = insn.dst as usize;
+    let src = insn.src as usize;
+    // Only "add r11, imm" addresses r11, which is not tracked
+    if dst > ebpf::FRAME_PTR_REG || src > ebpf::FRAME_PTR_REG {
+        return Ok(());
+    }
+    match insn.opc {
+        ebpf::LD_DW_IMM => state.registers[dst] = AbstractValue::exact(insn.imm as u64),
+        ebpf::LD_B_REG | ebpf::LD_H_REG | ebpf::LD_W_REG | ebpf::LD_DW_REG => {
+            let len = memory_access_size(insn.opc);
+            let address = state.registers[src].offset_by(insn.off as i64);
+            if check {
+                check_memory_access(config, input_len, address, len, false, insn.ptr)?;
+            }
+            state.registers[dst] = match (insn.opc, address) {
+                (
+                    ebpf::LD_DW_REG,
+                    AbstractValue::Pointer {
+                        provenance: Provenance::Stack,
+                        min,
+                        max,
+                    },
+                ) if min == max => state
+                    .stack_slots
+                    .get(&min)
+                    .copied()
+                    .unwrap_or(AbstractValue::Unknown),
+                (ebpf::LD_DW_REG, _) => AbstractValue::Unknown,
+                _ => AbstractValue::scalar_below(
+                    u64::MAX
+                        .checked_shr(64u32.saturating_sub((len as u32).saturating_mul(8)))
+                        .unwrap_or(u64::MAX),
+                ),
+            };
+        }
+        ebpf::ST_B_IMM | ebpf::ST_H_IMM | ebpf::ST_W_IMM | ebpf::ST_DW_IMM => {
+            let len = memory_access_size(insn.opc);
+            let address = state.registers[dst].offset_by(insn.off as i64);
+            if check {
+                check_memory_access(config, input_len, address, len, true, insn.ptr)?;
+            }
+            store_stack_slots(
+                state,
+                address,
+                len,
+                Some(AbstractValue::exact(insn.imm as u64)),
+            );
+        }
+        ebpf::ST_B_REG | ebpf::ST_H_REG | ebpf::ST_W_REG | ebpf::ST_DW_REG => {
+            let len = memory_access_size(insn.opc);
+            let address = state.registers[dst].offset_by(insn.off as i64);
+            if check {
+                check_memory_access(config, input_len, address, len, true, insn.ptr)?;
+            }
+            store_stack_slots(state, address, len, Some(state.registers[src]));
+        }
+        ebpf::CALL_IMM | ebpf::CALL_REG => state.clobber_call(),
+        opc if matches!(opc & 0x07, ebpf::BPF_ALU | ebpf::BPF_ALU64) => {
+            // BE has the BPF_X bit set, but the width of byte swaps is the immediate
+            let source = if opc & ebpf::BPF_X == ebpf::BPF_X && !matches!(opc, ebpf::LE | ebpf::BE)
+            {
+                state.registers[src]
+            } else {
+                AbstractValue::exact(insn.imm as u64)
+            };
+            if check && divides_by_zero(opc, source) {
+                return Err(VerifierError::DivisionByZero(adj_insn_ptr(insn.ptr)));
+            }
+            state.registers[dst] =
+                transfer_alu(opc, insn.imm, state.registers[dst], source, config);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn divides_by_zero(opc: u8, divisor: AbstractValue) -> bool {
+    match opc {
+        ebpf::DIV64_REG | ebpf::MOD64_REG | ebpf::SDIV64_REG => divisor.as_exact() == Some(0),
+        ebpf::DIV32_REG | ebpf::MOD32_REG | ebpf::SDIV32_REG => divisor
+            .as_exact()
+            .is_some_and(|divisor| divisor as u32 == 0),
+        _ => false,
+    }
+}
+
+/// Abstract value of the destination register after an ALU instruction
+fn transfer_alu(
+    opc: u8,
+    imm: i64,
+    destination: AbstractValue,
+    source: AbstractValue,
+    config: &Config,
+) -> AbstractValue {
+    if let (Some(destination), Some(source)) = (destination.as_exact(), source.as_exact()) {
+        if let Some(result) = evaluate_alu(opc, imm, destination, source, config) {
+            return AbstractValue::exact(result);
+        }
+    }
+    match (opc, destination, source) {
+        (ebpf::LE | ebpf::BE, _, _) if imm == 16 => AbstractValue::scalar_below(u16::MAX as u64),
+        (ebpf::LE | ebpf::BE, _, _) if imm == 32 => AbstractValue::scalar_below(u32::MAX as u64),
+        (ebpf::LE | ebpf::BE, AbstractValue::Scalar { .. }, _) => AbstractValue::ANY_SCALAR,
+        (ebpf::MOV64_IMM | ebpf::MOV64_REG, _, source) => source,
+        (ebpf::MOV32_REG, AbstractValue::Scalar { .. }, AbstractValue::Scalar { max, .. })
+            if max <= u32::MAX as u64 =>
+        {
+            source
+        }
+        // Pointer arithmetic
+        (
+            ebpf::ADD64_IMM | ebpf::ADD64_REG,
+            AbstractValue::Pointer {
+                provenance,
+                min,
+                max,
+            },
+            AbstractValue::Scalar {
+                min: source_min,
+                max: source_max,
+            },
+        )
+        | (
+            ebpf::ADD64_REG,
+            AbstractValue::Scalar {
+                min: source_min,
+                max: source_max,
+            },
+            AbstractValue::Pointer {
+                provenance,
+                min,
+                max,
+            },
+        ) => {
+            // Immediates are sign extended
+            let (source_min, source_max) = if opc == ebpf::ADD64_IMM {
+                (imm, imm)
+            } else if source_max <= i64::MAX as u64 {
+                (source_min as i64, source_max as i64)
+            } else {
+                return AbstractValue::Unknown;
+            };
+            AbstractValue::pointer(
+                provenance,
+                min.checked_add(source_min),
+                max.checked_add(source_max),
+            )
+        }
+        (
+            ebpf::SUB64_IMM | ebpf::SUB64_REG,
+            AbstractValue::Pointer {
+                provenance,
+                min,
+                max,
+            },
+            AbstractValue::Scalar {
+                min: source_min,
+                max: source_max,
+            },
+        ) => {
+            let (source_min, source_max) = if opc == ebpf::SUB64_IMM {
+                (imm, imm)
+            } else if source_max <= i64::MAX as u64 {
+                (source_min as i64, source_max as i64)
+            } else {
+                return AbstractValue::Unknown;
+            };
+            AbstractValue::pointer(
+                provenance,
+                min.checked_sub(source_max),
+                max.checked_sub(source_min),
+            )
+        }
+        (_, AbstractValue::Pointer { .. }, _)
+        | (_, _, AbstractValue::Pointer { .. })
+        | (_, AbstractValue::Unknown, _) => AbstractValue::Unknown,
+        // Scalar arithmetic
+        (
+            _,
+            AbstractValue::Scalar { min, max },
+            AbstractValue::Scalar {
+                min: source_min,
+                max: source_max,
+            },
+        ) => transfer_scalar_alu(opc, (min, max), (source_min, source_max)),
+        (_, AbstractValue::Scalar { min, max }, AbstractValue::Unknown) => match opc {
+            ebpf::AND64_REG => AbstractValue::scalar_below(max),
+            _ => transfer_scalar_alu(opc, (min, max), (0, u64::MAX)),
+        },
+    }
+}
+
+/// Interval arithmetic of ALU instructions on numbers
+fn transfer_scalar_alu(
+    opc: u8,
+    (min, max): (u64, u64),
+    (source_min, source_max): (u64, u64),
+) -> AbstractValue {
+    let range = |min: Option<u64>, max: Option<u64>| match (min, max) {
+        (Some(min), Some(max)) => AbstractValue::Scalar { min, max },
+        _ => AbstractValue::ANY_SCALAR,
+    };
+    let below_u32 = AbstractValue::scalar_below(u32::MAX as u64);
+    match opc {
+        ebpf::ADD64_IMM | ebpf::ADD64_REG => {
+            range(min.checked_add(source_min), max.checked_add(source_max))
+        }
+        ebpf::SUB64_IMM | ebpf::SUB64_REG => {
+            range(min.checked_sub(source_max), max.checked_sub(source_min))
+        }
+        ebpf::MUL64_IMM | ebpf::MUL64_REG => {
+            range(min.checked_mul(source_min), max.checked_mul(source_max))
+        }
+        ebpf::DIV64_IMM | ebpf::DIV64_REG => range(
+            min.checked_div(source_max),
+            max.checked_div(source_min.max(1)),
+        ),
+        ebpf::MOD64_IMM | ebpf::MOD64_REG => {
+            AbstractValue::scalar_below(max.min(source_max.saturating_sub(1)))
+        }
+        ebpf::AND64_IMM | ebpf::AND64_REG => AbstractValue::scalar_below(max.min(source_max)),
+        ebpf::RSH64_IMM | ebpf::RSH64_REG if source_max < 64 => range(
+            min.checked_shr(source_max as u32),
+            max.checked_shr(source_min as u32),
+        ),
+        ebpf::LSH64_IMM | ebpf::LSH64_REG
+            if source_max < 64 && max.leading_zeros() >= source_max as u32 =>
+        {
+            range(
+                min.checked_shl(source_min as u32),
+                max.checked_shl(source_max as u32),
+            )
+        }
+        ebpf::AND32_IMM | ebpf::AND32_REG => {
+            AbstractValue::scalar_below(max.min(source_max).min(u32::MAX as u64))
+        }
+        ebpf::MOD32_IMM | ebpf::MOD32_REG if source_max <= u32::MAX as u64 => {
+            AbstractValue::scalar_below(max.min(source_max.saturating_sub(1)).min(u32::MAX as u64))
+        }
+        ebpf::DIV32_IMM
+        | ebpf::DIV32_REG
+        | ebpf::MOD32_IMM
+        | ebpf::MOD32_REG
+        | ebpf::OR32_IMM
+        | ebpf::OR32_REG
+        | ebpf::XOR32_IMM
+        | ebpf::XOR32_REG
+        | ebpf::LSH32_IMM
+        | ebpf::LSH32_REG
+        | ebpf::RSH32_IMM
+        | ebpf::RSH32_REG
+        | ebpf::NEG32
+        | ebpf::MOV32_REG
+        | ebpf::ARSH32_IMM
+        | ebpf::ARSH32_REG => below_u32,
+        _ => AbstractValue::ANY_SCALAR,
+    }
+}
+
+/// Evaluates an ALU instruction on known numbers like the interpreter does
+///
+/// Returns `None` if the instruction would fault.
+fn evaluate_alu(opc: u8, imm: i64, destination: u64, source: u64, config: &Config) -> Option<u64> {
+    Some(match opc {
+        ebpf::ADD32_IMM | ebpf::ADD32_REG => {
+            (destination as i32).wrapping_add(source as i32) as u64
+        }
+        ebpf::SUB32_IMM | ebpf::SUB32_REG => {
+            (destination as i32).wrapping_sub(source as i32) as u64
+        }
+        ebpf::MUL32_IMM | ebpf::MUL32_REG => {
+            (destination as i32).wrapping_mul(source as i32) as u64
+        }
+        ebpf::DIV32_IMM | ebpf::DIV32_REG => {
+            (destination as u32).checked_div(source as u32)? as u64
+        }
+        ebpf::SDIV32_IMM | ebpf::SDIV32_REG if config.enable_sdiv => {
+            (destination as i32).checked_div(source as i32)? as u64
+        }
+        ebpf::OR32_IMM | ebpf::OR32_REG => (destination as u32 | source as u32) as u64,
+        ebpf::AND32_IMM | ebpf::AND32_REG => (destination as u32 & source as u32) as u64,
+        ebpf::LSH32_IMM | ebpf::LSH32_REG => {
+            (destination as u32).wrapping_shl(source as u32) as u64
+        }
+        ebpf::RSH32_IMM | ebpf::RSH32_REG => {
+            (destination as u32).wrapping_shr(source as u32) as u64
+        }
+        ebpf::NEG32 => (destination as i32).wrapping_neg() as u64 & (u32::MAX as u64),
+        ebpf::MOD32_IMM | ebpf::MOD32_REG => {
+            (destination as u32).checked_rem(source as u32)? as u64
+        }
+        ebpf::XOR32_IMM | ebpf::XOR32_REG => (destination as u32 ^ source as u32) as u64,
+        ebpf::MOV32_IMM => imm as u32 as u64,
+        ebpf::MOV32_REG => source as u32 as u64,
+        ebpf::ARSH32_IMM | ebpf::ARSH32_REG => {
+            (destination as i32).wrapping_shr(source as u32) as u64 & (u32::MAX as u64)
+        }
+        ebpf::LE => match imm {
+            16 => (destination as u16).to_le() as u64,
+            32 => (destination as u32).to_le() as u64,
+            64 => destination.to_le(),
+            _ => return None,
+        },
+        ebpf::BE => match imm {
+            16 => (destination as u16).to_be() as u64,
+            32 => (destination as u32).to_be() as u64,
+            64 => destination.to_be(),
+            _ => return None,
+        },
+        ebpf::ADD64_IMM | ebpf::ADD64_REG => destination.wrapping_add(source),
+        ebpf::SUB64_IMM | ebpf::SUB64_REG => destination.wrapping_sub(source),
+        ebpf::MUL64_IMM | ebpf::MUL64_REG => destination.wrapping_mul(source),
+        ebpf::DIV64_IMM | ebpf::DIV64_REG => destination.checked_div(source)?,
+        ebpf::SDIV64_IMM | ebpf::SDIV64_REG if config.enable_sdiv => {
+            (destination as i64).checked_div(source as i64)? as u64
+        }
+        ebpf::OR64_IMM | ebpf::OR64_REG => destination | source,
+        ebpf::AND64_IMM | ebpf::AND64_REG => destination & source,
+        ebpf::LSH64_IMM | ebpf::LSH64_REG => destination.wrapping_shl(source as u32),
+        ebpf::RSH64_IMM | ebpf::RSH64_REG => destination.wrapping_shr(source as u32),
+        ebpf::NEG64 => (destination as i64).wrapping_neg() as u64,
+        ebpf::MOD64_IMM | ebpf::MOD64_REG => destination.checked_rem(source)?,
+        ebpf::XOR64_IMM | ebpf::XOR64_REG => destination ^ source,
+        ebpf::MOV64_IMM | ebpf::MOV64_REG => source,
+        ebpf::ARSH64_IMM | ebpf::ARSH64_REG => {
+            (destination as i64).wrapping_shr(source as u32) as u64
+        }
+        _ => return None,
+    })
+}
+
+/// Verifier which tracks value ranges and pointer provenance by abstract interpretation
+///
+/// Runs the checks of [RequisiteVerifier] first. The abstract interpretation needs the loader
+/// of the executable to tell syscalls apart, so it only runs in `Executable::verified()` and
+/// `verify()` on its own is the same as [RequisiteVerifier]. It rejects programs with:
+///
+///   - Accesses through pointers derived from r10 which can leave the stack frame.
+///     With `config.dynamic_stack_frames` the whole stack is taken as the frame size.
+///   - Accesses through pointers derived from the input pointer r1 of the entrypoint which
+///     can start before the input region. The length of the input is only known at runtime,
+///     so accesses past its end are not checked, see `AbstractInterpretation::check()`.
+///   - Loads from and stores to addresses which always fault, like null pointers.
+///   - Divisions by registers which are always zero.
+///
+/// Values which can not be attributed to a region, like pointers returned by syscalls,
+/// are not checked.
+pub struct AbstractInterpretationVerifier {}
+impl Verifier for AbstractInterpretationVerifier {
+    fn verify(
+        prog: &[u8],
+        config: &Config,
+        function_registry: &FunctionRegistry,
+    ) -> Result<(), VerifierError> {
+        RequisiteVerifier::verify(prog, config, function_registry)
+    }
+
+    fn verify_executable<C: ContextObject>(
+        executable: &Executable<TautologyVerifier, C>,
+    ) -> Result<(), EbpfError> {
+        let config = executable.get_config();
+        RequisiteVerifier::verify(
+            executable.get_text_bytes().1,
+            config,
+            executable.get_function_registry(),
+        )?;
+        let analysis = Analysis::from_executable(executable)?;
+        AbstractInterpretation::new(&analysis, config).check(&analysis, config, None)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_join_and_widen() {
+        let a = AbstractValue::Scalar { min: 2, max: 4 };
+        let b = AbstractValue::Scalar { min: 3, max: 9 };
+        assert_eq!(a.join(b), AbstractValue::Scalar { min: 2, max: 9 });
+        assert_eq!(
+            a.widen(a.join(b)),
+            AbstractValue::Scalar {
+                min: 2,
+                max: u64::MAX
+            }
+        );
+        let stack = AbstractValue::pointer(Provenance::Stack, Some(-8), Some(-8));
+        let input = AbstractValue::pointer(Provenance::Input, Some(0), Some(0));
+        assert_eq!(stack.join(input), AbstractValue::Unknown);
+        assert_eq!(stack.join(a), AbstractValue::Unknown);
+        assert_eq!(
+            stack.join(stack.offset_by(-8)),
+            AbstractValue::pointer(Provenance::Stack, Some(-16), Some(-8))
+        );
+    }
+
+    #[test]
+    fn test_refine_branch() {
+        let mut state = AbstractState::function();
+        state.registers[2] = AbstractValue::Scalar { min: 0, max: 100 };
+        let insn = |opc, imm| ebpf::Insn {
+            ptr: 0,
+            opc,
+            dst: 2,
+            src: 0,
+            off: 1,
+            imm,
+        };
+        let refined = |opc, imm, taken| {
+            refine_branch(&state, &insn(opc, imm), taken).map(|state| state.registers[2])
+        };
+        assert_eq!(
+            refined(ebpf::JLT_IMM, 10, true),
+            Some(AbstractValue::Scalar { min: 0, max: 9 })
+        );
+        assert_eq!(
+            refined(ebpf::JLT_IMM, 10, false),
+            Some(AbstractValue::Scalar { min: 10, max: 100 })
+        );
+        assert_eq!(
+            refined(ebpf::JGT_IMM, 10, true),
+            Some(AbstractValue::Scalar { min: 11, max: 100 })
+        );
+        assert_eq!(
+            refined(ebpf::JSLE_IMM, 10, false),
+            Some(AbstractValue::Scalar { min: 11, max: 100 })
+        );
+        assert_eq!(
+            refined(ebpf::JEQ_IMM, 7, true),
+            Some(AbstractValue::exact(7))
+        );
+        assert_eq!(
+            refined(ebpf::JNE_IMM, 0, true),
+            Some(AbstractValue::Scalar { min: 1, max: 100 })
+        );
+        assert_eq!(refined(ebpf::JGT_IMM, 100, true), None);
+        assert_eq!(refined(ebpf::JGE_IMM, 0, false), None);
+        assert_eq!(refined(ebpf::JEQ_IMM, 200, true), None);
+    }
+}
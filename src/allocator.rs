@@ -0,0 +1,364 @@
+//! Heap allocator built-in which provides alloc, free and realloc syscalls over the heap region
+//!
+//! The syscalls follow the `GlobalAlloc` interface of Rust:
+//!
+//! * `bpf_alloc(size, align)` returns the address of the allocation or 0 if the heap is exhausted
+//! * `bpf_free(addr, size, align)` releases an allocation, freeing 0 does nothing
+//! * `bpf_realloc(addr, size, align, new_size)` moves an allocation and returns its new address
+//!   or 0 if the heap is exhausted, in which case the old allocation stays valid
+
+use crate::{
+    ebpf,
+    error::EbpfError,
+    memory_region::{AccessType, MemoryMapping},
+    vm::{BuiltInProgram, ContextObject, ProgramResult},
+};
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+
+/// Number of guard bytes before and after every allocation in `AllocatorMode::Debug`
+pub const RED_ZONE_SIZE: u64 = 16;
+
+/// Value the red zones are filled with
+pub const RED_ZONE_PATTERN: u8 = 0xfd;
+
+/// Error definitions
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum AllocatorError {
+    /// Alignment is not a power of two
+    #[error("invalid heap alignment {0}")]
+    InvalidAlignment(u64),
+    /// Allocation was freed before
+    #[error("double free of heap allocation at {0:#x}")]
+    DoubleFree(u64),
+    /// Address and size do not describe a live allocation
+    #[error("free of heap address {0:#x} with size {1} which was not allocated")]
+    InvalidFree(u64, u64),
+    /// A red zone around the allocation was overwritten
+    #[error("out-of-bounds write to heap allocation at {0:#x} of size {1}")]
+    OutOfBoundsWrite(u64, u64),
+    /// Address and size do not describe a previous allocation
+    #[error("realloc of heap address {0:#x} with size {1} which was not allocated")]
+    InvalidRealloc(u64, u64),
+    /// Allocations were still live when the program exited
+    #[error("{0} heap allocations of {1} bytes in total were leaked")]
+    MemoryLeak(usize, u64),
+}
+
+/// Allocation strategy of a `HeapAllocator`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocatorMode {
+    /// Only moves a pointer forward, free does nothing
+    Bump,
+    /// Bump allocation which tracks every allocation and guards it with red zones
+    ///
+    /// An overwritten red zone is not detected by the write itself, but only once its
+    /// allocation is freed or reallocated, or by `HeapAllocator::finish()`.
+    Debug,
+}
+
+/// Allocator over the heap region starting at `ebpf::MM_HEAP_START`
+///
+/// Memory is never reused, so a freed allocation stays unavailable until the allocator is reset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapAllocator {
+    mode: AllocatorMode,
+    /// Size of the heap region
+    heap_size: u64,
+    /// Offset of the first unused byte
+    position: u64,
+    /// Size of the live allocations by their address
+    allocations: BTreeMap<u64, u64>,
+    /// Addresses of the freed allocations
+    freed: BTreeSet<u64>,
+}
+
+impl HeapAllocator {
+    /// Creates an allocator for a heap region of `heap_size` bytes
+    pub fn new(mode: AllocatorMode, heap_size: u64) -> Self {
+        Self {
+            mode,
+            heap_size,
+            position: 0,
+            allocations: BTreeMap::new(),
+            freed: BTreeSet::new(),
+        }
+    }
+
+    /// Allocation strategy
+    pub fn mode(&self) -> AllocatorMode {
+        self.mode
+    }
+
+    /// Size of the live allocations by their address, only tracked in `AllocatorMode::Debug`
+    pub fn allocations(&self) -> &BTreeMap<u64, u64> {
+        &self.allocations
+    }
+
+    /// Bytes of the heap region which were handed out, including red zones and padding
+    pub fn used(&self) -> u64 {
+        self.position
+    }
+
+    /// Forgets all allocations
+    pub fn reset(&mut self) {
+        self.position = 0;
+        self.allocations.clear();
+        self.freed.clear();
+    }
+
+    /// Returns the address of the new allocation or 0 if the heap is exhausted
+    pub fn alloc(
+        &mut self,
+        size: u64,
+        align: u64,
+        memory_mapping: &MemoryMapping,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        if !align.is_power_of_two() {
+            return Err(AllocatorError::InvalidAlignment(align).into());
+        }
+        let red_zone_size = match self.mode {
+            AllocatorMode::Bump => 0,
+            AllocatorMode::Debug => RED_ZONE_SIZE,
+        };
+        let start = match self
+            .position
+            .checked_add(red_zone_size)
+            .and_then(|start| start.checked_add(align.saturating_sub(1)))
+            .map(|start| start & !align.saturating_sub(1))
+        {
+            Some(start) => start,
+            None => return Ok(0),
+        };
+        let end = match start
+            .checked_add(size)
+            .and_then(|end| end.checked_add(red_zone_size))
+        {
+            Some(end) if end <= self.heap_size => end,
+            _ => return Ok(0),
+        };
+        self.position = end;
+        let vm_addr = ebpf::MM_HEAP_START.saturating_add(start);
+        if self.mode == AllocatorMode::Debug {
+            self.fill_red_zone(vm_addr.saturating_sub(RED_ZONE_SIZE), memory_mapping)?;
+            self.fill_red_zone(vm_addr.saturating_add(size), memory_mapping)?;
+            self.allocations.insert(vm_addr, size);
+            self.freed.remove(&vm_addr);
+        }
+        Ok(vm_addr)
+    }
+
+    /// Releases the allocation at `vm_addr`
+    pub fn free(
+        &mut self,
+        vm_addr: u64,
+        size: u64,
+        memory_mapping: &MemoryMapping,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if vm_addr == 0 || self.mode == AllocatorMode::Bump {
+            return Ok(());
+        }
+        self.check_allocation(vm_addr, size, memory_mapping)?;
+        self.allocations.remove(&vm_addr);
+        self.freed.insert(vm_addr);
+        Ok(())
+    }
+
+    /// Moves the allocation at `vm_addr` into a new allocation of `new_size` bytes
+    ///
+    /// Returns the new address or 0 if the heap is exhausted.
+    pub fn realloc(
+        &mut self,
+        vm_addr: u64,
+        size: u64,
+        align: u64,
+        new_size: u64,
+        memory_mapping: &MemoryMapping,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        if vm_addr == 0 {
+            return self.alloc(new_size, align, memory_mapping);
+        }
+        // The source has to be handed out already, so it ends before the new allocation starts
+        let allocated = vm_addr
+            .checked_sub(ebpf::MM_HEAP_START)
+            .and_then(|start| start.checked_add(size))
+            .map(|end| end <= self.position)
+            .unwrap_or(false);
+        if !allocated {
+            return Err(AllocatorError::InvalidRealloc(vm_addr, size).into());
+        }
+        if self.mode == AllocatorMode::Debug {
+            self.check_allocation(vm_addr, size, memory_mapping)?;
+        }
+        let new_vm_addr = self.alloc(new_size, align, memory_mapping)?;
+        if new_vm_addr == 0 {
+            return Ok(0);
+        }
+        let len = size.min(new_size);
+        let src = Result::from(memory_mapping.map(AccessType::Load, vm_addr, len, 0))?;
+        let dst = Result::from(memory_mapping.map(AccessType::Store, new_vm_addr, len, 0))?;
+        // Safety:
+        // Both ranges were mapped, copy() tolerates overlapping ranges
+        unsafe {
+            std::ptr::copy(src as *const u8, dst as *mut u8, len as usize);
+        }
+        self.free(vm_addr, size, memory_mapping)?;
+        Ok(new_vm_addr)
+    }
+
+    /// Checks the red zones of all live allocations and reports leaks
+    ///
+    /// To be called after the program exited. Does nothing in `AllocatorMode::Bump`.
+    pub fn finish(&self, memory_mapping: &MemoryMapping) -> Result<(), Box<dyn std::error::Error>> {
+        for (vm_addr, size) in self.allocations.iter() {
+            self.check_red_zones(*vm_addr, *size, memory_mapping)?;
+        }
+        if !self.allocations.is_empty() {
+            let leaked_bytes = self
+                .allocations
+                .values()
+                .fold(0u64, |total, size| total.saturating_add(*size));
+            return Err(AllocatorError::MemoryLeak(self.allocations.len(), leaked_bytes).into());
+        }
+        Ok(())
+    }
+
+    fn check_allocation(
+        &self,
+        vm_addr: u64,
+        size: u64,
+        memory_mapping: &MemoryMapping,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self.allocations.get(&vm_addr) {
+            Some(allocated_size) if *allocated_size == size => {
+                self.check_red_zones(vm_addr, size, memory_mapping)
+            }
+            None if self.freed.contains(&vm_addr) => {
+                Err(AllocatorError::DoubleFree(vm_addr).into())
+            }
+            _ => Err(AllocatorError::InvalidFree(vm_addr, size).into()),
+        }
+    }
+
+    fn fill_red_zone(
+        &self,
+        vm_addr: u64,
+        memory_mapping: &MemoryMapping,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let host_addr =
+            Result::from(memory_mapping.map(AccessType::Store, vm_addr, RED_ZONE_SIZE, 0))?;
+        // Safety:
+        // map succeeded so we can write RED_ZONE_SIZE bytes
+        unsafe {
+            std::ptr::write_bytes(
+                host_addr as *mut u8,
+                RED_ZONE_PATTERN,
+                RED_ZONE_SIZE as usize,
+            );
+        }
+        Ok(())
+    }
+
+    fn check_red_zones(
+        &self,
+        vm_addr: u64,
+        size: u64,
+        memory_mapping: &MemoryMapping,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for red_zone in [
+            vm_addr.saturating_sub(RED_ZONE_SIZE),
+            vm_addr.saturating_add(size),
+        ] {
+            let intact = match memory_mapping.map(AccessType::Load, red_zone, RED_ZONE_SIZE, 0) {
+                // Safety:
+                // map succeeded so we can read RED_ZONE_SIZE bytes
+                ProgramResult::Ok(host_addr) => unsafe {
+                    std::slice::from_raw_parts(host_addr as *const u8, RED_ZONE_SIZE as usize)
+                }
+                .iter()
+                .all(|byte| *byte == RED_ZONE_PATTERN),
+                ProgramResult::Err(_) => false,
+            };
+            if !intact {
+                return Err(AllocatorError::OutOfBoundsWrite(vm_addr, size).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runtime context which owns a `HeapAllocator`
+pub trait HeapAllocatorContextObject: ContextObject {
+    /// The allocator used by the syscalls of this module
+    fn heap_allocator(&mut self) -> &mut HeapAllocator;
+}
+
+/// Registers `bpf_alloc`, `bpf_free` and `bpf_realloc`
+pub fn register_allocator<C: HeapAllocatorContextObject>(
+    loader: &mut BuiltInProgram<C>,
+) -> Result<(), EbpfError> {
+    loader.register_function(b"bpf_alloc", bpf_alloc::<C>)?;
+    loader.register_function(b"bpf_free", bpf_free::<C>)?;
+    loader.register_function(b"bpf_realloc", bpf_realloc::<C>)?;
+    Ok(())
+}
+
+/// Allocates `size` bytes aligned to `align`
+#[allow(clippy::too_many_arguments)]
+pub fn bpf_alloc<C: HeapAllocatorContextObject>(
+    context_object: &mut C,
+    size: u64,
+    align: u64,
+    _arg3: u64,
+    _arg4: u64,
+    _arg5: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult,
+) {
+    *result = ProgramResult::from(context_object.heap_allocator().alloc(
+        size,
+        align,
+        memory_mapping,
+    ));
+}
+
+/// Releases the allocation at `vm_addr` of `size` bytes
+#[allow(clippy::too_many_arguments)]
+pub fn bpf_free<C: HeapAllocatorContextObject>(
+    context_object: &mut C,
+    vm_addr: u64,
+    size: u64,
+    _align: u64,
+    _arg4: u64,
+    _arg5: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult,
+) {
+    *result = ProgramResult::from(
+        context_object
+            .heap_allocator()
+            .free(vm_addr, size, memory_mapping)
+            .map(|_| 0),
+    );
+}
+
+/// Moves the allocation at `vm_addr` of `size` bytes into one of `new_size` bytes
+#[allow(clippy::too_many_arguments)]
+pub fn bpf_realloc<C: HeapAllocatorContextObject>(
+    context_object: &mut C,
+    vm_addr: u64,
+    size: u64,
+    align: u64,
+    new_size: u64,
+    _arg5: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult,
+) {
+    *result = ProgramResult::from(context_object.heap_allocator().realloc(
+        vm_addr,
+        size,
+        align,
+        new_size,
+        memory_mapping,
+    ));
+}
@@ -0,0 +1,297 @@
+//! Static upper bounds on the instruction meter cost of functions
+//!
+//! The bound of a function is the longest path through its control-flow graph, weighted by
+//! `Config::instruction_costs`. Loops are found as the strongly connected components of the
+//! control-flow graph. Their bodies are bounded recursively and the result is multiplied with
+//! a symbolic iteration count, so a loop-free function gets a constant bound.
+
+use crate::{
+    ebpf,
+    static_analysis::{strongly_connected_components, Analysis},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// Upper bound on the cost of one call of a function
+///
+/// A polynomial in the iteration counts of the loops of the function and of its callees.
+/// Every loop is identified by the pc of its header, the first basic block of the loop which
+/// is entered from outside. Its iteration count is how often the header is executed each time
+/// the loop is entered. Costs charged by syscalls themselves are not included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CostBound {
+    /// Coefficients by the sorted loop headers whose iteration counts they are multiplied with
+    pub terms: BTreeMap<Vec<usize>, u64>,
+}
+
+impl CostBound {
+    /// A bound which does not depend on any iteration count
+    pub fn constant(cost: u64) -> Self {
+        let mut terms = BTreeMap::new();
+        if cost > 0 {
+            terms.insert(Vec::new(), cost);
+        }
+        Self { terms }
+    }
+
+    /// Returns the bound if it does not depend on any iteration count
+    pub fn as_constant(&self) -> Option<u64> {
+        match self.terms.keys().next_back() {
+            Some(loops) if !loops.is_empty() => None,
+            _ => Some(self.terms.get(&Vec::new()).copied().unwrap_or(0)),
+        }
+    }
+
+    /// Headers of the loops the bound depends on
+    pub fn loops(&self) -> BTreeSet<usize> {
+        self.terms.keys().flatten().copied().collect()
+    }
+
+    /// Evaluates the bound given the iteration count of every loop
+    pub fn evaluate<F: Fn(usize) -> u64>(&self, iterations: F) -> u64 {
+        self.terms.iter().fold(0u64, |total, (loops, coefficient)| {
+            let term = loops.iter().fold(*coefficient, |term, header| {
+                term.saturating_mul(iterations(*header))
+            });
+            total.saturating_add(term)
+        })
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (loops, coefficient) in other.terms.iter() {
+            let term = self.terms.entry(loops.clone()).or_insert(0);
+            *term = term.saturating_add(*coefficient);
+        }
+    }
+
+    /// Upper bound of both, the maximum of every coefficient
+    fn max(&mut self, other: &Self) {
+        for (loops, coefficient) in other.terms.iter() {
+            let term = self.terms.entry(loops.clone()).or_insert(0);
+            *term = (*term).max(*coefficient);
+        }
+    }
+
+    fn multiply_by_iterations(&self, header: usize) -> Self {
+        let terms = self
+            .terms
+            .iter()
+            .map(|(loops, coefficient)| {
+                let mut loops = loops.clone();
+                let index = loops.partition_point(|other_header| *other_header <= header);
+                loops.insert(index, header);
+                (loops, *coefficient)
+            })
+            .collect();
+        Self { terms }
+    }
+}
+
+impl fmt::Display for CostBound {
+    /// Formats the polynomial with `n<pc>` as the iteration count of the loop at `pc`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+        for (index, (loops, coefficient)) in self.terms.iter().enumerate() {
+            if index > 0 {
+                write!(f, " + ")?;
+            }
+            write!(f, "{coefficient}")?;
+            for header in loops.iter() {
+                write!(f, "*n{header}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Static cost bounds of all functions
+#[derive(Debug, Default)]
+pub struct CostAnalysis {
+    /// Bound of every function by the pc of its first instruction
+    ///
+    /// `None` if the function can recurse or uses `callx`, directly or through its callees.
+    pub functions: BTreeMap<usize, Option<CostBound>>,
+}
+
+impl CostAnalysis {
+    /// Bounds every function of the analysis
+    pub fn new(analysis: &Analysis) -> Self {
+        let mut result = Self::default();
+        let function_by_key = analysis
+            .functions
+            .iter()
+            .map(|(pc, (key, _name))| (*key, *pc))
+            .collect::<BTreeMap<u32, usize>>();
+        for function in analysis.functions.keys() {
+            result.bound_function(analysis, &function_by_key, *function, &mut BTreeSet::new());
+        }
+        result
+    }
+
+    /// Bound of the program as a whole, `None` if it is unbounded
+    pub fn entrypoint<'a>(&'a self, analysis: &Analysis) -> Option<&'a CostBound> {
+        self.functions.get(&analysis.entrypoint)?.as_ref()
+    }
+
+    fn bound_function(
+        &mut self,
+        analysis: &Analysis,
+        function_by_key: &BTreeMap<u32, usize>,
+        function: usize,
+        active_functions: &mut BTreeSet<usize>,
+    ) -> Option<CostBound> {
+        if let Some(bound) = self.functions.get(&function) {
+            return bound.clone();
+        }
+        if !active_functions.insert(function) {
+            // Recursion
+            return None;
+        }
+        let cfg_nodes = analysis
+            .iter_cfg_by_function()
+            .filter(|(function_range, cfg_node_start, _cfg_node)| {
+                function_range.start == function && function_range.contains(cfg_node_start)
+            })
+            .map(|(_function_range, cfg_node_start, _cfg_node)| cfg_node_start)
+            .collect::<BTreeSet<usize>>();
+        let mut weights = BTreeMap::new();
+        let mut bounded = true;
+        for cfg_node_start in cfg_nodes.iter() {
+            let mut weight = CostBound::default();
+            let cfg_node = &analysis.cfg_nodes[cfg_node_start];
+            for insn in analysis.instructions[cfg_node.instructions.clone()].iter() {
+                weight.add(&CostBound::constant(analysis.instruction_cost(insn)));
+                match insn.opc {
+                    ebpf::CALL_IMM if !analysis.is_syscall(insn) => {
+                        // Calls to unknown functions fault at runtime
+                        if let Some(callee) = function_by_key.get(&(insn.imm as u32)) {
+                            match self.bound_function(
+                                analysis,
+                                function_by_key,
+                                *callee,
+                                active_functions,
+                            ) {
+                                Some(callee_bound) => weight.add(&callee_bound),
+                                None => bounded = false,
+                            }
+                        }
+                    }
+                    ebpf::CALL_REG => bounded = false,
+                    _ => {}
+                }
+            }
+            weights.insert(*cfg_node_start, weight);
+        }
+        let bound = if bounded {
+            let entries = BTreeSet::from([function]);
+            longest_paths(analysis, &weights, &cfg_nodes, &entries, &BTreeSet::new())
+                .remove(&function)
+        } else {
+            None
+        };
+        active_functions.remove(&function);
+        self.functions.insert(function, bound.clone());
+        bound
+    }
+}
+
+/// Bounds the paths starting at every basic block of a region of the control-flow graph
+///
+/// Paths stay inside the region and do not follow edges to `excluded` basic blocks.
+/// `entries` are the basic blocks through which the region is entered.
+fn longest_paths(
+    analysis: &Analysis,
+    weights: &BTreeMap<usize, CostBound>,
+    region: &BTreeSet<usize>,
+    entries: &BTreeSet<usize>,
+    excluded: &BTreeSet<usize>,
+) -> BTreeMap<usize, CostBound> {
+    let successors = region
+        .iter()
+        .map(|cfg_node_start| {
+            let destinations = analysis.cfg_nodes[cfg_node_start]
+                .destinations
+                .iter()
+                .filter(|destination| {
+                    region.contains(destination) && !excluded.contains(destination)
+                })
+                .copied()
+                .collect::<Vec<usize>>();
+            (*cfg_node_start, destinations)
+        })
+        .collect::<BTreeMap<usize, Vec<usize>>>();
+    let mut costs = BTreeMap::<usize, CostBound>::new();
+    for component in components(&successors) {
+        let mut exit_cost = CostBound::default();
+        for cfg_node_start in component.iter() {
+            for destination in successors[cfg_node_start].iter() {
+                if !component.contains(destination) {
+                    exit_cost.max(&costs[destination]);
+                }
+            }
+        }
+        let cfg_node_start = *component.first().unwrap();
+        if component.len() == 1 && !successors[&cfg_node_start].contains(&cfg_node_start) {
+            let mut cost = weights[&cfg_node_start].clone();
+            cost.add(&exit_cost);
+            costs.insert(cfg_node_start, cost);
+            continue;
+        }
+        // A loop, every iteration starts at one of its headers
+        let mut headers = component
+            .iter()
+            .filter(|cfg_node_start| {
+                entries.contains(cfg_node_start)
+                    || analysis.cfg_nodes[cfg_node_start]
+                        .sources
+                        .iter()
+                        .any(|source| region.contains(source) && !component.contains(source))
+            })
+            .copied()
+            .collect::<BTreeSet<usize>>();
+        if headers.is_empty() {
+            headers.insert(cfg_node_start);
+        }
+        let inner_excluded = excluded.union(&headers).copied().collect();
+        let iterations = longest_paths(analysis, weights, &component, &headers, &inner_excluded);
+        let mut iteration = CostBound::default();
+        for header in headers.iter() {
+            iteration.max(&iterations[header]);
+        }
+        let mut cost = iteration.multiply_by_iterations(*headers.first().unwrap());
+        cost.add(&exit_cost);
+        for cfg_node_start in component.iter() {
+            costs.insert(*cfg_node_start, cost.clone());
+        }
+    }
+    costs
+}
+
+/// The strongly connected components of a region in reverse topological order
+fn components(successors: &BTreeMap<usize, Vec<usize>>) -> Vec<BTreeSet<usize>> {
+    let nodes = successors.keys().copied().collect::<Vec<usize>>();
+    let successor_indices = successors
+        .values()
+        .map(|destinations| {
+            destinations
+                .iter()
+                .map(|destination| nodes.binary_search(destination).unwrap())
+                .collect()
+        })
+        .collect::<Vec<Vec<usize>>>();
+    let mut components = Vec::<BTreeSet<usize>>::new();
+    for (node, topo_index) in nodes
+        .iter()
+        .zip(strongly_connected_components(&successor_indices))
+    {
+        if components.len() <= topo_index.scc_id {
+            components.resize_with(topo_index.scc_id.saturating_add(1), BTreeSet::new);
+        }
+        components[topo_index.scc_id].insert(*node);
+    }
+    components
+}
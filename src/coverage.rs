@@ -0,0 +1,391 @@
+//! Execution coverage of instructions and branches with lcov and Cobertura reports
+
+use crate::{
+    ebpf,
+    static_analysis::{Analysis, TraceLogEntry},
+};
+use std::{collections::BTreeMap, io::Write};
+
+/// Source file and line of every instruction, keyed by pc
+pub type LineTable = BTreeMap<usize, (String, usize)>;
+
+/// Execution counters of instructions and branch edges, accumulated over one or more runs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// How often every instruction was executed, keyed by pc
+    pub instructions: BTreeMap<usize, usize>,
+    /// pc of a conditional jump, pc of the destination, edge counter
+    pub branches: BTreeMap<usize, BTreeMap<usize, usize>>,
+}
+
+/// Coverage of a source line
+#[derive(Default)]
+struct LineReport {
+    hits: usize,
+    /// Conditional jumps on this line with their fall through and taken counters,
+    /// `None` if the jump was never executed
+    branches: Vec<(usize, Option<[usize; 2]>)>,
+}
+
+impl LineReport {
+    fn branch_counts(&self) -> (usize, usize) {
+        self.branches
+            .iter()
+            .fold((0, 0), |(covered, total), (_pc, counters)| {
+                let covered_here = counters
+                    .map(|counters| counters.iter().filter(|counter| **counter > 0).count())
+                    .unwrap_or(0);
+                (
+                    covered.saturating_add(covered_here),
+                    total.saturating_add(2),
+                )
+            })
+    }
+}
+
+/// Coverage of a function
+struct FunctionReport {
+    name: String,
+    line: usize,
+    hits: usize,
+    lines: BTreeMap<usize, usize>,
+}
+
+/// Coverage of a source file
+#[derive(Default)]
+struct FileReport {
+    lines: BTreeMap<usize, LineReport>,
+    functions: Vec<FunctionReport>,
+}
+
+impl FileReport {
+    fn line_counts(&self) -> (usize, usize) {
+        let covered = self.lines.values().filter(|line| line.hits > 0).count();
+        (covered, self.lines.len())
+    }
+
+    fn branch_counts(&self) -> (usize, usize) {
+        self.lines.values().map(LineReport::branch_counts).fold(
+            (0, 0),
+            |(covered, total), (line_covered, line_total)| {
+                (
+                    covered.saturating_add(line_covered),
+                    total.saturating_add(line_total),
+                )
+            },
+        )
+    }
+}
+
+/// Adds `amount` to an execution counter, saturating instead of overflowing
+pub(crate) fn increment(counter: &mut usize, amount: usize) {
+    *counter = counter.saturating_add(amount);
+}
+
+fn find_instruction<'a>(analysis: &'a Analysis, pc: usize) -> Option<&'a ebpf::Insn> {
+    analysis
+        .instructions
+        .binary_search_by_key(&pc, |insn| insn.ptr)
+        .ok()
+        .map(|index| &analysis.instructions[index])
+}
+
+fn is_conditional_jump(insn: &ebpf::Insn) -> bool {
+    insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP
+        && !matches!(
+            insn.opc & ebpf::BPF_ALU_OP_MASK,
+            ebpf::BPF_JA | ebpf::BPF_CALL | ebpf::BPF_EXIT
+        )
+}
+
+fn rate(covered: usize, total: usize) -> f64 {
+    if total == 0 {
+        1.0
+    } else {
+        covered as f64 / total as f64
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Attributes every instruction to its line in the output of `Analysis::disassemble()`
+///
+/// Used when the executable has no debug information to map it to its source.
+pub fn disassembly_line_table(analysis: &Analysis, file_name: &str) -> LineTable {
+    let mut line_table = LineTable::new();
+    let mut line = 0usize;
+    let mut last_basic_block = usize::MAX;
+    #[cfg(feature = "debug-info")]
+    let mut last_source_location = None;
+    for insn in analysis.instructions.iter() {
+        let mut labels = Vec::new();
+        analysis
+            .disassemble_label(
+                &mut labels,
+                Some(insn) == analysis.instructions.first(),
+                insn.ptr,
+                &mut last_basic_block,
+            )
+            .unwrap();
+        #[cfg(feature = "debug-info")]
+        analysis
+            .disassemble_source_location(&mut labels, insn.ptr, &mut last_source_location)
+            .unwrap();
+        let label_lines = labels.iter().filter(|byte| **byte == b'\n').count();
+        line = line.saturating_add(label_lines).saturating_add(1);
+        line_table.insert(insn.ptr, (file_name.to_string(), line));
+    }
+    line_table
+}
+
+impl Coverage {
+    /// Accumulates a trace log as recorded by `ContextObject::trace()`
+    pub fn add_trace_log(&mut self, analysis: &Analysis, trace_log: &[TraceLogEntry]) {
+        for entry in trace_log.iter() {
+            increment(self.instructions.entry(entry[11] as usize).or_insert(0), 1);
+        }
+        for entries in trace_log.windows(2) {
+            let pc = entries[0][11] as usize;
+            if find_instruction(analysis, pc).is_some_and(is_conditional_jump) {
+                let counter = self
+                    .branches
+                    .entry(pc)
+                    .or_default()
+                    .entry(entries[1][11] as usize)
+                    .or_insert(0);
+                increment(counter, 1);
+            }
+        }
+    }
+
+    /// Adds the counters of another run
+    pub fn merge(&mut self, other: &Self) {
+        for (pc, counter) in other.instructions.iter() {
+            increment(self.instructions.entry(*pc).or_insert(0), *counter);
+        }
+        for (pc, destinations) in other.branches.iter() {
+            let own_destinations = self.branches.entry(*pc).or_default();
+            for (destination, counter) in destinations.iter() {
+                increment(own_destinations.entry(*destination).or_insert(0), *counter);
+            }
+        }
+    }
+
+    /// Groups the counters by source file and line
+    ///
+    /// Instructions missing from the line table are left out.
+    fn file_reports(
+        &self,
+        analysis: &Analysis,
+        line_table: &LineTable,
+    ) -> BTreeMap<String, FileReport> {
+        let mut file_reports = BTreeMap::<String, FileReport>::new();
+        let function_starts = analysis.functions.keys().copied().collect::<Vec<_>>();
+        let mut functions = BTreeMap::<usize, FunctionReport>::new();
+        for insn in analysis.instructions.iter() {
+            let (file, line) = match line_table.get(&insn.ptr) {
+                Some(location) => location,
+                None => continue,
+            };
+            let hits = self.instructions.get(&insn.ptr).copied().unwrap_or(0);
+            let file_report = file_reports.entry(file.clone()).or_default();
+            let line_report = file_report.lines.entry(*line).or_default();
+            line_report.hits = line_report.hits.max(hits);
+            if is_conditional_jump(insn) {
+                let destinations = [
+                    insn.ptr.saturating_add(1),
+                    (insn.ptr as i64)
+                        .saturating_add(1)
+                        .saturating_add(insn.off as i64) as usize,
+                ];
+                let counters = (hits > 0).then(|| {
+                    destinations.map(|destination| {
+                        self.branches
+                            .get(&insn.ptr)
+                            .and_then(|destinations| destinations.get(&destination))
+                            .copied()
+                            .unwrap_or(0)
+                    })
+                });
+                line_report.branches.push((insn.ptr, counters));
+            }
+            let function_pc = match function_starts
+                .partition_point(|function_pc| *function_pc <= insn.ptr)
+                .checked_sub(1)
+            {
+                Some(index) => function_starts[index],
+                None => continue,
+            };
+            let function = functions
+                .entry(function_pc)
+                .or_insert_with(|| FunctionReport {
+                    name: analysis
+                        .cfg_nodes
+                        .get(&function_pc)
+                        .map(|cfg_node| cfg_node.label.clone())
+                        .unwrap_or_default(),
+                    line: *line,
+                    hits: self.instructions.get(&function_pc).copied().unwrap_or(0),
+                    lines: BTreeMap::new(),
+                });
+            let function_line = function.lines.entry(*line).or_insert(0);
+            *function_line = (*function_line).max(hits);
+        }
+        for (function_pc, function) in functions {
+            if let Some((file, _line)) = line_table.get(&function_pc) {
+                if let Some(file_report) = file_reports.get_mut(file) {
+                    file_report.functions.push(function);
+                }
+            }
+        }
+        file_reports
+    }
+
+    /// Writes an lcov tracefile
+    pub fn write_lcov<W: Write>(
+        &self,
+        output: &mut W,
+        analysis: &Analysis,
+        line_table: &LineTable,
+    ) -> std::io::Result<()> {
+        for (file, file_report) in self.file_reports(analysis, line_table) {
+            writeln!(output, "TN:")?;
+            writeln!(output, "SF:{file}")?;
+            for function in file_report.functions.iter() {
+                writeln!(output, "FN:{},{}", function.line, function.name)?;
+            }
+            for function in file_report.functions.iter() {
+                writeln!(output, "FNDA:{},{}", function.hits, function.name)?;
+            }
+            writeln!(output, "FNF:{}", file_report.functions.len())?;
+            writeln!(
+                output,
+                "FNH:{}",
+                file_report
+                    .functions
+                    .iter()
+                    .filter(|function| function.hits > 0)
+                    .count()
+            )?;
+            for (line, line_report) in file_report.lines.iter() {
+                for (pc, counters) in line_report.branches.iter() {
+                    for branch in 0..2 {
+                        match counters {
+                            Some(counters) => {
+                                writeln!(output, "BRDA:{line},{pc},{branch},{}", counters[branch])?
+                            }
+                            None => writeln!(output, "BRDA:{line},{pc},{branch},-")?,
+                        }
+                    }
+                }
+            }
+            let (branches_covered, branches_total) = file_report.branch_counts();
+            writeln!(output, "BRF:{branches_total}")?;
+            writeln!(output, "BRH:{branches_covered}")?;
+            for (line, line_report) in file_report.lines.iter() {
+                writeln!(output, "DA:{line},{}", line_report.hits)?;
+            }
+            let (lines_covered, lines_total) = file_report.line_counts();
+            writeln!(output, "LF:{lines_total}")?;
+            writeln!(output, "LH:{lines_covered}")?;
+            writeln!(output, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Writes a Cobertura XML report
+    pub fn write_cobertura<W: Write>(
+        &self,
+        output: &mut W,
+        analysis: &Analysis,
+        line_table: &LineTable,
+    ) -> std::io::Result<()> {
+        let file_reports = self.file_reports(analysis, line_table);
+        let (mut lines_covered, mut lines_total, mut branches_covered, mut branches_total) =
+            (0usize, 0usize, 0usize, 0usize);
+        for file_report in file_reports.values() {
+            let (covered, total) = file_report.line_counts();
+            lines_covered = lines_covered.saturating_add(covered);
+            lines_total = lines_total.saturating_add(total);
+            let (covered, total) = file_report.branch_counts();
+            branches_covered = branches_covered.saturating_add(covered);
+            branches_total = branches_total.saturating_add(total);
+        }
+        let line_rate = rate(lines_covered, lines_total);
+        let branch_rate = rate(branches_covered, branches_total);
+        writeln!(output, "<?xml version=\"1.0\" ?>")?;
+        writeln!(
+            output,
+            "<coverage line-rate=\"{line_rate:.4}\" branch-rate=\"{branch_rate:.4}\" lines-covered=\"{lines_covered}\" lines-valid=\"{lines_total}\" branches-covered=\"{branches_covered}\" branches-valid=\"{branches_total}\" complexity=\"0\" version=\"{}\" timestamp=\"0\">",
+            env!("CARGO_PKG_VERSION"),
+        )?;
+        writeln!(output, "  <sources><source>.</source></sources>")?;
+        writeln!(output, "  <packages>")?;
+        writeln!(
+            output,
+            "    <package name=\"program\" line-rate=\"{line_rate:.4}\" branch-rate=\"{branch_rate:.4}\" complexity=\"0\">"
+        )?;
+        writeln!(output, "      <classes>")?;
+        for (file, file_report) in file_reports.iter() {
+            let file = escape_xml(file);
+            let (covered, total) = file_report.line_counts();
+            let line_rate = rate(covered, total);
+            let (covered, total) = file_report.branch_counts();
+            let branch_rate = rate(covered, total);
+            writeln!(
+                output,
+                "        <class name=\"{file}\" filename=\"{file}\" line-rate=\"{line_rate:.4}\" branch-rate=\"{branch_rate:.4}\" complexity=\"0\">"
+            )?;
+            writeln!(output, "          <methods>")?;
+            for function in file_report.functions.iter() {
+                let covered = function.lines.values().filter(|hits| **hits > 0).count();
+                writeln!(
+                    output,
+                    "            <method name=\"{}\" signature=\"\" line-rate=\"{:.4}\" branch-rate=\"0\" complexity=\"0\">",
+                    escape_xml(&function.name),
+                    rate(covered, function.lines.len()),
+                )?;
+                writeln!(output, "              <lines>")?;
+                for (line, hits) in function.lines.iter() {
+                    writeln!(
+                        output,
+                        "                <line number=\"{line}\" hits=\"{hits}\"/>"
+                    )?;
+                }
+                writeln!(output, "              </lines>")?;
+                writeln!(output, "            </method>")?;
+            }
+            writeln!(output, "          </methods>")?;
+            writeln!(output, "          <lines>")?;
+            for (line, line_report) in file_report.lines.iter() {
+                let (covered, total) = line_report.branch_counts();
+                if total == 0 {
+                    writeln!(
+                        output,
+                        "            <line number=\"{line}\" hits=\"{}\" branch=\"false\"/>",
+                        line_report.hits,
+                    )?;
+                } else {
+                    writeln!(
+                        output,
+                        "            <line number=\"{line}\" hits=\"{}\" branch=\"true\" condition-coverage=\"{}% ({covered}/{total})\"/>",
+                        line_report.hits,
+                        covered.saturating_mul(100).checked_div(total).unwrap_or(0),
+                    )?;
+                }
+            }
+            writeln!(output, "          </lines>")?;
+            writeln!(output, "        </class>")?;
+        }
+        writeln!(output, "      </classes>")?;
+        writeln!(output, "    </package>")?;
+        writeln!(output, "  </packages>")?;
+        writeln!(output, "</coverage>")?;
+        Ok(())
+    }
+}
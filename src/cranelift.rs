@@ -0,0 +1,1514 @@
+#![allow(clippy::integer_arithmetic)]
+//! Second JIT backend which lowers eBPF to Cranelift IR
+//!
+//! Unlike `JitCompiler` this supports every host Cranelift does. The whole text section is
+//! lowered into a single function which runs the program until it exits, throws an error or
+//! is suspended: calls and exits maintain the call frames of the `RuntimeEnvironment` in the
+//! compiled code, and so do the meter checks, the error exits and the stack pointer.
+//! Only memory accesses, syscalls, tracing and the construction of errors go through the
+//! helpers below, like they go through Rust code in `JitCompiler`.
+//!
+//! The compiled code follows the interpreter instruction by instruction, so both can be
+//! compared on the results, instruction counts, traces, suspended states and backtraces.
+
+use crate::{
+    ebpf,
+    elf::Executable,
+    error::EbpfError,
+    interpreter::Interpreter,
+    verifier::Verifier,
+    vm::{BuiltInFunction, CallFrame, Config, ContextObject, ProgramResult, RuntimeEnvironment},
+};
+use cranelift_codegen::{
+    entity::EntityRef,
+    ir::{
+        condcodes::IntCC, types, AbiParam, Block, InstBuilder, MemFlags, SigRef, StackSlot,
+        StackSlotData, StackSlotKind, TrapCode, Type, UserFuncName, Value,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use std::{fmt::Debug, mem};
+
+/// Runs the program from `state.pc` on and returns one of the `EXIT_*` codes
+type CompiledFunction = unsafe extern "C" fn(state: *mut State) -> u64;
+
+/// The program exited from its outermost frame, the result is in r0
+const EXIT_RETURN: u64 = 0;
+/// The instruction at `state.pc` threw the error in `RuntimeEnvironment::program_result`
+const EXIT_THROW: u64 = 1;
+/// Like `EXIT_THROW`, but the execution is suspended and continues at `state.pc`
+const EXIT_SUSPEND: u64 = 2;
+
+/// Cranelift variables after the registers r0 to r10
+const DUE_INSN_COUNT: usize = 11;
+const PREVIOUS_INSTRUCTION_METER: usize = 12;
+const CALL_DEPTH: usize = 13;
+const STACK_POINTER: usize = 14;
+
+/// Execution state which the compiled code reads on entry and writes back when it returns
+#[repr(C)]
+struct State {
+    registers: [u64; 11],
+    pc: u64,
+    due_insn_count: u64,
+    previous_instruction_meter: u64,
+    call_depth: u64,
+    stack_pointer: u64,
+    call_frames: *mut CallFrame,
+    /// The `RuntimeEnvironment` of the context object type the program was compiled for
+    environment: *mut (),
+}
+
+/// Errors thrown by the compiled code, see `throw()`
+#[derive(Clone, Copy)]
+enum Fault {
+    DivideByZero,
+    DivideOverflow,
+    InvalidInstruction,
+    UnsupportedInstruction,
+    ExecutionOverrun,
+    ExceededMaxInstructions,
+    CallDepthExceeded,
+    CallOutsideTextSegment,
+}
+
+impl Fault {
+    const ALL: [Fault; 8] = [
+        Fault::DivideByZero,
+        Fault::DivideOverflow,
+        Fault::InvalidInstruction,
+        Fault::UnsupportedInstruction,
+        Fault::ExecutionOverrun,
+        Fault::ExceededMaxInstructions,
+        Fault::CallDepthExceeded,
+        Fault::CallOutsideTextSegment,
+    ];
+
+    fn into_error(self, pc: usize, argument: u64) -> EbpfError {
+        let pc = pc.wrapping_add(ebpf::ELF_INSN_DUMP_OFFSET);
+        match self {
+            Fault::DivideByZero => EbpfError::DivideByZero(pc),
+            Fault::DivideOverflow => EbpfError::DivideOverflow(pc),
+            Fault::InvalidInstruction => EbpfError::InvalidInstruction(pc),
+            Fault::UnsupportedInstruction => EbpfError::UnsupportedInstruction(pc),
+            Fault::ExecutionOverrun => EbpfError::ExecutionOverrun(pc),
+            Fault::ExceededMaxInstructions => EbpfError::ExceededMaxInstructions(pc),
+            Fault::CallDepthExceeded => EbpfError::CallDepthExceeded(pc, argument as usize),
+            Fault::CallOutsideTextSegment => EbpfError::CallOutsideTextSegment(pc, argument),
+        }
+    }
+}
+
+/// Program compiled by Cranelift
+pub struct CraneliftProgram {
+    /// Owns the machine code, `None` only while dropping
+    module: Option<JITModule>,
+    /// Entry of the machine code
+    function: CompiledFunction,
+    /// Size of the machine code in bytes
+    code_size: usize,
+    /// What the instruction meter charges for each instruction slot, read by `callx`
+    instruction_costs: Box<[u64]>,
+}
+
+impl Debug for CraneliftProgram {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_fmt(format_args!("CraneliftProgram {:?}", self as *const _))
+    }
+}
+
+impl PartialEq for CraneliftProgram {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self as *const _, other as *const _)
+    }
+}
+
+// Safety: The module is only accessed when dropping and the machine code is immutable once
+// finalized, so executables can still be shared between threads
+unsafe impl Send for CraneliftProgram {}
+unsafe impl Sync for CraneliftProgram {}
+
+impl Drop for CraneliftProgram {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Safety: The machine code is only referenced by self.function
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+impl CraneliftProgram {
+    /// Compiles the text section of the executable for the host
+    pub(crate) fn new<V: Verifier, C: ContextObject>(
+        executable: &Executable<V, C>,
+    ) -> Result<Self, EbpfError> {
+        let mut flag_builder = settings::builder();
+        flag_builder
+            .set("opt_level", "speed")
+            .map_err(compilation_error)?;
+        let isa = cranelift_native::builder()
+            .map_err(compilation_error)?
+            .finish(settings::Flags::new(flag_builder))
+            .map_err(compilation_error)?;
+        let program_length = executable.get_text_bytes().1.len() / ebpf::INSN_SIZE;
+        let instruction_costs = (0..program_length)
+            .map(|pc| decode(executable, pc).2)
+            .collect::<Box<[u64]>>();
+        let mut program = Self {
+            module: Some(JITModule::new(JITBuilder::with_isa(
+                isa,
+                default_libcall_names(),
+            ))),
+            function: not_compiled,
+            code_size: 0,
+            instruction_costs,
+        };
+        let module = program.module.as_mut().unwrap();
+        let pointer_type = module.target_config().pointer_type();
+        let signature = |params: &[Type], returns: &[Type]| {
+            let mut signature = module.make_signature();
+            signature
+                .params
+                .extend(params.iter().copied().map(AbiParam::new));
+            signature
+                .returns
+                .extend(returns.iter().copied().map(AbiParam::new));
+            signature
+        };
+        let (pointer, word) = (pointer_type, types::I64);
+        let load_signature = signature(&[pointer, word, word, word, pointer], &[word]);
+        let store_signature = signature(&[pointer, word, word, word, word], &[word]);
+        let trace_signature = signature(&[pointer, word], &[]);
+        let syscall_signature = signature(&[pointer, pointer, word, word], &[word]);
+        let throw_signature = signature(&[pointer, word, word, word], &[]);
+        let signature = signature(&[pointer], &[word]);
+        let function_id = module
+            .declare_function("entrypoint", Linkage::Local, &signature)
+            .map_err(compilation_error)?;
+        let mut context = module.make_context();
+        context.func.signature = signature;
+        context.func.name = UserFuncName::user(0, function_id.as_u32());
+        let mut function_builder_context = FunctionBuilderContext::new();
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut function_builder_context);
+        let signatures = [
+            load_signature,
+            store_signature,
+            trace_signature,
+            syscall_signature,
+            throw_signature,
+        ]
+        .map(|signature| builder.import_signature(signature));
+        Lowering::new(
+            builder,
+            executable,
+            pointer_type,
+            signatures,
+            program.instruction_costs.as_ptr(),
+        )
+        .lower();
+        module
+            .define_function(function_id, &mut context)
+            .map_err(compilation_error)?;
+        let code_size = context.compiled_code().map_or(0, |compiled_code| {
+            compiled_code.code_info().total_size as usize
+        });
+        module.clear_context(&mut context);
+        module.finalize_definitions().map_err(compilation_error)?;
+        let function = module.get_finalized_function(function_id);
+        // Safety: The function was declared with the signature of CompiledFunction
+        program.function = unsafe { mem::transmute::<*const u8, CompiledFunction>(function) };
+        program.code_size = code_size;
+        Ok(program)
+    }
+
+    /// Size of the machine code in bytes
+    pub fn code_size(&self) -> usize {
+        self.code_size
+    }
+
+    /// Calculate the total memory size of the compiled program
+    pub fn mem_size(&self) -> usize {
+        mem::size_of::<Self>()
+            .saturating_add(self.code_size)
+            .saturating_add(mem::size_of_val(&*self.instruction_costs))
+    }
+
+    /// Runs the compiled code on the state of the interpreter until the program terminates
+    ///
+    /// The interpreter only holds the state, it executes none of the instructions. Afterwards
+    /// its registers, pc and due instruction count are those the interpreter would end with,
+    /// and the suspended state or backtrace are captured like it does. Always returns false.
+    pub(crate) fn execute<V: Verifier, C: ContextObject>(
+        &self,
+        interpreter: &mut Interpreter<'_, '_, V, C>,
+    ) -> bool {
+        let env = &mut interpreter.vm.env;
+        let mut state = State {
+            registers: interpreter.reg,
+            pc: interpreter.pc as u64,
+            due_insn_count: interpreter.due_insn_count,
+            previous_instruction_meter: env.previous_instruction_meter,
+            call_depth: env.call_depth,
+            stack_pointer: env.stack_pointer,
+            call_frames: env.call_frames.as_mut_ptr(),
+            environment: env as *mut RuntimeEnvironment<C> as *mut (),
+        };
+        // Safety: The compiled code indexes the call frames below the max call depth only,
+        // and accesses the environment through the helpers of the same context object type.
+        let exit = unsafe { (self.function)(&mut state) };
+        interpreter.reg = state.registers;
+        interpreter.pc = state.pc as usize;
+        interpreter.due_insn_count = state.due_insn_count;
+        let env = &mut interpreter.vm.env;
+        env.previous_instruction_meter = state.previous_instruction_meter;
+        env.call_depth = state.call_depth;
+        env.stack_pointer = state.stack_pointer;
+        match exit {
+            EXIT_RETURN => env.program_result = ProgramResult::Ok(state.registers[0]),
+            EXIT_SUSPEND => interpreter.suspend(interpreter.pc),
+            _ => interpreter.terminate(interpreter.pc),
+        }
+        false
+    }
+}
+
+fn compilation_error<E: std::fmt::Display>(error: E) -> EbpfError {
+    EbpfError::CraneliftCompilationFailed(error.to_string())
+}
+
+/// What an instruction slot does, see `DecodedProgram::new()`
+enum Operation<C: ContextObject> {
+    /// Executed according to its opcode
+    Opcode,
+    /// `add64 r11, imm`, or `sub64 r11, imm` with the immediate negated
+    AdjustStackPointer,
+    /// Any other instruction on r11, which the verifier rejects
+    UnexpectedStackPointer,
+    /// `call imm` of a syscall
+    Syscall(BuiltInFunction<C>),
+    /// `call imm` of a function at the pc
+    Function(usize),
+    Unsupported,
+}
+
+/// Decodes the instruction slot `pc` like the interpreter, with the cost the meter charges
+fn decode<V: Verifier, C: ContextObject>(
+    executable: &Executable<V, C>,
+    pc: usize,
+) -> (ebpf::Insn, Operation<C>, u64) {
+    let config = executable.get_config();
+    let program = executable.get_text_bytes().1;
+    let mut insn = ebpf::get_insn_unchecked(program, pc);
+    let mut cost = config.instruction_costs.get(insn.opc, false);
+    let operation = if insn.dst as usize == ebpf::STACK_PTR_REG && config.dynamic_stack_frames {
+        match insn.opc {
+            ebpf::ADD64_IMM => Operation::AdjustStackPointer,
+            ebpf::SUB64_IMM => {
+                insn.imm = -insn.imm;
+                Operation::AdjustStackPointer
+            }
+            _ => Operation::UnexpectedStackPointer,
+        }
+    } else {
+        match insn.opc {
+            ebpf::LD_DW_IMM => {
+                if (pc + 2) * ebpf::INSN_SIZE <= program.len() {
+                    ebpf::augment_lddw_unchecked(program, &mut insn);
+                }
+                Operation::Opcode
+            }
+            ebpf::CALL_IMM => {
+                let (external, internal) = if config.static_syscalls {
+                    (insn.src == 0, insn.src != 0)
+                } else {
+                    (true, true)
+                };
+                let syscall = external
+                    .then(|| executable.get_loader().lookup_function(insn.imm as u32))
+                    .flatten();
+                let function = internal
+                    .then(|| executable.lookup_internal_function(insn.imm as u32))
+                    .flatten();
+                if let Some((_function_name, function)) = syscall {
+                    cost = config.instruction_costs.get(insn.opc, true);
+                    Operation::Syscall(function)
+                } else if let Some(function_pc) = function {
+                    Operation::Function(function_pc)
+                } else {
+                    Operation::Unsupported
+                }
+            }
+            // The interpreter would index out of its registers
+            ebpf::CALL_REG if insn.imm as u64 > ebpf::FRAME_PTR_REG as u64 => {
+                Operation::Unsupported
+            }
+            _ if !is_supported(&insn) => Operation::Unsupported,
+            _ => Operation::Opcode,
+        }
+    };
+    // The interpreter keeps the cost as u32
+    (insn, operation, cost as u32 as u64)
+}
+
+/// Whether the interpreter has a handler for the opcode and its registers exist
+fn is_supported(insn: &ebpf::Insn) -> bool {
+    let uses_dst = !matches!(insn.opc, ebpf::JA | ebpf::CALL_REG | ebpf::EXIT);
+    let uses_src = match insn.opc & ebpf::BPF_CLS_MASK {
+        ebpf::BPF_LDX | ebpf::BPF_STX => true,
+        ebpf::BPF_ALU | ebpf::BPF_ALU64 => {
+            insn.opc & ebpf::BPF_X != 0 && !matches!(insn.opc, ebpf::LE | ebpf::BE)
+        }
+        ebpf::BPF_JMP => insn.opc & ebpf::BPF_X != 0 && insn.opc != ebpf::CALL_REG,
+        _ => false,
+    };
+    if (uses_dst && insn.dst as usize > ebpf::FRAME_PTR_REG)
+        || (uses_src && insn.src as usize > ebpf::FRAME_PTR_REG)
+    {
+        return false;
+    }
+    matches!(
+        insn.opc,
+        ebpf::LD_DW_IMM
+            | ebpf::LD_B_REG
+            | ebpf::LD_H_REG
+            | ebpf::LD_W_REG
+            | ebpf::LD_DW_REG
+            | ebpf::ST_B_IMM
+            | ebpf::ST_H_IMM
+            | ebpf::ST_W_IMM
+            | ebpf::ST_DW_IMM
+            | ebpf::ST_B_REG
+            | ebpf::ST_H_REG
+            | ebpf::ST_W_REG
+            | ebpf::ST_DW_REG
+            | ebpf::ADD32_IMM
+            | ebpf::ADD32_REG
+            | ebpf::SUB32_IMM
+            | ebpf::SUB32_REG
+            | ebpf::MUL32_IMM
+            | ebpf::MUL32_REG
+            | ebpf::DIV32_IMM
+            | ebpf::DIV32_REG
+            | ebpf::SDIV32_IMM
+            | ebpf::SDIV32_REG
+            | ebpf::OR32_IMM
+            | ebpf::OR32_REG
+            | ebpf::AND32_IMM
+            | ebpf::AND32_REG
+            | ebpf::LSH32_IMM
+            | ebpf::LSH32_REG
+            | ebpf::RSH32_IMM
+            | ebpf::RSH32_REG
+            | ebpf::NEG32
+            | ebpf::MOD32_IMM
+            | ebpf::MOD32_REG
+            | ebpf::XOR32_IMM
+            | ebpf::XOR32_REG
+            | ebpf::MOV32_IMM
+            | ebpf::MOV32_REG
+            | ebpf::ARSH32_IMM
+            | ebpf::ARSH32_REG
+            | ebpf::LE
+            | ebpf::BE
+            | ebpf::ADD64_IMM
+            | ebpf::ADD64_REG
+            | ebpf::SUB64_IMM
+            | ebpf::SUB64_REG
+            | ebpf::MUL64_IMM
+            | ebpf::MUL64_REG
+            | ebpf::DIV64_IMM
+            | ebpf::DIV64_REG
+            | ebpf::SDIV64_IMM
+            | ebpf::SDIV64_REG
+            | ebpf::OR64_IMM
+            | ebpf::OR64_REG
+            | ebpf::AND64_IMM
+            | ebpf::AND64_REG
+            | ebpf::LSH64_IMM
+            | ebpf::LSH64_REG
+            | ebpf::RSH64_IMM
+            | ebpf::RSH64_REG
+            | ebpf::NEG64
+            | ebpf::MOD64_IMM
+            | ebpf::MOD64_REG
+            | ebpf::XOR64_IMM
+            | ebpf::XOR64_REG
+            | ebpf::MOV64_IMM
+            | ebpf::MOV64_REG
+            | ebpf::ARSH64_IMM
+            | ebpf::ARSH64_REG
+            | ebpf::JA
+            | ebpf::JEQ_IMM
+            | ebpf::JEQ_REG
+            | ebpf::JGT_IMM
+            | ebpf::JGT_REG
+            | ebpf::JGE_IMM
+            | ebpf::JGE_REG
+            | ebpf::JLT_IMM
+            | ebpf::JLT_REG
+            | ebpf::JLE_IMM
+            | ebpf::JLE_REG
+            | ebpf::JSET_IMM
+            | ebpf::JSET_REG
+            | ebpf::JNE_IMM
+            | ebpf::JNE_REG
+            | ebpf::JSGT_IMM
+            | ebpf::JSGT_REG
+            | ebpf::JSGE_IMM
+            | ebpf::JSGE_REG
+            | ebpf::JSLT_IMM
+            | ebpf::JSLT_REG
+            | ebpf::JSLE_IMM
+            | ebpf::JSLE_REG
+            | ebpf::CALL_REG
+            | ebpf::EXIT
+    )
+}
+
+/// Placeholder until the compiled code is available
+unsafe extern "C" fn not_compiled(_state: *mut State) -> u64 {
+    EXIT_THROW
+}
+
+/// The environment the state belongs to
+unsafe fn environment<'a, C: ContextObject>(
+    state: *mut State,
+) -> &'a mut RuntimeEnvironment<'a, C> {
+    &mut *((*state).environment as *mut RuntimeEnvironment<'a, C>)
+}
+
+/// Called by the compiled code, returns 0 if the load faults
+unsafe extern "C" fn load<C: ContextObject>(
+    state: *mut State,
+    vm_addr: u64,
+    size: u64,
+    pc: u64,
+    value: *mut u64,
+) -> u64 {
+    let env = environment::<C>(state);
+    let pc = pc as usize;
+    let result = match size {
+        1 => env.memory_mapping.load::<u8>(vm_addr, pc),
+        2 => env.memory_mapping.load::<u16>(vm_addr, pc),
+        4 => env.memory_mapping.load::<u32>(vm_addr, pc),
+        _ => env.memory_mapping.load::<u64>(vm_addr, pc),
+    };
+    match result {
+        ProgramResult::Ok(result) => {
+            *value = result;
+            1
+        }
+        ProgramResult::Err(err) => {
+            env.program_result = ProgramResult::Err(err);
+            0
+        }
+    }
+}
+
+/// Called by the compiled code, returns 0 if the store faults
+unsafe extern "C" fn store<C: ContextObject>(
+    state: *mut State,
+    vm_addr: u64,
+    size: u64,
+    pc: u64,
+    value: u64,
+) -> u64 {
+    let env = environment::<C>(state);
+    let pc = pc as usize;
+    let result = match size {
+        1 => env.memory_mapping.store(value as u8, vm_addr, pc),
+        2 => env.memory_mapping.store(value as u16, vm_addr, pc),
+        4 => env.memory_mapping.store(value as u32, vm_addr, pc),
+        _ => env.memory_mapping.store(value, vm_addr, pc),
+    };
+    match result {
+        ProgramResult::Ok(_) => 1,
+        ProgramResult::Err(err) => {
+            env.program_result = ProgramResult::Err(err);
+            0
+        }
+    }
+}
+
+/// Called by the compiled code before every instruction if tracing is enabled
+unsafe extern "C" fn trace<C: ContextObject>(state: *mut State, pc: u64) {
+    let mut registers = [0u64; 12];
+    registers[0..11].copy_from_slice(&(*state).registers);
+    registers[11] = pc;
+    environment::<C>(state)
+        .context_object_pointer
+        .trace(registers);
+}
+
+/// Called by the compiled code to call a syscall with the registers and due instruction count
+/// in the state, see `call_syscall()` of the interpreter
+///
+/// Returns 0 if the syscall succeeded, `EXIT_THROW` or `EXIT_SUSPEND` otherwise.
+unsafe extern "C" fn syscall<C: ContextObject>(
+    state: *mut State,
+    function: *const u8,
+    enable_instruction_meter: u64,
+    enable_suspension: u64,
+) -> u64 {
+    let env = environment::<C>(state);
+    let state = &mut *state;
+    if enable_instruction_meter != 0 {
+        env.context_object_pointer.consume(state.due_insn_count);
+    }
+    let function = mem::transmute::<*const u8, BuiltInFunction<C>>(function);
+    let reg = &mut state.registers;
+    function(
+        env.context_object_pointer,
+        reg[1],
+        reg[2],
+        reg[3],
+        reg[4],
+        reg[5],
+        &mut env.memory_mapping,
+        &mut env.program_result,
+    );
+    match &env.program_result {
+        ProgramResult::Ok(value) => {
+            reg[0] = *value;
+            if enable_instruction_meter != 0 {
+                state.previous_instruction_meter = env.context_object_pointer.get_remaining();
+            }
+            0
+        }
+        ProgramResult::Err(err) => {
+            if enable_suspension != 0
+                && matches!(
+                    err.downcast_ref::<EbpfError>(),
+                    Some(EbpfError::SyscallYield)
+                )
+            {
+                EXIT_SUSPEND
+            } else {
+                EXIT_THROW
+            }
+        }
+    }
+}
+
+/// Called by the compiled code to throw the `Fault` with the index `fault`
+unsafe extern "C" fn throw<C: ContextObject>(
+    state: *mut State,
+    fault: u64,
+    pc: u64,
+    argument: u64,
+) {
+    let error = Fault::ALL[fault as usize].into_error(pc as usize, argument);
+    environment::<C>(state).program_result = ProgramResult::Err(Box::new(error));
+}
+
+/// Number of bytes accessed by a load or store
+fn access_size(opc: u8) -> i64 {
+    match opc & 0x18 {
+        ebpf::BPF_B => 1,
+        ebpf::BPF_H => 2,
+        ebpf::BPF_W => 4,
+        _ => 8,
+    }
+}
+
+/// Builds the IR of a whole text section as a single function
+struct Lowering<'a, 'b, V: Verifier, C: ContextObject> {
+    builder: FunctionBuilder<'a>,
+    executable: &'b Executable<V, C>,
+    config: &'b Config,
+    program_vm_addr: u64,
+    /// Number of instruction slots
+    program_length: usize,
+    pointer_type: Type,
+    load_signature: SigRef,
+    store_signature: SigRef,
+    trace_signature: SigRef,
+    syscall_signature: SigRef,
+    throw_signature: SigRef,
+    /// `CraneliftProgram::instruction_costs`
+    instruction_costs: *const u64,
+    /// Receives the result of loads
+    value_slot: StackSlot,
+    /// Entry block of every instruction slot by pc
+    instruction_blocks: Vec<Block>,
+    /// Continues at the pc passed as block parameter, which must be in the text section
+    dispatch_block: Block,
+    /// Throws `ExecutionOverrun` at the pc passed as block parameter
+    overrun_block: Block,
+    /// Writes the state back and returns the exit code and pc passed as block parameters
+    exit_block: Block,
+    state: Option<Value>,
+    call_frames: Option<Value>,
+}
+
+impl<'a, 'b, V: Verifier, C: ContextObject> Lowering<'a, 'b, V, C> {
+    fn new(
+        mut builder: FunctionBuilder<'a>,
+        executable: &'b Executable<V, C>,
+        pointer_type: Type,
+        [load_signature, store_signature, trace_signature, syscall_signature, throw_signature]: [SigRef; 5],
+        instruction_costs: *const u64,
+    ) -> Self {
+        let (program_vm_addr, program) = executable.get_text_bytes();
+        let program_length = program.len() / ebpf::INSN_SIZE;
+        let value_slot = builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            mem::size_of::<u64>() as u32,
+            3,
+        ));
+        let instruction_blocks = (0..program_length)
+            .map(|_pc| builder.create_block())
+            .collect();
+        let dispatch_block = builder.create_block();
+        builder.append_block_param(dispatch_block, types::I64);
+        let overrun_block = builder.create_block();
+        builder.append_block_param(overrun_block, types::I64);
+        builder.set_cold_block(overrun_block);
+        let exit_block = builder.create_block();
+        builder.append_block_param(exit_block, types::I64);
+        builder.append_block_param(exit_block, types::I64);
+        Self {
+            builder,
+            executable,
+            config: executable.get_config(),
+            program_vm_addr,
+            program_length,
+            pointer_type,
+            load_signature,
+            store_signature,
+            trace_signature,
+            syscall_signature,
+            throw_signature,
+            instruction_costs,
+            value_slot,
+            instruction_blocks,
+            dispatch_block,
+            overrun_block,
+            exit_block,
+            state: None,
+            call_frames: None,
+        }
+    }
+
+    fn lower(mut self) {
+        for index in 0..=STACK_POINTER {
+            self.builder.declare_var(Variable::new(index), types::I64);
+        }
+
+        let entry_block = self.builder.create_block();
+        self.builder
+            .append_block_params_for_function_params(entry_block);
+        self.builder.switch_to_block(entry_block);
+        let state = self.builder.block_params(entry_block)[0];
+        self.state = Some(state);
+        for index in 0..=STACK_POINTER {
+            let value = self.load_state(Self::state_offset(index));
+            self.builder.def_var(Variable::new(index), value);
+        }
+        let call_frames = self.builder.ins().load(
+            self.pointer_type,
+            MemFlags::trusted(),
+            state,
+            mem::offset_of!(State, call_frames) as i32,
+        );
+        self.call_frames = Some(call_frames);
+        let pc = self.load_state(mem::offset_of!(State, pc));
+        let is_overrun = self.builder.ins().icmp_imm(
+            IntCC::UnsignedGreaterThanOrEqual,
+            pc,
+            self.program_length as i64,
+        );
+        self.builder.ins().brif(
+            is_overrun,
+            self.overrun_block,
+            &[pc],
+            self.dispatch_block,
+            &[pc],
+        );
+
+        self.builder.switch_to_block(self.dispatch_block);
+        let pc = self.builder.block_params(self.dispatch_block)[0];
+        let mut switch = Switch::new();
+        for (pc, block) in self.instruction_blocks.iter().enumerate() {
+            switch.set_entry(pc as u128, *block);
+        }
+        let unreachable_block = self.builder.create_block();
+        switch.emit(&mut self.builder, pc, unreachable_block);
+        self.builder.switch_to_block(unreachable_block);
+        self.builder.ins().trap(TrapCode::unwrap_user(1));
+
+        self.builder.switch_to_block(self.overrun_block);
+        let pc = self.builder.block_params(self.overrun_block)[0];
+        self.charge(self.config.instruction_costs.get_execution_overrun());
+        self.throw(Fault::ExecutionOverrun, pc, None);
+        self.exit(EXIT_THROW, pc);
+
+        for pc in 0..self.program_length {
+            self.builder.switch_to_block(self.instruction_blocks[pc]);
+            self.lower_instruction(pc);
+        }
+
+        self.builder.switch_to_block(self.exit_block);
+        let (exit, pc) = (
+            self.builder.block_params(self.exit_block)[0],
+            self.builder.block_params(self.exit_block)[1],
+        );
+        for index in 0..=STACK_POINTER {
+            let value = self.builder.use_var(Variable::new(index));
+            self.store_state(Self::state_offset(index), value);
+        }
+        self.store_state(mem::offset_of!(State, pc), pc);
+        self.builder.ins().return_(&[exit]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    /// Offset of the field in `State` which the variable is kept in
+    fn state_offset(index: usize) -> usize {
+        match index {
+            DUE_INSN_COUNT => mem::offset_of!(State, due_insn_count),
+            PREVIOUS_INSTRUCTION_METER => mem::offset_of!(State, previous_instruction_meter),
+            CALL_DEPTH => mem::offset_of!(State, call_depth),
+            STACK_POINTER => mem::offset_of!(State, stack_pointer),
+            _ => mem::offset_of!(State, registers) + index * mem::size_of::<u64>(),
+        }
+    }
+
+    fn load_state(&mut self, offset: usize) -> Value {
+        self.builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            self.state.unwrap(),
+            offset as i32,
+        )
+    }
+
+    fn store_state(&mut self, offset: usize, value: Value) {
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            value,
+            self.state.unwrap(),
+            offset as i32,
+        );
+    }
+
+    /// Writes the registers to the state, for the helpers which read them
+    fn store_registers(&mut self) {
+        for index in 0..=ebpf::FRAME_PTR_REG {
+            let value = self.register(index);
+            self.store_state(Self::state_offset(index), value);
+        }
+    }
+
+    fn lower_instruction(&mut self, pc: usize) {
+        let (insn, operation, cost) = decode(self.executable, pc);
+        let config = self.config;
+        let dst = insn.dst as usize;
+        self.charge(cost);
+        if config.enable_instruction_tracing {
+            self.store_registers();
+            let callee = self.function_address(trace::<C> as *const u8);
+            let pc = self.iconst(pc as i64);
+            let state = self.state.unwrap();
+            self.builder
+                .ins()
+                .call_indirect(self.trace_signature, callee, &[state, pc]);
+        }
+
+        match operation {
+            Operation::Opcode => {}
+            Operation::AdjustStackPointer => {
+                // Let the stack overflow, see `adjust_stack_pointer()` of the interpreter
+                let stack_pointer = self.builder.use_var(Variable::new(STACK_POINTER));
+                let stack_pointer = self.builder.ins().iadd_imm(stack_pointer, insn.imm);
+                self.builder
+                    .def_var(Variable::new(STACK_POINTER), stack_pointer);
+                self.check_instruction_meter_and_jump(pc, pc + 1, pc + 1);
+                return;
+            }
+            Operation::UnexpectedStackPointer => {
+                self.check_instruction_meter_and_jump(pc, pc + 1, pc + 1);
+                return;
+            }
+            Operation::Syscall(function) => {
+                self.syscall(pc, function);
+                return;
+            }
+            Operation::Function(target_pc) => {
+                self.push_frame(pc);
+                if target_pc >= self.program_length {
+                    let address = self
+                        .program_vm_addr
+                        .wrapping_add((target_pc as u64).wrapping_mul(ebpf::INSN_SIZE as u64));
+                    let address = self.iconst(address as i64);
+                    self.fail(Fault::CallOutsideTextSegment, pc, Some(address));
+                    return;
+                }
+                self.check_instruction_meter_and_jump(pc, pc + 1, target_pc);
+                return;
+            }
+            Operation::Unsupported => {
+                self.fail(Fault::UnsupportedInstruction, pc, None);
+                return;
+            }
+        }
+
+        let mut result = None;
+        match insn.opc & ebpf::BPF_CLS_MASK {
+            ebpf::BPF_LD => result = Some(self.iconst(insn.imm)),
+            ebpf::BPF_LDX => {
+                let vm_addr = self.register(insn.src as usize);
+                let vm_addr = self.builder.ins().iadd_imm(vm_addr, insn.off as i64);
+                result = Some(self.load(pc, vm_addr, access_size(insn.opc)));
+            }
+            ebpf::BPF_ST | ebpf::BPF_STX => {
+                let vm_addr = self.register(dst);
+                let vm_addr = self.builder.ins().iadd_imm(vm_addr, insn.off as i64);
+                let value = self.source(&insn);
+                self.store(pc, vm_addr, access_size(insn.opc), value);
+            }
+            ebpf::BPF_ALU => result = self.alu32(pc, &insn),
+            ebpf::BPF_ALU64 => result = Some(self.alu64(pc, &insn)),
+            _ => {
+                match insn.opc {
+                    ebpf::CALL_REG => self.call_register(pc, &insn),
+                    ebpf::EXIT => self.exit_function(pc, cost),
+                    _ => self.branch(pc, &insn),
+                }
+                return;
+            }
+        }
+        if let Some(result) = result {
+            self.builder.def_var(Variable::new(dst), result);
+        }
+        let next_pc = if insn.opc == ebpf::LD_DW_IMM {
+            pc + 2
+        } else {
+            pc + 1
+        };
+        self.check_instruction_meter_and_jump(pc, next_pc, next_pc);
+    }
+
+    fn register(&mut self, index: usize) -> Value {
+        self.builder.use_var(Variable::new(index))
+    }
+
+    fn iconst(&mut self, value: i64) -> Value {
+        self.builder.ins().iconst(types::I64, value)
+    }
+
+    fn function_address(&mut self, function: *const u8) -> Value {
+        self.builder
+            .ins()
+            .iconst(self.pointer_type, function as usize as i64)
+    }
+
+    /// Adds to the due instruction count
+    fn charge(&mut self, cost: u64) {
+        let due_insn_count = self.builder.use_var(Variable::new(DUE_INSN_COUNT));
+        let due_insn_count = self.builder.ins().iadd_imm(due_insn_count, cost as i64);
+        self.builder
+            .def_var(Variable::new(DUE_INSN_COUNT), due_insn_count);
+    }
+
+    /// The src register or the immediate, depending on the opcode
+    fn source(&mut self, insn: &ebpf::Insn) -> Value {
+        let uses_register = match insn.opc & ebpf::BPF_CLS_MASK {
+            ebpf::BPF_ST => false,
+            ebpf::BPF_STX => true,
+            _ => insn.opc & ebpf::BPF_X != 0,
+        };
+        if uses_register {
+            self.register(insn.src as usize)
+        } else {
+            self.iconst(insn.imm)
+        }
+    }
+
+    /// Returns `None` if the instruction throws unconditionally
+    fn alu32(&mut self, pc: usize, insn: &ebpf::Insn) -> Option<Value> {
+        let destination = self.register(insn.dst as usize);
+        if matches!(insn.opc, ebpf::LE | ebpf::BE) {
+            let value = match insn.imm {
+                16 => self.builder.ins().ireduce(types::I16, destination),
+                32 => self.builder.ins().ireduce(types::I32, destination),
+                64 => destination,
+                _ => {
+                    self.fail(Fault::InvalidInstruction, pc, None);
+                    // Lower the rest of the instruction in an unreachable block
+                    let block = self.builder.create_block();
+                    self.builder.switch_to_block(block);
+                    return None;
+                }
+            };
+            let swap = (insn.opc == ebpf::BE) == cfg!(target_endian = "little");
+            let value = if swap {
+                self.builder.ins().bswap(value)
+            } else {
+                value
+            };
+            return Some(if insn.imm == 64 {
+                value
+            } else {
+                self.builder.ins().uextend(types::I64, value)
+            });
+        }
+        let source = self.source(insn);
+        let destination = self.builder.ins().ireduce(types::I32, destination);
+        let source = self.builder.ins().ireduce(types::I32, source);
+        let ins = self.builder.ins();
+        let (value, is_signed) = match insn.opc & ebpf::BPF_ALU_OP_MASK {
+            ebpf::BPF_ADD => (ins.iadd(destination, source), true),
+            ebpf::BPF_SUB => (ins.isub(destination, source), true),
+            ebpf::BPF_MUL => (ins.imul(destination, source), true),
+            ebpf::BPF_DIV => {
+                self.check_divisor(pc, insn, source);
+                (self.builder.ins().udiv(destination, source), false)
+            }
+            ebpf::BPF_SDIV => {
+                self.check_divisor(pc, insn, source);
+                self.check_signed_division(pc, insn, destination, source, i32::MIN as i64);
+                (self.builder.ins().sdiv(destination, source), true)
+            }
+            ebpf::BPF_MOD => {
+                self.check_divisor(pc, insn, source);
+                (self.builder.ins().urem(destination, source), false)
+            }
+            ebpf::BPF_OR => (ins.bor(destination, source), false),
+            ebpf::BPF_AND => (ins.band(destination, source), false),
+            ebpf::BPF_XOR => (ins.bxor(destination, source), false),
+            ebpf::BPF_LSH => (ins.ishl(destination, source), false),
+            ebpf::BPF_RSH => (ins.ushr(destination, source), false),
+            ebpf::BPF_ARSH => (ins.sshr(destination, source), false),
+            ebpf::BPF_NEG => (ins.ineg(destination), false),
+            _ => (source, false), // ebpf::BPF_MOV
+        };
+        Some(if is_signed {
+            self.builder.ins().sextend(types::I64, value)
+        } else {
+            self.builder.ins().uextend(types::I64, value)
+        })
+    }
+
+    fn alu64(&mut self, pc: usize, insn: &ebpf::Insn) -> Value {
+        let destination = self.register(insn.dst as usize);
+        let source = self.source(insn);
+        let ins = self.builder.ins();
+        match insn.opc & ebpf::BPF_ALU_OP_MASK {
+            ebpf::BPF_ADD => ins.iadd(destination, source),
+            ebpf::BPF_SUB => ins.isub(destination, source),
+            ebpf::BPF_MUL => ins.imul(destination, source),
+            ebpf::BPF_DIV => {
+                self.check_divisor(pc, insn, source);
+                self.builder.ins().udiv(destination, source)
+            }
+            ebpf::BPF_SDIV => {
+                self.check_divisor(pc, insn, source);
+                self.check_signed_division(pc, insn, destination, source, i64::MIN);
+                self.builder.ins().sdiv(destination, source)
+            }
+            ebpf::BPF_MOD => {
+                self.check_divisor(pc, insn, source);
+                self.builder.ins().urem(destination, source)
+            }
+            ebpf::BPF_OR => ins.bor(destination, source),
+            ebpf::BPF_AND => ins.band(destination, source),
+            ebpf::BPF_XOR => ins.bxor(destination, source),
+            ebpf::BPF_LSH => ins.ishl(destination, source),
+            ebpf::BPF_RSH => ins.ushr(destination, source),
+            ebpf::BPF_ARSH => ins.sshr(destination, source),
+            ebpf::BPF_NEG => ins.ineg(destination),
+            _ => source, // ebpf::BPF_MOV
+        }
+    }
+
+    /// Throws `DivideByZero`
+    ///
+    /// The interpreter only checks the register operand, the verifier rejects an immediate
+    /// of zero. Without verification it is thrown as well instead of trapping on the host.
+    fn check_divisor(&mut self, pc: usize, insn: &ebpf::Insn, divisor: Value) {
+        if insn.opc & ebpf::BPF_X == 0 && insn.imm != 0 {
+            return;
+        }
+        let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, divisor, 0);
+        self.when(is_zero, |lowering| {
+            lowering.fail(Fault::DivideByZero, pc, None)
+        });
+    }
+
+    /// Throws `DivideOverflow`
+    fn check_signed_division(
+        &mut self,
+        pc: usize,
+        insn: &ebpf::Insn,
+        dividend: Value,
+        divisor: Value,
+        min: i64,
+    ) {
+        if insn.opc & ebpf::BPF_X == 0 && insn.imm != -1 {
+            return;
+        }
+        let is_min = self.builder.ins().icmp_imm(IntCC::Equal, dividend, min);
+        let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, divisor, -1);
+        let overflows = self.builder.ins().band(is_min, is_minus_one);
+        self.when(overflows, |lowering| {
+            lowering.fail(Fault::DivideOverflow, pc, None)
+        });
+    }
+
+    fn branch(&mut self, pc: usize, insn: &ebpf::Insn) {
+        let target_pc = (pc as isize)
+            .wrapping_add(insn.off as isize)
+            .wrapping_add(1) as usize;
+        if insn.opc == ebpf::JA {
+            self.check_instruction_meter_and_jump(pc, pc + 1, target_pc);
+            return;
+        }
+        let destination = self.register(insn.dst as usize);
+        let source = self.source(insn);
+        let condition = match insn.opc & ebpf::BPF_ALU_OP_MASK {
+            ebpf::BPF_JSET => {
+                let masked = self.builder.ins().band(destination, source);
+                self.builder.ins().icmp_imm(IntCC::NotEqual, masked, 0)
+            }
+            opcode => {
+                let condition = match opcode {
+                    ebpf::BPF_JEQ => IntCC::Equal,
+                    ebpf::BPF_JNE => IntCC::NotEqual,
+                    ebpf::BPF_JGT => IntCC::UnsignedGreaterThan,
+                    ebpf::BPF_JGE => IntCC::UnsignedGreaterThanOrEqual,
+                    ebpf::BPF_JLT => IntCC::UnsignedLessThan,
+                    ebpf::BPF_JLE => IntCC::UnsignedLessThanOrEqual,
+                    ebpf::BPF_JSGT => IntCC::SignedGreaterThan,
+                    ebpf::BPF_JSGE => IntCC::SignedGreaterThanOrEqual,
+                    ebpf::BPF_JSLT => IntCC::SignedLessThan,
+                    _ => IntCC::SignedLessThanOrEqual, // ebpf::BPF_JSLE
+                };
+                self.builder.ins().icmp(condition, destination, source)
+            }
+        };
+        let taken_block = self.builder.create_block();
+        let not_taken_block = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, taken_block, &[], not_taken_block, &[]);
+        self.builder.switch_to_block(taken_block);
+        self.check_instruction_meter_and_jump(pc, pc + 1, target_pc);
+        self.builder.switch_to_block(not_taken_block);
+        self.check_instruction_meter_and_jump(pc, pc + 1, pc + 1);
+    }
+
+    /// Saves the registers of the caller of `call` at `pc`, see `Interpreter::push_frame()`
+    fn push_frame(&mut self, pc: usize) {
+        let config = self.config;
+        let call_depth = self.builder.use_var(Variable::new(CALL_DEPTH));
+        let frame = self.call_frame(call_depth);
+        for (index, register) in
+            (ebpf::FIRST_SCRATCH_REG..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS).enumerate()
+        {
+            let value = self.register(register);
+            self.builder.ins().store(
+                MemFlags::trusted(),
+                value,
+                frame,
+                (mem::offset_of!(CallFrame, caller_saved_registers) + index * mem::size_of::<u64>())
+                    as i32,
+            );
+        }
+        let frame_pointer = self.register(ebpf::FRAME_PTR_REG);
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            frame_pointer,
+            frame,
+            mem::offset_of!(CallFrame, frame_pointer) as i32,
+        );
+        let return_pc = self.iconst((pc + 1) as i64);
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            return_pc,
+            frame,
+            mem::offset_of!(CallFrame, target_pc) as i32,
+        );
+        let call_depth = self.builder.ins().iadd_imm(call_depth, 1);
+        self.builder.def_var(Variable::new(CALL_DEPTH), call_depth);
+        let exceeded =
+            self.builder
+                .ins()
+                .icmp_imm(IntCC::Equal, call_depth, config.max_call_depth as i64);
+        self.when(exceeded, |lowering| {
+            let max_call_depth = lowering.iconst(config.max_call_depth as i64);
+            lowering.fail(Fault::CallDepthExceeded, pc, Some(max_call_depth));
+        });
+        let mut stack_pointer = self.builder.use_var(Variable::new(STACK_POINTER));
+        if !config.dynamic_stack_frames {
+            let stack_frame_size =
+                config.stack_frame_size * if config.enable_stack_frame_gaps { 2 } else { 1 };
+            stack_pointer = self
+                .builder
+                .ins()
+                .iadd_imm(stack_pointer, stack_frame_size as i64);
+            self.builder
+                .def_var(Variable::new(STACK_POINTER), stack_pointer);
+        }
+        self.builder
+            .def_var(Variable::new(ebpf::FRAME_PTR_REG), stack_pointer);
+    }
+
+    /// Address of the call frame at the depth
+    fn call_frame(&mut self, call_depth: Value) -> Value {
+        let offset = self
+            .builder
+            .ins()
+            .imul_imm(call_depth, mem::size_of::<CallFrame>() as i64);
+        self.builder.ins().iadd(self.call_frames.unwrap(), offset)
+    }
+
+    /// `callx`, see `Interpreter::execute_opcode()`
+    fn call_register(&mut self, pc: usize, insn: &ebpf::Insn) {
+        let target_address = self.register(insn.imm as usize);
+        self.push_frame(pc);
+        let is_below = self.builder.ins().icmp_imm(
+            IntCC::UnsignedLessThan,
+            target_address,
+            self.program_vm_addr as i64,
+        );
+        self.when(is_below, |lowering| {
+            let address = lowering
+                .builder
+                .ins()
+                .band_imm(target_address, !(ebpf::INSN_SIZE as i64 - 1));
+            lowering.fail(Fault::CallOutsideTextSegment, pc, Some(address));
+        });
+        let offset = self
+            .builder
+            .ins()
+            .iadd_imm(target_address, (self.program_vm_addr as i64).wrapping_neg());
+        let target_pc = self.builder.ins().udiv_imm(offset, ebpf::INSN_SIZE as i64);
+        let is_outside = self.builder.ins().icmp_imm(
+            IntCC::UnsignedGreaterThanOrEqual,
+            target_pc,
+            self.program_length as i64,
+        );
+        self.when(is_outside, |lowering| {
+            let address = lowering
+                .builder
+                .ins()
+                .imul_imm(target_pc, ebpf::INSN_SIZE as i64);
+            let address = lowering
+                .builder
+                .ins()
+                .iadd_imm(address, lowering.program_vm_addr as i64);
+            lowering.fail(Fault::CallOutsideTextSegment, pc, Some(address));
+        });
+        if self.config.static_syscalls {
+            let function_block = self.builder.create_block();
+            let unsupported_block = self.builder.create_block();
+            self.builder.set_cold_block(unsupported_block);
+            let mut switch = Switch::new();
+            for key in self.executable.get_function_registry().keys() {
+                if (*key as usize) < self.program_length {
+                    switch.set_entry(*key as u128, function_block);
+                }
+            }
+            switch.emit(&mut self.builder, target_pc, unsupported_block);
+            self.builder.switch_to_block(unsupported_block);
+            // Charged like the interpreter, which decodes the target before throwing
+            let offset = self
+                .builder
+                .ins()
+                .imul_imm(target_pc, mem::size_of::<u64>() as i64);
+            let instruction_costs = self
+                .builder
+                .ins()
+                .iconst(self.pointer_type, self.instruction_costs as i64);
+            let address = self.builder.ins().iadd(instruction_costs, offset);
+            let cost = self
+                .builder
+                .ins()
+                .load(types::I64, MemFlags::trusted(), address, 0);
+            let due_insn_count = self.builder.use_var(Variable::new(DUE_INSN_COUNT));
+            let due_insn_count = self.builder.ins().iadd(due_insn_count, cost);
+            self.builder
+                .def_var(Variable::new(DUE_INSN_COUNT), due_insn_count);
+            self.throw(Fault::UnsupportedInstruction, target_pc, None);
+            let pc = self.iconst(pc as i64);
+            self.exit(EXIT_THROW, pc);
+            self.builder.switch_to_block(function_block);
+        }
+        self.check_instruction_meter(pc, pc + 1, target_pc);
+        self.builder.ins().jump(self.dispatch_block, &[target_pc]);
+    }
+
+    /// `exit`, see `Interpreter::execute_opcode()`
+    fn exit_function(&mut self, pc: usize, cost: u64) {
+        let config = self.config;
+        let call_depth = self.builder.use_var(Variable::new(CALL_DEPTH));
+        let is_outermost = self.builder.ins().icmp_imm(IntCC::Equal, call_depth, 0);
+        let return_block = self.builder.create_block();
+        let outermost_block = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(is_outermost, outermost_block, &[], return_block, &[]);
+
+        self.builder.switch_to_block(outermost_block);
+        let pc_value = self.iconst(pc as i64);
+        if config.enable_instruction_meter {
+            let due_insn_count = self.builder.use_var(Variable::new(DUE_INSN_COUNT));
+            let previous_instruction_meter = self
+                .builder
+                .use_var(Variable::new(PREVIOUS_INSTRUCTION_METER));
+            let exceeded = self.builder.ins().icmp(
+                IntCC::UnsignedGreaterThan,
+                due_insn_count,
+                previous_instruction_meter,
+            );
+            self.when(exceeded, |lowering| {
+                lowering.throw(Fault::ExceededMaxInstructions, pc_value, None);
+                if config.enable_suspension {
+                    // The exit is executed again on resumption, so don't charge it now
+                    lowering.charge(cost.wrapping_neg());
+                    lowering.exit(EXIT_SUSPEND, pc_value);
+                } else {
+                    lowering.exit(EXIT_THROW, pc_value);
+                }
+            });
+        }
+        self.exit(EXIT_RETURN, pc_value);
+
+        self.builder.switch_to_block(return_block);
+        let call_depth = self.builder.ins().iadd_imm(call_depth, -1);
+        self.builder.def_var(Variable::new(CALL_DEPTH), call_depth);
+        let frame = self.call_frame(call_depth);
+        let target_pc = self.builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            frame,
+            mem::offset_of!(CallFrame, target_pc) as i32,
+        );
+        let frame_pointer = self.builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            frame,
+            mem::offset_of!(CallFrame, frame_pointer) as i32,
+        );
+        self.builder
+            .def_var(Variable::new(ebpf::FRAME_PTR_REG), frame_pointer);
+        for (index, register) in
+            (ebpf::FIRST_SCRATCH_REG..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS).enumerate()
+        {
+            let value = self.builder.ins().load(
+                types::I64,
+                MemFlags::trusted(),
+                frame,
+                (mem::offset_of!(CallFrame, caller_saved_registers) + index * mem::size_of::<u64>())
+                    as i32,
+            );
+            self.builder.def_var(Variable::new(register), value);
+        }
+        if !config.dynamic_stack_frames {
+            let stack_frame_size =
+                config.stack_frame_size * if config.enable_stack_frame_gaps { 2 } else { 1 };
+            let stack_pointer = self.builder.use_var(Variable::new(STACK_POINTER));
+            let stack_pointer = self
+                .builder
+                .ins()
+                .iadd_imm(stack_pointer, (stack_frame_size as i64).wrapping_neg());
+            self.builder
+                .def_var(Variable::new(STACK_POINTER), stack_pointer);
+        }
+        let is_outside = self.builder.ins().icmp_imm(
+            IntCC::UnsignedGreaterThanOrEqual,
+            target_pc,
+            self.program_length as i64,
+        );
+        self.when(is_outside, |lowering| {
+            let address = lowering
+                .builder
+                .ins()
+                .imul_imm(target_pc, ebpf::INSN_SIZE as i64);
+            let address = lowering
+                .builder
+                .ins()
+                .iadd_imm(address, lowering.program_vm_addr as i64);
+            lowering.fail(Fault::CallOutsideTextSegment, pc, Some(address));
+        });
+        self.check_instruction_meter(pc, pc + 1, target_pc);
+        self.builder.ins().jump(self.dispatch_block, &[target_pc]);
+    }
+
+    /// `call imm` of a syscall, see `call_syscall()` of the interpreter
+    fn syscall(&mut self, pc: usize, function: BuiltInFunction<C>) {
+        let config = self.config;
+        self.store_registers();
+        let due_insn_count = self.builder.use_var(Variable::new(DUE_INSN_COUNT));
+        self.store_state(Self::state_offset(DUE_INSN_COUNT), due_insn_count);
+        let callee = self.function_address(syscall::<C> as *const u8);
+        let function = self.function_address(function as *const u8);
+        let enable_instruction_meter = self.iconst(config.enable_instruction_meter as i64);
+        let enable_suspension = self.iconst(config.enable_suspension as i64);
+        let state = self.state.unwrap();
+        let call = self.builder.ins().call_indirect(
+            self.syscall_signature,
+            callee,
+            &[state, function, enable_instruction_meter, enable_suspension],
+        );
+        let exit = self.builder.inst_results(call)[0];
+        let zero = self.iconst(0);
+        self.builder.def_var(Variable::new(DUE_INSN_COUNT), zero);
+        let failed = self.builder.ins().icmp_imm(IntCC::NotEqual, exit, 0);
+        self.when(failed, |lowering| {
+            // Suspended at the next instruction, which sees r0 from before the syscall
+            let is_suspended =
+                lowering
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::Equal, exit, EXIT_SUSPEND as i64);
+            let (pc, next_pc) = (lowering.iconst(pc as i64), lowering.iconst(pc as i64 + 1));
+            let pc = lowering.builder.ins().select(is_suspended, next_pc, pc);
+            lowering
+                .builder
+                .ins()
+                .jump(lowering.exit_block, &[exit, pc]);
+        });
+        let result = self.load_state(Self::state_offset(0));
+        self.builder.def_var(Variable::new(0), result);
+        if config.enable_instruction_meter {
+            let previous_instruction_meter =
+                self.load_state(Self::state_offset(PREVIOUS_INSTRUCTION_METER));
+            self.builder.def_var(
+                Variable::new(PREVIOUS_INSTRUCTION_METER),
+                previous_instruction_meter,
+            );
+        }
+        self.check_instruction_meter_and_jump(pc, pc + 1, pc + 1);
+    }
+
+    fn load(&mut self, pc: usize, vm_addr: Value, size: i64) -> Value {
+        let callee = self.function_address(load::<C> as *const u8);
+        let size = self.iconst(size);
+        let elf_insn_pc = self.iconst((pc + ebpf::ELF_INSN_DUMP_OFFSET) as i64);
+        let value = self
+            .builder
+            .ins()
+            .stack_addr(self.pointer_type, self.value_slot, 0);
+        let state = self.state.unwrap();
+        let call = self.builder.ins().call_indirect(
+            self.load_signature,
+            callee,
+            &[state, vm_addr, size, elf_insn_pc, value],
+        );
+        let succeeded = self.builder.inst_results(call)[0];
+        let faulted = self.builder.ins().icmp_imm(IntCC::Equal, succeeded, 0);
+        self.when(faulted, |lowering| {
+            let pc = lowering.iconst(pc as i64);
+            lowering.exit(EXIT_THROW, pc);
+        });
+        self.builder
+            .ins()
+            .stack_load(types::I64, self.value_slot, 0)
+    }
+
+    fn store(&mut self, pc: usize, vm_addr: Value, size: i64, value: Value) {
+        let callee = self.function_address(store::<C> as *const u8);
+        let size = self.iconst(size);
+        let elf_insn_pc = self.iconst((pc + ebpf::ELF_INSN_DUMP_OFFSET) as i64);
+        let state = self.state.unwrap();
+        let call = self.builder.ins().call_indirect(
+            self.store_signature,
+            callee,
+            &[state, vm_addr, size, elf_insn_pc, value],
+        );
+        let succeeded = self.builder.inst_results(call)[0];
+        let faulted = self.builder.ins().icmp_imm(IntCC::Equal, succeeded, 0);
+        self.when(faulted, |lowering| {
+            let pc = lowering.iconst(pc as i64);
+            lowering.exit(EXIT_THROW, pc);
+        });
+    }
+
+    /// Sets the program result to the error
+    fn throw(&mut self, fault: Fault, pc: Value, argument: Option<Value>) {
+        let callee = self.function_address(throw::<C> as *const u8);
+        let fault = self.iconst(fault as i64);
+        let argument = argument.unwrap_or_else(|| self.iconst(0));
+        let state = self.state.unwrap();
+        self.builder.ins().call_indirect(
+            self.throw_signature,
+            callee,
+            &[state, fault, pc, argument],
+        );
+    }
+
+    /// Throws the error at the instruction `pc` and exits
+    fn fail(&mut self, fault: Fault, pc: usize, argument: Option<Value>) {
+        let pc = self.iconst(pc as i64);
+        self.throw(fault, pc, argument);
+        self.exit(EXIT_THROW, pc);
+    }
+
+    fn exit(&mut self, exit: u64, pc: Value) {
+        let exit = self.iconst(exit as i64);
+        self.builder.ins().jump(self.exit_block, &[exit, pc]);
+    }
+
+    /// Lowers `then`, which must not fall through, into a cold block taken if the condition holds
+    fn when<F: FnOnce(&mut Self)>(&mut self, condition: Value, then: F) {
+        let then_block = self.builder.create_block();
+        let continue_block = self.builder.create_block();
+        self.builder.set_cold_block(then_block);
+        self.builder
+            .ins()
+            .brif(condition, then_block, &[], continue_block, &[]);
+        self.builder.switch_to_block(then_block);
+        then(self);
+        self.builder.switch_to_block(continue_block);
+    }
+
+    /// Throws `ExceededMaxInstructions` at the end of the instruction `pc`, which continues at
+    /// `next_pc`, see `Interpreter::check_instruction_meter()`
+    fn check_instruction_meter(&mut self, pc: usize, end_pc: usize, next_pc: Value) {
+        let config = self.config;
+        if !config.enable_instruction_meter {
+            return;
+        }
+        let due_insn_count = self.builder.use_var(Variable::new(DUE_INSN_COUNT));
+        let previous_instruction_meter = self
+            .builder
+            .use_var(Variable::new(PREVIOUS_INSTRUCTION_METER));
+        let exceeded = self.builder.ins().icmp(
+            IntCC::UnsignedGreaterThanOrEqual,
+            due_insn_count,
+            previous_instruction_meter,
+        );
+        self.when(exceeded, |lowering| {
+            let end_pc = lowering.iconst(end_pc as i64);
+            lowering.throw(Fault::ExceededMaxInstructions, end_pc, None);
+            if config.enable_suspension {
+                lowering.exit(EXIT_SUSPEND, next_pc);
+            } else {
+                let pc = lowering.iconst(pc as i64);
+                lowering.exit(EXIT_THROW, pc);
+            }
+        });
+    }
+
+    /// Checks the instruction meter and continues at `next_pc`, which is known statically
+    fn check_instruction_meter_and_jump(&mut self, pc: usize, end_pc: usize, next_pc: usize) {
+        let next_pc_value = self.iconst(next_pc as i64);
+        self.check_instruction_meter(pc, end_pc, next_pc_value);
+        match self.instruction_blocks.get(next_pc) {
+            Some(block) => {
+                let block = *block;
+                self.builder.ins().jump(block, &[]);
+            }
+            None => {
+                self.builder
+                    .ins()
+                    .jump(self.overrun_block, &[next_pc_value]);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Source locations of instructions from the DWARF debug information (.debug_line and .debug_info)
+
+use crate::{coverage::LineTable, ebpf};
+use gimli::{AttributeValue, EndianSlice};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+type Dwarf<'a> = gimli::Dwarf<EndianSlice<'a, gimli::LittleEndian>>;
+type Unit<'a> = gimli::Unit<EndianSlice<'a, gimli::LittleEndian>>;
+
+/// Source file, line and function of an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+    /// Path of the source file
+    pub file: &'a str,
+    /// Line in the source file
+    pub line: usize,
+    /// Name of the function, if the debug information describes it
+    pub function: Option<&'a str>,
+}
+
+impl fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Map from pc to source location, parsed from the DWARF sections of an ELF
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// Paths of the source files
+    files: Vec<String>,
+    /// Names of the functions
+    functions: Vec<String>,
+    /// Index into `files` and line of every instruction, keyed by pc
+    lines: BTreeMap<usize, (usize, usize)>,
+    /// Index into `functions` of every instruction, keyed by pc
+    function_of_pc: BTreeMap<usize, usize>,
+}
+
+impl DebugInfo {
+    /// Parses the line tables and the functions of all compilation units
+    ///
+    /// `section` returns the (relocated) contents of a DWARF section by name.
+    /// Addresses are translated to pcs relative to `text_vaddr`, everything
+    /// outside of the `text_len` bytes of the text section is ignored.
+    /// Returns `None` if there is no `.debug_line` section.
+    pub fn parse<'a>(
+        section: impl Fn(&str) -> Option<&'a [u8]>,
+        text_vaddr: u64,
+        text_len: usize,
+    ) -> Result<Option<Self>, gimli::Error> {
+        if section(".debug_line").is_none() {
+            return Ok(None);
+        }
+        let dwarf = Dwarf::load(|id| -> Result<_, gimli::Error> {
+            Ok(EndianSlice::new(
+                section(id.name()).unwrap_or_default(),
+                gimli::LittleEndian,
+            ))
+        })?;
+        let instruction_count = text_len.checked_div(ebpf::INSN_SIZE).unwrap_or_default();
+        // Instructions which start within [begin, end)
+        let pc_range = |begin: u64, end: u64| {
+            let pc = |address: u64| {
+                (address.saturating_sub(text_vaddr) as usize)
+                    .checked_div(ebpf::INSN_SIZE)
+                    .unwrap_or_default()
+                    .min(instruction_count)
+            };
+            if begin < text_vaddr || end <= begin {
+                0..0
+            } else {
+                pc(begin)..pc(end.saturating_add(ebpf::INSN_SIZE as u64).saturating_sub(1))
+            }
+        };
+
+        let mut debug_info = Self::default();
+        let mut file_indices = HashMap::new();
+        let mut function_ranges = Vec::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if entry.tag() != gimli::DW_TAG_subprogram {
+                    continue;
+                }
+                let name = match function_name(&dwarf, &unit, entry)? {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let mut ranges = dwarf.die_ranges(&unit, entry)?;
+                while let Some(range) = ranges.next()? {
+                    let pcs = pc_range(range.begin, range.end);
+                    if !pcs.is_empty() {
+                        function_ranges.push((pcs, debug_info.functions.len()));
+                    }
+                }
+                debug_info.functions.push(name);
+            }
+
+            let program = match unit.line_program.clone() {
+                Some(program) => program,
+                None => continue,
+            };
+            let mut rows = program.rows();
+            let mut previous_row: Option<(u64, Option<usize>, usize)> = None;
+            while let Some((header, row)) = rows.next_row()? {
+                if let Some((address, Some(file_index), line)) = previous_row {
+                    for pc in pc_range(address, row.address()) {
+                        debug_info.lines.insert(pc, (file_index, line));
+                    }
+                }
+                previous_row = if row.end_sequence() {
+                    None
+                } else {
+                    // Line 0 marks instructions which are not attributable to any line
+                    let line = row.line().map(|line| line.get() as usize);
+                    let file_index = match (line, row.file(header)) {
+                        (Some(_), Some(file)) => {
+                            let path = file_path(&dwarf, &unit, header, file)?;
+                            let next_index = debug_info.files.len();
+                            let file_index =
+                                *file_indices.entry(path.clone()).or_insert(next_index);
+                            if file_index == next_index {
+                                debug_info.files.push(path);
+                            }
+                            Some(file_index)
+                        }
+                        _ => None,
+                    };
+                    Some((row.address(), file_index, line.unwrap_or(0)))
+                };
+            }
+        }
+
+        // Nested functions are more specific than the ones enclosing them
+        function_ranges.sort_by_key(|(pcs, _index)| std::cmp::Reverse(pcs.len()));
+        for (pcs, index) in function_ranges {
+            for pc in pcs {
+                debug_info.function_of_pc.insert(pc, index);
+            }
+        }
+        Ok(Some(debug_info))
+    }
+
+    /// Looks up the source location of the instruction at `pc`
+    pub fn lookup(&self, pc: usize) -> Option<SourceLocation<'_>> {
+        let (file_index, line) = self.lines.get(&pc)?;
+        Some(SourceLocation {
+            file: &self.files[*file_index],
+            line: *line,
+            function: self.function(pc),
+        })
+    }
+
+    /// Looks up the name of the function containing the instruction at `pc`
+    pub fn function(&self, pc: usize) -> Option<&str> {
+        self.function_of_pc
+            .get(&pc)
+            .map(|index| self.functions[*index].as_str())
+    }
+
+    /// Source file and line of every instruction, as used by coverage reports
+    pub fn line_table(&self) -> LineTable {
+        self.lines
+            .iter()
+            .map(|(pc, (file_index, line))| (*pc, (self.files[*file_index].clone(), *line)))
+            .collect()
+    }
+}
+
+/// Name of a subprogram, possibly declared by its abstract origin or specification
+fn function_name(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    entry: &gimli::DebuggingInformationEntry<EndianSlice<gimli::LittleEndian>>,
+) -> Result<Option<String>, gimli::Error> {
+    if let Some(name) = entry.attr_value(gimli::DW_AT_name)? {
+        return Ok(Some(
+            dwarf
+                .attr_string(unit, name)?
+                .to_string_lossy()
+                .into_owned(),
+        ));
+    }
+    for attribute in [gimli::DW_AT_abstract_origin, gimli::DW_AT_specification] {
+        if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(attribute)? {
+            return function_name(dwarf, unit, &unit.entry(offset)?);
+        }
+    }
+    Ok(None)
+}
+
+/// Path of a file of the line table, joined with its directory unless that is absolute already
+fn file_path(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    header: &gimli::LineProgramHeader<EndianSlice<gimli::LittleEndian>>,
+    file: &gimli::FileEntry<EndianSlice<gimli::LittleEndian>>,
+) -> Result<String, gimli::Error> {
+    let name = dwarf
+        .attr_string(unit, file.path_name())?
+        .to_string_lossy()
+        .into_owned();
+    if name.starts_with('/') {
+        return Ok(name);
+    }
+    let directory = match file.directory(header) {
+        Some(directory) => dwarf
+            .attr_string(unit, directory)?
+            .to_string_lossy()
+            .into_owned(),
+        None => String::default(),
+    };
+    Ok(if directory.is_empty() || directory == "." {
+        name
+    } else {
+        format!("{}/{}", directory.trim_end_matches('/'), name)
+    })
+}
@@ -19,6 +19,7 @@ use bpf_arch::reg::BpfRegs;
 use bpf_arch::Bpf;
 use gdbstub::target::ext::base::singlethread::{SingleThreadBase, SingleThreadResume};
 use gdbstub::target::ext::lldb_register_info_override::{Callback, CallbackToken};
+use gdbstub::target::ext::monitor_cmd::{outputln, ConsoleOutput};
 use gdbstub::target::ext::section_offsets::Offsets;
 
 use crate::{
@@ -149,6 +150,11 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Target for Interpreter<'a, 'b, V, C>
     {
         Some(self)
     }
+
+    #[inline(always)]
+    fn support_monitor_cmd(&mut self) -> Option<target::ext::monitor_cmd::MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
 }
 
 fn get_host_ptr<V: Verifier, C: ContextObject>(
@@ -314,6 +320,34 @@ impl<'a, 'b, V: Verifier, C: ContextObject> target::ext::section_offsets::Sectio
     }
 }
 
+impl<'a, 'b, V: Verifier, C: ContextObject> target::ext::monitor_cmd::MonitorCmd
+    for Interpreter<'a, 'b, V, C>
+{
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        match cmd {
+            #[cfg(feature = "debug-info")]
+            b"line" => match self.vm.executable.get_debug_info() {
+                Some(debug_info) => match debug_info.lookup(self.pc) {
+                    Some(location) => match location.function {
+                        Some(function) => outputln!(out, "{} in {}", location, function),
+                        None => outputln!(out, "{}", location),
+                    },
+                    None => outputln!(out, "No source location for pc {}", self.pc),
+                },
+                None => outputln!(out, "No debug information"),
+            },
+            #[cfg(not(feature = "debug-info"))]
+            b"line" => outputln!(out, "No debug information"),
+            _ => outputln!(out, "Supported commands: line"),
+        }
+        Ok(())
+    }
+}
+
 impl<'a, 'b, V: Verifier, C: ContextObject> target::ext::breakpoints::Breakpoints
     for Interpreter<'a, 'b, V, C>
 {
@@ -56,6 +56,8 @@ pub const MM_STACK_START: u64 = 0x200000000;
 pub const MM_HEAP_START: u64 = 0x300000000;
 /// Start of the input buffers in the memory map
 pub const MM_INPUT_START: u64 = 0x400000000;
+/// Start of the writable data sections (.data and .bss) in the memory map
+pub const MM_DATA_START: u64 = 0x500000000;
 
 // eBPF op codes.
 // See also https://www.kernel.org/doc/Documentation/networking/filter.txt
@@ -11,32 +11,43 @@ use crate::{
     ebpf::{self, EF_SBF_V2, HOST_ALIGN, INSN_SIZE},
     elf_parser::{
         consts::{
-            ELFCLASS64, ELFDATA2LSB, ELFOSABI_NONE, EM_BPF, EM_SBF, ET_DYN, R_X86_64_32,
-            R_X86_64_64, R_X86_64_NONE, R_X86_64_RELATIVE,
+            ELFCLASS64, ELFDATA2LSB, ELFOSABI_NONE, EM_BPF, EM_SBF, ET_DYN, ET_REL, R_BPF_64_ABS32,
+            R_BPF_64_ABS64, R_X86_64_32, R_X86_64_64, R_X86_64_NONE, R_X86_64_RELATIVE, SHF_ALLOC,
+            SHF_EXECINSTR, SHN_UNDEF, SHT_DYNAMIC, SHT_NOBITS,
         },
-        types::Elf64Word,
+        types::{Elf64Shdr, Elf64Word},
     },
     elf_parser_glue::{
         ElfParser, ElfProgramHeader, ElfRelocation, ElfSectionHeader, ElfSymbol, GoblinParser,
         NewParser,
     },
     error::EbpfError,
+    interpreter::DecodedProgram,
     memory_region::MemoryRegion,
     verifier::{TautologyVerifier, Verifier},
     vm::{BuiltInProgram, Config, ContextObject, FunctionRegistry},
 };
 
+#[cfg(feature = "cranelift")]
+use crate::cranelift::CraneliftProgram;
+#[cfg(feature = "debug-info")]
+use crate::debug_info::DebugInfo;
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
-use crate::jit::{JitCompiler, JitProgram};
+use crate::{
+    coverage::LineTable,
+    jit::{JitCompiler, JitProgram},
+};
 use byteorder::{ByteOrder, LittleEndian};
+use goblin::elf::SectionHeader;
 use std::{
     collections::{btree_map::Entry, BTreeMap},
     fmt::Debug,
+    iter,
     marker::PhantomData,
     mem,
     ops::Range,
     str,
-    sync::Arc,
+    sync::{Arc, OnceLock},
 };
 
 /// Error definitions
@@ -81,11 +92,11 @@ pub enum ElfError {
     /// Not one text section
     #[error("Multiple or no text sections, consider removing llc option: -function-sections")]
     NotOneTextSection,
-    /// Read-write data not supported
-    #[error("Found .bss section in ELF, read-write data not supported")]
+    /// .bss section is not writable
+    #[error("Found .bss section in ELF which is not writable")]
     BssNotSupported,
-    /// Read-write data not supported
-    #[error("Found writable section ({0}) in ELF, read-write data not supported")]
+    /// Writable section which is not mapped in the data region
+    #[error("Found writable section ({0}) in ELF, only .data and .bss are writable")]
     WritableSectionNotSupported(String),
     /// Relocation failed, no loadable section contains virtual address
     #[error("Relocation failed, no loadable section contains virtual address {0:#x}")]
@@ -114,6 +125,9 @@ pub enum ElfError {
     /// Invalid program header
     #[error("Invalid ELF program header")]
     InvalidProgramHeader,
+    /// Text sections overlap each other or another section
+    #[error("Text sections overlap each other or another section")]
+    OverlappingTextSections,
 }
 
 /// Generates the hash by which a symbol can be called
@@ -127,6 +141,12 @@ pub fn hash_internal_function(pc: usize, name: &str) -> u32 {
     }
 }
 
+/// Path of the perf map of this process, `/tmp/perf-<pid>.map`, which `perf` reads symbols from
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+pub fn default_perf_map_path() -> std::path::PathBuf {
+    std::path::PathBuf::from(format!("/tmp/perf-{}.map", std::process::id()))
+}
+
 /// Register a symbol or throw ElfError::SymbolHashCollision
 pub fn register_internal_function<
     C: ContextObject,
@@ -187,14 +207,14 @@ pub fn register_internal_function<
 //   32 bit immediate (imm)
 
 /// Byte offset of the immediate field in the instruction
-const BYTE_OFFSET_IMMEDIATE: usize = 4;
+pub(crate) const BYTE_OFFSET_IMMEDIATE: usize = 4;
 /// Byte length of the immediate field
 const BYTE_LENGTH_IMMEDIATE: usize = 4;
 
 /// BPF relocation types.
 #[allow(non_camel_case_types)]
 #[derive(Debug, PartialEq, Copy, Clone)]
-enum BpfRelocationType {
+pub(crate) enum BpfRelocationType {
     /// No relocation, placeholder
     R_Bpf_None = 0,
     /// R_BPF_64_64 relocation type is used for ld_imm64 instruction.
@@ -203,6 +223,11 @@ enum BpfRelocationType {
     /// (4 bytes). The relocation can be resolved with the symbol
     /// value plus implicit addend.
     R_Bpf_64_64 = 1,
+    /// Absolute 64 bit address, used in the DWARF sections of relocatable objects
+    R_Bpf_64_Abs64 = 2,
+    /// Absolute 32 bit address or section offset, used in the DWARF sections
+    /// of relocatable objects
+    R_Bpf_64_Abs32 = 3,
     /// 64 bit relocation of a ldxdw instruction.  The ldxdw
     /// instruction occupies two instruction slots. The 64-bit address
     /// to load from is split into the 32-bit imm field of each
@@ -228,10 +253,12 @@ enum BpfRelocationType {
     R_Bpf_64_32 = 10,
 }
 impl BpfRelocationType {
-    fn from_x86_relocation_type(from: u32) -> Option<BpfRelocationType> {
+    pub(crate) fn from_x86_relocation_type(from: u32) -> Option<BpfRelocationType> {
         match from {
             R_X86_64_NONE => Some(BpfRelocationType::R_Bpf_None),
             R_X86_64_64 => Some(BpfRelocationType::R_Bpf_64_64),
+            R_BPF_64_ABS64 => Some(BpfRelocationType::R_Bpf_64_Abs64),
+            R_BPF_64_ABS32 => Some(BpfRelocationType::R_Bpf_64_Abs32),
             R_X86_64_RELATIVE => Some(BpfRelocationType::R_Bpf_64_Relative),
             R_X86_64_32 => Some(BpfRelocationType::R_Bpf_64_32),
             _ => None,
@@ -266,6 +293,95 @@ pub(crate) enum Section {
     Borrowed(usize, Range<usize>),
 }
 
+/// Writable sections (.data and .bss), which are mapped at `ebpf::MM_DATA_START`
+#[derive(Debug, Default)]
+struct DataSections {
+    /// The lowest linked address of all writable sections
+    lowest_addr: u64,
+    /// The highest linked address of all writable sections
+    highest_addr: u64,
+    /// Linked address range and file range (None for NOBITS) of each writable section
+    sections: Vec<(Range<u64>, Option<Range<usize>>)>,
+}
+
+impl DataSections {
+    fn new<'a, P: ElfParser<'a>>(elf: &'a P) -> Result<Self, ElfError> {
+        let mut data_sections = Self {
+            lowest_addr: u64::MAX,
+            ..Self::default()
+        };
+        for section_header in elf.section_headers() {
+            match elf.section_name(section_header.sh_name()) {
+                Some(name) if section_header.is_writable() && is_data_section(name) => {}
+                _ => continue,
+            }
+
+            // Relocations refer to the sections by their address rooted at
+            // MM_PROGRAM_START, see relocate()
+            let mut section_addr = section_header.sh_addr();
+            if section_addr < ebpf::MM_PROGRAM_START {
+                section_addr = ebpf::MM_PROGRAM_START.saturating_add(section_addr);
+            }
+            let vm_range = section_addr..section_addr.saturating_add(section_header.sh_size());
+            data_sections.lowest_addr = data_sections.lowest_addr.min(vm_range.start);
+            data_sections.highest_addr = data_sections.highest_addr.max(vm_range.end);
+            // NOBITS sections (.bss) span sh_size bytes of the region, but none of the file
+            let file_range = if section_header.sh_type() == SHT_NOBITS {
+                None
+            } else {
+                let offset = section_header.sh_offset() as usize;
+                Some(offset..offset.saturating_add(section_header.sh_size() as usize))
+            };
+            data_sections.sections.push((vm_range, file_range));
+        }
+        if data_sections.sections.is_empty() {
+            return Ok(Self::default());
+        }
+
+        // The data region must fit in between MM_DATA_START and the next region
+        if data_sections
+            .highest_addr
+            .saturating_sub(data_sections.lowest_addr)
+            > 1 << ebpf::VIRTUAL_ADDRESS_BITS
+        {
+            return Err(ElfError::ValueOutOfBounds);
+        }
+        Ok(data_sections)
+    }
+
+    /// Translates a linked address into the data region if it points into a writable section
+    fn translate(&self, addr: u64) -> u64 {
+        if self
+            .sections
+            .iter()
+            .any(|(vm_range, _)| vm_range.contains(&addr))
+        {
+            ebpf::MM_DATA_START.saturating_add(addr.saturating_sub(self.lowest_addr))
+        } else {
+            addr
+        }
+    }
+
+    /// Copies the (relocated) writable sections into the initial contents of the data region
+    fn to_template(&self, elf_bytes: &[u8]) -> Result<Vec<u8>, ElfError> {
+        let mut template = vec![0; self.highest_addr.saturating_sub(self.lowest_addr) as usize];
+        for (vm_range, file_range) in self.sections.iter() {
+            // NOBITS sections stay zero filled
+            if let Some(file_range) = file_range {
+                let section_data = elf_bytes
+                    .get(file_range.clone())
+                    .ok_or(ElfError::ValueOutOfBounds)?;
+                let offset = vm_range.start.saturating_sub(self.lowest_addr) as usize;
+                template
+                    .get_mut(offset..offset.saturating_add(section_data.len()))
+                    .ok_or(ElfError::ValueOutOfBounds)?
+                    .copy_from_slice(section_data);
+            }
+        }
+        Ok(template)
+    }
+}
+
 /// Elf loader/relocator
 #[derive(Debug, PartialEq)]
 pub struct Executable<V: Verifier, C: ContextObject> {
@@ -275,17 +391,27 @@ pub struct Executable<V: Verifier, C: ContextObject> {
     elf_bytes: AlignedMemory<{ HOST_ALIGN }>,
     /// Read-only section
     ro_section: Section,
+    /// Initial contents of the writable sections
+    data_section: Vec<u8>,
     /// Text section info
     text_section_info: SectionInfo,
     /// Address of the entry point
     entry_pc: usize,
+    /// Source locations from DWARF, if present and config.enable_symbol_and_section_labels=true
+    #[cfg(feature = "debug-info")]
+    debug_info: Option<DebugInfo>,
     /// Call resolution map (hash, pc, name)
     function_registry: FunctionRegistry,
     /// Loader built-in program
     loader: Arc<BuiltInProgram<C>>,
+    /// Text section decoded for the interpreter on its first use
+    decoded_program: OnceLock<DecodedProgram<V, C>>,
     /// Compiled program and argument
     #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
     compiled_program: Option<JitProgram>,
+    /// Program compiled by Cranelift
+    #[cfg(feature = "cranelift")]
+    cranelift_program: Option<CraneliftProgram>,
 }
 
 impl<V: Verifier, C: ContextObject> Executable<V, C> {
@@ -294,6 +420,11 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         self.loader.get_config()
     }
 
+    /// Get the bytes of the ELF after relocation
+    pub(crate) fn get_elf_bytes(&self) -> &[u8] {
+        self.elf_bytes.as_slice()
+    }
+
     /// Get the .text section virtual address and bytes
     pub fn get_text_bytes(&self) -> (u64, &[u8]) {
         let (ro_offset, ro_section) = match &self.ro_section {
@@ -329,13 +460,45 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         get_ro_region(&self.ro_section, self.elf_bytes.as_slice())
     }
 
+    /// Get the initial contents of the merged writable sections (.data and .bss)
+    pub fn get_data_section(&self) -> &[u8] {
+        &self.data_section
+    }
+
+    /// Get a memory region that can be used to access the merged writable sections
+    ///
+    /// `data` is reset to the initial contents of the sections, so every invocation
+    /// should instantiate a fresh region. Returns `None` if there are no writable sections.
+    pub fn get_data_region(
+        &self,
+        data: &mut AlignedMemory<{ HOST_ALIGN }>,
+    ) -> Option<MemoryRegion> {
+        if self.data_section.is_empty() {
+            return None;
+        }
+        if data.len() == self.data_section.len() {
+            data.as_slice_mut().copy_from_slice(&self.data_section);
+        } else {
+            *data = AlignedMemory::from_slice(&self.data_section);
+        }
+        Some(MemoryRegion::new_writable(
+            data.as_slice_mut(),
+            ebpf::MM_DATA_START,
+        ))
+    }
+
     /// Get the entry point offset into the text section
     pub fn get_entrypoint_instruction_offset(&self) -> usize {
         self.entry_pc
     }
 
+    /// Get the source locations parsed from the DWARF sections, if any
+    #[cfg(feature = "debug-info")]
+    pub fn get_debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
     /// Get the text section offset
-    #[cfg(feature = "debugger")]
     pub fn get_text_section_offset(&self) -> u64 {
         self.text_section_info.offset_range.start as u64
     }
@@ -345,6 +508,22 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         self.function_registry.get(&hash).map(|(pc, _name)| *pc)
     }
 
+    /// Get the cost the instruction meter charges for an instruction
+    ///
+    /// Resolves `call imm` the same way execution does to distinguish syscalls.
+    pub fn get_instruction_cost(&self, insn: &ebpf::Insn) -> u64 {
+        self.get_config()
+            .instruction_costs
+            .get(insn.opc, self.is_syscall(insn))
+    }
+
+    /// Whether an instruction is a `call imm` which resolves to a syscall
+    pub(crate) fn is_syscall(&self, insn: &ebpf::Insn) -> bool {
+        insn.opc == ebpf::CALL_IMM
+            && (!self.get_config().static_syscalls || insn.src == 0)
+            && self.loader.lookup_function(insn.imm as u32).is_some()
+    }
+
     /// Get the loader built-in program
     pub fn get_loader(&self) -> &BuiltInProgram<C> {
         &self.loader
@@ -356,18 +535,28 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         self.compiled_program.as_ref()
     }
 
+    /// Get the program compiled by Cranelift
+    #[cfg(feature = "cranelift")]
+    pub fn get_cranelift_program(&self) -> Option<&CraneliftProgram> {
+        self.cranelift_program.as_ref()
+    }
+
     /// Verify the executable
-    pub fn verified(executable: Executable<TautologyVerifier, C>) -> Result<Self, EbpfError> {
-        <V as Verifier>::verify(
-            executable.get_text_bytes().1,
-            executable.get_config(),
-            executable.get_function_registry(),
-        )?;
+    pub fn verified(mut executable: Executable<TautologyVerifier, C>) -> Result<Self, EbpfError> {
+        <V as Verifier>::verify_executable(&executable)?;
+        // The interpreter decodes the program again, specialized for the verifier
+        executable.decoded_program = OnceLock::new();
         Ok(unsafe {
             std::mem::transmute::<Executable<TautologyVerifier, C>, Executable<V, C>>(executable)
         })
     }
 
+    /// Get the text section decoded for the interpreter, decoding it on the first call
+    pub(crate) fn get_decoded_program(&self) -> &DecodedProgram<V, C> {
+        self.decoded_program
+            .get_or_init(|| DecodedProgram::new(self))
+    }
+
     /// JIT compile the executable
     #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
     pub fn jit_compile(&mut self) -> Result<(), crate::error::EbpfError> {
@@ -376,6 +565,93 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         Ok(())
     }
 
+    /// JIT compile the executable, deferring each function to its first call
+    ///
+    /// Only the subroutines are compiled up front. A function is compiled together with the
+    /// functions it cannot be separated from, e.g. because they share a jump target.
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn jit_compile_lazily(&mut self) -> Result<(), crate::error::EbpfError> {
+        let jit = JitCompiler::<V, C>::new(self)?;
+        self.compiled_program = Some(jit.compile_lazily()?);
+        Ok(())
+    }
+
+    /// Serialize the JIT compiled program, so that `load_compiled_program()` can skip the compilation
+    ///
+    /// The bytes are authenticated with `key`, a secret of the embedder which whoever can write
+    /// the cache must not know, as loading the bytes maps their machine code executable.
+    /// The cached machine code keeps the noop insertion and constant blinding it was compiled
+    /// with, so every process which loads it shares the same diversification. Recompile to
+    /// re-randomize it. Returns `None` if the executable was not JIT compiled or was compiled lazily.
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn serialize_compiled_program(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.compiled_program.as_ref()?.serialize(self, key)
+    }
+
+    /// Load a JIT compiled program serialized by `serialize_compiled_program()`
+    ///
+    /// Fails with `EbpfError::InvalidJitCache` if the bytes are malformed, were not
+    /// authenticated with the same `key` or were serialized for different ELF bytes,
+    /// a different `Config`, function registry or set of syscalls.
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn load_compiled_program(
+        &mut self,
+        bytes: &[u8],
+        key: &[u8],
+    ) -> Result<(), crate::error::EbpfError> {
+        self.compiled_program = Some(JitProgram::deserialize(self, bytes, key)?);
+        Ok(())
+    }
+
+    /// Register the JIT compiled program with native profilers and debuggers
+    ///
+    /// Appends a symbol for every function of the function registry to the perf map at
+    /// `perf_map`, if any, see `default_perf_map_path()`. The same symbols and the line of
+    /// every instruction in `line_table` are registered through the GDB JIT compilation
+    /// interface until the JIT compiled program is dropped. Of a lazily compiled program
+    /// only the functions compiled so far are registered. Debuggers can only see the
+    /// registration if the `gdb-jit-interface` feature is enabled.
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn register_jit_symbols(
+        &mut self,
+        line_table: &LineTable,
+        perf_map: Option<&std::path::Path>,
+    ) -> Result<(), crate::error::EbpfError> {
+        self.compiled_program
+            .as_mut()
+            .ok_or(EbpfError::JitNotCompiled)?
+            .register_symbols(&self.function_registry, line_table, perf_map)
+            .map_err(|err| EbpfError::JitSymbolRegistrationFailed(err.to_string()))
+    }
+
+    /// Load the JIT compiled program from a cache, falling back to `jit_compile()`
+    ///
+    /// `key` authenticates the cache, see `serialize_compiled_program()`. Returns whether
+    /// the cache was used, `false` if it is missing, stale or was tampered with.
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn jit_compile_cached(
+        &mut self,
+        cache: Option<&[u8]>,
+        key: &[u8],
+    ) -> Result<bool, crate::error::EbpfError> {
+        if let Some(bytes) = cache {
+            if self.load_compiled_program(bytes, key).is_ok() {
+                return Ok(true);
+            }
+        }
+        self.jit_compile()?;
+        Ok(false)
+    }
+
+    /// Compile the executable with Cranelift, which supports more hosts than `jit_compile()`
+    ///
+    /// Run it with `EbpfVm::execute_program_cranelift()`.
+    #[cfg(feature = "cranelift")]
+    pub fn cranelift_compile(&mut self) -> Result<(), crate::error::EbpfError> {
+        self.cranelift_program = Some(CraneliftProgram::new(self)?);
+        Ok(())
+    }
+
     /// Get normal functions (if debug symbols are not stripped)
     pub fn get_function_registry(&self) -> &FunctionRegistry {
         &self.function_registry
@@ -400,9 +676,10 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
             0
         };
         Ok(Self {
-            _verifier: PhantomData::default(),
+            _verifier: PhantomData,
             elf_bytes,
             ro_section: Section::Borrowed(0, 0..text_bytes.len()),
+            data_section: Vec::new(),
             text_section_info: SectionInfo {
                 name: if enable_symbol_and_section_labels {
                     ".text".to_string()
@@ -413,10 +690,15 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                 offset_range: 0..text_bytes.len(),
             },
             entry_pc,
+            #[cfg(feature = "debug-info")]
+            debug_info: None,
             function_registry,
             loader,
+            decoded_program: OnceLock::new(),
             #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
             compiled_program: None,
+            #[cfg(feature = "cranelift")]
+            cranelift_program: None,
         })
     }
 
@@ -439,6 +721,371 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         }
     }
 
+    /// Loads a relocatable object (ET_REL) as emitted by `clang -target bpf -c`
+    ///
+    /// Every executable section other than `.text` (eg `xdp` or `.classifier`)
+    /// is a separate program, so one executable is returned per program section,
+    /// together with the name of that section. The subprograms in `.text` are
+    /// appended to each program and the `.rodata` sections follow in the
+    /// read-only region. The writable sections are mapped at `MM_DATA_START`,
+    /// the same as for shared objects. An object without program sections
+    /// loads `.text` as its only program.
+    pub fn load_relocatable(
+        bytes: &[u8],
+        loader: Arc<BuiltInProgram<C>>,
+    ) -> Result<Vec<(String, Self)>, ElfError> {
+        // Object files place the section contents after the string and symbol
+        // tables, which the new parser rejects. Thus always use goblin here.
+        let elf = GoblinParser::parse(bytes)?;
+        let header = elf.header();
+        if header.e_ident.ei_class != ELFCLASS64 {
+            return Err(ElfError::WrongClass);
+        }
+        if header.e_ident.ei_data != ELFDATA2LSB {
+            return Err(ElfError::WrongEndianess);
+        }
+        if header.e_ident.ei_osabi != ELFOSABI_NONE {
+            return Err(ElfError::WrongAbi);
+        }
+        if header.e_machine != EM_BPF {
+            return Err(ElfError::WrongMachine);
+        }
+        if header.e_type != ET_REL {
+            return Err(ElfError::WrongType);
+        }
+
+        let mut program_sections = Vec::new();
+        let mut subprogram_section = None;
+        let mut rodata_sections = Vec::new();
+        let mut data_sections = Vec::new();
+        for (index, section_header) in elf.section_headers().enumerate() {
+            if section_header.sh_flags() & SHF_ALLOC == 0 || section_header.sh_size() == 0 {
+                continue;
+            }
+            let name = elf
+                .section_name(section_header.sh_name())
+                .ok_or(ElfError::ValueOutOfBounds)?;
+            if section_header.sh_flags() & SHF_EXECINSTR != 0 {
+                if name == ".text" {
+                    subprogram_section = Some(index);
+                } else {
+                    program_sections.push((index, name));
+                }
+            } else if section_header.is_writable() {
+                if is_data_section(name) {
+                    data_sections.push(index);
+                }
+            } else if name.starts_with(".rodata") {
+                rodata_sections.push(index);
+            }
+        }
+        if program_sections.is_empty() {
+            program_sections.push((
+                subprogram_section
+                    .take()
+                    .ok_or(ElfError::NotOneTextSection)?,
+                ".text",
+            ));
+        }
+
+        // The writable sections are shared by all programs
+        let mut section_addresses = BTreeMap::new();
+        let mut data_section = Vec::new();
+        for index in data_sections {
+            let offset = append_section(
+                &mut data_section,
+                elf.section_header(index)
+                    .ok_or(ElfError::ValueOutOfBounds)?,
+                bytes,
+            )?;
+            section_addresses.insert(index, ebpf::MM_DATA_START.saturating_add(offset as u64));
+        }
+        if data_section.len() as u64 > 1 << ebpf::VIRTUAL_ADDRESS_BITS {
+            return Err(ElfError::ValueOutOfBounds);
+        }
+
+        program_sections
+            .into_iter()
+            .map(|(index, name)| {
+                let executable = Self::load_relocatable_program(
+                    &elf,
+                    bytes,
+                    loader.clone(),
+                    iter::once(index).chain(subprogram_section).collect(),
+                    &rodata_sections,
+                    section_addresses.clone(),
+                    data_section.clone(),
+                )?;
+                Ok((name.to_string(), executable))
+            })
+            .collect()
+    }
+
+    /// Lays out and relocates one program of a relocatable object
+    ///
+    /// The program section is the first of `text_sections`, the entrypoint is
+    /// at its start.
+    fn load_relocatable_program(
+        elf: &GoblinParser,
+        bytes: &[u8],
+        loader: Arc<BuiltInProgram<C>>,
+        text_sections: Vec<usize>,
+        rodata_sections: &[usize],
+        mut section_addresses: BTreeMap<usize, u64>,
+        data_section: Vec<u8>,
+    ) -> Result<Self, ElfError> {
+        let config = loader.get_config();
+
+        // Lay out the text sections back to back, followed by the read-only data
+        let mut ro_bytes = Vec::new();
+        for index in text_sections.iter() {
+            let offset = append_section(
+                &mut ro_bytes,
+                elf.section_header(*index)
+                    .ok_or(ElfError::ValueOutOfBounds)?,
+                bytes,
+            )?;
+            section_addresses.insert(*index, ebpf::MM_PROGRAM_START.saturating_add(offset as u64));
+        }
+        let text_section_end = ro_bytes.len();
+        for index in rodata_sections.iter() {
+            let offset = append_section(
+                &mut ro_bytes,
+                elf.section_header(*index)
+                    .ok_or(ElfError::ValueOutOfBounds)?,
+                bytes,
+            )?;
+            section_addresses.insert(*index, ebpf::MM_PROGRAM_START.saturating_add(offset as u64));
+        }
+        if ebpf::MM_PROGRAM_START.saturating_add(ro_bytes.len() as u64) > ebpf::MM_STACK_START {
+            return Err(ElfError::ValueOutOfBounds);
+        }
+
+        // Collect the relocations by their offset into the laid out text
+        let mut relocations = BTreeMap::new();
+        for index in text_sections.iter() {
+            let section_offset =
+                section_addresses[index].saturating_sub(ebpf::MM_PROGRAM_START) as usize;
+            for relocation in elf.section_relocations(*index) {
+                relocations.insert(
+                    section_offset.saturating_add(relocation.r_offset as usize),
+                    relocation,
+                );
+            }
+        }
+
+        // Calls which are resolved by relocations carry an addend instead of a
+        // relative offset. Hide them from fixup_relative_calls().
+        let mut call_addends = BTreeMap::new();
+        for (r_offset, relocation) in relocations.iter() {
+            if BpfRelocationType::from_x86_relocation_type(relocation.r_type)
+                == Some(BpfRelocationType::R_Bpf_64_32)
+            {
+                let imm_offset = r_offset.saturating_add(BYTE_OFFSET_IMMEDIATE);
+                let imm_slice = ro_bytes
+                    .get_mut(imm_offset..imm_offset.saturating_add(BYTE_LENGTH_IMMEDIATE))
+                    .ok_or(ElfError::ValueOutOfBounds)?;
+                call_addends.insert(*r_offset, LittleEndian::read_i32(imm_slice));
+                LittleEndian::write_i32(imm_slice, -1);
+            }
+        }
+
+        let mut function_registry = FunctionRegistry::default();
+        Self::fixup_relative_calls(
+            &mut function_registry,
+            &loader,
+            ro_bytes
+                .get_mut(..text_section_end)
+                .ok_or(ElfError::ValueOutOfBounds)?,
+        )?;
+
+        let mut syscall_cache = BTreeMap::new();
+        for (r_offset, relocation) in relocations.iter() {
+            let r_offset = *r_offset;
+            let symbol = elf
+                .symbol(relocation.r_sym as Elf64Word)
+                .ok_or(ElfError::UnknownSymbol(relocation.r_sym))?;
+            let name = elf
+                .symbol_name(symbol.st_name as Elf64Word)
+                .ok_or(ElfError::UnknownSymbol(symbol.st_name))?;
+            let imm_offset = r_offset.saturating_add(BYTE_OFFSET_IMMEDIATE);
+
+            match BpfRelocationType::from_x86_relocation_type(relocation.r_type) {
+                Some(BpfRelocationType::R_Bpf_64_64) => {
+                    // The lddw immediate holds the offset into the symbol
+                    let imm_slice = ro_bytes
+                        .get(imm_offset..imm_offset.saturating_add(BYTE_LENGTH_IMMEDIATE))
+                        .ok_or(ElfError::ValueOutOfBounds)?;
+                    let refd_addr = LittleEndian::read_u32(imm_slice) as u64;
+
+                    if symbol.st_shndx == SHN_UNDEF as usize {
+                        return Err(ElfError::UnknownSymbol(relocation.r_sym));
+                    }
+                    let section_addr =
+                        section_addresses.get(&symbol.st_shndx).ok_or_else(|| {
+                            ElfError::SectionNotFound(
+                                elf.section_header(symbol.st_shndx)
+                                    .and_then(|section_header| {
+                                        elf.section_name(section_header.sh_name as Elf64Word)
+                                    })
+                                    .unwrap_or_default()
+                                    .to_string(),
+                            )
+                        })?;
+                    let addr = section_addr
+                        .saturating_add(symbol.st_value)
+                        .saturating_add(refd_addr);
+
+                    // Write the low and the high side of the relocated address
+                    let imm_high_offset = imm_offset.saturating_add(INSN_SIZE);
+                    let imm_slice = ro_bytes
+                        .get_mut(imm_offset..imm_offset.saturating_add(BYTE_LENGTH_IMMEDIATE))
+                        .ok_or(ElfError::ValueOutOfBounds)?;
+                    LittleEndian::write_u32(imm_slice, (addr & 0xFFFFFFFF) as u32);
+                    let imm_slice = ro_bytes
+                        .get_mut(
+                            imm_high_offset..imm_high_offset.saturating_add(BYTE_LENGTH_IMMEDIATE),
+                        )
+                        .ok_or(ElfError::ValueOutOfBounds)?;
+                    LittleEndian::write_u32(
+                        imm_slice,
+                        addr.checked_shr(32).unwrap_or_default() as u32,
+                    );
+                }
+                Some(BpfRelocationType::R_Bpf_64_32) => {
+                    let key = if symbol.st_shndx == SHN_UNDEF as usize {
+                        // External functions are syscalls
+                        let hash = *syscall_cache
+                            .entry(symbol.st_name)
+                            .or_insert_with(|| ebpf::hash_symbol_name(name.as_bytes()));
+                        if config.reject_broken_elfs && loader.lookup_function(hash).is_none() {
+                            return Err(ElfError::UnresolvedSymbol(
+                                name.to_string(),
+                                r_offset
+                                    .checked_div(ebpf::INSN_SIZE)
+                                    .and_then(|offset| {
+                                        offset.checked_add(ebpf::ELF_INSN_DUMP_OFFSET)
+                                    })
+                                    .unwrap_or(ebpf::ELF_INSN_DUMP_OFFSET),
+                                r_offset,
+                            ));
+                        }
+                        hash
+                    } else {
+                        // Else it is a bpf-to-bpf call. The callee is at the
+                        // symbol plus the addend, relative to the next instruction.
+                        let section_addr = match section_addresses.get(&symbol.st_shndx) {
+                            Some(addr) if text_sections.contains(&symbol.st_shndx) => *addr,
+                            _ => return Err(ElfError::ValueOutOfBounds),
+                        };
+                        let target_pc = (section_addr
+                            .saturating_sub(ebpf::MM_PROGRAM_START)
+                            .saturating_add(symbol.st_value)
+                            as isize)
+                            .checked_div(ebpf::INSN_SIZE as isize)
+                            .unwrap_or_default()
+                            .saturating_add(call_addends[&r_offset] as isize)
+                            .saturating_add(1);
+                        let instruction_count = text_section_end
+                            .checked_div(ebpf::INSN_SIZE)
+                            .unwrap_or_default();
+                        if target_pc < 0 || target_pc >= instruction_count as isize {
+                            return Err(ElfError::RelativeJumpOutOfBounds(
+                                r_offset
+                                    .checked_div(ebpf::INSN_SIZE)
+                                    .unwrap_or_default()
+                                    .saturating_add(ebpf::ELF_INSN_DUMP_OFFSET),
+                            ));
+                        }
+                        let name = if symbol.is_function() {
+                            name.to_string()
+                        } else {
+                            format!("function_{target_pc}")
+                        };
+                        register_internal_function(
+                            &mut function_registry,
+                            &loader,
+                            target_pc as usize,
+                            name,
+                        )?
+                    };
+
+                    let imm_slice = ro_bytes
+                        .get_mut(imm_offset..imm_offset.saturating_add(BYTE_LENGTH_IMMEDIATE))
+                        .ok_or(ElfError::ValueOutOfBounds)?;
+                    LittleEndian::write_u32(imm_slice, key);
+                }
+                _ => return Err(ElfError::UnknownRelocation(relocation.r_type)),
+            }
+        }
+
+        if config.enable_symbol_and_section_labels {
+            // Register all known function names from the symbol table
+            for symbol in elf.symbols() {
+                if symbol.st_info() & 0xEF != 0x02 || !text_sections.contains(&symbol.st_shndx) {
+                    continue;
+                }
+                let target_pc = (section_addresses[&symbol.st_shndx]
+                    .saturating_sub(ebpf::MM_PROGRAM_START)
+                    .saturating_add(symbol.st_value) as usize)
+                    .checked_div(ebpf::INSN_SIZE)
+                    .unwrap_or_default();
+                let name = elf
+                    .symbol_name(symbol.st_name as Elf64Word)
+                    .ok_or(ElfError::UnknownSymbol(symbol.st_name))?;
+                register_internal_function(&mut function_registry, &loader, target_pc, name)?;
+            }
+        }
+        register_internal_function(&mut function_registry, &loader, 0, "entrypoint")?;
+
+        #[cfg(feature = "debug-info")]
+        let debug_info = if config.enable_symbol_and_section_labels {
+            let debug_sections = relocated_debug_sections(elf, bytes, &section_addresses)?;
+            DebugInfo::parse(
+                |name| debug_sections.get(name).map(Vec::as_slice),
+                ebpf::MM_PROGRAM_START,
+                text_section_end,
+            )
+            .ok()
+            .flatten()
+        } else {
+            None
+        };
+
+        let text_section_info = SectionInfo {
+            name: if config.enable_symbol_and_section_labels {
+                elf.section_header(text_sections[0])
+                    .and_then(|section_header| {
+                        elf.section_name(section_header.sh_name as Elf64Word)
+                    })
+                    .unwrap_or(".text")
+                    .to_string()
+            } else {
+                String::default()
+            },
+            vaddr: ebpf::MM_PROGRAM_START,
+            offset_range: 0..text_section_end,
+        };
+        let ro_section = Section::Borrowed(0, 0..ro_bytes.len());
+        Ok(Self {
+            _verifier: PhantomData,
+            elf_bytes: AlignedMemory::from_slice(&ro_bytes),
+            ro_section,
+            data_section,
+            text_section_info,
+            entry_pc: 0,
+            #[cfg(feature = "debug-info")]
+            debug_info,
+            function_registry,
+            loader,
+            decoded_program: OnceLock::new(),
+            #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+            compiled_program: None,
+            #[cfg(feature = "cranelift")]
+            cranelift_program: None,
+        })
+    }
+
     fn load_with_parser<'a, P: ElfParser<'a>>(
         elf: &'a P,
         bytes: &[u8],
@@ -450,7 +1097,11 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         Self::validate(config, elf, elf_bytes.as_slice())?;
 
         // calculate the text section info
-        let text_section = elf.section(".text")?;
+        let text_sections =
+            TextSections::new(elf.section_headers().map(|section_header| {
+                (elf.section_name(section_header.sh_name()), section_header)
+            }))?;
+        let text_section = &text_sections.merged;
         let text_section_info = SectionInfo {
             name: if config.enable_symbol_and_section_labels {
                 elf.section_name(text_section.sh_name())
@@ -484,16 +1135,41 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         }
 
         // relocate symbols
+        let data_sections = DataSections::new(elf)?;
         let mut function_registry = FunctionRegistry::default();
         Self::relocate(
             &mut function_registry,
             &loader,
             elf,
+            &text_sections,
+            &data_sections,
             elf_bytes.as_slice_mut(),
         )?;
 
+        // Broken debug information is not worth rejecting the executable for
+        #[cfg(feature = "debug-info")]
+        let debug_info = if config.enable_symbol_and_section_labels {
+            DebugInfo::parse(
+                |name| {
+                    elf.section(name)
+                        .ok()
+                        .and_then(|section_header| section_header.file_range())
+                        .and_then(|file_range| bytes.get(file_range))
+                },
+                text_section.sh_addr(),
+                text_section.sh_size() as usize,
+            )
+            .ok()
+            .flatten()
+        } else {
+            None
+        };
+
         // calculate entrypoint offset into the text section
-        let offset = elf.header().e_entry.saturating_sub(text_section.sh_addr());
+        let offset = text_sections
+            .translate(elf.header().e_entry)
+            .ok_or(ElfError::EntrypointOutOfBounds)?
+            .saturating_sub(text_section.sh_addr());
         if offset.checked_rem(ebpf::INSN_SIZE as u64) != Some(0) {
             return Err(ElfError::InvalidEntrypoint);
         }
@@ -507,23 +1183,46 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
             return Err(ElfError::InvalidEntrypoint);
         };
 
+        // The text sections were merged into the lowest one
+        let ro_sections = elf
+            .section_headers()
+            .enumerate()
+            .filter_map(|(index, section_header)| {
+                let name = elf.section_name(section_header.sh_name());
+                if !name.map(is_text_section).unwrap_or(false) {
+                    Some((name, section_header_copy(section_header)))
+                } else if index == text_sections.first_index {
+                    Some((name, text_section.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
         let ro_section = Self::parse_ro_sections(
             config,
-            elf.section_headers()
-                .map(|s| (elf.section_name(s.sh_name()), s)),
+            ro_sections
+                .iter()
+                .map(|(name, section_header)| (*name, section_header)),
             elf_bytes.as_slice(),
         )?;
+        let data_section = data_sections.to_template(elf_bytes.as_slice())?;
 
         Ok(Self {
-            _verifier: PhantomData::default(),
+            _verifier: PhantomData,
             elf_bytes,
             ro_section,
+            data_section,
             text_section_info,
             entry_pc,
+            #[cfg(feature = "debug-info")]
+            debug_info,
             function_registry,
             loader,
+            decoded_program: OnceLock::new(),
             #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
             compiled_program: None,
+            #[cfg(feature = "cranelift")]
+            cranelift_program: None,
         })
     }
 
@@ -540,6 +1239,8 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                 Section::Owned(_, data) => data.capacity(),
                 Section::Borrowed(_, _) => 0,
             })
+            // data section
+            .saturating_add(self.data_section.capacity())
             // text section info
             .saturating_add(self.text_section_info.mem_size())
             // bpf functions
@@ -551,13 +1252,19 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                 .saturating_add(mem::size_of_val(&name)
                 .saturating_add(name.capacity())))))
             // loader built-in program
-            .saturating_add(self.loader.mem_size());
+            .saturating_add(self.loader.mem_size())
+            // decoded program
+            .saturating_add(self.decoded_program.get().map_or(0, |program| program.mem_size()));
 
         #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
         {
             // compiled programs
             total = total.saturating_add(self.compiled_program.as_ref().map_or(0, |program| program.mem_size()));
         }
+        #[cfg(feature = "cranelift")]
+        {
+            total = total.saturating_add(self.cranelift_program.as_ref().map_or(0, |program| program.mem_size()));
+        }
 
         total
     }
@@ -671,34 +1378,27 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
             }
         }
 
-        let num_text_sections = elf
-            .section_headers()
-            .fold(0, |count: usize, section_header| {
-                if let Some(this_name) = elf.section_name(section_header.sh_name()) {
-                    if this_name == ".text" {
-                        return count.saturating_add(1);
-                    }
-                }
-                count
-            });
-        if 1 != num_text_sections {
-            return Err(ElfError::NotOneTextSection);
-        }
-
         for section_header in elf.section_headers() {
             if let Some(name) = elf.section_name(section_header.sh_name()) {
-                if name.starts_with(".bss")
-                    || (section_header.is_writable()
-                        && (name.starts_with(".data") && !name.starts_with(".data.rel")))
+                if name.starts_with(".bss") && !section_header.is_writable() {
+                    return Err(ElfError::BssNotSupported);
+                }
+                // Only .data and .bss are mapped in the data region, see DataSections
+                if section_header.is_writable()
+                    && section_header.sh_type() != SHT_DYNAMIC
+                    && !name.starts_with(".data.rel.ro")
+                    && !is_data_section(name)
                 {
                     return Err(ElfError::WritableSectionNotSupported(name.to_owned()));
-                } else if name == ".bss" {
-                    return Err(ElfError::BssNotSupported);
                 }
             }
         }
 
         for section_header in elf.section_headers() {
+            if section_header.sh_type() == SHT_NOBITS {
+                // .bss has no file contents
+                continue;
+            }
             let start = section_header.sh_offset() as usize;
             let end = section_header
                 .sh_offset()
@@ -708,8 +1408,11 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                 .get(start..end)
                 .ok_or(ElfError::ValueOutOfBounds)?;
         }
-        let text_section = elf.section(".text")?;
-        if !text_section.vm_range().contains(&header.e_entry) {
+        let text_sections =
+            TextSections::new(elf.section_headers().map(|section_header| {
+                (elf.section_name(section_header.sh_name()), section_header)
+            }))?;
+        if text_sections.translate(header.e_entry).is_none() {
             return Err(ElfError::EntrypointOutOfBounds);
         }
 
@@ -747,7 +1450,7 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         for (i, (name, section_header)) in sections.into_iter().enumerate() {
             match name {
                 Some(name)
-                    if name == ".text"
+                    if is_text_section(name)
                         || name == ".rodata"
                         || name == ".data.rel.ro"
                         || name == ".eh_frame" => {}
@@ -893,10 +1596,14 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
         function_registry: &mut FunctionRegistry,
         loader: &BuiltInProgram<C>,
         elf: &'a P,
+        text_sections: &TextSections,
+        data_sections: &DataSections,
         elf_bytes: &mut [u8],
     ) -> Result<(), ElfError> {
         let mut syscall_cache = BTreeMap::new();
-        let text_section = elf.section(".text")?;
+        let config = loader.get_config();
+        let text_section = &text_sections.merged;
+        text_sections.lay_out(config, elf_bytes)?;
 
         // Fixup all program counter relative call instructions
         Self::fixup_relative_calls(
@@ -907,12 +1614,13 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                 .ok_or(ElfError::ValueOutOfBounds)?,
         )?;
 
-        let config = loader.get_config();
         let mut program_header: Option<&<P as ElfParser<'a>>::ProgramHeader> = None;
 
         // Fixup all the relocations in the relocation section if exists
         for relocation in elf.dynamic_relocations() {
-            let mut r_offset = relocation.r_offset() as usize;
+            // Relocations in the text sections moved along with them
+            let r_offset = relocation.r_offset();
+            let mut r_offset = text_sections.translate(r_offset).unwrap_or(r_offset) as usize;
 
             // When config.enable_elf_vaddr=true, we allow section.sh_addr !=
             // section.sh_offset so we need to bring r_offset to the correct
@@ -959,7 +1667,8 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
 
                     // The relocated address is relative to the address of the
                     // symbol at index `r_sym`
-                    let mut addr = symbol.st_value().saturating_add(refd_addr);
+                    let addr = symbol.st_value().saturating_add(refd_addr);
+                    let mut addr = text_sections.translate(addr).unwrap_or(addr);
 
                     // The "physical address" from the VM's perspective is rooted
                     // at `MM_PROGRAM_START`. If the linker hasn't already put
@@ -969,6 +1678,9 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                         addr = ebpf::MM_PROGRAM_START.saturating_add(addr);
                     }
 
+                    // Writable sections are mapped in their own region
+                    let addr = data_sections.translate(addr);
+
                     if text_section
                         .file_range()
                         .unwrap_or_default()
@@ -1051,12 +1763,14 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                         if refd_addr == 0 {
                             return Err(ElfError::InvalidVirtualAddress(refd_addr));
                         }
+                        refd_addr = text_sections.translate(refd_addr).unwrap_or(refd_addr);
 
                         if refd_addr < ebpf::MM_PROGRAM_START {
                             // The linker hasn't already placed rodata within
                             // MM_PROGRAM_START, so we do so now
                             refd_addr = ebpf::MM_PROGRAM_START.saturating_add(refd_addr);
                         }
+                        let refd_addr = data_sections.translate(refd_addr);
 
                         // Write back the low half
                         let imm_slice = elf_bytes
@@ -1086,7 +1800,9 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                             let addr_slice = elf_bytes
                                 .get(r_offset..r_offset.saturating_add(mem::size_of::<u64>()))
                                 .ok_or(ElfError::ValueOutOfBounds)?;
-                            let mut refd_addr = LittleEndian::read_u64(addr_slice);
+                            let refd_addr = LittleEndian::read_u64(addr_slice);
+                            let mut refd_addr =
+                                text_sections.translate(refd_addr).unwrap_or(refd_addr);
                             if refd_addr < ebpf::MM_PROGRAM_START {
                                 // Not within MM_PROGRAM_START, do it now
                                 refd_addr = ebpf::MM_PROGRAM_START.saturating_add(refd_addr);
@@ -1102,13 +1818,15 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                                 .get(imm_offset..imm_offset.saturating_add(BYTE_LENGTH_IMMEDIATE))
                                 .ok_or(ElfError::ValueOutOfBounds)?;
                             let refd_addr = LittleEndian::read_u32(addr_slice) as u64;
-                            ebpf::MM_PROGRAM_START.saturating_add(refd_addr)
+                            ebpf::MM_PROGRAM_START.saturating_add(
+                                text_sections.translate(refd_addr).unwrap_or(refd_addr),
+                            )
                         };
 
                         let addr_slice = elf_bytes
                             .get_mut(r_offset..r_offset.saturating_add(mem::size_of::<u64>()))
                             .ok_or(ElfError::ValueOutOfBounds)?;
-                        LittleEndian::write_u64(addr_slice, refd_addr);
+                        LittleEndian::write_u64(addr_slice, data_sections.translate(refd_addr));
                     }
                 }
                 Some(BpfRelocationType::R_Bpf_64_32) => {
@@ -1129,10 +1847,10 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
 
                     // If the symbol is defined, this is a bpf-to-bpf call
                     let key = if symbol.is_function() && symbol.st_value() != 0 {
-                        if !text_section.vm_range().contains(&symbol.st_value()) {
-                            return Err(ElfError::ValueOutOfBounds);
-                        }
-                        let target_pc = (symbol.st_value().saturating_sub(text_section.sh_addr())
+                        let target_addr = text_sections
+                            .translate(symbol.st_value())
+                            .ok_or(ElfError::ValueOutOfBounds)?;
+                        let target_pc = (target_addr.saturating_sub(text_section.sh_addr())
                             as usize)
                             .checked_div(ebpf::INSN_SIZE)
                             .unwrap_or_default();
@@ -1172,10 +1890,10 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
                 if symbol.st_info() & 0xEF != 0x02 {
                     continue;
                 }
-                if !text_section.vm_range().contains(&symbol.st_value()) {
-                    return Err(ElfError::ValueOutOfBounds);
-                }
-                let target_pc = (symbol.st_value().saturating_sub(text_section.sh_addr()) as usize)
+                let target_addr = text_sections
+                    .translate(symbol.st_value())
+                    .ok_or(ElfError::ValueOutOfBounds)?;
+                let target_pc = (target_addr.saturating_sub(text_section.sh_addr()) as usize)
                     .checked_div(ebpf::INSN_SIZE)
                     .unwrap_or_default();
                 let name = elf
@@ -1203,6 +1921,299 @@ impl<V: Verifier, C: ContextObject> Executable<V, C> {
     }
 }
 
+/// Whether the section contains code: .text or, with -function-sections, .text.*
+fn is_text_section(name: &str) -> bool {
+    name == ".text" || name.starts_with(".text.")
+}
+
+/// Whether the section holds writable data: .data or .bss, but not .data.rel.ro
+fn is_data_section(name: &str) -> bool {
+    (name.starts_with(".data") && !name.starts_with(".data.rel.ro")) || name.starts_with(".bss")
+}
+
+/// Appends a section of a relocatable object at its alignment and returns its offset
+///
+/// NOBITS sections (.bss) are zero filled.
+fn append_section(
+    buffer: &mut Vec<u8>,
+    section_header: &SectionHeader,
+    bytes: &[u8],
+) -> Result<usize, ElfError> {
+    let alignment = section_header.sh_addralign.max(1) as usize;
+    let offset = buffer
+        .len()
+        .checked_add(alignment.saturating_sub(1))
+        .and_then(|end| end.checked_div(alignment))
+        .and_then(|slots| slots.checked_mul(alignment))
+        .ok_or(ElfError::ValueOutOfBounds)?;
+    buffer.resize(offset, 0);
+    match section_header.file_range() {
+        Some(file_range) => {
+            buffer.extend_from_slice(bytes.get(file_range).ok_or(ElfError::ValueOutOfBounds)?)
+        }
+        None => buffer.resize(offset.saturating_add(section_header.sh_size as usize), 0),
+    }
+    Ok(offset)
+}
+
+/// Copies the DWARF sections of a relocatable object and applies their relocations
+///
+/// Addresses in sections which are not part of the loaded program resolve to
+/// the end of the address space, where they match no instruction.
+#[cfg(feature = "debug-info")]
+fn relocated_debug_sections<'a>(
+    elf: &'a GoblinParser,
+    bytes: &[u8],
+    section_addresses: &BTreeMap<usize, u64>,
+) -> Result<BTreeMap<&'a str, Vec<u8>>, ElfError> {
+    let mut debug_sections = BTreeMap::new();
+    for (index, section_header) in elf.section_headers().enumerate() {
+        let name = match elf.section_name(section_header.sh_name()) {
+            Some(name) if name.starts_with(".debug_") => name,
+            _ => continue,
+        };
+        let mut section_data = section_header
+            .file_range()
+            .and_then(|file_range| bytes.get(file_range))
+            .ok_or(ElfError::ValueOutOfBounds)?
+            .to_vec();
+        for relocation in elf.section_relocations(index) {
+            let symbol = elf
+                .symbol(relocation.r_sym as Elf64Word)
+                .ok_or(ElfError::UnknownSymbol(relocation.r_sym))?;
+            let symbol_addr = match section_addresses.get(&symbol.st_shndx) {
+                Some(section_addr) => section_addr.saturating_add(symbol.st_value),
+                // References into other DWARF sections are offsets
+                None if elf
+                    .section_header(symbol.st_shndx)
+                    .map(|section_header| section_header.sh_flags & SHF_ALLOC == 0)
+                    .unwrap_or(false) =>
+                {
+                    symbol.st_value
+                }
+                None => u64::MAX,
+            };
+            let r_offset = relocation.r_offset as usize;
+            match BpfRelocationType::from_x86_relocation_type(relocation.r_type) {
+                Some(BpfRelocationType::R_Bpf_64_Abs64) => {
+                    let slice = section_data
+                        .get_mut(r_offset..r_offset.saturating_add(mem::size_of::<u64>()))
+                        .ok_or(ElfError::ValueOutOfBounds)?;
+                    let addend = LittleEndian::read_u64(slice);
+                    LittleEndian::write_u64(slice, symbol_addr.saturating_add(addend));
+                }
+                Some(BpfRelocationType::R_Bpf_64_Abs32) => {
+                    let slice = section_data
+                        .get_mut(r_offset..r_offset.saturating_add(mem::size_of::<u32>()))
+                        .ok_or(ElfError::ValueOutOfBounds)?;
+                    let addend = LittleEndian::read_u32(slice) as u64;
+                    LittleEndian::write_u32(
+                        slice,
+                        symbol_addr.saturating_add(addend).min(u32::MAX as u64) as u32,
+                    );
+                }
+                _ => {}
+            }
+        }
+        debug_sections.insert(name, section_data);
+    }
+    Ok(debug_sections)
+}
+
+/// The text sections of a shared object, laid out back to back
+///
+/// With -function-sections every function is emitted into its own
+/// `.text.<name>` section, which the linker may align and thus leave gaps in
+/// between. The loader closes these gaps by moving the text sections together
+/// in the order of their addresses, starting at the first one, so that
+/// instruction offsets into the merged text section are program counters.
+/// Addresses into the text sections have to be translated accordingly.
+struct TextSections {
+    /// The merged text section
+    merged: Elf64Shdr,
+    /// Index of the lowest text section in the section header table
+    first_index: usize,
+    /// Address range, file range and merged address of each text section
+    sections: Vec<(Range<u64>, Range<usize>, u64)>,
+}
+
+/// Copies the section header of either ELF parser
+fn section_header_copy<T: ElfSectionHeader>(section_header: &T) -> Elf64Shdr {
+    Elf64Shdr {
+        sh_name: section_header.sh_name(),
+        sh_type: section_header.sh_type(),
+        sh_flags: section_header.sh_flags(),
+        sh_addr: section_header.sh_addr(),
+        sh_offset: section_header.sh_offset(),
+        sh_size: section_header.sh_size(),
+        sh_link: 0,
+        sh_info: 0,
+        sh_addralign: 0,
+        sh_entsize: 0,
+    }
+}
+
+impl TextSections {
+    fn new<'a, T: ElfSectionHeader + 'a, S: IntoIterator<Item = (Option<&'a str>, &'a T)>>(
+        sections: S,
+    ) -> Result<Self, ElfError> {
+        let mut text_sections = Vec::new();
+        let mut other_sections = Vec::new();
+        for (index, (name, section_header)) in sections.into_iter().enumerate() {
+            if name.map(is_text_section).unwrap_or(false) {
+                text_sections.push((index, section_header));
+            } else if section_header.sh_size() > 0 {
+                other_sections.push(section_header);
+            }
+        }
+        text_sections.sort_by_key(|(_index, section_header)| {
+            (section_header.sh_addr(), section_header.sh_size())
+        });
+        let (first_index, first) = *text_sections.first().ok_or(ElfError::NotOneTextSection)?;
+        let mut merged = section_header_copy(first);
+        merged.sh_size = 0;
+        let mut layout = Vec::with_capacity(text_sections.len());
+        let mut vm_end = merged.sh_addr;
+        let mut file_end = merged.sh_offset as usize;
+        for (_index, section_header) in text_sections {
+            let vm_range = section_header.vm_range();
+            let file_range = section_header
+                .file_range()
+                .ok_or(ElfError::ValueOutOfBounds)?;
+            if vm_range.start < vm_end || file_range.start < file_end {
+                return Err(ElfError::OverlappingTextSections);
+            }
+            vm_end = vm_range.end;
+            file_end = file_range.end;
+            layout.push((
+                vm_range,
+                file_range,
+                merged.sh_addr.saturating_add(merged.sh_size),
+            ));
+            merged.sh_size = merged
+                .sh_size
+                .checked_add(section_header.sh_size())
+                .ok_or(ElfError::ValueOutOfBounds)?;
+        }
+
+        // The gaps are closed, so no other section may lie in between
+        let vm_span = merged.sh_addr..vm_end;
+        let file_span = merged.sh_offset as usize..file_end;
+        for section_header in other_sections {
+            let vm_range = section_header.vm_range();
+            if section_header.sh_flags() & SHF_ALLOC != 0
+                && vm_range.start < vm_span.end
+                && vm_span.start < vm_range.end
+            {
+                return Err(ElfError::OverlappingTextSections);
+            }
+            if let Some(file_range) = section_header.file_range() {
+                if file_range.start < file_span.end && file_span.start < file_range.end {
+                    return Err(ElfError::OverlappingTextSections);
+                }
+            }
+        }
+
+        Ok(Self {
+            merged,
+            first_index,
+            sections: layout,
+        })
+    }
+
+    /// Translates an address into one of the text sections to the merged text section
+    fn translate(&self, addr: u64) -> Option<u64> {
+        self.sections
+            .iter()
+            .find(|(vm_range, _file_range, _merged_addr)| vm_range.contains(&addr))
+            .map(|(vm_range, _file_range, merged_addr)| {
+                merged_addr.saturating_add(addr.saturating_sub(vm_range.start))
+            })
+    }
+
+    /// Moves the text sections together in `elf_bytes`
+    ///
+    /// Relative calls from one text section into another are adjusted to the
+    /// distance between the two once merged.
+    fn lay_out(&self, config: &Config, elf_bytes: &mut [u8]) -> Result<(), ElfError> {
+        let merged_offset = self.merged.sh_offset as usize;
+        if self
+            .sections
+            .iter()
+            .all(|(vm_range, file_range, merged_addr)| {
+                vm_range.start == *merged_addr
+                    && file_range.start
+                        == merged_offset
+                            .saturating_add(merged_addr.saturating_sub(self.merged.sh_addr) as usize)
+            })
+        {
+            return Ok(());
+        }
+
+        for (vm_range, file_range, merged_addr) in self.sections.iter() {
+            let section_offset = merged_addr.saturating_sub(self.merged.sh_addr) as usize;
+            let section = elf_bytes
+                .get_mut(file_range.clone())
+                .ok_or(ElfError::ValueOutOfBounds)?;
+            let first_pc = section_offset
+                .checked_div(ebpf::INSN_SIZE)
+                .ok_or(ElfError::ValueOutOfBounds)?;
+            let instruction_count = section
+                .len()
+                .checked_div(ebpf::INSN_SIZE)
+                .ok_or(ElfError::ValueOutOfBounds)?;
+            for i in 0..instruction_count {
+                let mut insn = ebpf::get_insn(section, i);
+                if insn.opc == ebpf::CALL_IMM
+                    && insn.imm != -1
+                    && !(config.static_syscalls && insn.src == 0)
+                {
+                    let pc = first_pc.saturating_add(i);
+                    let target_addr = (i as i64)
+                        .saturating_add(1)
+                        .saturating_add(insn.imm)
+                        .saturating_mul(ebpf::INSN_SIZE as i64)
+                        .saturating_add(vm_range.start as i64);
+                    let target_pc = (target_addr >= 0)
+                        .then_some(target_addr as u64)
+                        .and_then(|target_addr| self.translate(target_addr))
+                        .and_then(|target_addr| {
+                            (target_addr.saturating_sub(self.merged.sh_addr) as usize)
+                                .checked_div(ebpf::INSN_SIZE)
+                        })
+                        .ok_or(ElfError::RelativeJumpOutOfBounds(
+                            pc.saturating_add(ebpf::ELF_INSN_DUMP_OFFSET),
+                        ))?;
+                    insn.imm = (target_pc as i64)
+                        .saturating_sub(pc as i64)
+                        .saturating_sub(1);
+                    let offset = i.saturating_mul(ebpf::INSN_SIZE);
+                    section
+                        .get_mut(offset..offset.saturating_add(ebpf::INSN_SIZE))
+                        .ok_or(ElfError::ValueOutOfBounds)?
+                        .copy_from_slice(&insn.to_array());
+                }
+            }
+            elf_bytes.copy_within(
+                file_range.clone(),
+                merged_offset.saturating_add(section_offset),
+            );
+        }
+
+        // Clear what is left over behind the merged text section
+        let file_end = self
+            .sections
+            .last()
+            .map(|(_vm_range, file_range, _merged_addr)| file_range.end)
+            .unwrap_or_default();
+        elf_bytes
+            .get_mut(merged_offset.saturating_add(self.merged.sh_size as usize)..file_end)
+            .ok_or(ElfError::ValueOutOfBounds)?
+            .fill(0);
+        Ok(())
+    }
+}
+
 pub(crate) fn get_ro_region(ro_section: &Section, elf: &[u8]) -> MemoryRegion {
     let (offset, ro_data) = match ro_section {
         Section::Owned(offset, data) => (*offset, data.as_slice()),
@@ -1225,7 +2236,7 @@ mod test {
         ebpf,
         elf_parser::{
             // FIXME consts::{ELFCLASS32, ELFDATA2MSB, ET_REL},
-            consts::{ELFCLASS32, ELFDATA2MSB, ET_REL},
+            consts::{ELFCLASS32, ELFDATA2MSB, ET_REL, SHF_WRITE},
             types::{Elf64Ehdr, Elf64Shdr},
         },
         fuzz::fuzz,
@@ -2103,20 +3114,324 @@ mod test {
         );
     }
 
+    fn assert_data_address<V: Verifier, C: ContextObject>(executable: &Executable<V, C>) {
+        let (_vaddr, text_bytes) = executable.get_text_bytes();
+        let insn = ebpf::get_insn(text_bytes, 0);
+        let next_insn = ebpf::get_insn(text_bytes, 1);
+        assert_eq!(insn.opc, ebpf::LD_DW_IMM);
+        assert_eq!(
+            (insn.imm as u32 as u64) | ((next_insn.imm as u64) << 32),
+            ebpf::MM_DATA_START
+        );
+    }
+
     #[test]
-    #[should_panic(expected = r#"validation failed: WritableSectionNotSupported(".data")"#)]
     fn test_writable_data_section() {
         let elf_bytes =
             std::fs::read("tests/elfs/writable_data_section.so").expect("failed to read elf file");
-        ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        assert_eq!(executable.get_data_section(), &42u32.to_le_bytes());
+        assert_data_address(&executable);
+
+        let mut data = AlignedMemory::from_slice(&[0xFF; 8]);
+        let region = executable.get_data_region(&mut data).unwrap();
+        assert_eq!(region.vm_addr, ebpf::MM_DATA_START);
+        assert_eq!(region.len, 4);
+        assert_eq!(data.as_slice(), &42u32.to_le_bytes());
     }
 
     #[test]
-    #[should_panic(expected = r#"validation failed: WritableSectionNotSupported(".bss")"#)]
     fn test_bss_section() {
         let elf_bytes =
             std::fs::read("tests/elfs/bss_section.so").expect("failed to read elf file");
-        ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        assert_eq!(executable.get_data_section(), &[0; 4]);
+        assert_data_address(&executable);
+    }
+
+    fn patch_section_header(elf_bytes: &mut [u8], name: &str, patch: fn(&mut Elf64Shdr)) {
+        let parsed_elf = NewParser::parse(elf_bytes).unwrap();
+        let (index, section_header) = parsed_elf
+            .section_headers()
+            .enumerate()
+            .find(|(_, section_header)| {
+                parsed_elf.section_name(section_header.sh_name) == Some(name)
+            })
+            .unwrap();
+        let offset = index
+            .checked_mul(mem::size_of::<Elf64Shdr>())
+            .unwrap()
+            .saturating_add(parsed_elf.header().e_shoff as usize);
+        let mut section_header = section_header.clone();
+        patch(&mut section_header);
+        unsafe {
+            std::ptr::write_unaligned(
+                elf_bytes.as_mut_ptr().add(offset) as *mut Elf64Shdr,
+                section_header,
+            );
+        }
+    }
+
+    #[test]
+    fn test_bss_section_zero_filled() {
+        let mut elf_bytes =
+            std::fs::read("tests/elfs/bss_section.so").expect("failed to read elf file");
+        // The file bytes at the offset of .bss belong to other sections
+        patch_section_header(&mut elf_bytes, ".bss", |section_header| {
+            section_header.sh_size = 16
+        });
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        assert_eq!(executable.get_data_section(), &[0; 16]);
+    }
+
+    #[test]
+    fn test_writable_section_not_supported() {
+        let mut elf_bytes =
+            std::fs::read("tests/elfs/bss_section.so").expect("failed to read elf file");
+        patch_section_header(&mut elf_bytes, ".dynstr", |section_header| {
+            section_header.sh_flags |= SHF_WRITE
+        });
+        assert_eq!(
+            ElfExecutable::load(&elf_bytes, loader()).err(),
+            Some(ElfError::WritableSectionNotSupported(".dynstr".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_function_sections() {
+        let elf_bytes =
+            std::fs::read("tests/elfs/function_sections.so").expect("failed to read elf file");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        let (vaddr, text_bytes) = executable.get_text_bytes();
+        assert_eq!(vaddr, ebpf::MM_PROGRAM_START + 0xe8);
+        assert_eq!(text_bytes.len(), 0x80);
+        assert_eq!(executable.get_entrypoint_instruction_offset(), 8);
+        // The relative call in .text.entrypoint targets .text.function_foo
+        let call = ebpf::get_insn(text_bytes, 14);
+        assert_eq!(call.opc, ebpf::CALL_IMM);
+        assert_eq!(
+            executable.lookup_internal_function(call.imm as u32),
+            Some(0)
+        );
+
+        // The symbols of all text sections are registered
+        let mut loader = BuiltInProgram::new_loader(Config {
+            enable_symbol_and_section_labels: true,
+            ..Config::default()
+        });
+        loader
+            .register_function(b"log", syscalls::bpf_syscall_string)
+            .unwrap();
+        let executable =
+            ElfExecutable::load(&elf_bytes, Arc::new(loader)).expect("validation failed");
+        let mut functions = executable
+            .get_function_registry()
+            .values()
+            .map(|(pc, name)| (*pc, name.as_str()))
+            .collect::<Vec<_>>();
+        functions.sort();
+        assert_eq!(functions, [(0, "function_foo"), (8, "entrypoint")]);
+    }
+
+    #[test]
+    fn test_function_sections_with_gap() {
+        let elf_bytes =
+            std::fs::read("tests/elfs/function_sections.so").expect("failed to read elf file");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        let (_vaddr, text_bytes) = executable.get_text_bytes();
+        let entrypoint_bytes = text_bytes[0x40..].to_vec();
+
+        // Leave a gap of one instruction between the two text sections
+        let mut elf_bytes = elf_bytes;
+        patch_section_header(&mut elf_bytes, ".text.function_foo", |section_header| {
+            section_header.sh_size -= 8
+        });
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        let (vaddr, text_bytes) = executable.get_text_bytes();
+        assert_eq!(vaddr, ebpf::MM_PROGRAM_START + 0xe8);
+        assert_eq!(text_bytes.len(), 0x78);
+        assert_eq!(executable.get_entrypoint_instruction_offset(), 7);
+        assert_eq!(text_bytes[0x38..], entrypoint_bytes);
+        let call = ebpf::get_insn(text_bytes, 13);
+        assert_eq!(call.opc, ebpf::CALL_IMM);
+        assert_eq!(
+            executable.lookup_internal_function(call.imm as u32),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_function_sections_long_names() {
+        // Only the names of text sections may have 16 bytes or more
+        let mut elf_bytes =
+            std::fs::read("tests/elfs/function_sections.so").expect("failed to read elf file");
+        let parsed_elf = NewParser::parse(&elf_bytes).unwrap();
+        assert!(parsed_elf.section_headers().any(|section_header| parsed_elf
+            .section_name(section_header.sh_name)
+            == Some(".text.function_foo")));
+        let offset = elf_bytes
+            .windows(b".text.function_foo\0".len())
+            .position(|window| window == b".text.function_foo\0")
+            .unwrap();
+        elf_bytes[offset..offset.saturating_add(b".text.".len())].copy_from_slice(b".data.");
+        assert_eq!(
+            ElfExecutable::load(&elf_bytes, loader()).err(),
+            Some(ElfError::FailedToParse("invalid string".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_function_sections_overlapping() {
+        let mut elf_bytes =
+            std::fs::read("tests/elfs/function_sections.so").expect("failed to read elf file");
+        patch_section_header(&mut elf_bytes, ".text.entrypoint", |section_header| {
+            section_header.sh_addr += 0x40
+        });
+        assert_eq!(
+            ElfExecutable::load(&elf_bytes, loader()).err(),
+            Some(ElfError::OverlappingTextSections)
+        );
+    }
+
+    #[test]
+    fn test_text_sections_lay_out() {
+        let config = Config::default();
+        let function_foo = new_section(0, 16);
+        let entrypoint = new_section(24, 16);
+        let text_sections = TextSections::new([
+            (Some(".text.function_foo"), &function_foo),
+            (Some(".text.entrypoint"), &entrypoint),
+        ])
+        .unwrap();
+        assert_eq!(text_sections.merged.sh_size, 32);
+        assert_eq!(text_sections.translate(8), Some(8));
+        assert_eq!(text_sections.translate(16), None);
+        assert_eq!(text_sections.translate(32), Some(24));
+
+        // The call in .text.entrypoint skips the gap to reach .text.function_foo
+        #[rustfmt::skip]
+        let mut elf_bytes = vec![
+            0xb7, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x85, 0x10, 0x00, 0x00, 0xfc, 0xff, 0xff, 0xff,
+            0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        text_sections.lay_out(&config, &mut elf_bytes).unwrap();
+        #[rustfmt::skip]
+        assert_eq!(elf_bytes, [
+            0xb7, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x85, 0x10, 0x00, 0x00, 0xfd, 0xff, 0xff, 0xff,
+            0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_load_relocatable() {
+        let object_bytes =
+            std::fs::read("tests/elfs/relocatable.o").expect("failed to read object file");
+        let loader = Arc::new(BuiltInProgram::new_loader(Config {
+            enable_symbol_and_section_labels: true,
+            ..Config::default()
+        }));
+        let executables =
+            ElfExecutable::load_relocatable(&object_bytes, loader).expect("validation failed");
+        let names = executables
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["xdp", ".classifier"]);
+
+        // .text follows the program and .rodata follows .text
+        let executable = &executables[0].1;
+        let (vaddr, text_bytes) = executable.get_text_bytes();
+        assert_eq!(vaddr, ebpf::MM_PROGRAM_START);
+        assert_eq!(text_bytes.len(), 0x58);
+        assert_eq!(executable.get_ro_region().len, 0x68);
+        let mut lddw = ebpf::get_insn(text_bytes, 0);
+        ebpf::augment_lddw_unchecked(text_bytes, &mut lddw);
+        assert_eq!(lddw.imm as u64, ebpf::MM_PROGRAM_START + 0x58 + 8);
+        let call = ebpf::get_insn(text_bytes, 3);
+        assert_eq!(
+            executable.lookup_internal_function(call.imm as u32),
+            Some(5)
+        );
+
+        // .data is mapped at MM_DATA_START, the calls target the appended .text
+        let executable = &executables[1].1;
+        let (_vaddr, text_bytes) = executable.get_text_bytes();
+        let mut lddw = ebpf::get_insn(text_bytes, 0);
+        ebpf::augment_lddw_unchecked(text_bytes, &mut lddw);
+        assert_eq!(lddw.imm as u64, ebpf::MM_DATA_START);
+        assert_eq!(executable.get_data_section(), &5u64.to_le_bytes());
+        let mut functions = executable
+            .get_function_registry()
+            .values()
+            .map(|(pc, name)| (*pc, name.as_str()))
+            .collect::<Vec<_>>();
+        functions.sort();
+        assert_eq!(
+            functions,
+            [
+                (0, "prog_classifier"),
+                (10, "double_it"),
+                (13, "function_13")
+            ]
+        );
+    }
+
+    #[cfg(feature = "debug-info")]
+    #[test]
+    fn test_load_relocatable_debug_info() {
+        let object_bytes =
+            std::fs::read("tests/elfs/debug_info.o").expect("failed to read object file");
+        let executables =
+            ElfExecutable::load_relocatable(&object_bytes, loader()).expect("validation failed");
+        assert!(executables[0].1.get_debug_info().is_none());
+
+        let loader = Arc::new(BuiltInProgram::new_loader(Config {
+            enable_symbol_and_section_labels: true,
+            ..Config::default()
+        }));
+        let executables =
+            ElfExecutable::load_relocatable(&object_bytes, loader).expect("validation failed");
+        let debug_info = executables[0].1.get_debug_info().unwrap();
+        // prog in xdp is followed by triple in .text
+        let locations = (0..7)
+            .map(|pc| {
+                let location = debug_info.lookup(pc).unwrap();
+                (location.line, location.function.unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locations,
+            [
+                (10, "prog"),
+                (11, "prog"),
+                (11, "prog"),
+                (11, "prog"),
+                (5, "triple"),
+                (6, "triple"),
+                (6, "triple"),
+            ]
+        );
+        assert_eq!(debug_info.lookup(0).unwrap().to_string(), "debug_info.c:10");
+        assert!(debug_info.lookup(7).is_none());
+        assert_eq!(debug_info.line_table().len(), 7);
+    }
+
+    #[test]
+    fn test_load_relocatable_wrong_type() {
+        let mut object_bytes =
+            std::fs::read("tests/elfs/relocatable.o").expect("failed to read object file");
+        let mut header =
+            unsafe { std::ptr::read_unaligned(object_bytes.as_ptr() as *const Elf64Ehdr) };
+        header.e_type = ET_DYN;
+        unsafe { std::ptr::write_unaligned(object_bytes.as_mut_ptr() as *mut Elf64Ehdr, header) };
+        assert_eq!(
+            ElfExecutable::load_relocatable(&object_bytes, loader()).err(),
+            Some(ElfError::WrongType)
+        );
     }
 
     #[test]
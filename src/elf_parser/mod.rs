@@ -10,6 +10,8 @@ use {consts::*, types::*};
 
 const SECTION_NAME_LENGTH_MAXIMUM: usize = 16;
 const SYMBOL_NAME_LENGTH_MAXIMUM: usize = 1024;
+// Fits the ".text.<symbol>" sections emitted with -function-sections
+const TEXT_SECTION_NAME_LENGTH_MAXIMUM: usize = ".text.".len() + SYMBOL_NAME_LENGTH_MAXIMUM;
 
 /// Error definitions
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
@@ -247,15 +249,8 @@ impl<'a> Elf64<'a> {
                 }
             }
         }
-        let section_names_section_header = self
-            .section_names_section_header
-            .ok_or(ElfParserError::NoSectionNameStringTable)?;
         for section_header in self.section_header_table.iter() {
-            let section_name = self.get_string_in_section(
-                section_names_section_header,
-                section_header.sh_name,
-                SECTION_NAME_LENGTH_MAXIMUM,
-            )?;
+            let section_name = self.section_name(section_header.sh_name)?;
             section_header_by_name!(
                 self, section_header, section_name,
                 ".symtab" => symbol_section_header,
@@ -401,13 +396,20 @@ impl<'a> Elf64<'a> {
     }
 
     /// Returns the string corresponding to the given `sh_name`
+    ///
+    /// Only the names of text sections may be longer than `SECTION_NAME_LENGTH_MAXIMUM`.
     pub fn section_name(&self, sh_name: Elf64Word) -> Result<&'a str, ElfParserError> {
-        self.get_string_in_section(
+        let section_name = self.get_string_in_section(
             self.section_names_section_header
                 .ok_or(ElfParserError::NoSectionNameStringTable)?,
             sh_name,
-            SECTION_NAME_LENGTH_MAXIMUM,
-        )
+            TEXT_SECTION_NAME_LENGTH_MAXIMUM,
+        )?;
+        if section_name.len() >= SECTION_NAME_LENGTH_MAXIMUM && !section_name.starts_with(".text.")
+        {
+            return Err(ElfParserError::InvalidString);
+        }
+        Ok(section_name)
     }
 
     /// Returns the name of the `st_name` symbol
@@ -504,13 +506,7 @@ impl<'a> fmt::Debug for Elf64<'a> {
             writeln!(f, "{program_header:#X?}")?;
         }
         for section_header in self.section_header_table.iter() {
-            let section_name = self
-                .get_string_in_section(
-                    self.section_names_section_header.unwrap(),
-                    section_header.sh_name,
-                    SECTION_NAME_LENGTH_MAXIMUM,
-                )
-                .unwrap();
+            let section_name = self.section_name(section_header.sh_name).unwrap();
             writeln!(f, "{section_name}")?;
             writeln!(f, "{section_header:#X?}")?;
         }
@@ -252,6 +252,35 @@ impl<'a> ElfParser<'a> for GoblinParser<'a> {
     }
 }
 
+impl<'a> GoblinParser<'a> {
+    /// Returns the section header at the given `index`.
+    pub fn section_header(&self, index: usize) -> Option<&SectionHeader> {
+        self.elf.section_headers.get(index)
+    }
+
+    /// Returns the symbol at the given `index` of the symbol table.
+    pub fn symbol(&self, index: Elf64Word) -> Option<Sym> {
+        self.elf.syms.get(index as usize)
+    }
+
+    /// Returns the relocations which apply to the section at `section_index`.
+    ///
+    /// Only relocatable objects (ET_REL) carry such per section relocations.
+    pub fn section_relocations(&'a self, section_index: usize) -> impl Iterator<Item = Reloc> + 'a {
+        self.elf
+            .shdr_relocs
+            .iter()
+            .filter(move |(index, _)| {
+                self.elf
+                    .section_headers
+                    .get(*index)
+                    .map(|section_header| section_header.sh_info as usize)
+                    == Some(section_index)
+            })
+            .flat_map(|(_, relocations)| relocations.iter())
+    }
+}
+
 impl From<Header> for Elf64Ehdr {
     fn from(h: Header) -> Self {
         Elf64Ehdr {
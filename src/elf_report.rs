@@ -0,0 +1,619 @@
+//! Readelf style report of an ELF and of what the loader makes of it
+
+use crate::{
+    aligned_memory::AlignedMemory,
+    ebpf::{self, EF_SBF_V2, HOST_ALIGN, INSN_SIZE},
+    elf::{BpfRelocationType, ElfError, Executable, Section, BYTE_OFFSET_IMMEDIATE},
+    elf_parser::{
+        consts::{
+            EM_BPF, EM_SBF, ET_DYN, ET_EXEC, ET_REL, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD,
+            PT_NULL, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_DYNAMIC, SHT_DYNSYM, SHT_HASH,
+            SHT_NOBITS, SHT_NULL, SHT_PROGBITS, SHT_REL, SHT_RELA, SHT_STRTAB, SHT_SYMTAB,
+            STT_FUNC, STT_NOTYPE, STT_OBJECT, STT_SECTION,
+        },
+        types::{Elf64Ehdr, Elf64Phdr, Elf64Rel, Elf64Shdr, Elf64Sym},
+        Elf64,
+    },
+    elf_parser_glue::{ElfProgramHeader, ElfRelocation},
+    verifier::TautologyVerifier,
+    vm::{BuiltInProgram, ContextObject, FunctionRegistry},
+};
+use byteorder::{ByteOrder, LittleEndian};
+use std::{fmt, io::Write, mem, ops::Range, sync::Arc};
+
+/// Section header and name
+#[derive(Clone, Debug)]
+pub struct SectionReport {
+    /// Name of the section
+    pub name: String,
+    /// Section header
+    pub header: Elf64Shdr,
+}
+
+/// Dynamic symbol and name
+#[derive(Clone, Debug)]
+pub struct SymbolReport {
+    /// Name of the symbol
+    pub name: String,
+    /// Symbol table entry
+    pub symbol: Elf64Sym,
+}
+
+/// What the loader resolved a relocation to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelocationTarget {
+    /// Virtual address written into a lddw instruction or a data word
+    Address(u64),
+    /// Call of the function registered at pc with the given name
+    Function(usize, String),
+    /// Call of the syscall with the given hash and, if the loader knows it, name
+    Syscall(u32, Option<String>),
+}
+
+impl fmt::Display for RelocationTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(address) => write!(f, "{address:#x}"),
+            Self::Function(pc, name) => write!(
+                f,
+                "function {name} at #{}",
+                pc.saturating_add(ebpf::ELF_INSN_DUMP_OFFSET)
+            ),
+            Self::Syscall(hash, Some(name)) => write!(f, "syscall {name} ({hash:#x})"),
+            Self::Syscall(hash, None) => write!(f, "unresolved syscall ({hash:#x})"),
+        }
+    }
+}
+
+/// Dynamic relocation, symbol and resolved target
+#[derive(Clone, Debug)]
+pub struct RelocationReport {
+    /// Offset (or virtual address if config.enable_elf_vaddr=true) to relocate
+    pub offset: u64,
+    /// Raw relocation type
+    pub r_type: u32,
+    /// Name of the referenced symbol, if any
+    pub symbol: Option<String>,
+    /// Resolved target, `None` if the executable failed to load
+    pub target: Option<RelocationTarget>,
+}
+
+impl RelocationReport {
+    /// Name of the relocation type, eg "R_BPF_64_32"
+    pub fn type_name(&self) -> String {
+        match BpfRelocationType::from_x86_relocation_type(self.r_type) {
+            Some(relocation_type) => format!("{relocation_type:?}").to_uppercase(),
+            None => format!("UNKNOWN({})", self.r_type),
+        }
+    }
+}
+
+/// Layout of the read-only region which the loader maps at `ebpf::MM_PROGRAM_START`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoRegionReport {
+    /// Virtual address of the first mapped byte
+    pub vm_addr: u64,
+    /// Length of the region in bytes
+    pub len: usize,
+    /// Range in the ELF if the region is borrowed from it, `None` if it is a copy
+    pub file_range: Option<Range<usize>>,
+}
+
+/// Everything the loader sees of an ELF
+///
+/// The tables are reported as parsed by `elf_parser::Elf64`. The read-only
+/// region and the function registry are what `Executable::load()` makes of
+/// them, or the `ElfError` it fails with.
+#[derive(Debug)]
+pub struct ElfReport {
+    /// File header
+    pub file_header: Elf64Ehdr,
+    /// Program header table
+    pub program_headers: Vec<Elf64Phdr>,
+    /// Section header table
+    pub sections: Vec<SectionReport>,
+    /// Dynamic symbol table
+    pub dynamic_symbols: Vec<SymbolReport>,
+    /// Dynamic relocations
+    pub relocations: Vec<RelocationReport>,
+    /// Layout of the read-only region
+    pub ro_region: Result<RoRegionReport, ElfError>,
+    /// Function registry of the loaded executable
+    pub function_registry: Result<FunctionRegistry, ElfError>,
+}
+
+impl ElfReport {
+    /// Parses and loads the ELF with the config and syscalls of `loader`
+    ///
+    /// Fails only if the ELF can not be parsed at all.
+    pub fn new<C: ContextObject>(
+        bytes: &[u8],
+        loader: Arc<BuiltInProgram<C>>,
+    ) -> Result<Self, ElfError> {
+        // The parser creates references into the bytes, so they must be aligned
+        let aligned = AlignedMemory::<{ HOST_ALIGN }>::from_slice(bytes);
+        let bytes = aligned.as_slice();
+        let elf = Elf64::parse(bytes)?;
+        let config = loader.get_config();
+
+        let sections = elf
+            .section_header_table()
+            .iter()
+            .map(|header| SectionReport {
+                name: elf
+                    .section_name(header.sh_name)
+                    .unwrap_or_default()
+                    .to_string(),
+                header: header.clone(),
+            })
+            .collect();
+        let dynamic_symbols = elf
+            .dynamic_symbol_table()
+            .unwrap_or_default()
+            .iter()
+            .map(|symbol| SymbolReport {
+                name: elf
+                    .dynamic_symbol_name(symbol.st_name)
+                    .unwrap_or_default()
+                    .to_string(),
+                symbol: symbol.clone(),
+            })
+            .collect();
+        let ro_region = Executable::<TautologyVerifier, C>::parse_ro_sections(
+            config,
+            elf.section_header_table()
+                .iter()
+                .map(|header| (elf.section_name(header.sh_name).ok(), header)),
+            bytes,
+        )
+        .map(|section| {
+            let (offset, len, file_range) = match section {
+                Section::Owned(offset, data) => (offset, data.len(), None),
+                Section::Borrowed(offset, file_range) => {
+                    (offset, file_range.len(), Some(file_range))
+                }
+            };
+            RoRegionReport {
+                vm_addr: ebpf::MM_PROGRAM_START.saturating_add(offset as u64),
+                len,
+                file_range,
+            }
+        });
+
+        let executable = Executable::<TautologyVerifier, C>::load(bytes, loader.clone());
+        let relocations = elf
+            .dynamic_relocations_table()
+            .unwrap_or_default()
+            .iter()
+            .map(|relocation| RelocationReport {
+                offset: relocation.r_offset,
+                r_type: relocation.r_type(),
+                symbol: elf
+                    .dynamic_symbol_table()
+                    .and_then(|table| table.get(relocation.r_sym() as usize))
+                    .filter(|symbol| symbol.st_name != 0)
+                    .and_then(|symbol| elf.dynamic_symbol_name(symbol.st_name).ok())
+                    .map(str::to_string),
+                target: executable
+                    .as_ref()
+                    .ok()
+                    .and_then(|executable| relocation_target(&elf, executable, relocation)),
+            })
+            .collect();
+
+        Ok(Self {
+            file_header: elf.file_header().clone(),
+            program_headers: elf.program_header_table().to_vec(),
+            sections,
+            dynamic_symbols,
+            relocations,
+            ro_region,
+            function_registry: executable
+                .map(|executable| executable.get_function_registry().clone()),
+        })
+    }
+
+    /// Writes the report in a human readable format, similar to `readelf -a`
+    pub fn write_text<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let header = &self.file_header;
+        writeln!(output, "ELF Header:")?;
+        writeln!(output, "  Type:    {}", elf_type_name(header.e_type))?;
+        writeln!(output, "  Machine: {}", machine_name(header.e_machine))?;
+        writeln!(output, "  Flags:   {:#x}", header.e_flags)?;
+        writeln!(output, "  Entry:   {:#x}", header.e_entry)?;
+
+        writeln!(output, "\nProgram Headers:")?;
+        writeln!(
+            output,
+            "  {:<8} {:>10} {:>18} {:>10} {:>10} Flg",
+            "Type", "Offset", "VirtAddr", "FileSiz", "MemSiz"
+        )?;
+        for program_header in self.program_headers.iter() {
+            writeln!(
+                output,
+                "  {:<8} {:>#10x} {:>#18x} {:>#10x} {:>#10x} {}",
+                program_header_type_name(program_header.p_type),
+                program_header.p_offset,
+                program_header.p_vaddr,
+                program_header.p_filesz,
+                program_header.p_memsz,
+                program_header_flags(program_header.p_flags),
+            )?;
+        }
+
+        writeln!(output, "\nSections:")?;
+        writeln!(
+            output,
+            "  [Nr] {:<20} {:<10} {:>18} {:>10} {:>10} Flg",
+            "Name", "Type", "Address", "Offset", "Size"
+        )?;
+        for (index, section) in self.sections.iter().enumerate() {
+            writeln!(
+                output,
+                "  [{:>2}] {:<20} {:<10} {:>#18x} {:>#10x} {:>#10x} {}",
+                index,
+                section.name,
+                section_type_name(section.header.sh_type),
+                section.header.sh_addr,
+                section.header.sh_offset,
+                section.header.sh_size,
+                section_flags(section.header.sh_flags),
+            )?;
+        }
+
+        writeln!(output, "\nDynamic Symbols:")?;
+        writeln!(
+            output,
+            "  Num: {:>18} {:>6} {:<7} {:<6} {:>5} Name",
+            "Value", "Size", "Type", "Bind", "Ndx"
+        )?;
+        for (index, symbol) in self.dynamic_symbols.iter().enumerate() {
+            writeln!(
+                output,
+                "  {:>3}: {:>#18x} {:>6} {:<7} {:<6} {:>5} {}",
+                index,
+                symbol.symbol.st_value,
+                symbol.symbol.st_size,
+                symbol_type_name(symbol.symbol.st_info),
+                symbol_binding_name(symbol.symbol.st_info),
+                symbol.symbol.st_shndx,
+                symbol.name,
+            )?;
+        }
+
+        writeln!(output, "\nRelocations:")?;
+        writeln!(
+            output,
+            "  {:>18} {:<20} {:<24} Target",
+            "Offset", "Type", "Symbol"
+        )?;
+        for relocation in self.relocations.iter() {
+            writeln!(
+                output,
+                "  {:>#18x} {:<20} {:<24} {}",
+                relocation.offset,
+                relocation.type_name(),
+                relocation.symbol.as_deref().unwrap_or("-"),
+                relocation
+                    .target
+                    .as_ref()
+                    .map(|target| target.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            )?;
+        }
+
+        writeln!(output, "\nRead-only Region:")?;
+        match &self.ro_region {
+            Ok(ro_region) => {
+                write!(
+                    output,
+                    "  {:#x}..{:#x} ({:#x} bytes), ",
+                    ro_region.vm_addr,
+                    ro_region.vm_addr.saturating_add(ro_region.len as u64),
+                    ro_region.len,
+                )?;
+                match &ro_region.file_range {
+                    Some(file_range) => writeln!(
+                        output,
+                        "borrowed from file offsets {:#x}..{:#x}",
+                        file_range.start, file_range.end
+                    )?,
+                    None => writeln!(output, "copied")?,
+                }
+            }
+            Err(err) => writeln!(output, "  Error: {err}")?,
+        }
+
+        writeln!(output, "\nFunction Registry:")?;
+        match &self.function_registry {
+            Ok(function_registry) => {
+                writeln!(output, "  {:>10} {:>8} Name", "Hash", "Insn")?;
+                for (hash, (pc, name)) in function_registry.iter() {
+                    writeln!(
+                        output,
+                        "  {:>#10x} {:>8} {}",
+                        hash,
+                        format!("#{}", pc.saturating_add(ebpf::ELF_INSN_DUMP_OFFSET)),
+                        name
+                    )?;
+                }
+            }
+            Err(err) => writeln!(output, "  Error: {err}")?,
+        }
+        Ok(())
+    }
+
+    /// Writes the report as a JSON object (feature "json")
+    ///
+    /// Headers and tables use the raw numeric ELF values, errors are reported
+    /// as `{"error": "..."}` in place of the section which failed.
+    #[cfg(feature = "json")]
+    pub fn write_json<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let header = &self.file_header;
+        let program_headers = self
+            .program_headers
+            .iter()
+            .map(|program_header| {
+                json::object! {
+                    "type": program_header.p_type,
+                    "flags": program_header.p_flags,
+                    "offset": program_header.p_offset,
+                    "vaddr": program_header.p_vaddr,
+                    "paddr": program_header.p_paddr,
+                    "filesz": program_header.p_filesz,
+                    "memsz": program_header.p_memsz,
+                    "align": program_header.p_align,
+                }
+            })
+            .collect::<Vec<_>>();
+        let sections = self
+            .sections
+            .iter()
+            .map(|section| {
+                json::object! {
+                    "name": section.name.as_str(),
+                    "type": section.header.sh_type,
+                    "flags": section.header.sh_flags,
+                    "addr": section.header.sh_addr,
+                    "offset": section.header.sh_offset,
+                    "size": section.header.sh_size,
+                    "link": section.header.sh_link,
+                    "info": section.header.sh_info,
+                    "addralign": section.header.sh_addralign,
+                    "entsize": section.header.sh_entsize,
+                }
+            })
+            .collect::<Vec<_>>();
+        let dynamic_symbols = self
+            .dynamic_symbols
+            .iter()
+            .map(|symbol| {
+                json::object! {
+                    "name": symbol.name.as_str(),
+                    "value": symbol.symbol.st_value,
+                    "size": symbol.symbol.st_size,
+                    "info": symbol.symbol.st_info,
+                    "other": symbol.symbol.st_other,
+                    "shndx": symbol.symbol.st_shndx,
+                }
+            })
+            .collect::<Vec<_>>();
+        let relocations = self
+            .relocations
+            .iter()
+            .map(|relocation| {
+                let target = match &relocation.target {
+                    Some(RelocationTarget::Address(address)) => json::object! {
+                        "address": *address,
+                    },
+                    Some(RelocationTarget::Function(pc, name)) => json::object! {
+                        "function": name.as_str(),
+                        "pc": *pc,
+                    },
+                    Some(RelocationTarget::Syscall(hash, name)) => json::object! {
+                        "syscall": name.as_deref(),
+                        "hash": *hash,
+                    },
+                    None => json::JsonValue::Null,
+                };
+                json::object! {
+                    "offset": relocation.offset,
+                    "type": relocation.r_type,
+                    "type_name": relocation.type_name(),
+                    "symbol": relocation.symbol.as_deref(),
+                    "target": target,
+                }
+            })
+            .collect::<Vec<_>>();
+        let ro_region = match &self.ro_region {
+            Ok(ro_region) => json::object! {
+                "vm_addr": ro_region.vm_addr,
+                "len": ro_region.len,
+                "file_range": ro_region
+                    .file_range
+                    .as_ref()
+                    .map(|file_range| vec![file_range.start, file_range.end]),
+            },
+            Err(err) => json_error(err),
+        };
+        let function_registry = match &self.function_registry {
+            Ok(function_registry) => function_registry
+                .iter()
+                .map(|(hash, (pc, name))| {
+                    json::object! {
+                        "hash": *hash,
+                        "pc": *pc,
+                        "name": name.as_str(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .into(),
+            Err(err) => json_error(err),
+        };
+        let report = json::object! {
+            "file_header": {
+                "type": header.e_type,
+                "machine": header.e_machine,
+                "flags": header.e_flags,
+                "entry": header.e_entry,
+                "program_header_offset": header.e_phoff,
+                "section_header_offset": header.e_shoff,
+                "section_name_index": header.e_shstrndx,
+            },
+            "program_headers": program_headers,
+            "sections": sections,
+            "dynamic_symbols": dynamic_symbols,
+            "relocations": relocations,
+            "ro_region": ro_region,
+            "function_registry": function_registry,
+        };
+        report.write_pretty(output, 2)?;
+        writeln!(output)
+    }
+}
+
+/// Reads back what the loader wrote at the location of a relocation
+fn relocation_target<C: ContextObject>(
+    elf: &Elf64,
+    executable: &Executable<TautologyVerifier, C>,
+    relocation: &Elf64Rel,
+) -> Option<RelocationTarget> {
+    let relocated_bytes = executable.get_elf_bytes();
+    let mut r_offset = relocation.r_offset as usize;
+    if executable.get_config().enable_elf_vaddr {
+        let program_header = elf
+            .program_header_table()
+            .iter()
+            .find(|header| header.vm_range().contains(&relocation.r_offset))?;
+        r_offset = r_offset
+            .saturating_sub(program_header.p_vaddr as usize)
+            .saturating_add(program_header.p_offset as usize);
+    }
+    let text_offset = executable.get_text_section_offset() as usize;
+    let in_text_section = (text_offset
+        ..text_offset.saturating_add(executable.get_text_bytes().1.len()))
+        .contains(&r_offset);
+    let read_u32 = |offset: usize| {
+        relocated_bytes
+            .get(offset..offset.saturating_add(mem::size_of::<u32>()))
+            .map(LittleEndian::read_u32)
+    };
+    let relocation_type = BpfRelocationType::from_x86_relocation_type(relocation.r_type())?;
+    match relocation_type {
+        BpfRelocationType::R_Bpf_64_64 | BpfRelocationType::R_Bpf_64_Relative => {
+            // Same distinction between lddw instructions and data words as in relocate()
+            if in_text_section
+                || (relocation_type == BpfRelocationType::R_Bpf_64_64
+                    && elf.file_header().e_flags != EF_SBF_V2)
+            {
+                let imm_offset = r_offset.saturating_add(BYTE_OFFSET_IMMEDIATE);
+                let low = read_u32(imm_offset)? as u64;
+                let high = read_u32(imm_offset.saturating_add(INSN_SIZE))? as u64;
+                Some(RelocationTarget::Address(
+                    high.checked_shl(32).unwrap_or_default() | low,
+                ))
+            } else {
+                relocated_bytes
+                    .get(r_offset..r_offset.saturating_add(mem::size_of::<u64>()))
+                    .map(|bytes| RelocationTarget::Address(LittleEndian::read_u64(bytes)))
+            }
+        }
+        BpfRelocationType::R_Bpf_64_32 => {
+            let key = read_u32(r_offset.saturating_add(BYTE_OFFSET_IMMEDIATE))?;
+            Some(match executable.get_function_registry().get(&key) {
+                Some((pc, name)) => RelocationTarget::Function(*pc, name.clone()),
+                None => RelocationTarget::Syscall(
+                    key,
+                    executable
+                        .get_loader()
+                        .lookup_function(key)
+                        .map(|(name, _function)| String::from_utf8_lossy(name).into_owned()),
+                ),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn elf_type_name(e_type: u16) -> String {
+    match e_type {
+        ET_REL => "REL".to_string(),
+        ET_EXEC => "EXEC".to_string(),
+        ET_DYN => "DYN".to_string(),
+        _ => format!("{e_type:#x}"),
+    }
+}
+
+fn machine_name(e_machine: u16) -> String {
+    match e_machine {
+        EM_BPF => "BPF".to_string(),
+        EM_SBF => "SBF".to_string(),
+        _ => format!("{e_machine:#x}"),
+    }
+}
+
+fn program_header_type_name(p_type: u32) -> String {
+    match p_type {
+        PT_NULL => "NULL".to_string(),
+        PT_LOAD => "LOAD".to_string(),
+        PT_DYNAMIC => "DYNAMIC".to_string(),
+        _ => format!("{p_type:#x}"),
+    }
+}
+
+fn program_header_flags(p_flags: u32) -> String {
+    [(PF_R, 'R'), (PF_W, 'W'), (PF_X, 'E')]
+        .iter()
+        .map(|(flag, letter)| if p_flags & flag != 0 { *letter } else { ' ' })
+        .collect()
+}
+
+fn section_type_name(sh_type: u32) -> String {
+    match sh_type {
+        SHT_NULL => "NULL".to_string(),
+        SHT_PROGBITS => "PROGBITS".to_string(),
+        SHT_SYMTAB => "SYMTAB".to_string(),
+        SHT_STRTAB => "STRTAB".to_string(),
+        SHT_RELA => "RELA".to_string(),
+        SHT_HASH => "HASH".to_string(),
+        SHT_DYNAMIC => "DYNAMIC".to_string(),
+        SHT_NOBITS => "NOBITS".to_string(),
+        SHT_REL => "REL".to_string(),
+        SHT_DYNSYM => "DYNSYM".to_string(),
+        _ => format!("{sh_type:#x}"),
+    }
+}
+
+fn section_flags(sh_flags: u64) -> String {
+    [(SHF_WRITE, 'W'), (SHF_ALLOC, 'A'), (SHF_EXECINSTR, 'X')]
+        .iter()
+        .filter(|(flag, _letter)| sh_flags & flag != 0)
+        .map(|(_flag, letter)| *letter)
+        .collect()
+}
+
+fn symbol_type_name(st_info: u8) -> String {
+    match st_info & 0xF {
+        STT_NOTYPE => "NOTYPE".to_string(),
+        STT_OBJECT => "OBJECT".to_string(),
+        STT_FUNC => "FUNC".to_string(),
+        STT_SECTION => "SECTION".to_string(),
+        symbol_type => symbol_type.to_string(),
+    }
+}
+
+fn symbol_binding_name(st_info: u8) -> String {
+    match st_info.checked_shr(4).unwrap_or_default() {
+        0 => "LOCAL".to_string(),
+        1 => "GLOBAL".to_string(),
+        2 => "WEAK".to_string(),
+        binding => binding.to_string(),
+    }
+}
+
+#[cfg(feature = "json")]
+fn json_error(err: &ElfError) -> json::JsonValue {
+    json::object! {
+        "error": err.to_string(),
+    }
+}
@@ -86,4 +86,45 @@ pub enum EbpfError {
     /// Verifier error
     #[error("Verifier error: {0}")]
     VerifierError(#[from] VerifierError),
+    /// Syscall requested to suspend the program
+    #[error("syscall requested to yield")]
+    SyscallYield,
+    /// Suspended state does not fit the executable
+    #[error("invalid suspended state")]
+    InvalidSuspendedState,
+    /// Snapshot is malformed or does not fit the memory regions
+    #[error("invalid snapshot")]
+    InvalidSnapshot,
+    /// Snapshot of a VM whose last execution was neither suspended nor captured a Backtrace
+    #[error("program is not suspended")]
+    NotSuspended,
+    /// The sum of the instruction costs exceeds what the JIT can meter
+    #[error("instruction costs overflow the JIT instruction meter at BPF instruction #{0}")]
+    InstructionCostOverflow(usize),
+    /// Trace is malformed or could not be read
+    #[error("invalid trace")]
+    InvalidTrace,
+    /// Replayed execution does not match the trace
+    #[error("replay diverged from the trace at record #{0}")]
+    TraceDivergence(usize),
+    /// Syscall failed while the trace was recorded
+    #[error("syscall failed during the recording: {0}")]
+    ReplayedSyscallError(String),
+    /// Recording in the JIT needs config.enable_instruction_tracing
+    #[error("recording in the JIT needs instruction tracing")]
+    UnsupportedRecording,
+    /// Load from stack or heap memory which was never written (config.enable_memory_sanitizer)
+    #[error(
+        "Uninitialized read in {3} section at address {1:#x} of size {2:?} at BPF instruction #{0}"
+    )]
+    UninitializedRead(usize, u64, u64, &'static str),
+    /// Cranelift could not compile the program (feature "cranelift")
+    #[error("Cranelift compilation failed: {0}")]
+    CraneliftCompilationFailed(String),
+    /// Serialized JIT compiled program is malformed or does not fit the executable
+    #[error("invalid JIT cache")]
+    InvalidJitCache,
+    /// Writing the perf map failed
+    #[error("JIT symbol registration failed: {0}")]
+    JitSymbolRegistrationFailed(String),
 }
@@ -15,11 +15,14 @@
 use crate::{
     ebpf,
     ebpf::STACK_PTR_REG,
+    elf::Executable,
     error::EbpfError,
     verifier::Verifier,
-    vm::{Config, ContextObject, EbpfVm, ProgramResult},
+    vm::{
+        Backtrace, BuiltInFunction, Config, ContextObject, EbpfVm, ProgramResult, SuspendedState,
+    },
 };
-use std::convert::TryInto;
+use std::{convert::TryInto, fmt::Debug};
 
 /// Virtual memory operation helper.
 macro_rules! translate_memory_access {
@@ -67,8 +70,11 @@ pub enum DebugState {
 /// State of an interpreter
 pub struct Interpreter<'a, 'b, V: Verifier, C: ContextObject> {
     pub(crate) vm: &'a mut EbpfVm<'b, V, C>,
+    pub(crate) config: &'b Config,
     pub(crate) program: &'a [u8],
     pub(crate) program_vm_addr: u64,
+    pub(crate) instructions: &'b [DecodedInstruction<V, C>],
+    pub(crate) syscalls: &'b [BuiltInFunction<C>],
     pub(crate) due_insn_count: u64,
 
     /// General purpose self.registers
@@ -85,11 +91,16 @@ pub struct Interpreter<'a, 'b, V: Verifier, C: ContextObject> {
 impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
     /// Creates a new interpreter state
     pub fn new(vm: &'a mut EbpfVm<'b, V, C>, registers: [u64; 12]) -> Self {
-        let (program_vm_addr, program) = vm.executable.get_text_bytes();
+        let executable = vm.executable;
+        let (program_vm_addr, program) = executable.get_text_bytes();
+        let decoded_program = executable.get_decoded_program();
         Self {
             vm,
+            config: executable.get_config(),
             program,
             program_vm_addr,
+            instructions: &decoded_program.instructions,
+            syscalls: &decoded_program.syscalls,
             due_insn_count: 0,
             reg: registers[0..11].try_into().unwrap(),
             pc: registers[11] as usize,
@@ -155,52 +166,148 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
         true
     }
 
+    /// Captures the state from which the execution can be resumed at `pc`
+    pub(crate) fn suspend(&mut self, pc: usize) {
+        let mut registers = [0u64; 12];
+        registers[0..11].copy_from_slice(&self.reg);
+        registers[11] = pc as u64;
+        let env = &mut self.vm.env;
+        env.suspended_state = Some(SuspendedState {
+            registers,
+            call_depth: env.call_depth,
+            stack_pointer: env.stack_pointer,
+            call_frames: env.call_frames[0..env.call_depth as usize].to_vec(),
+            overdue_insn_count: 0,
+        });
+    }
+
+    /// Captures the call stack and registers at the instruction `pc` which failed
+    fn capture_backtrace(&mut self, pc: usize) {
+        let mut registers = [0u64; 12];
+        registers[0..11].copy_from_slice(&self.reg);
+        registers[11] = pc as u64;
+        let env = &mut self.vm.env;
+        let mut call_depth = env.call_depth as usize;
+        match &env.program_result {
+            ProgramResult::Err(err) => match err.downcast_ref::<EbpfError>() {
+                // A call which exceeded the max call depth or left the text segment did not
+                // happen, so its frame is dropped and the frame pointer of the caller restored
+                Some(EbpfError::CallDepthExceeded(_, _))
+                | Some(EbpfError::CallOutsideTextSegment(_, _)) => {
+                    call_depth -= 1;
+                    registers[ebpf::FRAME_PTR_REG] = env.call_frames[call_depth].frame_pointer;
+                }
+                // A callx to an unsupported instruction fails at its target
+                Some(EbpfError::UnsupportedInstruction(error_pc))
+                    if self.program.get(pc * ebpf::INSN_SIZE) == Some(&ebpf::CALL_REG) =>
+                {
+                    registers[11] = (*error_pc - ebpf::ELF_INSN_DUMP_OFFSET) as u64;
+                }
+                // The budget is exceeded at the end of the instruction, like in the JIT
+                Some(EbpfError::ExceededMaxInstructions(error_pc)) => {
+                    registers[11] = (*error_pc - ebpf::ELF_INSN_DUMP_OFFSET) as u64;
+                }
+                _ => {}
+            },
+            ProgramResult::Ok(_) => {}
+        }
+        env.backtrace = Some(Backtrace {
+            registers,
+            call_frames: env.call_frames[0..call_depth].to_vec(),
+            frames: Vec::new(),
+        });
+    }
+
     /// Advances the interpreter state by one instruction
     ///
     /// Returns false if the program terminated or threw an error.
-    #[rustfmt::skip]
     pub fn step(&mut self) -> bool {
-        let config = &self.vm.executable.get_config();
-
-        let mut instruction_width = 1;
-        self.due_insn_count += 1;
         let pc = self.pc;
-        self.pc += instruction_width;
-        if self.pc * ebpf::INSN_SIZE > self.program.len() {
-            throw_error!(self, EbpfError::ExecutionOverrun(pc + ebpf::ELF_INSN_DUMP_OFFSET));
+        if self.execute_instruction() {
+            return true;
         }
-        let mut insn = ebpf::get_insn_unchecked(self.program, pc);
-        let dst = insn.dst as usize;
-        let src = insn.src as usize;
+        self.terminate(pc);
+        false
+    }
+
+    /// Advances the interpreter state until the program terminates or throws an error
+    ///
+    /// Behaves like calling `step()` until it returns false, which it returns as well.
+    pub fn run(&mut self) -> bool {
+        loop {
+            let pc = self.pc;
+            if !self.execute_instruction() {
+                self.terminate(pc);
+                return false;
+            }
+        }
+    }
+
+    /// Captures the backtrace if the instruction `pc` threw an error
+    pub(crate) fn terminate(&mut self, pc: usize) {
+        if self.vm.executable.get_config().enable_backtrace
+            && self.vm.env.program_result.is_err()
+            && self.vm.env.suspended_state.is_none()
+        {
+            self.capture_backtrace(pc);
+        }
+    }
+
+    #[inline(always)]
+    fn execute_instruction(&mut self) -> bool {
+        let instructions = self.instructions;
+        let pc = self.pc;
+        self.pc += 1;
+        let insn = match instructions.get(pc) {
+            Some(insn) => insn,
+            None => {
+                self.due_insn_count += self.config.instruction_costs.get_execution_overrun();
+                throw_error!(
+                    self,
+                    EbpfError::ExecutionOverrun(pc + ebpf::ELF_INSN_DUMP_OFFSET)
+                );
+            }
+        };
+        // A `call imm` which resolves to a syscall is charged as such
+        self.due_insn_count += insn.cost as u64;
 
-        if config.enable_instruction_tracing {
+        if self.config.enable_instruction_tracing {
             let mut state = [0u64; 12];
             state[0..11].copy_from_slice(&self.reg);
             state[11] = pc as u64;
             self.vm.env.context_object_pointer.trace(state);
         }
 
-        match insn.opc {
-            _ if dst == STACK_PTR_REG && config.dynamic_stack_frames => {
-                // Let the stack overflow. For legitimate programs, this is a nearly
-                // impossible condition to hit since programs are metered and we already
-                // enforce a maximum call depth. For programs that intentionally mess
-                // around with the stack pointer, MemoryRegion::map will return
-                // InvalidVirtualAddress(stack_ptr) once an invalid stack address is
-                // accessed.
-                match insn.opc {
-                    ebpf::SUB64_IMM => { self.vm.env.stack_pointer = self.vm.env.stack_pointer.overflowing_add(-insn.imm as u64).0; }
-                    ebpf::ADD64_IMM => { self.vm.env.stack_pointer = self.vm.env.stack_pointer.overflowing_add(insn.imm as u64).0; }
-                    _ => {
-                        #[cfg(debug_assertions)]
-                        unreachable!("unexpected insn on r11")
-                    }
-                }
+        (insn.handler)(self, insn, pc)
+    }
+
+    /// Throws `ExceededMaxInstructions` at the end of an instruction which ends at `end_pc`
+    #[inline(always)]
+    fn check_instruction_meter(&mut self, end_pc: usize) -> bool {
+        if self.config.enable_instruction_meter
+            && self.due_insn_count >= self.vm.env.previous_instruction_meter
+        {
+            if self.config.enable_suspension {
+                self.suspend(self.pc);
             }
+            // Use `end_pc` instead of `self.pc` here because jumps and calls don't continue at the end of the instruction
+            throw_error!(
+                self,
+                EbpfError::ExceededMaxInstructions(end_pc + ebpf::ELF_INSN_DUMP_OFFSET)
+            );
+        }
+        true
+    }
 
+    #[rustfmt::skip]
+    #[inline(always)]
+    fn execute_opcode<const OPC: u8>(&mut self, insn: &DecodedInstruction<V, C>, pc: usize) -> bool {
+        let config = self.config;
+        let dst = insn.dst as usize;
+        let src = insn.src as usize;
+
+        match OPC {
             ebpf::LD_DW_IMM  => {
-                ebpf::augment_lddw_unchecked(self.program, &mut insn);
-                instruction_width = 2;
                 self.pc += 1;
                 self.reg[dst] = insn.imm as u64;
             },
@@ -384,29 +491,29 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
             ebpf::ARSH64_REG => self.reg[dst] = (self.reg[dst] as i64).wrapping_shr(self.reg[src] as u32) as u64,
 
             // BPF_JMP class
-            ebpf::JA         =>                                                   { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JEQ_IMM    => if  self.reg[dst] == insn.imm as u64              { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JEQ_REG    => if  self.reg[dst] == self.reg[src]                { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JGT_IMM    => if  self.reg[dst] >  insn.imm as u64              { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JGT_REG    => if  self.reg[dst] >  self.reg[src]                { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JGE_IMM    => if  self.reg[dst] >= insn.imm as u64              { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JGE_REG    => if  self.reg[dst] >= self.reg[src]                { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JLT_IMM    => if  self.reg[dst] <  insn.imm as u64              { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JLT_REG    => if  self.reg[dst] <  self.reg[src]                { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JLE_IMM    => if  self.reg[dst] <= insn.imm as u64              { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JLE_REG    => if  self.reg[dst] <= self.reg[src]                { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSET_IMM   => if  self.reg[dst] &  insn.imm as u64 != 0         { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSET_REG   => if  self.reg[dst] &  self.reg[src] != 0           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JNE_IMM    => if  self.reg[dst] != insn.imm as u64              { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JNE_REG    => if  self.reg[dst] != self.reg[src]                { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSGT_IMM   => if (self.reg[dst] as i64) >  insn.imm             { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSGT_REG   => if (self.reg[dst] as i64) >  self.reg[src] as i64 { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSGE_IMM   => if (self.reg[dst] as i64) >= insn.imm             { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSGE_REG   => if (self.reg[dst] as i64) >= self.reg[src] as i64 { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSLT_IMM   => if (self.reg[dst] as i64) <  insn.imm             { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSLT_REG   => if (self.reg[dst] as i64) <  self.reg[src] as i64 { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSLE_IMM   => if (self.reg[dst] as i64) <= insn.imm             { self.pc = (self.pc as isize + insn.off as isize) as usize; },
-            ebpf::JSLE_REG   => if (self.reg[dst] as i64) <= self.reg[src] as i64 { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JA         =>                                                   { self.pc = insn.target_pc; },
+            ebpf::JEQ_IMM    => if  self.reg[dst] == insn.imm as u64              { self.pc = insn.target_pc; },
+            ebpf::JEQ_REG    => if  self.reg[dst] == self.reg[src]                { self.pc = insn.target_pc; },
+            ebpf::JGT_IMM    => if  self.reg[dst] >  insn.imm as u64              { self.pc = insn.target_pc; },
+            ebpf::JGT_REG    => if  self.reg[dst] >  self.reg[src]                { self.pc = insn.target_pc; },
+            ebpf::JGE_IMM    => if  self.reg[dst] >= insn.imm as u64              { self.pc = insn.target_pc; },
+            ebpf::JGE_REG    => if  self.reg[dst] >= self.reg[src]                { self.pc = insn.target_pc; },
+            ebpf::JLT_IMM    => if  self.reg[dst] <  insn.imm as u64              { self.pc = insn.target_pc; },
+            ebpf::JLT_REG    => if  self.reg[dst] <  self.reg[src]                { self.pc = insn.target_pc; },
+            ebpf::JLE_IMM    => if  self.reg[dst] <= insn.imm as u64              { self.pc = insn.target_pc; },
+            ebpf::JLE_REG    => if  self.reg[dst] <= self.reg[src]                { self.pc = insn.target_pc; },
+            ebpf::JSET_IMM   => if  self.reg[dst] &  insn.imm as u64 != 0         { self.pc = insn.target_pc; },
+            ebpf::JSET_REG   => if  self.reg[dst] &  self.reg[src] != 0           { self.pc = insn.target_pc; },
+            ebpf::JNE_IMM    => if  self.reg[dst] != insn.imm as u64              { self.pc = insn.target_pc; },
+            ebpf::JNE_REG    => if  self.reg[dst] != self.reg[src]                { self.pc = insn.target_pc; },
+            ebpf::JSGT_IMM   => if (self.reg[dst] as i64) >  insn.imm             { self.pc = insn.target_pc; },
+            ebpf::JSGT_REG   => if (self.reg[dst] as i64) >  self.reg[src] as i64 { self.pc = insn.target_pc; },
+            ebpf::JSGE_IMM   => if (self.reg[dst] as i64) >= insn.imm             { self.pc = insn.target_pc; },
+            ebpf::JSGE_REG   => if (self.reg[dst] as i64) >= self.reg[src] as i64 { self.pc = insn.target_pc; },
+            ebpf::JSLT_IMM   => if (self.reg[dst] as i64) <  insn.imm             { self.pc = insn.target_pc; },
+            ebpf::JSLT_REG   => if (self.reg[dst] as i64) <  self.reg[src] as i64 { self.pc = insn.target_pc; },
+            ebpf::JSLE_IMM   => if (self.reg[dst] as i64) <= insn.imm             { self.pc = insn.target_pc; },
+            ebpf::JSLE_REG   => if (self.reg[dst] as i64) <= self.reg[src] as i64 { self.pc = insn.target_pc; },
 
             ebpf::CALL_REG   => {
                 let target_address = self.reg[insn.imm as usize];
@@ -421,72 +528,19 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
                     return false;
                 }
                 if config.static_syscalls && self.vm.executable.lookup_internal_function(self.pc as u32).is_none() {
-                    self.due_insn_count += 1;
+                    self.due_insn_count += self.instructions[self.pc].cost as u64;
                     throw_error!(self, EbpfError::UnsupportedInstruction(self.pc + ebpf::ELF_INSN_DUMP_OFFSET));
                 }
             },
 
-            // Do not delegate the check to the verifier, since self.registered functions can be
-            // changed after the program has been verified.
-            ebpf::CALL_IMM   => {
-                let mut resolved = false;
-                let (external, internal) = if config.static_syscalls {
-                    (insn.src == 0, insn.src != 0)
-                } else {
-                    (true, true)
-                };
-
-                if external {
-                    if let Some((_function_name, function)) = self.vm.executable.get_loader().lookup_function(insn.imm as u32) {
-                        resolved = true;
-
-                        if config.enable_instruction_meter {
-                            self.vm.env.context_object_pointer.consume(self.due_insn_count);
-                        }
-                        self.due_insn_count = 0;
-                        function(
-                            self.vm.env.context_object_pointer,
-                            self.reg[1],
-                            self.reg[2],
-                            self.reg[3],
-                            self.reg[4],
-                            self.reg[5],
-                            &mut self.vm.env.memory_mapping,
-                            &mut self.vm.env.program_result,
-                        );
-                        self.reg[0] = match &self.vm.env.program_result {
-                            ProgramResult::Ok(value) => *value,
-                            ProgramResult::Err(_err) => return false,
-                        };
-                        if config.enable_instruction_meter {
-                            self.vm.env.previous_instruction_meter = self.vm.env.context_object_pointer.get_remaining();
-                        }
-                    }
-                }
-
-                if internal && !resolved {
-                    if let Some(target_pc) = self.vm.executable.lookup_internal_function(insn.imm as u32) {
-                        resolved = true;
-
-                        // make BPF to BPF call
-                        if !self.push_frame(config) {
-                            return false;
-                        }
-                        self.pc = target_pc;
-                        if !self.check_pc(pc) {
-                            return false;
-                        }
-                    }
-                }
-
-                if !resolved {
-                    throw_error!(self, EbpfError::UnsupportedInstruction(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
-            }
-
             ebpf::EXIT       => {
                 if self.vm.env.call_depth == 0 {
                     if config.enable_instruction_meter && self.due_insn_count > self.vm.env.previous_instruction_meter {
+                        if config.enable_suspension {
+                            // The exit is executed again on resumption, so don't charge it now
+                            self.due_insn_count -= insn.cost as u64;
+                            self.suspend(pc);
+                        }
                         throw_error!(self, EbpfError::ExceededMaxInstructions(pc + ebpf::ELF_INSN_DUMP_OFFSET));
                     }
                     self.vm.env.program_result = ProgramResult::Ok(self.reg[0]);
@@ -509,14 +563,458 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
                     return false;
                 }
             }
-            _ => throw_error!(self, EbpfError::UnsupportedInstruction(pc + ebpf::ELF_INSN_DUMP_OFFSET)),
+            _ => {
+                #[cfg(debug_assertions)]
+                unreachable!("opcode without handler");
+            },
         }
 
-        if config.enable_instruction_meter && self.due_insn_count >= self.vm.env.previous_instruction_meter {
-            // Use `pc + instruction_width` instead of `self.pc` here because jumps and calls don't continue at the end of this instruction
-            throw_error!(self, EbpfError::ExceededMaxInstructions(pc + instruction_width + ebpf::ELF_INSN_DUMP_OFFSET));
+        self.check_instruction_meter(if OPC == ebpf::LD_DW_IMM { pc + 2 } else { pc + 1 })
+    }
+}
+
+/// Executes a pre-decoded instruction at the given pc, see `Interpreter::step()`
+type Handler<V, C> = fn(&mut Interpreter<'_, '_, V, C>, &DecodedInstruction<V, C>, usize) -> bool;
+
+/// Instruction decoded ahead of its execution
+pub(crate) struct DecodedInstruction<V: Verifier, C: ContextObject> {
+    /// Executes the instruction, specialized for its opcode
+    handler: Handler<V, C>,
+    /// Immediate, of `lddw` with both halves merged
+    imm: i64,
+    /// Jump target, pc of the called function or index of the called syscall
+    target_pc: usize,
+    /// What the instruction meter charges
+    cost: u32,
+    off: i16,
+    dst: u8,
+    src: u8,
+}
+
+/// Text section of an executable decoded for the interpreter
+///
+/// Every instruction slot is decoded, including the second halves of `lddw`,
+/// because jumps can target them.
+pub(crate) struct DecodedProgram<V: Verifier, C: ContextObject> {
+    instructions: Vec<DecodedInstruction<V, C>>,
+    /// The syscalls which `call imm` instructions resolve to
+    syscalls: Vec<BuiltInFunction<C>>,
+}
+
+impl<V: Verifier, C: ContextObject> DecodedProgram<V, C> {
+    /// Decodes the text section of `executable`
+    pub(crate) fn new(executable: &Executable<V, C>) -> Self {
+        let config = executable.get_config();
+        let (_program_vm_addr, program) = executable.get_text_bytes();
+        let mut syscalls = Vec::new();
+        let instructions = (0..program.len() / ebpf::INSN_SIZE)
+            .map(|pc| {
+                let mut insn = ebpf::get_insn_unchecked(program, pc);
+                let mut cost = config.instruction_costs.get(insn.opc, false);
+                let mut target_pc = (pc as isize)
+                    .wrapping_add(insn.off as isize)
+                    .wrapping_add(1) as usize;
+                let handler: Handler<V, C> = if insn.dst as usize == STACK_PTR_REG
+                    && config.dynamic_stack_frames
+                {
+                    match insn.opc {
+                        ebpf::ADD64_IMM => adjust_stack_pointer,
+                        ebpf::SUB64_IMM => {
+                            insn.imm = -insn.imm;
+                            adjust_stack_pointer
+                        }
+                        _ => unexpected_stack_pointer_instruction,
+                    }
+                } else {
+                    match insn.opc {
+                        ebpf::LD_DW_IMM => {
+                            // The verifier rejects a trailing `lddw`, which overruns the text section
+                            if (pc + 2) * ebpf::INSN_SIZE <= program.len() {
+                                ebpf::augment_lddw_unchecked(program, &mut insn);
+                            }
+                            execute::<V, C, { ebpf::LD_DW_IMM }>
+                        }
+                        // Do not delegate the check to the verifier, since registered functions
+                        // can be changed after the program has been verified.
+                        ebpf::CALL_IMM => {
+                            let (external, internal) = if config.static_syscalls {
+                                (insn.src == 0, insn.src != 0)
+                            } else {
+                                (true, true)
+                            };
+                            let syscall = external
+                                .then(|| executable.get_loader().lookup_function(insn.imm as u32))
+                                .flatten();
+                            let function = internal
+                                .then(|| executable.lookup_internal_function(insn.imm as u32))
+                                .flatten();
+                            if let Some((_function_name, function)) = syscall {
+                                cost = config.instruction_costs.get(insn.opc, true);
+                                target_pc = syscalls.len();
+                                syscalls.push(function);
+                                call_syscall
+                            } else if let Some(function_pc) = function {
+                                target_pc = function_pc;
+                                call_function
+                            } else {
+                                unsupported_instruction
+                            }
+                        }
+                        opc => decode_handler(opc),
+                    }
+                };
+                DecodedInstruction {
+                    handler,
+                    imm: insn.imm,
+                    target_pc,
+                    cost: cost as u32,
+                    off: insn.off,
+                    dst: insn.dst,
+                    src: insn.src,
+                }
+            })
+            .collect();
+        Self {
+            instructions,
+            syscalls,
         }
+    }
 
-        true
+    /// Returns the in-memory size in bytes
+    pub(crate) fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.instructions.capacity() * std::mem::size_of::<DecodedInstruction<V, C>>()
+            + self.syscalls.capacity() * std::mem::size_of::<BuiltInFunction<C>>()
+    }
+}
+
+impl<V: Verifier, C: ContextObject> Debug for DecodedProgram<V, C> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_fmt(format_args!("DecodedProgram {:?}", self as *const _))
+    }
+}
+
+impl<V: Verifier, C: ContextObject> PartialEq for DecodedProgram<V, C> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self as *const _, other as *const _)
+    }
+}
+
+/// Selects the handler of the opcodes which need no further decoding
+fn decode_handler<V: Verifier, C: ContextObject>(opc: u8) -> Handler<V, C> {
+    macro_rules! handlers {
+        ($($opc:ident),* $(,)?) => {
+            match opc {
+                $(ebpf::$opc => execute::<V, C, { ebpf::$opc }>,)*
+                _ => unsupported_instruction,
+            }
+        };
+    }
+    handlers!(
+        LD_B_REG, LD_H_REG, LD_W_REG, LD_DW_REG, ST_B_IMM, ST_H_IMM, ST_W_IMM, ST_DW_IMM, ST_B_REG,
+        ST_H_REG, ST_W_REG, ST_DW_REG, ADD32_IMM, ADD32_REG, SUB32_IMM, SUB32_REG, MUL32_IMM,
+        MUL32_REG, DIV32_IMM, DIV32_REG, SDIV32_IMM, SDIV32_REG, OR32_IMM, OR32_REG, AND32_IMM,
+        AND32_REG, LSH32_IMM, LSH32_REG, RSH32_IMM, RSH32_REG, NEG32, MOD32_IMM, MOD32_REG,
+        XOR32_IMM, XOR32_REG, MOV32_IMM, MOV32_REG, ARSH32_IMM, ARSH32_REG, LE, BE, ADD64_IMM,
+        ADD64_REG, SUB64_IMM, SUB64_REG, MUL64_IMM, MUL64_REG, DIV64_IMM, DIV64_REG, SDIV64_IMM,
+        SDIV64_REG, OR64_IMM, OR64_REG, AND64_IMM, AND64_REG, LSH64_IMM, LSH64_REG, RSH64_IMM,
+        RSH64_REG, NEG64, MOD64_IMM, MOD64_REG, XOR64_IMM, XOR64_REG, MOV64_IMM, MOV64_REG,
+        ARSH64_IMM, ARSH64_REG, JA, JEQ_IMM, JEQ_REG, JGT_IMM, JGT_REG, JGE_IMM, JGE_REG, JLT_IMM,
+        JLT_REG, JLE_IMM, JLE_REG, JSET_IMM, JSET_REG, JNE_IMM, JNE_REG, JSGT_IMM, JSGT_REG,
+        JSGE_IMM, JSGE_REG, JSLT_IMM, JSLT_REG, JSLE_IMM, JSLE_REG, CALL_REG, EXIT,
+    )
+}
+
+fn execute<V: Verifier, C: ContextObject, const OPC: u8>(
+    interpreter: &mut Interpreter<V, C>,
+    insn: &DecodedInstruction<V, C>,
+    pc: usize,
+) -> bool {
+    interpreter.execute_opcode::<OPC>(insn, pc)
+}
+
+fn unsupported_instruction<V: Verifier, C: ContextObject>(
+    interpreter: &mut Interpreter<V, C>,
+    _insn: &DecodedInstruction<V, C>,
+    pc: usize,
+) -> bool {
+    throw_error!(
+        interpreter,
+        EbpfError::UnsupportedInstruction(pc + ebpf::ELF_INSN_DUMP_OFFSET)
+    );
+}
+
+fn adjust_stack_pointer<V: Verifier, C: ContextObject>(
+    interpreter: &mut Interpreter<V, C>,
+    insn: &DecodedInstruction<V, C>,
+    pc: usize,
+) -> bool {
+    // Let the stack overflow. For legitimate programs, this is a nearly
+    // impossible condition to hit since programs are metered and we already
+    // enforce a maximum call depth. For programs that intentionally mess
+    // around with the stack pointer, MemoryRegion::map will return
+    // InvalidVirtualAddress(stack_ptr) once an invalid stack address is
+    // accessed.
+    let env = &mut interpreter.vm.env;
+    env.stack_pointer = env.stack_pointer.overflowing_add(insn.imm as u64).0;
+    interpreter.check_instruction_meter(pc + 1)
+}
+
+fn unexpected_stack_pointer_instruction<V: Verifier, C: ContextObject>(
+    interpreter: &mut Interpreter<V, C>,
+    _insn: &DecodedInstruction<V, C>,
+    pc: usize,
+) -> bool {
+    if cfg!(debug_assertions) {
+        unreachable!("unexpected insn on r11");
+    }
+    interpreter.check_instruction_meter(pc + 1)
+}
+
+fn call_syscall<V: Verifier, C: ContextObject>(
+    interpreter: &mut Interpreter<V, C>,
+    insn: &DecodedInstruction<V, C>,
+    pc: usize,
+) -> bool {
+    let config = interpreter.config;
+    let function = interpreter.syscalls[insn.target_pc];
+    if config.enable_instruction_meter {
+        interpreter
+            .vm
+            .env
+            .context_object_pointer
+            .consume(interpreter.due_insn_count);
+    }
+    interpreter.due_insn_count = 0;
+    let reg = &mut interpreter.reg;
+    let env = &mut interpreter.vm.env;
+    function(
+        env.context_object_pointer,
+        reg[1],
+        reg[2],
+        reg[3],
+        reg[4],
+        reg[5],
+        &mut env.memory_mapping,
+        &mut env.program_result,
+    );
+    reg[0] = match &env.program_result {
+        ProgramResult::Ok(value) => *value,
+        ProgramResult::Err(err) => {
+            if config.enable_suspension
+                && matches!(
+                    err.downcast_ref::<EbpfError>(),
+                    Some(EbpfError::SyscallYield)
+                )
+            {
+                interpreter.suspend(interpreter.pc);
+            }
+            return false;
+        }
+    };
+    if config.enable_instruction_meter {
+        env.previous_instruction_meter = env.context_object_pointer.get_remaining();
+    }
+    interpreter.check_instruction_meter(pc + 1)
+}
+
+fn call_function<V: Verifier, C: ContextObject>(
+    interpreter: &mut Interpreter<V, C>,
+    insn: &DecodedInstruction<V, C>,
+    pc: usize,
+) -> bool {
+    // make BPF to BPF call
+    if !interpreter.push_frame(interpreter.config) {
+        return false;
+    }
+    interpreter.pc = insn.target_pc;
+    if !interpreter.check_pc(pc) {
+        return false;
+    }
+    interpreter.check_instruction_meter(pc + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        aligned_memory::AlignedMemory,
+        ebpf::HOST_ALIGN,
+        elf::Executable,
+        memory_region::{MemoryMapping, MemoryRegion},
+        syscalls,
+        verifier::{RequisiteVerifier, TautologyVerifier},
+        vm::{BuiltInProgram, InstructionCosts, TestContextObject},
+    };
+    use std::sync::Arc;
+
+    fn assemble(src: &str, config: Config) -> Executable<TautologyVerifier, TestContextObject> {
+        let mut loader = BuiltInProgram::new_loader(config);
+        loader
+            .register_function(b"bpf_syscall_u64", syscalls::bpf_syscall_u64)
+            .unwrap();
+        crate::assembler::assemble::<TestContextObject>(src, Arc::new(loader)).unwrap()
+    }
+
+    /// Runs `executable` with `Interpreter::step()` or `Interpreter::run()` and describes the outcome
+    fn execute<V: Verifier>(
+        executable: &Executable<V, TestContextObject>,
+        budget: u64,
+        stepwise: bool,
+    ) -> String {
+        let config = executable.get_config();
+        let mut stack = AlignedMemory::<{ HOST_ALIGN }>::zero_filled(config.stack_size());
+        let stack_len = stack.len();
+        let regions = vec![
+            executable.get_ro_region(),
+            MemoryRegion::new_writable_gapped(
+                stack.as_slice_mut(),
+                ebpf::MM_STACK_START,
+                if !config.dynamic_stack_frames && config.enable_stack_frame_gaps {
+                    config.stack_frame_size as u64
+                } else {
+                    0
+                },
+            ),
+        ];
+        let memory_mapping = MemoryMapping::new(regions, config).unwrap();
+        let mut context_object = TestContextObject::new(budget);
+        let mut vm = EbpfVm::new(executable, &mut context_object, memory_mapping, stack_len);
+        let registers = vm.entrypoint_registers();
+        let (instruction_count, result) = if stepwise {
+            vm.execute(true, registers, false, |interpreter| interpreter.step())
+        } else {
+            vm.execute(true, registers, false, |interpreter| interpreter.run())
+        };
+        let (suspended_state, backtrace) = (vm.env.suspended_state, vm.env.backtrace.take());
+        format!(
+            "{instruction_count} {result:?} {suspended_state:?} {backtrace:?} {} {:?}",
+            context_object.remaining, context_object.trace_log,
+        )
+    }
+
+    #[test]
+    fn test_run_like_step() {
+        // Calls a function and a syscall, then jumps into the second half of an lddw
+        let src = "
+            mov64 r1, 1
+            call function_foo
+            syscall bpf_syscall_u64
+            lddw r0, 0x1122334455667788
+            jeq r1, 4, -2
+            exit
+        function_foo:
+            add64 r1, 1
+            jlt r1, 4, -2
+            exit";
+        let configs = [
+            Config {
+                enable_instruction_tracing: true,
+                enable_backtrace: true,
+                ..Config::default()
+            },
+            Config {
+                enable_suspension: true,
+                ..Config::default()
+            },
+        ];
+        for config in configs {
+            let executable = assemble(src, config);
+            for budget in 0..16 {
+                assert_eq!(
+                    execute(&executable, budget, true),
+                    execute(&executable, budget, false)
+                );
+            }
+            assert!(
+                execute(&executable, 16, false).starts_with("13 Err(UnsupportedInstruction(33))")
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_like_step_lddw_last() {
+        // The decoder must not read past the end of the text section
+        let config = Config {
+            enable_instruction_tracing: true,
+            ..Config::default()
+        };
+        let executable = assemble(
+            "
+            mov64 r0, 1
+            lddw r0, 0x1122334455667788",
+            config,
+        );
+        for budget in 0..4 {
+            assert_eq!(
+                execute(&executable, budget, true),
+                execute(&executable, budget, false)
+            );
+        }
+        assert!(execute(&executable, 4, false).starts_with("3 Err(ExecutionOverrun(32))"));
+    }
+
+    #[test]
+    fn test_run_like_step_after_verification() {
+        // Decoded before the verification, which must not keep the calls resolved back then
+        let src = "
+            mov64 r1, 1
+            call function_foo
+            mov64 r0, r1
+            exit
+        function_foo:
+            call function_bar
+            exit
+        function_bar:
+            add64 r1, 1
+            exit";
+        let executable = assemble(src, Config::default());
+        let unverified = execute(&executable, 10, false);
+        let executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+        assert_eq!(execute(&executable, 10, false), unverified);
+        assert_eq!(execute(&executable, 10, true), unverified);
+        assert!(unverified.starts_with("8 Ok(2)"));
+    }
+
+    #[test]
+    fn test_run_like_step_weighted_instruction_costs() {
+        // The meter is charged by cost, which lands the budget between and on instructions
+        let config = Config {
+            enable_instruction_tracing: true,
+            instruction_costs: InstructionCosts {
+                alu: 2,
+                mul: 3,
+                memory: 5,
+                jump: 4,
+                call: 7,
+                syscall: 11,
+                ..InstructionCosts::default()
+            },
+            ..Config::default()
+        };
+        let executable = assemble(
+            "
+            mov64 r1, 1
+            call function_foo
+            syscall bpf_syscall_u64
+            stxdw [r10-8], r1
+            ldxdw r0, [r10-8]
+            lddw r2, 0x1122334455667788
+            exit
+        function_foo:
+            mul64 r1, 3
+            jlt r1, 20, -2
+            exit",
+            config,
+        );
+        let complete = execute(&executable, 1_000, false);
+        assert!(complete.starts_with("67 Ok(27)"));
+        assert_eq!(execute(&executable, 1_000, true), complete);
+        for budget in 0..70 {
+            assert_eq!(
+                execute(&executable, budget, true),
+                execute(&executable, budget, false)
+            );
+        }
     }
 }
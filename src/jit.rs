@@ -10,33 +10,130 @@
 // the MIT license <http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use std::{fmt::Debug, mem, ptr};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    io::Cursor,
+    mem,
+    ops::Range,
+    ptr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+};
 
 use crate::{
+    coverage::LineTable,
     ebpf::{self, FIRST_SCRATCH_REG, FRAME_PTR_REG, INSN_SIZE, SCRATCH_REGS, STACK_PTR_REG},
     elf::Executable,
     error::EbpfError,
+    jit_optimizer::{optimize, Optimization},
+    jit_symbols::{elf_object, write_perf_map, GdbJitRegistration, JitSymbol},
     memory_management::{
         allocate_pages, free_pages, get_system_page_size, protect_pages, round_to_page_size,
     },
     memory_region::{AccessType, MemoryMapping},
+    static_analysis::Analysis,
     verifier::Verifier,
-    vm::{Config, ContextObject, ProgramResult, RuntimeEnvironment},
-    x86::*,
+    vm::{
+        Backtrace, CallFrame, Config, ContextObject, FunctionRegistry, ProgramResult,
+        RuntimeEnvironment, SuspendedState,
+    },
+    x86::{disassemble_instruction as disassemble_x86_instruction, *},
 };
 
 const MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH: usize = 4096;
 const MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION: usize = 110;
+/// `cmp` with a 32 bit immediate (7 bytes) and `jcc` with a 32 bit displacement (6 bytes)
 const MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT: usize = 13;
+/// `mov` of the 32 bit pc into R11 (7 bytes), which precedes every checkpoint if suspension is enabled
+const MACHINE_CODE_PER_SUSPENSION_CHECKPOINT: usize = 7;
+/// Number of words a BPF to BPF call occupies on the host stack
+const HOST_CALL_FRAME_SIZE: usize = 2 + ebpf::SCRATCH_REGS;
+/// Identifies the serialized form of a JitProgram
+const CACHE_MAGIC: &[u8; 8] = b"RBPFJITC";
+/// Version of the serialized form of a JitProgram
+const CACHE_VERSION: u32 = 5;
 
 pub struct JitProgram {
     /// OS page size in bytes and the alignment of the sections
     page_size: usize,
     /// A `*const u8` pointer into the text_section for each BPF instruction
-    pc_section: &'static mut [usize],
+    ///
+    /// Read-only once sealed. A lazily compiled program dispatches through
+    /// `LazyCompilation::dispatch_table` instead.
+    pc_section: *mut [usize],
     /// The x86 machinecode
-    text_section: &'static mut [u8],
+    ///
+    /// Raw, because a lazily compiled program writes units while other threads run the rest.
+    text_section: *mut [u8],
+    /// Host addresses and relocations, which grow as functions are compiled lazily
+    tables: RwLock<JitTables>,
+    /// Sum of the instruction costs before each pc, empty if every instruction costs one
+    instruction_meter_offsets: Vec<u64>,
+    /// Same as instruction_meter_offsets, but the second half of lddw does not count the lddw
+    exceeded_instruction_meter_offsets: Vec<u64>,
+    /// Offsets into the text_section where the subroutines begin, paired with their anchor
+    anchors: Vec<(usize, usize)>,
+    /// Compiles the functions on their first call, `None` if everything was compiled up front
+    lazy_compilation: Option<Box<LazyCompilation>>,
+    /// Symbols registered with a debugger, see `register_symbols()`
+    gdb_registration: Option<GdbJitRegistration>,
+}
+
+// Through `&self` the pc_section is only read and the text_section is only written by
+// `JitProgram::compile_lazily()`, where no invocation runs yet
+unsafe impl Send for JitProgram {}
+unsafe impl Sync for JitProgram {}
+
+/// Host addresses of a JitProgram by pc and the relocations of its machine code
+#[derive(Debug, Default)]
+struct JitTables {
+    /// Pairs of the target_pc of a CallFrame and the host address it returns to, sorted
+    call_return_addresses: Vec<(usize, usize)>,
+    /// Host address of every instruction, as pc_section only keeps the function entries
+    ///
+    /// Used to resume suspended programs and to attribute the machine code to instructions.
+    instruction_addresses: Vec<usize>,
+    /// Offsets into the text_section of the host addresses embedded in the machine code
+    relocations: Vec<(usize, Relocation)>,
+}
+
+/// State of a JitProgram which compiles its functions on their first call
+///
+/// Boxed, because the machine code refers to it and the JitProgram moves with its Executable.
+struct LazyCompilation {
+    /// Host addresses of the subroutines, which the units are compiled against
+    anchors: [usize; ANCHOR_COUNT],
+    /// Offset into the text_section where the subroutines end
+    subroutines_length: usize,
+    /// The units in the order of their pcs, which is also the order of their pages
+    units: Vec<CompilationUnit>,
+    /// Offset into the text_section where the machine code of each unit ends, once compiled
+    ///
+    /// Held while a unit is compiled, so that no unit is compiled twice.
+    unit_ends: Mutex<Vec<Option<usize>>>,
+    /// Pcs of the functions in ascending order
+    functions: Vec<usize>,
+    /// Calls and back-edges of each function, see `JitProgram::count_call_or_back_edge()`
+    hotness: Vec<AtomicU64>,
+    /// Takes the place of the pc_section, which stays read-only, as units are installed
+    ///
+    /// Other threads read it while `JitProgram::install_unit()` stores to it.
+    dispatch_table: Box<[AtomicUsize]>,
+}
+
+/// Range of instructions which is compiled as a whole, see `JitCompiler::compile_lazily()`
+#[derive(Debug)]
+struct CompilationUnit {
+    /// The instructions
+    pcs: Range<usize>,
+    /// Offsets into the text_section of the pages reserved for the machine code
+    text: Range<usize>,
 }
 
 impl JitProgram {
@@ -48,20 +145,57 @@ impl JitProgram {
             let raw = allocate_pages(pc_loc_table_size + over_allocated_code_size)?;
             Ok(Self {
                 page_size,
-                pc_section: std::slice::from_raw_parts_mut(raw as *mut usize, pc),
-                text_section: std::slice::from_raw_parts_mut(
+                pc_section: ptr::slice_from_raw_parts_mut(raw as *mut usize, pc),
+                text_section: ptr::slice_from_raw_parts_mut(
                     (raw as *mut u8).add(pc_loc_table_size),
                     over_allocated_code_size,
                 ),
+                tables: RwLock::new(JitTables::default()),
+                instruction_meter_offsets: Vec::new(),
+                exceeded_instruction_meter_offsets: Vec::new(),
+                anchors: Vec::new(),
+                lazy_compilation: None,
+                gdb_registration: None,
             })
         }
     }
 
+    /// The pc_section of a sealed program
+    fn pc_section(&self) -> &[usize] {
+        unsafe { &*self.pc_section }
+    }
+
+    /// The pc_section of a program which is not sealed yet
+    fn pc_section_mut(&mut self) -> &mut [usize] {
+        unsafe { &mut *self.pc_section }
+    }
+
+    /// Host address a call to the instruction at `pc` dispatches to
+    fn dispatch(&self, pc: usize) -> usize {
+        match self.lazy_compilation.as_ref() {
+            Some(lazy_compilation) => lazy_compilation.dispatch_table[pc].load(Ordering::Acquire),
+            None => self.pc_section()[pc],
+        }
+    }
+
+    /// The machine code in `range` of the text_section
+    ///
+    /// `range` must not overlap a unit which is being compiled.
+    fn machine_code(&self, range: Range<usize>) -> &[u8] {
+        assert!(range.start <= range.end && range.end <= self.text_section.len());
+        unsafe {
+            std::slice::from_raw_parts(
+                (self.text_section as *const u8).add(range.start),
+                range.len(),
+            )
+        }
+    }
+
     fn seal(&mut self, text_section_usage: usize) -> Result<(), EbpfError> {
         if self.page_size == 0 {
             return Ok(());
         }
-        let raw = self.pc_section.as_ptr() as *mut u8;
+        let raw = self.pc_section as *mut u8;
         let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
         let over_allocated_code_size = round_to_page_size(self.text_section.len(), self.page_size);
         let code_size = round_to_page_size(text_section_usage, self.page_size);
@@ -79,32 +213,184 @@ impl JitProgram {
                 )?;
             }
             self.text_section =
-                std::slice::from_raw_parts_mut(raw.add(pc_loc_table_size), text_section_usage);
-            protect_pages(
-                self.pc_section.as_mut_ptr() as *mut u8,
-                pc_loc_table_size,
-                false,
-            )?;
-            protect_pages(self.text_section.as_mut_ptr(), code_size, true)?;
+                ptr::slice_from_raw_parts_mut(raw.add(pc_loc_table_size), text_section_usage);
+            protect_pages(self.pc_section as *mut u8, pc_loc_table_size, false)?;
+            protect_pages(self.text_section as *mut u8, code_size, true)?;
+        }
+        Ok(())
+    }
+
+    /// Like `seal()`, but only the subroutines become executable, the units stay writable
+    fn seal_subroutines(&mut self, subroutines_length: usize) -> Result<(), EbpfError> {
+        let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
+        let code_size = round_to_page_size(subroutines_length, self.page_size);
+        unsafe {
+            // Fill with debugger traps
+            std::ptr::write_bytes(
+                (self.text_section as *mut u8).add(subroutines_length),
+                0xcc,
+                code_size - subroutines_length,
+            );
+            protect_pages(self.pc_section as *mut u8, pc_loc_table_size, false)?;
+            protect_pages(self.text_section as *mut u8, code_size, true)?;
         }
         Ok(())
     }
 
-    pub fn invoke<C: ContextObject>(
+    /// Compiles the unit of the instruction at `pc` unless that happened already
+    ///
+    /// Returns the host address of the instruction. Concurrent invocations keep running, as
+    /// only the pages reserved for the unit are written before the dispatch_table points to them.
+    pub(crate) fn compile_lazily<V: Verifier, C: ContextObject>(
+        &self,
+        executable: &Executable<V, C>,
+        pc: usize,
+    ) -> Result<usize, EbpfError> {
+        if let Some(lazy_compilation) = self.lazy_compilation.as_ref() {
+            let index = lazy_compilation
+                .units
+                .partition_point(|unit| unit.pcs.end <= pc);
+            if let Some(unit) = lazy_compilation.units.get(index) {
+                let mut unit_ends = lazy_compilation.unit_ends.lock().unwrap();
+                if unit_ends[index].is_none() {
+                    let (compiled_unit, unit_end) =
+                        JitCompiler::new_for_unit(executable, self)?.compile_unit(unit)?;
+                    self.install_unit(executable, unit, compiled_unit, unit_end)?;
+                    unit_ends[index] = Some(unit_end);
+                }
+            }
+        }
+        self.tables
+            .read()
+            .unwrap()
+            .instruction_addresses
+            .get(pc)
+            .copied()
+            .ok_or(EbpfError::JitNotCompiled)
+    }
+
+    /// Counts a call or back-edge of the function containing `pc` for the tiered execution
+    ///
+    /// Returns whether the machine code can take over at `pc`, which it can once the function
+    /// was counted `threshold` times and its unit compiled. A function which fails to compile
+    /// stays interpreted until it was counted `threshold` times again.
+    pub(crate) fn count_call_or_back_edge<V: Verifier, C: ContextObject>(
         &self,
-        config: &Config,
+        executable: &Executable<V, C>,
+        pc: usize,
+        threshold: u64,
+    ) -> bool {
+        let lazy_compilation = match self.lazy_compilation.as_ref() {
+            Some(lazy_compilation) => lazy_compilation,
+            None => return true,
+        };
+        let index = match lazy_compilation
+            .functions
+            .partition_point(|function_pc| *function_pc <= pc)
+            .checked_sub(1)
+        {
+            Some(index) => index,
+            None => return false,
+        };
+        let hotness = &lazy_compilation.hotness[index];
+        if hotness.fetch_add(1, Ordering::Relaxed).saturating_add(1) < threshold {
+            return false;
+        }
+        if self.compile_lazily(executable, pc).is_err() {
+            hotness.store(0, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Makes the machine code of a unit executable and points the tables to it
+    ///
+    /// `compiled_unit` holds the host addresses of the unit in its tables and pc_section.
+    fn install_unit<V: Verifier, C: ContextObject>(
+        &self,
+        executable: &Executable<V, C>,
+        unit: &CompilationUnit,
+        mut compiled_unit: JitProgram,
+        unit_end: usize,
+    ) -> Result<(), EbpfError> {
+        let lazy_compilation = match self.lazy_compilation.as_ref() {
+            Some(lazy_compilation) => lazy_compilation,
+            None => return Err(EbpfError::JitNotCompiled),
+        };
+        let text_section = self.text_section as *mut u8;
+        let code_size = round_to_page_size(unit_end - unit.text.start, self.page_size);
+        let unit_tables = mem::take(compiled_unit.tables.get_mut().unwrap());
+        let mut tables = self.tables.write().unwrap();
+        unsafe {
+            // Fill with debugger traps
+            std::ptr::write_bytes(
+                text_section.add(unit_end),
+                0xcc,
+                unit.text.start + code_size - unit_end,
+            );
+            protect_pages(text_section.add(unit.text.start), code_size, true)?;
+        }
+        for pc in unit.pcs.clone() {
+            let host_address = compiled_unit.pc_section_mut()[pc];
+            tables.instruction_addresses[pc] = host_address;
+            // Same as `JitCompiler::resolve_jumps()`, other threads read the dispatch_table meanwhile
+            if !executable.get_config().static_syscalls
+                || executable
+                    .get_function_registry()
+                    .contains_key(&(pc as u32))
+            {
+                lazy_compilation.dispatch_table[pc].store(host_address, Ordering::Release);
+            }
+        }
+        let index = tables
+            .call_return_addresses
+            .partition_point(|(target_pc, _)| *target_pc < unit.pcs.start);
+        tables
+            .call_return_addresses
+            .splice(index..index, unit_tables.call_return_addresses);
+        tables.relocations.extend(unit_tables.relocations);
+        Ok(())
+    }
+
+    /// Invokes the program at the pc in `registers[11]`
+    ///
+    /// `executable` has to be the one this program was compiled from. If `host_call_frames`
+    /// are given, the execution of a suspended program resumes, see `host_call_frames()`.
+    pub fn invoke<V: Verifier, C: ContextObject>(
+        &self,
+        executable: &Executable<V, C>,
         env: &mut RuntimeEnvironment<C>,
         registers: [u64; 12],
+        host_call_frames: Option<&[u64]>,
     ) -> i64 {
+        let config = executable.get_config();
+        env.executable = executable as *const _ as *const ();
+        let (host_target_address, host_call_frames) = match host_call_frames {
+            Some(host_call_frames) => (
+                self.tables.read().unwrap().instruction_addresses[registers[11] as usize],
+                host_call_frames,
+            ),
+            None => (self.dispatch(registers[11] as usize), &[][..]),
+        };
         unsafe {
-            let mut instruction_meter =
-                (env.previous_instruction_meter as i64).wrapping_add(registers[11] as i64);
+            let mut instruction_meter = (env.previous_instruction_meter as i64)
+                .wrapping_add(self.instruction_meter_offset(registers[11] as usize) as i64);
             std::arch::asm!(
                 // RBP and RBX must be saved and restored manually in the current version of rustc and llvm.
                 "push rbx",
                 "push rbp",
+                // Push the return address manually, so that the call frames of a resumed program can go on top of it
+                "lea rbp, [rip + 4f]",
+                "push rbp",
                 "mov [{host_stack_pointer}], rsp",
-                "add QWORD PTR [{host_stack_pointer}], -8", // We will push RIP in "call r10" later
+                "test rcx, rcx",
+                "jz 3f",
+                "2:",
+                "push QWORD PTR [rax]",
+                "add rax, 8",
+                "dec rcx",
+                "jnz 2b",
+                "3:",
                 "mov rbp, {rbp}",
                 "mov rbx, {rbx}",
                 "mov rax, [r11 + 0x00]",
@@ -118,16 +404,19 @@ impl JitProgram {
                 "mov r14, [r11 + 0x40]",
                 "mov r15, [r11 + 0x48]",
                 "mov r11, [r11 + 0x58]",
-                "call r10",
+                "jmp r10",
+                "4:",
                 "pop rbp",
                 "pop rbx",
                 host_stack_pointer = in(reg) &mut env.host_stack_pointer,
                 rbp = in(reg) (env as *mut _ as *mut u64).offset(config.runtime_environment_key as isize),
                 rbx = in(reg) registers[ebpf::FRAME_PTR_REG],
                 inlateout("rdi") instruction_meter,
-                inlateout("r10") self.pc_section[registers[11] as usize] => _,
+                inlateout("r10") host_target_address => _,
                 inlateout("r11") &registers => _,
-                lateout("rax") _, lateout("rsi") _, lateout("rdx") _, lateout("rcx") _, lateout("r8") _,
+                inlateout("rax") host_call_frames.as_ptr() => _,
+                inlateout("rcx") host_call_frames.len() => _,
+                lateout("rsi") _, lateout("rdx") _, lateout("r8") _,
                 lateout("r9") _, lateout("r12") _, lateout("r13") _, lateout("r14") _, lateout("r15") _,
                 // lateout("rbp") _, lateout("rbx") _,
             );
@@ -135,8 +424,526 @@ impl JitProgram {
         }
     }
 
+    /// Sum of the instruction costs from the beginning of the program up to `pc`
+    fn instruction_meter_offset(&self, pc: usize) -> u64 {
+        if self.instruction_meter_offsets.is_empty() {
+            pc as u64
+        } else {
+            self.instruction_meter_offsets[pc]
+        }
+    }
+
+    /// Lays out call frames the way the machine code keeps them on the host stack
+    ///
+    /// Returns `None` if a target_pc does not follow a call instruction.
+    pub(crate) fn host_call_frames<V: Verifier, C: ContextObject>(
+        &self,
+        executable: &Executable<V, C>,
+        call_frames: &[CallFrame],
+    ) -> Option<Vec<u64>> {
+        let mut host_call_frames = Vec::with_capacity(call_frames.len() * HOST_CALL_FRAME_SIZE);
+        for frame in call_frames {
+            if self.lazy_compilation.is_some() {
+                // The call instruction is in the unit the frame returns to
+                self.compile_lazily(executable, frame.target_pc.checked_sub(1)?)
+                    .ok()?;
+            }
+            let tables = self.tables.read().unwrap();
+            let index = tables
+                .call_return_addresses
+                .binary_search_by_key(&frame.target_pc, |(target_pc, _)| *target_pc)
+                .ok()?;
+            host_call_frames.extend_from_slice(&frame.caller_saved_registers);
+            host_call_frames.push(frame.frame_pointer);
+            host_call_frames.push(tables.call_return_addresses[index].1 as u64);
+        }
+        Some(host_call_frames)
+    }
+
+    /// Replaces the host return addresses captured by `suspend()` with the target_pc they belong to
+    pub(crate) fn resolve_call_frames(&self, call_frames: &mut [CallFrame]) {
+        let tables = self.tables.read().unwrap();
+        for frame in call_frames {
+            let index = tables
+                .call_return_addresses
+                .binary_search_by_key(&frame.target_pc, |(_, host_address)| *host_address);
+            debug_assert!(index.is_ok());
+            if let Ok(index) = index {
+                frame.target_pc = tables.call_return_addresses[index].0;
+            }
+        }
+    }
+
+    /// Serializes the machine code and its tables into a byte blob
+    ///
+    /// Host addresses are replaced by their relocations and host addresses inside the
+    /// text_section by their offsets. The blob starts with an HMAC keyed by `key` over the rest
+    /// of it and everything the machine code was compiled from, see `cache_mac()`.
+    /// Returns `None` if the functions are compiled lazily, as the machine code is incomplete.
+    pub(crate) fn serialize<V: Verifier, C: ContextObject>(
+        &self,
+        executable: &Executable<V, C>,
+        key: &[u8],
+    ) -> Option<Vec<u8>> {
+        if self.lazy_compilation.is_some() {
+            return None;
+        }
+        let tables = self.tables.read().unwrap();
+        let text_section_base = self.text_section as *const u8 as usize;
+        let mut text_section = self.machine_code(0..self.text_section.len()).to_vec();
+        for (offset, _relocation) in tables.relocations.iter() {
+            text_section[*offset..*offset + mem::size_of::<u64>()].fill(0);
+        }
+        let mut payload = Vec::new();
+        payload
+            .write_u64::<LittleEndian>(text_section.len() as u64)
+            .unwrap();
+        payload.extend_from_slice(&text_section);
+        payload
+            .write_u64::<LittleEndian>(self.pc_section.len() as u64)
+            .unwrap();
+        for host_address in self.pc_section().iter() {
+            payload
+                .write_u64::<LittleEndian>((host_address - text_section_base) as u64)
+                .unwrap();
+        }
+        payload
+            .write_u64::<LittleEndian>(tables.call_return_addresses.len() as u64)
+            .unwrap();
+        for (target_pc, host_address) in tables.call_return_addresses.iter() {
+            payload
+                .write_u64::<LittleEndian>(*target_pc as u64)
+                .unwrap();
+            payload
+                .write_u64::<LittleEndian>((*host_address - text_section_base) as u64)
+                .unwrap();
+        }
+        payload
+            .write_u64::<LittleEndian>(tables.instruction_addresses.len() as u64)
+            .unwrap();
+        for host_address in tables.instruction_addresses.iter() {
+            payload
+                .write_u64::<LittleEndian>((*host_address - text_section_base) as u64)
+                .unwrap();
+        }
+        for offsets in [
+            &self.instruction_meter_offsets,
+            &self.exceeded_instruction_meter_offsets,
+        ] {
+            payload
+                .write_u64::<LittleEndian>(offsets.len() as u64)
+                .unwrap();
+            for offset in offsets.iter() {
+                payload.write_u64::<LittleEndian>(*offset).unwrap();
+            }
+        }
+        payload
+            .write_u64::<LittleEndian>(tables.relocations.len() as u64)
+            .unwrap();
+        for (offset, relocation) in tables.relocations.iter() {
+            let (kind, argument) = relocation.encode();
+            payload.write_u64::<LittleEndian>(*offset as u64).unwrap();
+            payload.write_u8(kind).unwrap();
+            payload.write_u32::<LittleEndian>(argument).unwrap();
+        }
+        payload
+            .write_u64::<LittleEndian>(self.anchors.len() as u64)
+            .unwrap();
+        for (offset, anchor) in self.anchors.iter() {
+            payload.write_u64::<LittleEndian>(*offset as u64).unwrap();
+            payload.write_u8(*anchor as u8).unwrap();
+        }
+        let mut bytes = Vec::with_capacity(CACHE_MAGIC.len() + 4 + 32 + payload.len());
+        bytes.extend_from_slice(CACHE_MAGIC);
+        bytes.write_u32::<LittleEndian>(CACHE_VERSION).unwrap();
+        bytes.extend_from_slice(&cache_mac(key, executable, &payload));
+        bytes.extend_from_slice(&payload);
+        Some(bytes)
+    }
+
+    /// Loads a byte blob created by `serialize()` into executable memory
+    ///
+    /// Nothing is mapped unless the HMAC verifies with `key`, which rejects stale, corrupted
+    /// and forged blobs alike.
+    pub(crate) fn deserialize<V: Verifier, C: ContextObject>(
+        executable: &Executable<V, C>,
+        bytes: &[u8],
+        key: &[u8],
+    ) -> Result<Self, EbpfError> {
+        let header_length = CACHE_MAGIC.len() + 4 + 32;
+        if bytes.len() < header_length
+            || &bytes[0..CACHE_MAGIC.len()] != CACHE_MAGIC
+            || bytes[CACHE_MAGIC.len()..CACHE_MAGIC.len() + 4] != CACHE_VERSION.to_le_bytes()
+        {
+            return Err(EbpfError::InvalidJitCache);
+        }
+        let payload = &bytes[header_length..];
+        let mac = cache_mac(key, executable, payload);
+        // Compares in constant time, so that the MAC can not be guessed byte by byte
+        let difference = bytes[CACHE_MAGIC.len() + 4..header_length]
+            .iter()
+            .zip(mac.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        if difference != 0 {
+            return Err(EbpfError::InvalidJitCache);
+        }
+        let mut reader = Cursor::new(payload);
+        let text_section_length = read_cache_length(&mut reader, 1)?;
+        if text_section_length == 0 {
+            return Err(EbpfError::InvalidJitCache);
+        }
+        let text_section_offset = reader.position() as usize;
+        reader.set_position((text_section_offset + text_section_length) as u64);
+        let pc_count = read_cache_length(&mut reader, mem::size_of::<u64>())?;
+        let mut result = Self::new(pc_count, text_section_length)?;
+        let mut tables = JitTables::default();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                payload[text_section_offset..].as_ptr(),
+                result.text_section as *mut u8,
+                text_section_length,
+            );
+        }
+        let text_section_base = result.text_section as *const u8 as usize;
+        let read_host_address = |reader: &mut Cursor<&[u8]>| {
+            let offset = read_cache_u64(reader)? as usize;
+            if offset >= text_section_length {
+                return Err(EbpfError::InvalidJitCache);
+            }
+            Ok(text_section_base + offset)
+        };
+        for pc in 0..pc_count {
+            result.pc_section_mut()[pc] = read_host_address(&mut reader)?;
+        }
+        let call_return_address_count = read_cache_length(&mut reader, 2 * mem::size_of::<u64>())?;
+        for _ in 0..call_return_address_count {
+            let target_pc = read_cache_u64(&mut reader)? as usize;
+            let host_address = read_host_address(&mut reader)?;
+            tables.call_return_addresses.push((target_pc, host_address));
+        }
+        let instruction_address_count = read_cache_length(&mut reader, mem::size_of::<u64>())?;
+        if instruction_address_count != pc_count {
+            return Err(EbpfError::InvalidJitCache);
+        }
+        for _ in 0..instruction_address_count {
+            tables
+                .instruction_addresses
+                .push(read_host_address(&mut reader)?);
+        }
+        for offsets in [
+            &mut result.instruction_meter_offsets,
+            &mut result.exceeded_instruction_meter_offsets,
+        ] {
+            let offset_count = read_cache_length(&mut reader, mem::size_of::<u64>())?;
+            // Prefix sums with three more slots for the bumper at the end
+            if offset_count != 0 && offset_count != pc_count + 3 {
+                return Err(EbpfError::InvalidJitCache);
+            }
+            for _ in 0..offset_count {
+                offsets.push(read_cache_u64(&mut reader)?);
+            }
+        }
+        if result.instruction_meter_offsets.len() != result.exceeded_instruction_meter_offsets.len()
+        {
+            return Err(EbpfError::InvalidJitCache);
+        }
+        let relocation_count = read_cache_length(&mut reader, mem::size_of::<u64>() + 5)?;
+        for _ in 0..relocation_count {
+            let offset = read_cache_u64(&mut reader)? as usize;
+            let kind = reader.read_u8().map_err(|_| EbpfError::InvalidJitCache)?;
+            let argument = reader
+                .read_u32::<LittleEndian>()
+                .map_err(|_| EbpfError::InvalidJitCache)?;
+            let relocation =
+                Relocation::decode(kind, argument).ok_or(EbpfError::InvalidJitCache)?;
+            // A syscall which is not registered anymore also makes the cache stale
+            let address = relocation
+                .address(&result, executable)
+                .ok_or(EbpfError::InvalidJitCache)?;
+            if offset.saturating_add(mem::size_of::<u64>()) > text_section_length {
+                return Err(EbpfError::InvalidJitCache);
+            }
+            unsafe {
+                ptr::write_unaligned(
+                    (result.text_section as *mut u8).add(offset) as *mut u64,
+                    address as u64,
+                );
+            }
+            tables.relocations.push((offset, relocation));
+        }
+        let anchor_count = read_cache_length(&mut reader, mem::size_of::<u64>() + 1)?;
+        for _ in 0..anchor_count {
+            let offset = read_cache_u64(&mut reader)? as usize;
+            let anchor = reader.read_u8().map_err(|_| EbpfError::InvalidJitCache)? as usize;
+            if offset >= text_section_length || anchor >= ANCHOR_COUNT {
+                return Err(EbpfError::InvalidJitCache);
+            }
+            result.anchors.push((offset, anchor));
+        }
+        if reader.position() != payload.len() as u64 {
+            return Err(EbpfError::InvalidJitCache);
+        }
+        *result.tables.get_mut().unwrap() = tables;
+        result.seal(text_section_length)?;
+        Ok(result)
+    }
+
+    /// Registers the machine code with the perf map at `perf_map` and the GDB JIT compilation interface
+    ///
+    /// There is a symbol for every function in `function_registry`, spanning the machine code
+    /// up to the next function, and one for the subroutines in front of the first instruction.
+    pub(crate) fn register_symbols(
+        &mut self,
+        function_registry: &FunctionRegistry,
+        line_table: &LineTable,
+        perf_map: Option<&std::path::Path>,
+    ) -> std::io::Result<()> {
+        let (subroutines_length, units) = self.compiled_units();
+        let tables = self.tables.get_mut().unwrap();
+        let text_section_start = self.text_section as *const u8 as usize;
+        let text_section_end = text_section_start + self.text_section.len();
+        let first_instruction = text_section_start + subroutines_length;
+        let mut functions = function_registry
+            .values()
+            .filter(|(pc, _name)| *pc < tables.instruction_addresses.len())
+            .collect::<Vec<_>>();
+        functions.sort_unstable();
+        functions.dedup_by_key(|(pc, _name)| *pc);
+        let mut symbols = vec![JitSymbol {
+            name: "rbpf_jit_subroutines".to_string(),
+            start: text_section_start,
+            end: first_instruction,
+        }];
+        for unit in units.iter() {
+            let unit_end = text_section_start + unit.text.end;
+            let unit_functions = functions
+                .iter()
+                .filter(|(pc, _name)| unit.pcs.contains(pc))
+                .collect::<Vec<_>>();
+            for (index, (pc, name)) in unit_functions.iter().enumerate() {
+                symbols.push(JitSymbol {
+                    name: if name.is_empty() {
+                        format!("function_{}", pc)
+                    } else {
+                        name.clone()
+                    },
+                    start: tables.instruction_addresses[*pc],
+                    end: unit_functions
+                        .get(index + 1)
+                        .map(|(next_pc, _name)| tables.instruction_addresses[*next_pc])
+                        .unwrap_or(unit_end),
+                });
+            }
+        }
+        // The second half of lddw points to a subroutine, which would break the ordering
+        let mut lines = Vec::with_capacity(line_table.len());
+        let mut previous_address = first_instruction;
+        for (pc, (file, line)) in line_table.iter() {
+            let unit_end = match units.iter().find(|unit| unit.pcs.contains(pc)) {
+                Some(unit) => text_section_start + unit.text.end,
+                None => continue,
+            };
+            if let Some(address) = tables.instruction_addresses.get(*pc) {
+                if *address >= previous_address && *address < unit_end {
+                    lines.push((*address, file.as_str(), *line));
+                    previous_address = *address;
+                }
+            }
+        }
+        if let Some(perf_map) = perf_map {
+            write_perf_map(perf_map, &symbols)?;
+        }
+        self.gdb_registration = None;
+        self.gdb_registration = Some(GdbJitRegistration::new(elf_object(
+            (text_section_start, text_section_end),
+            &symbols,
+            &lines,
+        )));
+        Ok(())
+    }
+
+    /// The ELF object registered by `register_symbols()`
+    ///
+    /// Can be written to a file to symbolize the machine code offline.
+    pub fn gdb_jit_object(&self) -> Option<&[u8]> {
+        self.gdb_registration
+            .as_ref()
+            .map(|registration| registration.object())
+    }
+
+    /// Writes a listing of the machine code, interleaved with the instructions it implements
+    ///
+    /// The subroutines come first, each labeled by its anchor, followed by every instruction of
+    /// `analysis` and the machine code emitted for it. `analysis` has to be of the executable
+    /// this program was compiled from. Offsets are relative to the start of the machine code.
+    pub fn disassemble<W: std::io::Write>(
+        &self,
+        analysis: &Analysis,
+        output: &mut W,
+    ) -> std::io::Result<()> {
+        let (first_instruction, units) = self.compiled_units();
+        let tables = self.tables.read().unwrap();
+        let text_section_base = self.text_section as *const u8 as usize;
+        let unit_end = |pc: usize| {
+            units
+                .iter()
+                .find(|unit| unit.pcs.contains(&pc))
+                .map(|unit| unit.text.end)
+        };
+        let instruction_offset = |pc: usize| {
+            unit_end(pc)?;
+            tables
+                .instruction_addresses
+                .get(pc)
+                .map(|host_address| host_address - text_section_base)
+        };
+        let mut labels = BTreeMap::new();
+        for (offset, anchor) in self.anchors.iter() {
+            labels
+                .entry(*offset)
+                .or_insert_with(|| anchor_name(*anchor));
+        }
+        for (pc, cfg_node) in analysis.cfg_nodes.iter() {
+            if let Some(offset) = instruction_offset(*pc) {
+                labels
+                    .entry(offset)
+                    .or_insert_with(|| cfg_node.label.clone());
+            }
+        }
+        let relocations = tables
+            .relocations
+            .iter()
+            .copied()
+            .collect::<BTreeMap<_, _>>();
+        let first_anchor = self
+            .anchors
+            .first()
+            .map(|(offset, _anchor)| *offset)
+            .unwrap_or(first_instruction);
+        self.disassemble_machine_code(output, 0..first_anchor, &labels, &relocations)?;
+        for (index, (offset, anchor)) in self.anchors.iter().enumerate() {
+            writeln!(output, "{}:", anchor_name(*anchor))?;
+            let end = self
+                .anchors
+                .get(index + 1)
+                .map(|(next_offset, _anchor)| *next_offset)
+                .unwrap_or(first_instruction);
+            self.disassemble_machine_code(output, *offset..end, &labels, &relocations)?;
+        }
+        let mut last_basic_block = usize::MAX;
+        for (index, insn) in analysis.instructions.iter().enumerate() {
+            analysis.disassemble_label(output, false, insn.ptr, &mut last_basic_block)?;
+            writeln!(output, "    {}", analysis.disassemble_instruction(insn))?;
+            if let Some(start) = instruction_offset(insn.ptr) {
+                // The second half of lddw has no machine code of its own
+                let end = unit_end(insn.ptr).unwrap();
+                let end = analysis
+                    .instructions
+                    .get(index + 1)
+                    .filter(|next_insn| unit_end(next_insn.ptr) == Some(end))
+                    .and_then(|next_insn| instruction_offset(next_insn.ptr))
+                    .unwrap_or(end);
+                self.disassemble_machine_code(output, start..end, &labels, &relocations)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes one line per host instruction in `range` of the text_section
+    ///
+    /// Relative jumps and calls are annotated with the label they lead to and instructions
+    /// which embed a host address with their relocation.
+    fn disassemble_machine_code<W: std::io::Write>(
+        &self,
+        output: &mut W,
+        range: std::ops::Range<usize>,
+        labels: &BTreeMap<usize, String>,
+        relocations: &BTreeMap<usize, Relocation>,
+    ) -> std::io::Result<()> {
+        let mut offset = range.start;
+        let machine_code = self.machine_code(range.clone());
+        while offset < range.end {
+            let (length, mut text) =
+                match disassemble_x86_instruction(&machine_code[offset - range.start..], offset) {
+                    Some(instruction) => {
+                        let mut text = instruction.text;
+                        if let Some(label) =
+                            instruction.target.and_then(|target| labels.get(&target))
+                        {
+                            text.push_str(&format!(" <{}>", label));
+                        }
+                        (instruction.length, text)
+                    }
+                    None => (
+                        1,
+                        format!(".byte {:#04x}", machine_code[offset - range.start]),
+                    ),
+                };
+            if let Some((_offset, relocation)) = relocations.range(offset..offset + length).next() {
+                text.push_str(&format!(" # {:?}", relocation));
+            }
+            let bytes = machine_code[offset - range.start..offset + length - range.start]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(output, "        {:6x}:  {:<36} {}", offset, bytes, text)?;
+            offset += length;
+        }
+        Ok(())
+    }
+
+    /// Whether the instruction at `pc` has machine code, see `Executable::jit_compile_lazily()`
+    pub fn is_compiled(&self, pc: usize) -> bool {
+        let (_subroutines_length, units) = self.compiled_units();
+        units.iter().any(|unit| unit.pcs.contains(&pc))
+    }
+
+    /// Offset into the text_section where the subroutines end, followed by the units which
+    /// have machine code, each with the text range that machine code actually spans
+    fn compiled_units(&self) -> (usize, Vec<CompilationUnit>) {
+        match self.lazy_compilation.as_ref() {
+            Some(lazy_compilation) => {
+                let unit_ends = lazy_compilation.unit_ends.lock().unwrap();
+                let units = lazy_compilation
+                    .units
+                    .iter()
+                    .zip(unit_ends.iter())
+                    .filter_map(|(unit, unit_end)| {
+                        Some(CompilationUnit {
+                            pcs: unit.pcs.clone(),
+                            text: unit.text.start..(*unit_end)?,
+                        })
+                    })
+                    .collect();
+                (lazy_compilation.subroutines_length, units)
+            }
+            None => {
+                let subroutines_length = self
+                    .tables
+                    .read()
+                    .unwrap()
+                    .instruction_addresses
+                    .first()
+                    .map(|host_address| host_address - self.text_section as *const u8 as usize)
+                    .unwrap_or(self.text_section.len());
+                (
+                    subroutines_length,
+                    vec![CompilationUnit {
+                        pcs: 0..self.pc_section.len(),
+                        text: subroutines_length..self.text_section.len(),
+                    }],
+                )
+            }
+        }
+    }
+
+    /// Length of the machine code, of a lazily compiled program only what is compiled so far
     pub fn machine_code_length(&self) -> usize {
-        self.text_section.len()
+        let (subroutines_length, units) = self.compiled_units();
+        units
+            .iter()
+            .fold(subroutines_length, |length, unit| length + unit.text.len())
     }
 
     pub fn mem_size(&self) -> usize {
@@ -148,14 +955,13 @@ impl JitProgram {
 
 impl Drop for JitProgram {
     fn drop(&mut self) {
+        // Unregister before the machine code is gone
+        self.gdb_registration = None;
         let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
         let code_size = round_to_page_size(self.text_section.len(), self.page_size);
         if pc_loc_table_size + code_size > 0 {
             unsafe {
-                let _ = free_pages(
-                    self.pc_section.as_ptr() as *mut u8,
-                    pc_loc_table_size + code_size,
-                );
+                let _ = free_pages(self.pc_section as *mut u8, pc_loc_table_size + code_size);
             }
         }
     }
@@ -173,6 +979,246 @@ impl PartialEq for JitProgram {
     }
 }
 
+/// HMAC-SHA-256 (RFC 2104) over the message which `message` feeds into the inner hash
+fn hmac_sha256(key: &[u8], message: impl FnOnce(&mut Sha256)) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[0..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[0..key.len()].copy_from_slice(key);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(block.map(|byte| byte ^ 0x36));
+    message(&mut hasher);
+    let inner = hasher.finalize();
+    let mut hasher = Sha256::new();
+    hasher.update(block.map(|byte| byte ^ 0x5c));
+    hasher.update(inner);
+    hasher.finalize().into()
+}
+
+/// HMAC over a serialized JitProgram and everything its machine code depends on
+///
+/// That is the version of this crate, the ELF bytes, the `Config`, the function registry
+/// and the keys and names of the syscalls registered in the loader.
+fn cache_mac<V: Verifier, C: ContextObject>(
+    key: &[u8],
+    executable: &Executable<V, C>,
+    payload: &[u8],
+) -> [u8; 32] {
+    hmac_sha256(key, |hasher| {
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        let elf_bytes = executable.get_elf_bytes();
+        hasher.update((elf_bytes.len() as u64).to_le_bytes());
+        hasher.update(elf_bytes);
+        // The Debug output covers every field of the Config
+        let config = format!("{:?}", executable.get_config());
+        hasher.update((config.len() as u64).to_le_bytes());
+        hasher.update(config.as_bytes());
+        let function_registry = executable.get_function_registry();
+        hasher.update((function_registry.len() as u64).to_le_bytes());
+        for (key, (pc, name)) in function_registry.iter() {
+            hasher.update(key.to_le_bytes());
+            hasher.update((*pc as u64).to_le_bytes());
+            hasher.update((name.len() as u64).to_le_bytes());
+            hasher.update(name.as_bytes());
+        }
+        let mut syscalls = executable.get_loader().iter_functions().collect::<Vec<_>>();
+        syscalls.sort_unstable();
+        hasher.update((syscalls.len() as u64).to_le_bytes());
+        for (key, name) in syscalls {
+            hasher.update(key.to_le_bytes());
+            hasher.update((name.len() as u64).to_le_bytes());
+            hasher.update(name);
+        }
+        hasher.update(payload);
+    })
+}
+
+fn read_cache_u64(reader: &mut Cursor<&[u8]>) -> Result<u64, EbpfError> {
+    reader
+        .read_u64::<LittleEndian>()
+        .map_err(|_| EbpfError::InvalidJitCache)
+}
+
+/// Reads the number of elements of a table and checks that they are actually there
+fn read_cache_length(reader: &mut Cursor<&[u8]>, element_size: usize) -> Result<usize, EbpfError> {
+    let length = read_cache_u64(reader)?;
+    let remaining = (reader.get_ref().len() as u64).saturating_sub(reader.position());
+    if length > remaining / element_size as u64 {
+        return Err(EbpfError::InvalidJitCache);
+    }
+    Ok(length as usize)
+}
+
+/// Reads the call frames of the outermost `call_depth` calls from the host stack
+///
+/// The target_pc of the call frames are host return addresses until `JitProgram::resolve_call_frames()`.
+unsafe fn host_stack_call_frames<C: ContextObject>(
+    env: &RuntimeEnvironment<C>,
+    call_depth: u64,
+) -> Vec<CallFrame> {
+    // Each call frame consists of: return address, frame pointer, scratch registers in reverse order
+    (0..call_depth as usize)
+        .map(|depth| {
+            let host_call_frame = env
+                .host_stack_pointer
+                .sub((depth + 1) * HOST_CALL_FRAME_SIZE);
+            let mut caller_saved_registers = [0u64; ebpf::SCRATCH_REGS];
+            for (i, reg) in caller_saved_registers.iter_mut().enumerate() {
+                *reg = *host_call_frame.add(HOST_CALL_FRAME_SIZE - 1 - i);
+            }
+            CallFrame {
+                caller_saved_registers,
+                frame_pointer: *host_call_frame.add(1),
+                target_pc: *host_call_frame as usize,
+            }
+        })
+        .collect()
+}
+
+/// Called by ANCHOR_SUSPEND to capture the SuspendedState from the host stack
+///
+/// `registers` points to r0 to r10 followed by the pc to resume at.
+unsafe fn suspend<C: ContextObject>(env: &mut RuntimeEnvironment<C>, registers: *const [u64; 12]) {
+    let suspendable = match &env.program_result {
+        ProgramResult::Ok(_) => false,
+        ProgramResult::Err(err) => matches!(
+            err.downcast_ref::<EbpfError>(),
+            Some(EbpfError::ExceededMaxInstructions(_)) | Some(EbpfError::SyscallYield)
+        ),
+    };
+    if !suspendable {
+        return;
+    }
+    env.suspended_state = Some(SuspendedState {
+        registers: *registers,
+        call_depth: env.call_depth,
+        stack_pointer: env.stack_pointer,
+        call_frames: host_stack_call_frames(env, env.call_depth),
+        overdue_insn_count: 0,
+    });
+}
+
+/// Called by ANCHOR_THROW_EXCEPTION_UNCHECKED to capture the Backtrace from the host stack
+///
+/// `registers` points to r0 to r10 followed by the pc of the instruction which failed.
+unsafe fn backtrace<C: ContextObject>(
+    env: &mut RuntimeEnvironment<C>,
+    registers: *const [u64; 12],
+) {
+    let mut registers = *registers;
+    let mut call_frames = host_stack_call_frames(env, env.call_depth);
+    // The frame of a call which exceeded the max call depth or left the text segment is only
+    // partially pushed, but it holds the frame pointer of the caller
+    if let ProgramResult::Err(err) = &env.program_result {
+        if matches!(
+            err.downcast_ref::<EbpfError>(),
+            Some(EbpfError::CallDepthExceeded(_, _))
+                | Some(EbpfError::CallOutsideTextSegment(_, _))
+        ) {
+            if let Some(frame) = call_frames.pop() {
+                registers[ebpf::FRAME_PTR_REG] = frame.frame_pointer;
+            }
+        }
+    }
+    env.backtrace = Some(Backtrace {
+        registers,
+        call_frames,
+        frames: Vec::new(),
+    });
+}
+
+/// Called by ANCHOR_TRACE before every instruction
+///
+/// `registers` points to r0 to r10 followed by the pc of the instruction.
+unsafe fn trace<C: ContextObject>(env: &mut RuntimeEnvironment<C>, registers: *const [u64; 12]) {
+    env.context_object_pointer.trace(*registers);
+    if let Some(recording) = env.recording.as_mut() {
+        recording.instruction(*registers);
+    }
+}
+
+/// Called by ANCHOR_EXTERNAL_FUNCTION_CALL before a syscall if instructions are traced
+fn syscall_entry<C: ContextObject>(env: &mut RuntimeEnvironment<C>) {
+    if let Some(recording) = env.recording.as_mut() {
+        recording.syscall_entry(
+            env.context_object_pointer.get_remaining(),
+            &env.memory_mapping,
+        );
+    }
+}
+
+/// Called by ANCHOR_EXTERNAL_FUNCTION_CALL after a syscall if instructions are traced
+fn syscall_exit<C: ContextObject>(env: &mut RuntimeEnvironment<C>) {
+    if let Some(recording) = env.recording.as_mut() {
+        recording.syscall_exit(
+            env.context_object_pointer.get_remaining(),
+            &env.program_result,
+            &env.memory_mapping,
+        );
+    }
+}
+
+/// Called by ANCHOR_EPILOGUE to print the stop watch value
+fn stopwatch_result(numerator: u64, denominator: u64) {
+    println!(
+        "Stop watch: {} / {} = {}",
+        numerator,
+        denominator,
+        if denominator == 0 {
+            0.0
+        } else {
+            numerator as f64 / denominator as f64
+        }
+    );
+}
+
+/// Called by ANCHOR_ALLOCATE_EXCEPTION to box the error the machine code writes
+unsafe fn allocate_error(result: &mut ProgramResult) -> *mut EbpfError {
+    let err_ptr = std::alloc::alloc(std::alloc::Layout::new::<EbpfError>()) as *mut EbpfError;
+    *result = ProgramResult::Err(Box::from_raw(err_ptr));
+    err_ptr
+}
+
+/// Called by ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS to find the pc to report
+///
+/// That is the first pc at which the sum of the instruction costs reaches the instruction meter.
+unsafe fn exceeded_instruction_meter_pc(
+    offsets: *const u64,
+    length: usize,
+    instruction_meter: u64,
+) -> u64 {
+    std::slice::from_raw_parts(offsets, length)
+        .partition_point(|offset| *offset < instruction_meter) as u64
+}
+
+/// Called by ANCHOR_LAZY_COMPILE to compile the unit of `pc`
+///
+/// `executable` is the one of the invocation, see `RuntimeEnvironment::executable`.
+/// Returns the host address to continue at, which is ANCHOR_EPILOGUE if the compilation failed.
+unsafe fn compile_on_first_call<V: Verifier, C: ContextObject>(
+    lazy_compilation: *const LazyCompilation,
+    executable: *const Executable<V, C>,
+    pc: usize,
+    result: &mut ProgramResult,
+) -> usize {
+    let lazy_compilation = &*lazy_compilation;
+    let executable = &*executable;
+    match executable
+        .get_compiled_program()
+        .ok_or(EbpfError::JitNotCompiled)
+        .and_then(|program| program.compile_lazily(executable, pc))
+    {
+        Ok(host_address) => host_address,
+        Err(err) => {
+            *result = ProgramResult::Err(Box::new(err));
+            lazy_compilation.anchors[ANCHOR_EPILOGUE]
+        }
+    }
+}
+
 // Used to define subroutines and then call them
 // See JitCompiler::set_anchor() and JitCompiler::relative_to_anchor()
 const ANCHOR_TRACE: usize = 0;
@@ -191,9 +1237,49 @@ const ANCHOR_CALL_UNSUPPORTED_INSTRUCTION: usize = 12;
 const ANCHOR_EXTERNAL_FUNCTION_CALL: usize = 13;
 const ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_PROLOGUE: usize = 14;
 const ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_REG: usize = 15;
+const ANCHOR_SUSPEND: usize = 16;
+const ANCHOR_SYSCALL_YIELD: usize = 17;
+const ANCHOR_LAZY_COMPILE: usize = 18;
 const ANCHOR_TRANSLATE_MEMORY_ADDRESS: usize = 23;
 const ANCHOR_COUNT: usize = 32; // Update me when adding or removing anchors
 
+/// Label of an anchor in listings
+fn anchor_name(anchor: usize) -> String {
+    match anchor {
+        ANCHOR_TRACE => "trace",
+        ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS => "call_exceeded_max_instructions",
+        ANCHOR_EPILOGUE => "epilogue",
+        ANCHOR_ALLOCATE_EXCEPTION => "allocate_exception",
+        ANCHOR_THROW_EXCEPTION_UNCHECKED => "throw_exception_unchecked",
+        ANCHOR_EXIT => "exit",
+        ANCHOR_THROW_EXCEPTION => "throw_exception",
+        ANCHOR_ACCESS_VIOLATION => "access_violation",
+        ANCHOR_CALL_DEPTH_EXCEEDED => "call_depth_exceeded",
+        ANCHOR_CALL_OUTSIDE_TEXT_SEGMENT => "call_outside_text_segment",
+        ANCHOR_DIV_BY_ZERO => "div_by_zero",
+        ANCHOR_DIV_OVERFLOW => "div_overflow",
+        ANCHOR_CALL_UNSUPPORTED_INSTRUCTION => "call_unsupported_instruction",
+        ANCHOR_EXTERNAL_FUNCTION_CALL => "external_function_call",
+        ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_PROLOGUE => "internal_function_call_prologue",
+        ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_REG => "internal_function_call_reg",
+        ANCHOR_SUSPEND => "suspend",
+        ANCHOR_SYSCALL_YIELD => "syscall_yield",
+        ANCHOR_LAZY_COMPILE => "lazy_compile",
+        _ if (ANCHOR_TRANSLATE_MEMORY_ADDRESS..ANCHOR_TRANSLATE_MEMORY_ADDRESS + 8)
+            .contains(&anchor) =>
+        {
+            let target_offset = anchor - ANCHOR_TRANSLATE_MEMORY_ADDRESS;
+            return format!(
+                "translate_memory_address_{}{}",
+                if target_offset < 4 { "load" } else { "store" },
+                8 << (target_offset % 4),
+            );
+        }
+        _ => return format!("anchor_{}", anchor),
+    }
+    .to_string()
+}
+
 const REGISTER_MAP: [u8; 11] = [
     CALLER_SAVED_REGISTERS[0],
     ARGUMENT_REGISTERS[1],
@@ -211,7 +1297,7 @@ const REGISTER_MAP: [u8; 11] = [
 // Special registers:
 //     ARGUMENT_REGISTERS[0]  RDI  BPF program counter limit (used by instruction meter)
 // CALLER_SAVED_REGISTERS[8]  R11  Scratch register
-// CALLER_SAVED_REGISTERS[7]  R10  Unused for the most part, scratch register for exception handling
+// CALLER_SAVED_REGISTERS[7]  R10  Unused for the most part, scratch register for exception handling and sanitized branches
 // CALLEE_SAVED_REGISTERS[0]  RBP  Constant pointer to initial RSP - 8
 
 #[derive(Copy, Clone, Debug)]
@@ -229,6 +1315,7 @@ enum Value {
     RegisterPlusConstant32(u8, i32, bool),
     RegisterPlusConstant64(u8, i64, bool),
     Constant64(i64, bool),
+    Relocation(Relocation),
 }
 
 struct Argument {
@@ -242,6 +1329,127 @@ struct Jump {
     target_pc: usize,
 }
 
+/// Host address which is embedded in the machine code and differs between processes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Relocation {
+    Trace,
+    StopwatchResult,
+    AllocateError,
+    Suspend,
+    ExceededInstructionMeterPc,
+    Backtrace,
+    Consume,
+    GetRemaining,
+    /// MemoryMapping::load() of the given number of bytes
+    Load(u8),
+    /// MemoryMapping::store() of the given number of bytes
+    Store(u8),
+    /// The built-in function registered in the loader under the given key
+    Syscall(u32),
+    PcSection,
+    InstructionMeterOffsets,
+    ExceededInstructionMeterOffsets,
+    CompileOnFirstCall,
+    /// The state of a lazily compiled program
+    LazyCompilation,
+    SyscallEntry,
+    SyscallExit,
+}
+
+impl Relocation {
+    /// Resolves the host address, `None` if there is nothing to point to
+    fn address<V: Verifier, C: ContextObject>(
+        self,
+        program: &JitProgram,
+        executable: &Executable<V, C>,
+    ) -> Option<usize> {
+        Some(match self {
+            Self::Trace => trace::<C> as *const u8 as usize,
+            Self::StopwatchResult => stopwatch_result as *const u8 as usize,
+            Self::AllocateError => allocate_error as *const u8 as usize,
+            Self::Suspend => suspend::<C> as *const u8 as usize,
+            Self::ExceededInstructionMeterPc => exceeded_instruction_meter_pc as *const u8 as usize,
+            Self::Backtrace => backtrace::<C> as *const u8 as usize,
+            Self::Consume => C::consume as *const u8 as usize,
+            Self::GetRemaining => C::get_remaining as *const u8 as usize,
+            Self::Load(1) => MemoryMapping::load::<u8> as *const u8 as usize,
+            Self::Load(2) => MemoryMapping::load::<u16> as *const u8 as usize,
+            Self::Load(4) => MemoryMapping::load::<u32> as *const u8 as usize,
+            Self::Load(8) => MemoryMapping::load::<u64> as *const u8 as usize,
+            Self::Store(1) => MemoryMapping::store::<u8> as *const u8 as usize,
+            Self::Store(2) => MemoryMapping::store::<u16> as *const u8 as usize,
+            Self::Store(4) => MemoryMapping::store::<u32> as *const u8 as usize,
+            Self::Store(8) => MemoryMapping::store::<u64> as *const u8 as usize,
+            Self::Load(_) | Self::Store(_) => return None,
+            Self::Syscall(key) => executable.get_loader().lookup_function(key)?.1 as usize,
+            Self::PcSection => match program.lazy_compilation.as_ref() {
+                Some(lazy_compilation) => lazy_compilation.dispatch_table.as_ptr() as usize,
+                None => program.pc_section as *const usize as usize,
+            },
+            Self::InstructionMeterOffsets => program.instruction_meter_offsets.as_ptr() as usize,
+            Self::ExceededInstructionMeterOffsets => {
+                program.exceeded_instruction_meter_offsets.as_ptr() as usize
+            }
+            Self::CompileOnFirstCall => compile_on_first_call::<V, C> as *const u8 as usize,
+            Self::LazyCompilation => {
+                program.lazy_compilation.as_deref()? as *const LazyCompilation as usize
+            }
+            Self::SyscallEntry => syscall_entry::<C> as *const u8 as usize,
+            Self::SyscallExit => syscall_exit::<C> as *const u8 as usize,
+        })
+    }
+
+    /// Kind and argument of the serialized form
+    fn encode(self) -> (u8, u32) {
+        match self {
+            Self::Trace => (0, 0),
+            Self::StopwatchResult => (1, 0),
+            Self::AllocateError => (2, 0),
+            Self::Suspend => (3, 0),
+            Self::ExceededInstructionMeterPc => (4, 0),
+            Self::Backtrace => (5, 0),
+            Self::Consume => (6, 0),
+            Self::GetRemaining => (7, 0),
+            Self::Load(len) => (8, len as u32),
+            Self::Store(len) => (9, len as u32),
+            Self::Syscall(key) => (10, key),
+            Self::PcSection => (11, 0),
+            Self::InstructionMeterOffsets => (12, 0),
+            Self::ExceededInstructionMeterOffsets => (13, 0),
+            Self::CompileOnFirstCall => (14, 0),
+            Self::LazyCompilation => (15, 0),
+            Self::SyscallEntry => (16, 0),
+            Self::SyscallExit => (17, 0),
+        }
+    }
+
+    fn decode(kind: u8, argument: u32) -> Option<Self> {
+        let relocation = match kind {
+            0 => Self::Trace,
+            1 => Self::StopwatchResult,
+            2 => Self::AllocateError,
+            3 => Self::Suspend,
+            4 => Self::ExceededInstructionMeterPc,
+            5 => Self::Backtrace,
+            6 => Self::Consume,
+            7 => Self::GetRemaining,
+            8 => Self::Load(argument as u8),
+            9 => Self::Store(argument as u8),
+            10 => Self::Syscall(argument),
+            11 => Self::PcSection,
+            12 => Self::InstructionMeterOffsets,
+            13 => Self::ExceededInstructionMeterOffsets,
+            14 => Self::CompileOnFirstCall,
+            15 => Self::LazyCompilation,
+            16 => Self::SyscallEntry,
+            17 => Self::SyscallExit,
+            _ => return None,
+        };
+        // Also rejects arguments which do not fit or are not expected
+        (relocation.encode() == (kind, argument)).then_some(relocation)
+    }
+}
+
 /// Indices of slots inside RuntimeEnvironment
 enum RuntimeEnvironmentSlot {
     HostStackPointer = 0,
@@ -251,8 +1459,9 @@ enum RuntimeEnvironmentSlot {
     PreviousInstructionMeter = 4,
     StopwatchNumerator = 5,
     StopwatchDenominator = 6,
-    ProgramResult = 7,
-    MemoryMapping = 10,
+    Executable = 7,
+    ProgramResult = 8,
+    MemoryMapping = 11,
 }
 
 /* Explaination of the Instruction Meter
@@ -302,10 +1511,27 @@ enum RuntimeEnvironmentSlot {
     and then after returning use the undo profiling. The trick is, that the undo profiling now
     has the current pc which is the BPF return address. The virtual target pc we count towards
     and undo again can be anything, so we just set it to zero.
+
+    If Config::instruction_costs are not uniform, the cost function is no longer constant and the
+    pc stops being its antiderivative. Instead, the prefix sum of the instruction costs is used,
+    which is precomputed for every pc and stored in JitProgram::instruction_meter_offsets. All
+    of the above still applies, only with every pc replaced by its offset in that table. Where
+    the pc is only known at runtime (in R11), the offset is looked up in the table. When the limit
+    is reached, the pc to report is found by a binary search in the table.
 */
 
 pub struct JitCompiler<'a, V: Verifier, C: ContextObject> {
     result: JitProgram,
+    /// Where the machine code goes, the text_section of `parent` when compiling a unit
+    text_section: *mut u8,
+    /// Offset into `text_section` at which the machine code has to end
+    text_section_length: usize,
+    /// The lazily compiled program which a unit is compiled for
+    parent: Option<&'a JitProgram>,
+    /// The instructions to compile
+    unit: Range<usize>,
+    /// How to emit each instruction of `unit`
+    optimizations: Vec<Optimization>,
     text_section_jumps: Vec<Jump>,
     anchors: [*const u8; ANCHOR_COUNT],
     offset_in_text_section: usize,
@@ -320,12 +1546,30 @@ pub struct JitCompiler<'a, V: Verifier, C: ContextObject> {
     stopwatch_is_active: bool,
 }
 
+/// Upper bound of the machine code length of the subroutines and `instruction_count` instructions
+fn code_length_estimate(config: &Config, instruction_count: usize) -> usize {
+    let mut code_length_estimate = MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH
+        + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION * instruction_count;
+    if config.noop_instruction_rate != 0 {
+        code_length_estimate += code_length_estimate / config.noop_instruction_rate as usize;
+    }
+    if config.instruction_meter_checkpoint_distance != 0 {
+        let mut checkpoint_length = MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT;
+        if config.enable_suspension {
+            checkpoint_length += MACHINE_CODE_PER_SUSPENSION_CHECKPOINT;
+        }
+        code_length_estimate +=
+            instruction_count / config.instruction_meter_checkpoint_distance * checkpoint_length;
+    }
+    code_length_estimate
+}
+
 #[rustfmt::skip]
 impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
     /// Constructs a new compiler and allocates memory for the compilation output
     pub fn new(executable: &'a Executable<V, C>) -> Result<Self, EbpfError> {
         let config = executable.get_config();
-        let (program_vm_addr, program) = executable.get_text_bytes();
+        let (_program_vm_addr, program) = executable.get_text_bytes();
 
         // Scan through program to find actual number of instructions
         let mut pc = 0;
@@ -337,17 +1581,65 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
             };
         }
 
-        let mut code_length_estimate = MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION * pc;
-        if config.noop_instruction_rate != 0 {
-            code_length_estimate += code_length_estimate / config.noop_instruction_rate as usize;
+        let mut result = JitProgram::new(pc, code_length_estimate(config, pc))?;
+        if config.enable_instruction_meter && !config.instruction_costs.is_uniform() {
+            // Prefix sums of the instruction costs, with three more slots for the bumper at the end
+            let mut offsets = Vec::with_capacity(pc + 3);
+            let mut exceeded_offsets = Vec::with_capacity(pc + 3);
+            let mut offset = 0u64;
+            let mut second_half_of_lddw = false;
+            for slot in 0..pc + 3 {
+                offsets.push(offset);
+                // The interpreter reports the pc after the lddw, so skip its second half
+                exceeded_offsets.push(if second_half_of_lddw { exceeded_offsets[slot - 1] } else { offset });
+                let cost = if second_half_of_lddw {
+                    // The lddw is charged once, like in the interpreter
+                    second_half_of_lddw = false;
+                    0
+                } else if (slot + 1) * ebpf::INSN_SIZE <= program.len() {
+                    let insn = ebpf::get_insn_unchecked(program, slot);
+                    second_half_of_lddw = insn.opc == ebpf::LD_DW_IMM;
+                    executable.get_instruction_cost(&insn)
+                } else {
+                    config.instruction_costs.get_execution_overrun()
+                };
+                offset += cost;
+                if offset > i32::MAX as u64 {
+                    return Err(EbpfError::InstructionCostOverflow(slot + ebpf::ELF_INSN_DUMP_OFFSET));
+                }
+            }
+            result.instruction_meter_offsets = offsets;
+            result.exceeded_instruction_meter_offsets = exceeded_offsets;
         }
-        if config.instruction_meter_checkpoint_distance != 0 {
-            code_length_estimate += pc / config.instruction_meter_checkpoint_distance * MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT;
+        Self::with_result(executable, result, None)
+    }
+
+    /// Constructs a compiler for a unit of `parent`, see `JitProgram::compile_lazily()`
+    fn new_for_unit(executable: &'a Executable<V, C>, parent: &'a JitProgram) -> Result<Self, EbpfError> {
+        let mut result = JitProgram::new(parent.pc_section.len(), 0)?;
+        result.instruction_meter_offsets = parent.instruction_meter_offsets.clone();
+        result.exceeded_instruction_meter_offsets = parent.exceeded_instruction_meter_offsets.clone();
+        let mut compiler = Self::with_result(executable, result, Some(parent))?;
+        compiler.text_section = parent.text_section as *mut u8;
+        if let Some(lazy_compilation) = parent.lazy_compilation.as_ref() {
+            for (anchor, host_address) in compiler.anchors.iter_mut().zip(lazy_compilation.anchors.iter()) {
+                *anchor = *host_address as *const u8;
+            }
         }
-        
+        Ok(compiler)
+    }
+
+    fn with_result(executable: &'a Executable<V, C>, result: JitProgram, parent: Option<&'a JitProgram>) -> Result<Self, EbpfError> {
+        let config = executable.get_config();
+        let (program_vm_addr, program) = executable.get_text_bytes();
         let mut diversification_rng = SmallRng::from_rng(rand::thread_rng()).map_err(|_| EbpfError::JitNotCompiled)?;
         Ok(Self {
-            result: JitProgram::new(pc, code_length_estimate)?,
+            text_section: (result.text_section as *mut u8),
+            text_section_length: result.text_section.len(),
+            parent,
+            unit: 0..result.pc_section.len(),
+            optimizations: Vec::new(),
+            result,
             text_section_jumps: vec![],
             anchors: [std::ptr::null(); ANCHOR_COUNT],
             offset_in_text_section: 0,
@@ -365,16 +1657,137 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
 
     /// Compiles the given executable, consuming the compiler
     pub fn compile(mut self) -> Result<JitProgram, EbpfError> {
-        let text_section_base = self.result.text_section.as_ptr();
+        self.emit_subroutines();
+        self.compile_instructions()?;
+        self.resolve_jumps();
+        self.result.seal(self.offset_in_text_section)?;
+        Ok(self.result)
+    }
+
+    /// Compiles only the subroutines, each unit of functions follows on its first call
+    ///
+    /// The instructions are partitioned into units at function boundaries which no jump
+    /// crosses and no instruction falls through. Each unit has pages of its own reserved,
+    /// in the order of the pcs, so that the host addresses remain sorted like the pcs.
+    pub fn compile_lazily(mut self) -> Result<JitProgram, EbpfError> {
+        let pc_count = self.result.pc_section.len();
+        let subroutines_size = round_to_page_size(MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH, get_system_page_size());
+        let mut text_offset = subroutines_size;
+        let units = self.compilation_units().into_iter().map(|pcs| {
+            let start = text_offset;
+            text_offset += round_to_page_size(code_length_estimate(self.config, pcs.len()), get_system_page_size());
+            CompilationUnit { pcs, text: start..text_offset }
+        }).collect::<Vec<_>>();
+        let mut functions = self.executable.get_function_registry().values().map(|(pc, _name)| *pc).collect::<Vec<_>>();
+        functions.sort_unstable();
+        functions.dedup();
+        let mut result = JitProgram::new(pc_count, text_offset)?;
+        result.instruction_meter_offsets = mem::take(&mut self.result.instruction_meter_offsets);
+        result.exceeded_instruction_meter_offsets = mem::take(&mut self.result.exceeded_instruction_meter_offsets);
+        result.lazy_compilation = Some(Box::new(LazyCompilation {
+            anchors: [0; ANCHOR_COUNT],
+            subroutines_length: 0,
+            unit_ends: Mutex::new(vec![None; units.len()]),
+            units,
+            hotness: functions.iter().map(|_| AtomicU64::new(0)).collect(),
+            functions,
+            dispatch_table: (0..pc_count).map(|_| AtomicUsize::new(0)).collect(),
+        }));
+        self.text_section = result.text_section as *mut u8;
+        self.text_section_length = subroutines_size;
+        self.result = result;
 
         self.emit_subroutines();
+        if self.offset_in_text_section > self.text_section_length {
+            return Err(EbpfError::ExhaustedTextSegment(0));
+        }
+        let lazy_compile = self.anchors[ANCHOR_LAZY_COMPILE] as usize;
+        let call_unsupported_instruction = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
+        let tables = self.result.tables.get_mut().unwrap();
+        tables.instruction_addresses = vec![lazy_compile; pc_count];
+        if let Some(lazy_compilation) = self.result.lazy_compilation.as_mut() {
+            for (pc, host_address) in lazy_compilation.dispatch_table.iter_mut().enumerate() {
+                // Same as `resolve_jumps()`, only the functions can be called with static syscalls
+                *host_address.get_mut() = if self.config.static_syscalls && !self.executable.get_function_registry().contains_key(&(pc as u32)) {
+                    call_unsupported_instruction
+                } else {
+                    lazy_compile
+                };
+            }
+            for (host_address, anchor) in lazy_compilation.anchors.iter_mut().zip(self.anchors.iter()) {
+                *host_address = *anchor as usize;
+            }
+            lazy_compilation.subroutines_length = self.offset_in_text_section;
+        }
+        self.result.seal_subroutines(self.offset_in_text_section)?;
+        Ok(self.result)
+    }
 
-        while self.pc * ebpf::INSN_SIZE < self.program.len() {
-            if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.result.text_section.len() {
+    /// Compiles a unit into the pages reserved for it in `parent`
+    ///
+    /// Returns the host addresses in the tables and pc_section of a JitProgram without
+    /// machine code of its own and the offset into the text_section where the unit ends.
+    fn compile_unit(mut self, unit: &CompilationUnit) -> Result<(JitProgram, usize), EbpfError> {
+        self.unit = unit.pcs.clone();
+        self.pc = unit.pcs.start;
+        self.last_instruction_meter_validation_pc = unit.pcs.start;
+        self.offset_in_text_section = unit.text.start;
+        self.text_section_length = unit.text.end;
+        self.compile_instructions()?;
+        self.relocate_forward_jumps();
+        Ok((self.result, self.offset_in_text_section))
+    }
+
+    /// Splits the instructions into the units compiled by `JitProgram::compile_lazily()`
+    fn compilation_units(&self) -> Vec<Range<usize>> {
+        let pc_count = self.result.pc_section.len();
+        // Difference of the number of jumps crossing the boundary in front of a pc to the previous one
+        let mut crossing_jumps = vec![0isize; pc_count + 2];
+        // Whether the instruction in front of a pc can not fall through
+        let mut is_separable = vec![false; pc_count + 1];
+        let mut pc = 0;
+        while pc < pc_count {
+            let insn = ebpf::get_insn_unchecked(self.program, pc);
+            if insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP && !matches!(insn.opc, ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT) {
+                let target_pc = (pc as isize + insn.off as isize + 1).clamp(0, pc_count as isize) as usize;
+                crossing_jumps[pc.min(target_pc) + 1] += 1;
+                crossing_jumps[pc.max(target_pc) + 1] -= 1;
+            }
+            pc += if insn.opc == ebpf::LD_DW_IMM { 2 } else { 1 };
+            if pc <= pc_count {
+                is_separable[pc] = matches!(insn.opc, ebpf::EXIT | ebpf::JA);
+            }
+        }
+        let mut crossing_jump_count = 0;
+        for difference in crossing_jumps.iter_mut() {
+            crossing_jump_count += *difference;
+            *difference = crossing_jump_count;
+        }
+        let mut functions = self.executable.get_function_registry().values().map(|(pc, _name)| *pc).collect::<Vec<_>>();
+        functions.sort_unstable();
+        let mut units = Vec::new();
+        let mut start = 0;
+        for pc in functions {
+            if pc > start && pc < pc_count && is_separable[pc] && crossing_jumps[pc] == 0 {
+                units.push(start..pc);
+                start = pc;
+            }
+        }
+        if start < pc_count {
+            units.push(start..pc_count);
+        }
+        units
+    }
+
+    /// Emits the instructions of `unit`, followed by the bumper if it is the last one
+    fn compile_instructions(&mut self) -> Result<(), EbpfError> {
+        self.optimizations = optimize(self.executable, self.unit.clone());
+        while self.pc < self.unit.end {
+            if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.text_section_length {
                 return Err(EbpfError::ExhaustedTextSegment(self.pc));
             }
             let mut insn = ebpf::get_insn_unchecked(self.program, self.pc);
-            self.result.pc_section[self.pc] = unsafe { text_section_base.add(self.offset_in_text_section) } as usize;
+            self.result.pc_section_mut()[self.pc] = unsafe { self.text_section.add(self.offset_in_text_section) } as usize;
 
             // Regular instruction meter checkpoints to prevent long linear runs from exceeding their budget
             if self.last_instruction_meter_validation_pc + self.config.instruction_meter_checkpoint_distance <= self.pc {
@@ -391,6 +1804,23 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
             let src = REGISTER_MAP[insn.src as usize];
             let target_pc = (self.pc as isize + insn.off as isize + 1) as usize;
 
+            match self.optimizations[self.pc - self.unit.start] {
+                Optimization::None => {},
+                // Registers are observed at checkpoints, so the move only goes if none comes before the overwrite
+                Optimization::DeadMove(overwritten_at) if self.config.enable_instruction_meter
+                    && self.last_instruction_meter_validation_pc + self.config.instruction_meter_checkpoint_distance <= overwritten_at => {},
+                Optimization::ImmediateSource(imm) => {
+                    insn.opc &= !ebpf::BPF_X;
+                    insn.imm = imm;
+                },
+                Optimization::StaticBranch(true) => insn.opc = ebpf::JA,
+                optimization => {
+                    self.emit_optimized_instruction(&insn, dst, optimization);
+                    self.pc += 1;
+                    continue;
+                },
+            }
+
             match insn.opc {
                 _ if insn.dst == STACK_PTR_REG as u8 && self.config.dynamic_stack_frames => {
                     let stack_ptr_access = X86IndirectAccess::Offset(self.slot_on_environment_stack(RuntimeEnvironmentSlot::StackPointer));
@@ -407,7 +1837,7 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
                 ebpf::LD_DW_IMM  => {
                     self.emit_validate_and_profile_instruction_count(true, Some(self.pc + 2));
                     self.pc += 1;
-                    self.result.pc_section[self.pc] = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
+                    self.result.pc_section_mut()[self.pc] = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
                     ebpf::augment_lddw_unchecked(self.program, &mut insn);
                     if self.should_sanitize_constant(insn.imm) {
                         self.emit_sanitized_load_immediate(OperandSize::S64, dst, insn.imm);
@@ -598,14 +2028,16 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
                         (true, true)
                     };
 
-                    if external {
-                        if let Some((_function_name, function)) = self.executable.get_loader().lookup_function(insn.imm as u32) {
-                            self.emit_validate_and_profile_instruction_count(true, Some(0));
-                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, function as usize as i64));
-                            self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_EXTERNAL_FUNCTION_CALL, 5)));
-                            self.emit_undo_profile_instruction_count(0);
-                            resolved = true;
+                    if external && self.executable.get_loader().lookup_function(insn.imm as u32).is_some() {
+                        self.emit_validate_and_profile_instruction_count(true, Some(0));
+                        if self.config.enable_suspension {
+                            // ANCHOR_SYSCALL_YIELD needs to know where to resume
+                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R10, self.pc as i64));
                         }
+                        self.emit_load_relocated(R11, Relocation::Syscall(insn.imm as u32));
+                        self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_EXTERNAL_FUNCTION_CALL, 5)));
+                        self.emit_undo_profile_instruction_count(0);
+                        resolved = true;
                     }
 
                     if internal {
@@ -625,17 +2057,20 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
                 },
                 ebpf::EXIT      => {
                     let call_depth_access = X86IndirectAccess::Offset(self.slot_on_environment_stack(RuntimeEnvironmentSlot::CallDepth));
-                    self.emit_ins(X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], call_depth_access));
 
                     // If CallDepth == 0, we've reached the exit instruction of the entry point
-                    self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S32, REGISTER_MAP[FRAME_PTR_REG], 0, None));
+                    self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S32, RBP, 0, Some(call_depth_access)));
                     if self.config.enable_instruction_meter {
                         self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, self.pc as i64));
                     }
                     // we're done
                     self.emit_ins(X86Instruction::conditional_jump_immediate(0x84, self.relative_to_anchor(ANCHOR_EXIT, 6)));
 
+                    // Validate before the frame is popped, so that a suspension sees a consistent state
+                    self.emit_validate_instruction_count(false, Some(self.pc));
+
                     // else decrement and update CallDepth
+                    self.emit_ins(X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], call_depth_access));
                     self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, REGISTER_MAP[FRAME_PTR_REG], 1, None));
                     self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], RBP, call_depth_access));
 
@@ -646,7 +2081,9 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
                     }
 
                     // and return
-                    self.emit_validate_and_profile_instruction_count(false, Some(0));
+                    if self.config.enable_instruction_meter {
+                        self.emit_profile_instruction_count(Some(0));
+                    }
                     self.emit_ins(X86Instruction::return_near());
                 },
 
@@ -657,17 +2094,76 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         }
 
         // Bumper in case there was no final exit
-        if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.result.text_section.len() {
-            return Err(EbpfError::ExhaustedTextSegment(self.pc));
-        }        
-        self.emit_validate_and_profile_instruction_count(true, Some(self.pc + 2));
-        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, self.pc as i64));
-        self.emit_set_exception_kind(EbpfError::ExecutionOverrun(0));
-        self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 5)));
+        if self.unit.end == self.result.pc_section.len() {
+            if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.text_section_length {
+                return Err(EbpfError::ExhaustedTextSegment(self.pc));
+            }
+            self.emit_validate_and_profile_instruction_count(true, Some(self.pc + 2));
+            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, self.pc as i64));
+            self.emit_set_exception_kind(EbpfError::ExecutionOverrun(0));
+            self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 5)));
+        }
+        Ok(())
+    }
 
-        self.resolve_jumps();
-        self.result.seal(self.offset_in_text_section)?;
-        Ok(self.result)
+    /// Emits an instruction which `optimize()` replaced
+    fn emit_optimized_instruction(&mut self, insn: &ebpf::Insn, dst: u8, optimization: Optimization) {
+        match optimization {
+            Optimization::Constant(value) => {
+                if self.should_sanitize_constant(value as i64) {
+                    self.emit_sanitized_load_immediate(OperandSize::S64, dst, value as i64);
+                } else {
+                    self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, dst, value as i64));
+                }
+            },
+            Optimization::StaticBranch(false) => {
+                // Validate like the conditional branch, so that the checkpoints stay the same
+                self.emit_validate_instruction_count(false, Some(self.pc));
+            },
+            Optimization::ForwardLoad(reg) => {
+                let src = REGISTER_MAP[reg as usize];
+                match insn.opc {
+                    ebpf::LD_B_REG => {
+                        self.emit_ins(X86Instruction::mov(OperandSize::S64, src, dst));
+                        self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, 0xff, None));
+                    },
+                    ebpf::LD_H_REG => {
+                        self.emit_ins(X86Instruction::mov(OperandSize::S64, src, dst));
+                        self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, 0xffff, None));
+                    },
+                    ebpf::LD_W_REG => self.emit_ins(X86Instruction::mov(OperandSize::S32, src, dst)),
+                    _ if src != dst => self.emit_ins(X86Instruction::mov(OperandSize::S64, src, dst)),
+                    _ => {},
+                }
+            },
+            Optimization::ConstantAddress(vm_addr) => {
+                let vm_addr = Value::Constant64(vm_addr as i64, true);
+                let src = REGISTER_MAP[insn.src as usize];
+                match insn.opc {
+                    ebpf::LD_B_REG   => self.emit_address_translation(Some(dst), vm_addr, 1, None),
+                    ebpf::LD_H_REG   => self.emit_address_translation(Some(dst), vm_addr, 2, None),
+                    ebpf::LD_W_REG   => self.emit_address_translation(Some(dst), vm_addr, 4, None),
+                    ebpf::LD_DW_REG  => self.emit_address_translation(Some(dst), vm_addr, 8, None),
+                    ebpf::ST_B_IMM   => self.emit_address_translation(None, vm_addr, 1, Some(Value::Constant64(insn.imm, true))),
+                    ebpf::ST_H_IMM   => self.emit_address_translation(None, vm_addr, 2, Some(Value::Constant64(insn.imm, true))),
+                    ebpf::ST_W_IMM   => self.emit_address_translation(None, vm_addr, 4, Some(Value::Constant64(insn.imm, true))),
+                    ebpf::ST_DW_IMM  => self.emit_address_translation(None, vm_addr, 8, Some(Value::Constant64(insn.imm, true))),
+                    ebpf::ST_B_REG   => self.emit_address_translation(None, vm_addr, 1, Some(Value::Register(src))),
+                    ebpf::ST_H_REG   => self.emit_address_translation(None, vm_addr, 2, Some(Value::Register(src))),
+                    ebpf::ST_W_REG   => self.emit_address_translation(None, vm_addr, 4, Some(Value::Register(src))),
+                    ebpf::ST_DW_REG  => self.emit_address_translation(None, vm_addr, 8, Some(Value::Register(src))),
+                    _ => {
+                        #[cfg(debug_assertions)]
+                        unreachable!();
+                    },
+                }
+            },
+            Optimization::DeadMove(_) | Optimization::Redundant => {},
+            Optimization::None | Optimization::ImmediateSource(_) | Optimization::StaticBranch(true) => {
+                #[cfg(debug_assertions)]
+                unreachable!();
+            },
+        }
     }
 
     #[inline]
@@ -698,7 +2194,7 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
     #[inline]
     pub(crate) fn emit<T>(&mut self, data: T) {
         unsafe {
-            let ptr = self.result.text_section.as_ptr().add(self.offset_in_text_section);
+            let ptr = self.text_section.add(self.offset_in_text_section);
             #[allow(clippy::cast_ptr_alignment)]
             ptr::write_unaligned(ptr as *mut T, data as T);
         }
@@ -800,25 +2296,54 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         if !self.config.enable_instruction_meter {
             return;
         }
-        // Update `MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT` if you change the code generation here
+        // Update `MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT` and `MACHINE_CODE_PER_SUSPENSION_CHECKPOINT` if you change the code generation here
         if let Some(pc) = pc {
             self.last_instruction_meter_validation_pc = pc;
-            self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, ARGUMENT_REGISTERS[0], pc as i64 + 1, None));
-        } else {
+            if self.config.enable_suspension {
+                // ANCHOR_SUSPEND needs to know where to resume
+                self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, pc as i64));
+            }
+            if exclusive {
+                self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, ARGUMENT_REGISTERS[0], self.instruction_meter_offset(pc) + 1, None));
+            } else {
+                self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, ARGUMENT_REGISTERS[0], self.instruction_meter_offset(pc + 1), None));
+            }
+            self.emit_ins(X86Instruction::conditional_jump_immediate(if exclusive { 0x82 } else { 0x86 }, self.relative_to_anchor(ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS, 6)));
+        } else if self.result.instruction_meter_offsets.is_empty() {
+            // Before or after the instruction at R11 are the same, because it costs one
             self.emit_ins(X86Instruction::cmp(OperandSize::S64, R11, ARGUMENT_REGISTERS[0], None));
+            self.emit_ins(X86Instruction::conditional_jump_immediate(0x86, self.relative_to_anchor(ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS, 6)));
+        } else {
+            // Exclusive: instruction_meter <= offset(R11), inclusive: instruction_meter < offset(R11 + 1)
+            self.emit_ins(X86Instruction::push(R10, None));
+            self.emit_load_relocated(R10, Relocation::InstructionMeterOffsets);
+            self.emit_ins(X86Instruction::load(OperandSize::S64, R10, R10, X86IndirectAccess::OffsetIndexShift(if exclusive { 0 } else { 8 }, R11, 3)));
+            self.emit_ins(X86Instruction::cmp(OperandSize::S64, R10, ARGUMENT_REGISTERS[0], None));
+            self.emit_ins(X86Instruction::pop(R10));
+            self.emit_ins(X86Instruction::conditional_jump_immediate(if exclusive { 0x86 } else { 0x82 }, self.relative_to_anchor(ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS, 6)));
         }
-        self.emit_ins(X86Instruction::conditional_jump_immediate(if exclusive { 0x82 } else { 0x86 }, self.relative_to_anchor(ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS, 6)));
+    }
+
+    /// Sum of the instruction costs from the beginning of the program up to `pc`
+    #[inline]
+    fn instruction_meter_offset(&self, pc: usize) -> i64 {
+        self.result.instruction_meter_offset(pc) as i64
     }
 
     #[inline]
     fn emit_profile_instruction_count(&mut self, target_pc: Option<usize>) {
         match target_pc {
             Some(target_pc) => {
-                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, ARGUMENT_REGISTERS[0], target_pc as i64 - self.pc as i64 - 1, None)); // instruction_meter += target_pc - (self.pc + 1);
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, ARGUMENT_REGISTERS[0], self.instruction_meter_offset(target_pc) - self.instruction_meter_offset(self.pc + 1), None)); // instruction_meter += offset(target_pc) - offset(self.pc + 1);
             },
             None => {
-                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, ARGUMENT_REGISTERS[0], self.pc as i64 + 1, None)); // instruction_meter -= self.pc + 1;
-                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x01, R11, ARGUMENT_REGISTERS[0], self.pc as i64, None)); // instruction_meter += target_pc;
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, ARGUMENT_REGISTERS[0], self.instruction_meter_offset(self.pc + 1), None)); // instruction_meter -= offset(self.pc + 1);
+                if self.result.instruction_meter_offsets.is_empty() {
+                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x01, R11, ARGUMENT_REGISTERS[0], self.pc as i64, None)); // instruction_meter += target_pc;
+                } else {
+                    self.emit_load_relocated(R10, Relocation::InstructionMeterOffsets);
+                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x03, ARGUMENT_REGISTERS[0], R10, 0, Some(X86IndirectAccess::OffsetIndexShift(0, R11, 3)))); // instruction_meter += offset(target_pc);
+                }
             },
         }
     }
@@ -834,10 +2359,18 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
     #[inline]
     fn emit_undo_profile_instruction_count(&mut self, target_pc: usize) {
         if self.config.enable_instruction_meter {
-            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, ARGUMENT_REGISTERS[0], self.pc as i64 + 1 - target_pc as i64, None)); // instruction_meter += (self.pc + 1) - target_pc;
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, ARGUMENT_REGISTERS[0], self.instruction_meter_offset(self.pc + 1) - self.instruction_meter_offset(target_pc), None)); // instruction_meter += offset(self.pc + 1) - offset(target_pc);
         }
     }
 
+    fn emit_load_relocated(&mut self, destination: u8, relocation: Relocation) {
+        let address = relocation.address(self.parent.unwrap_or(&self.result), self.executable);
+        debug_assert!(address.is_some());
+        // The address follows the REX prefix and the opcode
+        self.result.tables.get_mut().unwrap().relocations.push((self.offset_in_text_section + 2, relocation));
+        self.emit_ins(X86Instruction::load_immediate_address(destination, address.unwrap_or(0) as i64));
+    }
+
     fn emit_rust_call(&mut self, dst: Value, arguments: &[Argument], result_reg: Option<u8>) {
         let mut saved_registers = CALLER_SAVED_REGISTERS.to_vec();
         if let Some(reg) = result_reg {
@@ -902,6 +2435,10 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
                     debug_assert!(!user_provided && !is_stack_argument);
                     self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, dst, value));
                 },
+                Value::Relocation(relocation) => {
+                    debug_assert!(!is_stack_argument);
+                    self.emit_load_relocated(dst, relocation);
+                },
             }
         }
     
@@ -914,6 +2451,10 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
                 self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, RAX, value));
                 self.emit_ins(X86Instruction::call_reg(RAX, None));
             },
+            Value::Relocation(relocation) => {
+                self.emit_load_relocated(RAX, relocation);
+                self.emit_ins(X86Instruction::call_reg(RAX, None));
+            },
             _ => {
                 #[cfg(debug_assertions)]
                 unreachable!();
@@ -937,6 +2478,8 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         // Store PC in case the bounds check fails
         self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, self.pc as i64));
 
+        // Validate before the prologue pushes the call frame, so that a suspension sees a consistent state
+        self.emit_validate_instruction_count(false, Some(self.pc));
         self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_PROLOGUE, 5)));
 
         match dst {
@@ -949,17 +2492,27 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
 
                 self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_REG, 5)));
 
-                self.emit_validate_and_profile_instruction_count(false, None);
+                if self.config.enable_instruction_meter {
+                    self.emit_profile_instruction_count(None);
+                }
                 self.emit_ins(X86Instruction::mov(OperandSize::S64, REGISTER_MAP[0], R10));
                 self.emit_ins(X86Instruction::pop(REGISTER_MAP[0])); // Restore RAX
-                self.emit_ins(X86Instruction::call_reg(R10, None)); // callq *%r10
+                X86Instruction::call_reg(R10, None).emit(self); // callq *%r10
             },
             Value::Constant64(target_pc, user_provided) => {
                 debug_assert!(!user_provided);
-                self.emit_validate_and_profile_instruction_count(false, Some(target_pc as usize));
+                if self.config.enable_instruction_meter {
+                    self.emit_profile_instruction_count(Some(target_pc as usize));
+                }
                 self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, target_pc));
-                let jump_offset = self.relative_to_target_pc(target_pc as usize, 5);
-                self.emit_ins(X86Instruction::call_immediate(jump_offset));
+                if self.unit.contains(&(target_pc as usize)) {
+                    let jump_offset = self.relative_to_target_pc(target_pc as usize, 5);
+                    X86Instruction::call_immediate(jump_offset).emit(self);
+                } else {
+                    // Another unit, which might not be compiled yet
+                    self.emit_load_relocated(R10, Relocation::PcSection);
+                    X86Instruction::call_reg(R10, Some(X86IndirectAccess::Offset(target_pc as i32 * 8))).emit(self);
+                }
             },
             _ => {
                 #[cfg(debug_assertions)]
@@ -967,6 +2520,9 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
             }
         }
 
+        // The calls above bypass emit_ins(), so that no noop can be inserted in front of their return address
+        let return_address = unsafe { self.text_section.add(self.offset_in_text_section) } as usize;
+        self.result.tables.get_mut().unwrap().call_return_addresses.push((self.pc + 1, return_address));
         self.emit_undo_profile_instruction_count(0);
 
         // Restore the previous frame pointer
@@ -1045,12 +2601,13 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
     #[inline]
     fn emit_conditional_branch_reg(&mut self, op: u8, bitwise: bool, first_operand: u8, second_operand: u8, target_pc: usize) {
         self.emit_validate_and_profile_instruction_count(false, Some(target_pc));
+        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, target_pc as i64));
+        // Adjacent to the conditional jump without a noop in between, so that the CPU fuses them
         if bitwise { // Logical
-            self.emit_ins(X86Instruction::test(OperandSize::S64, first_operand, second_operand, None));
+            X86Instruction::test(OperandSize::S64, first_operand, second_operand, None).emit(self);
         } else { // Arithmetic
-            self.emit_ins(X86Instruction::cmp(OperandSize::S64, first_operand, second_operand, None));
+            X86Instruction::cmp(OperandSize::S64, first_operand, second_operand, None).emit(self);
         }
-        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, target_pc as i64));
         let jump_offset = self.relative_to_target_pc(target_pc, 6);
         self.emit_ins(X86Instruction::conditional_jump_immediate(op, jump_offset));
         self.emit_undo_profile_instruction_count(target_pc);
@@ -1060,18 +2617,24 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
     fn emit_conditional_branch_imm(&mut self, op: u8, bitwise: bool, immediate: i64, second_operand: u8, target_pc: usize) {
         self.emit_validate_and_profile_instruction_count(false, Some(target_pc));
         if self.should_sanitize_constant(immediate) {
-            self.emit_sanitized_load_immediate(OperandSize::S64, R11, immediate);
+            // Clobbers R11 if the immediate does not fit into 32 bits
+            self.emit_sanitized_load_immediate(OperandSize::S64, R10, immediate);
+            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, target_pc as i64));
+            // Adjacent to the conditional jump without a noop in between, so that the CPU fuses them
             if bitwise { // Logical
-                self.emit_ins(X86Instruction::test(OperandSize::S64, R11, second_operand, None));
+                X86Instruction::test(OperandSize::S64, R10, second_operand, None).emit(self);
             } else { // Arithmetic
-                self.emit_ins(X86Instruction::cmp(OperandSize::S64, R11, second_operand, None));
+                X86Instruction::cmp(OperandSize::S64, R10, second_operand, None).emit(self);
+            }
+        } else {
+            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, target_pc as i64));
+            // Adjacent to the conditional jump without a noop in between, so that the CPU fuses them
+            if bitwise { // Logical
+                X86Instruction::test_immediate(OperandSize::S64, second_operand, immediate, None).emit(self);
+            } else { // Arithmetic
+                X86Instruction::cmp_immediate(OperandSize::S64, second_operand, immediate, None).emit(self);
             }
-        } else if bitwise { // Logical
-            self.emit_ins(X86Instruction::test_immediate(OperandSize::S64, second_operand, immediate, None));
-        } else { // Arithmetic
-            self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, second_operand, immediate, None));
         }
-        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, R11, target_pc as i64));
         let jump_offset = self.relative_to_target_pc(target_pc, 6);
         self.emit_ins(X86Instruction::conditional_jump_immediate(op, jump_offset));
         self.emit_undo_profile_instruction_count(target_pc);
@@ -1224,9 +2787,9 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
             }
             self.emit_ins(X86Instruction::mov(OperandSize::S64, RSP, REGISTER_MAP[0]));
             self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, - 8 * 3, None)); // RSP -= 8 * 3;
-            self.emit_rust_call(Value::Constant64(C::trace as *const u8 as i64, false), &[
+            self.emit_rust_call(Value::Relocation(Relocation::Trace), &[
                 Argument { index: 1, value: Value::Register(REGISTER_MAP[0]) }, // registers
-                Argument { index: 0, value: Value::RegisterIndirect(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::ContextObjectPointer), false) },
+                Argument { index: 0, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::HostStackPointer), false) },
             ], None);
             // Pop stack and return
             self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, 8 * 3, None)); // RSP += 8 * 3;
@@ -1239,15 +2802,17 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         // Epilogue
         self.set_anchor(ANCHOR_EPILOGUE);
         if self.config.enable_instruction_meter {
-            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, ARGUMENT_REGISTERS[0], 1, None)); // instruction_meter -= 1;
-            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x29, R11, ARGUMENT_REGISTERS[0], 0, None)); // instruction_meter -= pc;
+            if self.result.instruction_meter_offsets.is_empty() {
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, ARGUMENT_REGISTERS[0], 1, None)); // instruction_meter -= 1;
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x29, R11, ARGUMENT_REGISTERS[0], 0, None)); // instruction_meter -= pc;
+            } else {
+                self.emit_load_relocated(R10, Relocation::InstructionMeterOffsets);
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x2B, ARGUMENT_REGISTERS[0], R10, 0, Some(X86IndirectAccess::OffsetIndexShift(8, R11, 3)))); // instruction_meter -= offset(pc + 1);
+            }
         }
         // Print stop watch value
-        fn stopwatch_result(numerator: u64, denominator: u64) {
-            println!("Stop watch: {} / {} = {}", numerator, denominator, if denominator == 0 { 0.0 } else { numerator as f64 / denominator as f64 });
-        }
         if self.stopwatch_is_active {
-            self.emit_rust_call(Value::Constant64(stopwatch_result as *const u8 as i64, false), &[
+            self.emit_rust_call(Value::Relocation(Relocation::StopwatchResult), &[
                 Argument { index: 1, value: Value::RegisterIndirect(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::StopwatchDenominator), false) },
                 Argument { index: 0, value: Value::RegisterIndirect(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::StopwatchNumerator), false) },
             ], None);
@@ -1258,25 +2823,91 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
 
         // Routine for allocating errors
         self.set_anchor(ANCHOR_ALLOCATE_EXCEPTION);
-        unsafe fn allocate_error(result: &mut ProgramResult) -> *mut EbpfError {
-            let err_ptr = std::alloc::alloc(std::alloc::Layout::new::<EbpfError>()) as *mut EbpfError;
-            *result = ProgramResult::Err(Box::from_raw(err_ptr));
-            err_ptr
-        }
         self.emit_ins(X86Instruction::lea(OperandSize::S64, RBP, R10, Some(X86IndirectAccess::Offset(self.slot_on_environment_stack(RuntimeEnvironmentSlot::ProgramResult)))));
-        self.emit_rust_call(Value::Constant64(allocate_error as usize as i64, false), &[
+        self.emit_rust_call(Value::Relocation(Relocation::AllocateError), &[
             Argument { index: 0, value: Value::Register(R10) },
         ], Some(R10));
         self.emit_ins(X86Instruction::return_near());
 
+        // Routine for suspending the program, expects the pc to resume at in R11
+        if self.config.enable_suspension {
+            self.set_anchor(ANCHOR_SUSPEND);
+            // Save registers on stack
+            self.emit_ins(X86Instruction::push(R11, None));
+            for reg in REGISTER_MAP.iter().rev() {
+                self.emit_ins(X86Instruction::push(*reg, None));
+            }
+            self.emit_ins(X86Instruction::mov(OperandSize::S64, RSP, REGISTER_MAP[0]));
+            // Align the stack, the epilogue restores it anyway
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 4, RSP, -16, None)); // RSP &= -16;
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, -8, None)); // RSP -= 8;
+            self.emit_rust_call(Value::Relocation(Relocation::Suspend), &[
+                Argument { index: 1, value: Value::Register(REGISTER_MAP[0]) }, // registers
+                Argument { index: 0, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], None);
+            // The instruction at R11 was not executed, so don't count it in the epilogue
+            if self.result.instruction_meter_offsets.is_empty() {
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, ARGUMENT_REGISTERS[0], 1, None)); // instruction_meter += 1;
+            } else {
+                self.emit_load_relocated(R10, Relocation::InstructionMeterOffsets);
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x03, ARGUMENT_REGISTERS[0], R10, 0, Some(X86IndirectAccess::OffsetIndexShift(8, R11, 3)))); // instruction_meter += offset(pc + 1);
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x2B, ARGUMENT_REGISTERS[0], R10, 0, Some(X86IndirectAccess::OffsetIndexShift(0, R11, 3)))); // instruction_meter -= offset(pc);
+            }
+            self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EPILOGUE, 5)));
+
+            // Routine for syscalls which failed, expects the pc of the call in R10
+            self.set_anchor(ANCHOR_SYSCALL_YIELD);
+            self.emit_ins(X86Instruction::lea(OperandSize::S64, R10, R11, Some(X86IndirectAccess::Offset(1)))); // R11 = R10 + 1;
+            if self.result.instruction_meter_offsets.is_empty() {
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x01, R11, ARGUMENT_REGISTERS[0], 0, None)); // instruction_meter += R11;
+            } else {
+                self.emit_load_relocated(R10, Relocation::InstructionMeterOffsets);
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x03, ARGUMENT_REGISTERS[0], R10, 0, Some(X86IndirectAccess::OffsetIndexShift(0, R11, 3)))); // instruction_meter += offset(R11);
+            }
+            self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_SUSPEND, 5)));
+        }
+
         // Handler for EbpfError::ExceededMaxInstructions
         self.set_anchor(ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS);
+        if self.config.enable_suspension {
+            self.emit_ins(X86Instruction::push(R11, None));
+        }
         self.emit_set_exception_kind(EbpfError::ExceededMaxInstructions(0));
-        self.emit_ins(X86Instruction::mov(OperandSize::S64, ARGUMENT_REGISTERS[0], R11)); // R11 = instruction_meter;
+        if self.result.instruction_meter_offsets.is_empty() {
+            self.emit_ins(X86Instruction::mov(OperandSize::S64, ARGUMENT_REGISTERS[0], R11)); // R11 = instruction_meter;
+        } else {
+            let exceeded_offsets_length = self.result.exceeded_instruction_meter_offsets.len() as i64;
+            self.emit_rust_call(Value::Relocation(Relocation::ExceededInstructionMeterPc), &[
+                Argument { index: 2, value: Value::Register(ARGUMENT_REGISTERS[0]) },
+                Argument { index: 1, value: Value::Constant64(exceeded_offsets_length, false) },
+                Argument { index: 0, value: Value::Relocation(Relocation::ExceededInstructionMeterOffsets) },
+            ], Some(R11));
+        }
+        if self.config.enable_suspension {
+            self.emit_ins(X86Instruction::store(OperandSize::S64, R11, R10, X86IndirectAccess::Offset(std::mem::size_of::<u64>() as i32))); // result.pc = R11;
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, R10, ebpf::ELF_INSN_DUMP_OFFSET as i64, Some(X86IndirectAccess::Offset(std::mem::size_of::<u64>() as i32)))); // result.pc += ebpf::ELF_INSN_DUMP_OFFSET;
+            self.emit_ins(X86Instruction::pop(R11));
+            self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_SUSPEND, 5)));
+        }
         // Fall through
 
         // Epilogue for errors
         self.set_anchor(ANCHOR_THROW_EXCEPTION_UNCHECKED);
+        if self.config.enable_backtrace {
+            // Save registers on stack
+            self.emit_ins(X86Instruction::push(R11, None));
+            for reg in REGISTER_MAP.iter().rev() {
+                self.emit_ins(X86Instruction::push(*reg, None));
+            }
+            self.emit_ins(X86Instruction::mov(OperandSize::S64, RSP, REGISTER_MAP[0]));
+            // Align the stack, the epilogue restores it anyway
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 4, RSP, -16, None)); // RSP &= -16;
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, -8, None)); // RSP -= 8;
+            self.emit_rust_call(Value::Relocation(Relocation::Backtrace), &[
+                Argument { index: 1, value: Value::Register(REGISTER_MAP[0]) }, // registers
+                Argument { index: 0, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], None);
+        }
         self.emit_ins(X86Instruction::store(OperandSize::S64, R11, R10, X86IndirectAccess::Offset(std::mem::size_of::<u64>() as i32))); // result.pc = self.pc;
         self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, R10, ebpf::ELF_INSN_DUMP_OFFSET as i64, Some(X86IndirectAccess::Offset(std::mem::size_of::<u64>() as i32)))); // result.pc += ebpf::ELF_INSN_DUMP_OFFSET;
         self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EPILOGUE, 5)));
@@ -1292,7 +2923,7 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         // Handler for exceptions which report their pc
         self.set_anchor(ANCHOR_THROW_EXCEPTION);
         // Validate that we did not reach the instruction meter limit before the exception occured
-        self.emit_validate_instruction_count(false, None);
+        self.emit_validate_instruction_count(true, None);
         self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION_UNCHECKED, 5)));
 
         // Handler for EbpfError::AccessViolation
@@ -1310,6 +2941,7 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         self.set_anchor(ANCHOR_CALL_OUTSIDE_TEXT_SEGMENT);
         self.emit_set_exception_kind(EbpfError::CallOutsideTextSegment(0, 0));
         self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_MAP[0], R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * 2) as i32))); // target_address = RAX;
+        self.emit_ins(X86Instruction::load(OperandSize::S64, RSP, REGISTER_MAP[0], X86IndirectAccess::OffsetIndexShift(8, RSP, 0))); // Restore RAX, which emit_internal_call() pushed before calling this routine
         self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 5)));
 
         // Handler for EbpfError::DivideByZero
@@ -1337,11 +2969,16 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
             // RDI = *PreviousInstructionMeter - RDI;
             self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x2B, ARGUMENT_REGISTERS[0], RBP, 0, Some(X86IndirectAccess::Offset(self.slot_on_environment_stack(RuntimeEnvironmentSlot::PreviousInstructionMeter))))); // RDI -= *PreviousInstructionMeter;
             self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xf7, 3, ARGUMENT_REGISTERS[0], 0, None)); // RDI = -RDI;
-            self.emit_rust_call(Value::Constant64(C::consume as *const u8 as i64, false), &[
+            self.emit_rust_call(Value::Relocation(Relocation::Consume), &[
                 Argument { index: 1, value: Value::Register(ARGUMENT_REGISTERS[0]) },
                 Argument { index: 0, value: Value::RegisterIndirect(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::ContextObjectPointer), false) },
             ], None);
         }
+        if self.config.enable_instruction_tracing {
+            self.emit_rust_call(Value::Relocation(Relocation::SyscallEntry), &[
+                Argument { index: 0, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], None);
+        }
         self.emit_rust_call(Value::Register(R11), &[
             Argument { index: 7, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::ProgramResult), false) },
             Argument { index: 6, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::MemoryMapping), false) },
@@ -1352,8 +2989,13 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
             Argument { index: 1, value: Value::Register(ARGUMENT_REGISTERS[1]) },
             Argument { index: 0, value: Value::RegisterIndirect(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::ContextObjectPointer), false) },
         ], None);
+        if self.config.enable_instruction_tracing {
+            self.emit_rust_call(Value::Relocation(Relocation::SyscallExit), &[
+                Argument { index: 0, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], None);
+        }
         if self.config.enable_instruction_meter {
-            self.emit_rust_call(Value::Constant64(C::get_remaining as *const u8 as i64, false), &[
+            self.emit_rust_call(Value::Relocation(Relocation::GetRemaining), &[
                 Argument { index: 0, value: Value::RegisterIndirect(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::ContextObjectPointer), false) },
             ], Some(ARGUMENT_REGISTERS[0]));
             self.emit_ins(X86Instruction::store(OperandSize::S64, ARGUMENT_REGISTERS[0], RBP, X86IndirectAccess::Offset(self.slot_on_environment_stack(RuntimeEnvironmentSlot::PreviousInstructionMeter)))); // *PreviousInstructionMeter = RDI;
@@ -1362,7 +3004,8 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         // Test if result indicates that an error occured
         self.emit_result_is_err(R11);
         self.emit_ins(X86Instruction::pop(R11));
-        self.emit_ins(X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(ANCHOR_EPILOGUE, 6)));
+        let error_handler = if self.config.enable_suspension { ANCHOR_SYSCALL_YIELD } else { ANCHOR_EPILOGUE };
+        self.emit_ins(X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(error_handler, 6)));
         // Store Ok value in result register
         self.emit_ins(X86Instruction::lea(OperandSize::S64, RBP, R11, Some(X86IndirectAccess::Offset(self.slot_on_environment_stack(RuntimeEnvironmentSlot::ProgramResult)))));
         self.emit_ins(X86Instruction::load(OperandSize::S64, R11, REGISTER_MAP[0], X86IndirectAccess::Offset(8)));
@@ -1423,13 +3066,31 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xc1, 5, R11, shift_amount as i64, None));
         // Load host target_address from self.result.pc_section
         debug_assert_eq!(INSN_SIZE, 8); // Because the instruction size is also the slot size we do not need to shift the offset
-        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], self.result.pc_section.as_ptr() as i64));
+        self.emit_load_relocated(REGISTER_MAP[FRAME_PTR_REG], Relocation::PcSection);
         self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x01, REGISTER_MAP[FRAME_PTR_REG], REGISTER_MAP[0], 0, None)); // RAX += self.result.pc_section;
-        self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_MAP[0], REGISTER_MAP[0], X86IndirectAccess::Offset(0))); // RAX = self.result.pc_section[RAX / 8];
+        self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_MAP[0], REGISTER_MAP[0], X86IndirectAccess::Offset(0))); // RAX = self.result.pc_section_mut()[RAX / 8];
         // Load the frame pointer again since we've clobbered REGISTER_MAP[FRAME_PTR_REG]
         self.emit_ins(X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], X86IndirectAccess::Offset(self.slot_on_environment_stack(RuntimeEnvironmentSlot::StackPointer))));
         self.emit_ins(X86Instruction::return_near());
 
+        // Routine for compiling a unit on its first call, expects the pc in R11
+        if self.result.lazy_compilation.is_some() {
+            self.set_anchor(ANCHOR_LAZY_COMPILE);
+            // Align the stack, but keep the return address of the call in place
+            self.emit_ins(X86Instruction::mov(OperandSize::S64, RSP, R10));
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 4, RSP, -16, None)); // RSP &= -16;
+            self.emit_ins(X86Instruction::push(R10, None));
+            self.emit_ins(X86Instruction::push(R10, None));
+            self.emit_rust_call(Value::Relocation(Relocation::CompileOnFirstCall), &[
+                Argument { index: 3, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::ProgramResult), false) },
+                Argument { index: 2, value: Value::Register(R11) },
+                Argument { index: 1, value: Value::RegisterIndirect(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::Executable), false) },
+                Argument { index: 0, value: Value::Relocation(Relocation::LazyCompilation) },
+            ], Some(R10));
+            self.emit_ins(X86Instruction::load(OperandSize::S64, RSP, RSP, X86IndirectAccess::OffsetIndexShift(0, RSP, 0))); // RSP = *RSP;
+            self.emit_ins(X86Instruction::jump_reg(R10, None));
+        }
+
         // Translates a vm memory address to a host memory address
         for (access_type, len) in &[
             (AccessType::Load, 1i32),
@@ -1445,28 +3106,14 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
             self.set_anchor(ANCHOR_TRANSLATE_MEMORY_ADDRESS + target_offset);
             // call MemoryMapping::(load|store) storing the result in RuntimeEnvironmentSlot::ProgramResult
             if *access_type == AccessType::Load {
-                let load = match len {
-                    1 => MemoryMapping::load::<u8> as *const u8 as i64,
-                    2 => MemoryMapping::load::<u16> as *const u8 as i64,
-                    4 => MemoryMapping::load::<u32> as *const u8 as i64,
-                    8 => MemoryMapping::load::<u64> as *const u8 as i64,
-                    _ => unreachable!()
-                };
-                self.emit_rust_call(Value::Constant64(load, false), &[
+                self.emit_rust_call(Value::Relocation(Relocation::Load(*len as u8)), &[
                     Argument { index: 2, value: Value::Register(R11) }, // Specify first as the src register could be overwritten by other arguments
                     Argument { index: 3, value: Value::Constant64(0, false) }, // self.pc is set later
                     Argument { index: 1, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::MemoryMapping), false) },
                     Argument { index: 0, value: Value::RegisterPlusConstant32(RBP, self.slot_on_environment_stack(RuntimeEnvironmentSlot::ProgramResult), false) },
                 ], None);
             } else {
-                let store = match len {
-                    1 => MemoryMapping::store::<u8> as *const u8 as i64,
-                    2 => MemoryMapping::store::<u16> as *const u8 as i64,
-                    4 => MemoryMapping::store::<u32> as *const u8 as i64,
-                    8 => MemoryMapping::store::<u64> as *const u8 as i64,
-                    _ => unreachable!()
-                };
-                self.emit_rust_call(Value::Constant64(store, false), &[
+                self.emit_rust_call(Value::Relocation(Relocation::Store(*len as u8)), &[
                     Argument { index: 3, value: Value::Register(R11) }, // Specify first as the src register could be overwritten by other arguments
                     Argument { index: 2, value: Value::Register(R10) },
                     Argument { index: 4, value: Value::Constant64(0, false) }, // self.pc is set later
@@ -1490,14 +3137,15 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
     }
 
     fn set_anchor(&mut self, anchor: usize) {
-        self.anchors[anchor] = unsafe { self.result.text_section.as_ptr().add(self.offset_in_text_section) };
+        self.anchors[anchor] = unsafe { self.text_section.add(self.offset_in_text_section) };
+        self.result.anchors.push((self.offset_in_text_section, anchor));
     }
 
     // instruction_length = 5 (Unconditional jump / call)
     // instruction_length = 6 (Conditional jump)
     #[inline]
     fn relative_to_anchor(&self, anchor: usize, instruction_length: usize) -> i32 {
-        let instruction_end = unsafe { self.result.text_section.as_ptr().add(self.offset_in_text_section).add(instruction_length) };
+        let instruction_end = unsafe { self.text_section.add(self.offset_in_text_section).add(instruction_length) };
         let destination = self.anchors[anchor];
         debug_assert!(!destination.is_null());
         (unsafe { destination.offset_from(instruction_end) } as i32) // Relative jump
@@ -1505,10 +3153,10 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
 
     #[inline]
     fn relative_to_target_pc(&mut self, target_pc: usize, instruction_length: usize) -> i32 {
-        let instruction_end = unsafe { self.result.text_section.as_ptr().add(self.offset_in_text_section).add(instruction_length) };
-        let destination = if self.result.pc_section[target_pc] != 0 {
+        let instruction_end = unsafe { self.text_section.add(self.offset_in_text_section).add(instruction_length) };
+        let destination = if self.result.pc_section_mut()[target_pc] != 0 {
             // Backward jump
-            self.result.pc_section[target_pc] as *const u8
+            self.result.pc_section_mut()[target_pc] as *const u8
         } else {
             // Forward jump, needs relocation
             self.text_section_jumps.push(Jump { location: unsafe { instruction_end.sub(4) }, target_pc });
@@ -1518,15 +3166,19 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
         (unsafe { destination.offset_from(instruction_end) } as i32) // Relative jump
     }
 
-    fn resolve_jumps(&mut self) {
-        // Relocate forward jumps
+    fn relocate_forward_jumps(&mut self) {
         for jump in &self.text_section_jumps {
-            let destination = self.result.pc_section[jump.target_pc] as *const u8;
+            let destination = self.result.pc_section_mut()[jump.target_pc] as *const u8;
             let offset_value = 
                 unsafe { destination.offset_from(jump.location) } as i32 // Relative jump
                 - mem::size_of::<i32>() as i32; // Jump from end of instruction
             unsafe { ptr::write_unaligned(jump.location as *mut i32, offset_value); }
         }
+    }
+
+    fn resolve_jumps(&mut self) {
+        self.relocate_forward_jumps();
+        self.result.tables.get_mut().unwrap().instruction_addresses = self.result.pc_section_mut().to_vec();
         // There is no `VerifierError::JumpToMiddleOfLDDW` for `call imm` so patch it here
         let call_unsupported_instruction = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
         if self.config.static_syscalls {
@@ -1536,12 +3188,12 @@ impl<'a, V: Verifier, C: ContextObject> JitCompiler<'a, V, C> {
                     break;
                 }
                 for pc in prev_pc..*current_pc as usize {
-                    self.result.pc_section[pc] = call_unsupported_instruction;
+                    self.result.pc_section_mut()[pc] = call_unsupported_instruction;
                 }
                 prev_pc = *current_pc as usize + 1;
             }
             for pc in prev_pc..self.result.pc_section.len() {
-                self.result.pc_section[pc] = call_unsupported_instruction;
+                self.result.pc_section_mut()[pc] = call_unsupported_instruction;
             }
         }
     }
@@ -1570,9 +3222,13 @@ mod tests {
             previous_instruction_meter: 0,
             stopwatch_numerator: 0,
             stopwatch_denominator: 0,
+            executable: std::ptr::null(),
             program_result: ProgramResult::Ok(0),
             memory_mapping: MemoryMapping::new(Vec::new(), &config).unwrap(),
             call_frames: Vec::new(),
+            suspended_state: None,
+            backtrace: None,
+            recording: None,
         };
 
         macro_rules! check_slot {
@@ -1594,10 +3250,39 @@ mod tests {
         check_slot!(env, previous_instruction_meter, PreviousInstructionMeter);
         check_slot!(env, stopwatch_numerator, StopwatchNumerator);
         check_slot!(env, stopwatch_denominator, StopwatchDenominator);
+        check_slot!(env, executable, Executable);
         check_slot!(env, program_result, ProgramResult);
         check_slot!(env, memory_mapping, MemoryMapping);
     }
 
+    #[test]
+    fn test_hmac_sha256() {
+        // Test cases 2 and 6 of RFC 4231
+        let mac = hmac_sha256(b"Jefe", |hasher| {
+            hasher.update(b"what do ya want ");
+            hasher.update(b"for nothing?");
+        });
+        assert_eq!(
+            mac,
+            [
+                0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+                0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+                0x64, 0xec, 0x38, 0x43,
+            ]
+        );
+        let mac = hmac_sha256(&[0xaa; 131], |hasher| {
+            hasher.update(b"Test Using Larger Than Block-Size Key - Hash Key First");
+        });
+        assert_eq!(
+            mac,
+            [
+                0x60, 0xe4, 0x31, 0x59, 0x1e, 0xe0, 0xb6, 0x7f, 0x0d, 0x8a, 0x26, 0xaa, 0xcb, 0xf5,
+                0xb7, 0x7f, 0x8e, 0x0b, 0xc6, 0x21, 0x37, 0x28, 0xc5, 0x14, 0x05, 0x46, 0x04, 0x0f,
+                0x0e, 0xe3, 0x7f, 0x54,
+            ]
+        );
+    }
+
     fn create_mockup_executable(
         program: &[u8],
     ) -> Executable<TautologyVerifier, TestContextObject> {
@@ -1634,6 +3319,9 @@ mod tests {
                 .machine_code_length()
         };
         assert!(empty_program_machine_code_length <= MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH);
+        let mut executable = create_mockup_executable(&prog[0..ebpf::INSN_SIZE]);
+        Executable::<TautologyVerifier, TestContextObject>::jit_compile_lazily(&mut executable)
+            .unwrap();
 
         for mut opcode in 0x00..=0xFF {
             let immediate = match opcode {
@@ -1667,7 +3355,7 @@ mod tests {
                 .get_compiled_program()
                 .unwrap()
                 .machine_code_length()
-                - empty_program_machine_code_length;
+                .saturating_sub(empty_program_machine_code_length);
             let instruction_count = if opcode == 0x18 {
                 // LDDW takes two slots
                 INSTRUCTION_COUNT / 2
@@ -1685,4 +3373,247 @@ mod tests {
             }*/
         }
     }
+
+    #[test]
+    fn test_instruction_meter_checkpoint_length() {
+        const INSTRUCTION_COUNT: usize = 256;
+        let mut prog = [0; ebpf::INSN_SIZE * (INSTRUCTION_COUNT + 1)];
+        for pc in 0..INSTRUCTION_COUNT {
+            prog[pc * ebpf::INSN_SIZE] = ebpf::MOV64_IMM;
+        }
+        prog[INSTRUCTION_COUNT * ebpf::INSN_SIZE] = ebpf::EXIT;
+        let machine_code_length = |enable_suspension: bool, checkpoint_distance: usize| {
+            let loader = BuiltInProgram::new_loader(Config {
+                noop_instruction_rate: 0,
+                instruction_meter_checkpoint_distance: checkpoint_distance,
+                enable_suspension,
+                ..Config::default()
+            });
+            let mut executable =
+                Executable::<TautologyVerifier, TestContextObject>::from_text_bytes(
+                    &prog,
+                    Arc::new(loader),
+                    FunctionRegistry::default(),
+                )
+                .unwrap();
+            Executable::<TautologyVerifier, TestContextObject>::jit_compile(&mut executable)
+                .unwrap();
+            executable
+                .get_compiled_program()
+                .unwrap()
+                .machine_code_length()
+        };
+        for (enable_suspension, expected_length) in [
+            (false, MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT),
+            (
+                true,
+                MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT
+                    + MACHINE_CODE_PER_SUSPENSION_CHECKPOINT,
+            ),
+        ] {
+            let checkpoints_length = machine_code_length(enable_suspension, 1)
+                - machine_code_length(enable_suspension, INSTRUCTION_COUNT * 2);
+            assert_eq!(
+                (checkpoints_length + INSTRUCTION_COUNT / 2) / INSTRUCTION_COUNT,
+                expected_length
+            );
+        }
+    }
+
+    #[test]
+    fn test_compilation_units() {
+        let executable = crate::assembler::assemble::<TestContextObject>(
+            "
+            call function_a
+            call function_b
+            call function_c
+            exit
+            function_a:
+            mov r0, 1
+            exit
+            function_b:
+            mov r0, 2
+            function_c:
+            jeq r0, 0, +2
+            exit
+            function_d:
+            exit
+            exit",
+            Arc::new(BuiltInProgram::new_loader(Config::default())),
+        )
+        .unwrap();
+        let compiler = JitCompiler::new(&executable).unwrap();
+        // function_c falls through from function_b and a jump crosses into function_d
+        assert_eq!(compiler.compilation_units(), vec![0..4, 4..6, 6..11]);
+    }
+
+    #[test]
+    fn test_register_symbols() {
+        let executable = crate::assembler::assemble::<TestContextObject>(
+            "
+            mov r0, 1
+            call function_foo
+            exit
+            function_foo:
+            lddw r0, 0x100000002
+            exit",
+            Arc::new(BuiltInProgram::new_loader(Config {
+                enable_symbol_and_section_labels: true,
+                ..Config::default()
+            })),
+        )
+        .unwrap();
+        let mut executable = Executable::<TautologyVerifier, _>::verified(executable).unwrap();
+        assert!(matches!(
+            executable.register_jit_symbols(&LineTable::new(), None),
+            Err(EbpfError::JitNotCompiled)
+        ));
+        executable.jit_compile().unwrap();
+        let analysis = crate::static_analysis::Analysis::from_executable(&executable).unwrap();
+        let line_table = crate::coverage::disassembly_line_table(&analysis, "program.s");
+        // Not the perf map of this process, which perf would pick up
+        let perf_map = std::env::temp_dir().join(format!(
+            "rbpf-test-register-symbols-{}.map",
+            std::process::id()
+        ));
+        assert_ne!(perf_map, crate::elf::default_perf_map_path());
+        let _ = std::fs::remove_file(&perf_map);
+        executable
+            .register_jit_symbols(&line_table, Some(&perf_map))
+            .unwrap();
+
+        let compiled_program = executable.get_compiled_program().unwrap();
+
+        let object = compiled_program.gdb_jit_object().unwrap();
+        let text_section_start = compiled_program.text_section as *const u8 as usize;
+        let text_section_end = text_section_start + compiled_program.text_section.len();
+        let instruction_addresses = &compiled_program
+            .tables
+            .read()
+            .unwrap()
+            .instruction_addresses;
+        let expected_symbols = [
+            (
+                "rbpf_jit_subroutines",
+                text_section_start,
+                instruction_addresses[0],
+            ),
+            (
+                "entrypoint",
+                instruction_addresses[0],
+                instruction_addresses[3],
+            ),
+            ("function_foo", instruction_addresses[3], text_section_end),
+        ];
+        let elf = goblin::elf::Elf::parse(object).unwrap();
+        let text_section = &elf.section_headers[1];
+        assert_eq!(elf.shdr_strtab.get_at(text_section.sh_name), Some(".text"));
+        assert_eq!(text_section.sh_addr as usize, text_section_start);
+        assert_eq!(
+            text_section.sh_size as usize,
+            text_section_end - text_section_start
+        );
+        let symbols = elf
+            .syms
+            .iter()
+            .skip(1)
+            .map(|symbol| {
+                (
+                    elf.strtab.get_at(symbol.st_name).unwrap(),
+                    symbol.st_value as usize,
+                    (symbol.st_value + symbol.st_size) as usize,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(symbols, expected_symbols);
+        let section_names = elf
+            .section_headers
+            .iter()
+            .map(|section_header| elf.shdr_strtab.get_at(section_header.sh_name).unwrap())
+            .collect::<Vec<_>>();
+        assert!(section_names.contains(&".debug_line"));
+
+        // Appended to the perf map
+        let perf_map_contents = std::fs::read_to_string(&perf_map).unwrap();
+        std::fs::remove_file(&perf_map).unwrap();
+        for (name, start, end) in expected_symbols {
+            assert!(perf_map_contents.contains(&format!(
+                "{:x} {:x} {}\n",
+                start,
+                end - start,
+                name
+            )));
+        }
+    }
+    #[test]
+    fn test_disassemble() {
+        let mut loader = BuiltInProgram::new_loader(Config {
+            enable_symbol_and_section_labels: true,
+            ..Config::default()
+        });
+        loader
+            .register_function(b"bpf_gather_bytes", syscalls::bpf_gather_bytes)
+            .unwrap();
+        let executable = crate::assembler::assemble::<TestContextObject>(
+            "
+            mov r0, 1
+            call function_foo
+            syscall bpf_gather_bytes
+            exit
+            function_foo:
+            lddw r0, 0x100000002
+            ldxdw r1, [r10-8]
+            jne r1, 0, function_foo
+            exit",
+            Arc::new(loader),
+        )
+        .unwrap();
+        let mut executable = Executable::<TautologyVerifier, _>::verified(executable).unwrap();
+        executable.jit_compile().unwrap();
+        let analysis = crate::static_analysis::Analysis::from_executable(&executable).unwrap();
+        let compiled_program = executable.get_compiled_program().unwrap();
+        let mut listing = Vec::new();
+        compiled_program
+            .disassemble(&analysis, &mut listing)
+            .unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+
+        // Every byte of the machine code is decoded exactly once and in order
+        let mut machine_code = Vec::new();
+        for line in listing.lines() {
+            assert!(!line.contains(".byte"), "{}", line);
+            if let Some((offset, rest)) = line.trim_start().split_once(":  ") {
+                assert_eq!(usize::from_str_radix(offset, 16), Ok(machine_code.len()));
+                for byte in rest.split_whitespace().take_while(|token| {
+                    token.len() == 2 && token.chars().all(|c| c.is_ascii_hexdigit())
+                }) {
+                    machine_code.push(u8::from_str_radix(byte, 16).unwrap());
+                }
+            }
+        }
+        assert_eq!(
+            machine_code,
+            compiled_program.machine_code(0..compiled_program.text_section.len())
+        );
+
+        for expected in [
+            "epilogue:\n",
+            "throw_exception:\n",
+            "translate_memory_address_load64:\n",
+            "entrypoint:\n    mov64 r0, 1\n",
+            "\nfunction_foo:\n    lddw r0, 0x100000002\n",
+            "    ldxdw r1, [r10-0x8]\n",
+            "    syscall bpf_gather_bytes\n",
+            " # Syscall(",
+            " <translate_memory_address_load64>\n",
+            " <function_foo>\n",
+        ] {
+            assert!(
+                listing.contains(expected),
+                "{} not in\n{}",
+                expected,
+                listing
+            );
+        }
+    }
 }
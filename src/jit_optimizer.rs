@@ -0,0 +1,635 @@
+#![allow(clippy::integer_arithmetic)]
+//! Optimizations across the instructions of a basic block, applied by the JIT
+//!
+//! The pass decides how each instruction is emitted, but never merges or reorders them:
+//! Every instruction keeps its own machine code, meter checkpoints and pc, so that the
+//! instruction meter, suspension and errors behave exactly like without the pass.
+//!
+//! Facts are only carried forward inside a basic block. Its first instruction is a jump
+//! target, function entry or follows a jump, call or exit. Any instruction can be called
+//! with `config.static_syscalls=false`, which leaves the facts to single instructions.
+
+use crate::{ebpf, elf::Executable, verifier::Verifier, vm::ContextObject};
+use std::ops::Range;
+
+/// How the JIT emits an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Optimization {
+    /// Emit the instruction as it is
+    None,
+    /// Emit the immediate form, the source register holds this value
+    ImmediateSource(i64),
+    /// Load this value into the destination register instead
+    Constant(u64),
+    /// The conditional branch is always (true) or never (false) taken
+    StaticBranch(bool),
+    /// Copy the loaded value from this register, the memory still holds it
+    ForwardLoad(u8),
+    /// Translate this address instead of the base register plus offset
+    ConstantAddress(u64),
+    /// Skip the move, the destination register is overwritten at this pc before it is read
+    ///
+    /// Only applies if there is no instruction meter checkpoint up to that pc.
+    DeadMove(usize),
+    /// Skip the instruction, it does not change the registers
+    Redundant,
+}
+
+/// Value of a register or a memory location
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    Constant(u64),
+}
+
+/// Memory location which is known to hold a value
+#[derive(Clone, Copy, Debug)]
+struct MemoryFact {
+    base: u8,
+    offset: i16,
+    size: u64,
+    value: Operand,
+}
+
+/// Facts about the registers and memory at an instruction of a basic block
+#[derive(Default)]
+struct BlockState {
+    /// Values of r0 to r10 which are known at compile time
+    constants: [Option<u64>; 11],
+    /// Results of previous loads and stores
+    memory: Vec<MemoryFact>,
+}
+
+impl BlockState {
+    fn constant(&self, reg: u8) -> Option<u64> {
+        self.constants.get(reg as usize).copied().flatten()
+    }
+
+    fn write(&mut self, reg: u8, value: Option<u64>) {
+        self.constants[reg as usize] = value;
+        self.memory
+            .retain(|fact| fact.base != reg && fact.value != Operand::Register(reg));
+    }
+
+    /// Decides how to emit `insn` and applies its effects
+    fn step(&mut self, insn: &ebpf::Insn) -> Optimization {
+        if insn.dst as usize >= self.constants.len() {
+            // Stack pointer adjustments with dynamic stack frames leave r0 to r10 unchanged
+            return Optimization::None;
+        }
+        match insn.opc & ebpf::BPF_CLS_MASK {
+            ebpf::BPF_ALU | ebpf::BPF_ALU64 => self.alu(insn),
+            ebpf::BPF_LDX => self.load(insn),
+            ebpf::BPF_ST | ebpf::BPF_STX => self.store(insn),
+            ebpf::BPF_JMP => self.branch(insn),
+            _ => {
+                match insn.opc {
+                    ebpf::LD_DW_IMM => self.write(insn.dst, Some(insn.imm as u64)),
+                    _ => *self = Self::default(),
+                }
+                Optimization::None
+            }
+        }
+    }
+
+    fn alu(&mut self, insn: &ebpf::Insn) -> Optimization {
+        let dst = self.constant(insn.dst);
+        let (opc, source, result) = match insn.opc & ebpf::BPF_ALU_OP_MASK {
+            ebpf::BPF_END => (insn.opc, None, dst.and_then(|dst| swap_bytes(insn, dst))),
+            ebpf::BPF_NEG if insn.opc & ebpf::BPF_X == 0 => {
+                (insn.opc, None, evaluate(insn.opc, dst, None))
+            }
+            ebpf::BPF_NEG => (insn.opc, None, None),
+            _ if insn.opc & ebpf::BPF_X != 0 => {
+                let opc = insn.opc & !ebpf::BPF_X;
+                let source = self.constant(insn.src);
+                (opc, source, evaluate(opc, dst, source))
+            }
+            _ => (
+                insn.opc,
+                None,
+                evaluate(insn.opc, dst, Some(insn.imm as u64)),
+            ),
+        };
+        let optimization = match result {
+            Some(value) if dst == Some(value) => Optimization::Redundant,
+            Some(_) if matches!(insn.opc, ebpf::MOV32_IMM | ebpf::MOV64_IMM) => Optimization::None,
+            Some(value) => Optimization::Constant(value),
+            None if insn.opc == ebpf::MOV64_REG && insn.dst == insn.src => Optimization::Redundant,
+            None => source
+                .and_then(|source| immediate_operand(opc, source))
+                .map(Optimization::ImmediateSource)
+                .unwrap_or(Optimization::None),
+        };
+        if optimization != Optimization::Redundant {
+            self.write(insn.dst, result);
+        }
+        optimization
+    }
+
+    fn load(&mut self, insn: &ebpf::Insn) -> Optimization {
+        let size = match memory_access_size(insn.opc) {
+            Some(size) => size,
+            None => {
+                *self = Self::default();
+                return Optimization::None;
+            }
+        };
+        let forwarded = self
+            .memory
+            .iter()
+            .find(|fact| fact.base == insn.src && fact.offset == insn.off && fact.size == size);
+        let (optimization, value) = match forwarded.map(|fact| fact.value) {
+            Some(Operand::Constant(value)) => {
+                if self.constant(insn.dst) == Some(value) {
+                    return Optimization::Redundant;
+                }
+                (Optimization::Constant(value), Some(value))
+            }
+            Some(Operand::Register(reg)) => (
+                Optimization::ForwardLoad(reg),
+                self.constant(reg).map(|value| value & size_mask(size)),
+            ),
+            None => match self.constant(insn.src) {
+                Some(base) => (
+                    Optimization::ConstantAddress(base.wrapping_add(insn.off as i64 as u64)),
+                    None,
+                ),
+                None => (Optimization::None, None),
+            },
+        };
+        self.write(insn.dst, value);
+        if insn.dst != insn.src {
+            self.memory.push(MemoryFact {
+                base: insn.src,
+                offset: insn.off,
+                size,
+                value: Operand::Register(insn.dst),
+            });
+        }
+        optimization
+    }
+
+    fn store(&mut self, insn: &ebpf::Insn) -> Optimization {
+        // Another base register could point to the same memory
+        self.memory.clear();
+        let size = match memory_access_size(insn.opc) {
+            Some(size) => size,
+            None => {
+                *self = Self::default();
+                return Optimization::None;
+            }
+        };
+        let value = if insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_ST {
+            Operand::Constant(insn.imm as u64 & size_mask(size))
+        } else {
+            match self.constant(insn.src) {
+                Some(value) => Operand::Constant(value & size_mask(size)),
+                None => Operand::Register(insn.src),
+            }
+        };
+        self.memory.push(MemoryFact {
+            base: insn.dst,
+            offset: insn.off,
+            size,
+            value,
+        });
+        match self.constant(insn.dst) {
+            Some(base) => Optimization::ConstantAddress(base.wrapping_add(insn.off as i64 as u64)),
+            None => Optimization::None,
+        }
+    }
+
+    fn branch(&mut self, insn: &ebpf::Insn) -> Optimization {
+        if matches!(
+            insn.opc,
+            ebpf::JA | ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT
+        ) {
+            *self = Self::default();
+            return Optimization::None;
+        }
+        let (opc, source) = if insn.opc & ebpf::BPF_X != 0 {
+            (insn.opc & !ebpf::BPF_X, self.constant(insn.src))
+        } else {
+            (insn.opc, Some(insn.imm as u64))
+        };
+        let source = match source {
+            Some(source) => source,
+            None => return Optimization::None,
+        };
+        match self.constant(insn.dst) {
+            Some(dst) => condition(opc, dst, source)
+                .map(Optimization::StaticBranch)
+                .unwrap_or(Optimization::None),
+            None if insn.opc & ebpf::BPF_X != 0 => immediate_operand(opc, source)
+                .map(Optimization::ImmediateSource)
+                .unwrap_or(Optimization::None),
+            None => Optimization::None,
+        }
+    }
+}
+
+/// Bytes accessed by a load or store
+fn memory_access_size(opc: u8) -> Option<u64> {
+    match opc {
+        ebpf::LD_B_REG | ebpf::ST_B_IMM | ebpf::ST_B_REG => Some(1),
+        ebpf::LD_H_REG | ebpf::ST_H_IMM | ebpf::ST_H_REG => Some(2),
+        ebpf::LD_W_REG | ebpf::ST_W_IMM | ebpf::ST_W_REG => Some(4),
+        ebpf::LD_DW_REG | ebpf::ST_DW_IMM | ebpf::ST_DW_REG => Some(8),
+        _ => None,
+    }
+}
+
+fn size_mask(size: u64) -> u64 {
+    u64::MAX >> (64 - 8 * size)
+}
+
+/// Result of `opc` in the immediate form, same as in the interpreter
+fn evaluate(opc: u8, dst: Option<u64>, src: Option<u64>) -> Option<u64> {
+    match opc {
+        ebpf::MOV32_IMM => return src.map(|src| src as u32 as u64),
+        ebpf::MOV64_IMM => return src,
+        ebpf::NEG32 => {
+            return dst.map(|dst| (dst as i32).wrapping_neg() as u64 & (u32::MAX as u64))
+        }
+        ebpf::NEG64 => return dst.map(|dst| (dst as i64).wrapping_neg() as u64),
+        _ => {}
+    }
+    let (dst, src) = (dst?, src?);
+    Some(match opc {
+        ebpf::ADD32_IMM => (dst as i32).wrapping_add(src as i32) as u64,
+        ebpf::SUB32_IMM => (dst as i32).wrapping_sub(src as i32) as u64,
+        ebpf::MUL32_IMM => (dst as i32).wrapping_mul(src as i32) as u64,
+        ebpf::DIV32_IMM if src as u32 != 0 => (dst as u32 / src as u32) as u64,
+        ebpf::SDIV32_IMM if src as i32 != 0 && !(dst as i32 == i32::MIN && src as i32 == -1) => {
+            (dst as i32 / src as i32) as u64
+        }
+        ebpf::MOD32_IMM if src as u32 != 0 => (dst as u32 % src as u32) as u64,
+        ebpf::OR32_IMM => (dst as u32 | src as u32) as u64,
+        ebpf::AND32_IMM => (dst as u32 & src as u32) as u64,
+        ebpf::XOR32_IMM => (dst as u32 ^ src as u32) as u64,
+        ebpf::LSH32_IMM => (dst as u32).wrapping_shl(src as u32) as u64,
+        ebpf::RSH32_IMM => (dst as u32).wrapping_shr(src as u32) as u64,
+        ebpf::ARSH32_IMM => (dst as i32).wrapping_shr(src as u32) as u64 & (u32::MAX as u64),
+        ebpf::ADD64_IMM => dst.wrapping_add(src),
+        ebpf::SUB64_IMM => dst.wrapping_sub(src),
+        ebpf::MUL64_IMM => dst.wrapping_mul(src),
+        ebpf::DIV64_IMM if src != 0 => dst / src,
+        ebpf::SDIV64_IMM if src != 0 && !(dst as i64 == i64::MIN && src as i64 == -1) => {
+            (dst as i64 / src as i64) as u64
+        }
+        ebpf::MOD64_IMM if src != 0 => dst % src,
+        ebpf::OR64_IMM => dst | src,
+        ebpf::AND64_IMM => dst & src,
+        ebpf::XOR64_IMM => dst ^ src,
+        ebpf::LSH64_IMM => dst.wrapping_shl(src as u32),
+        ebpf::RSH64_IMM => dst.wrapping_shr(src as u32),
+        ebpf::ARSH64_IMM => (dst as i64).wrapping_shr(src as u32) as u64,
+        _ => return None,
+    })
+}
+
+/// Result of LE or BE, None if the width is invalid
+fn swap_bytes(insn: &ebpf::Insn, dst: u64) -> Option<u64> {
+    Some(match (insn.opc, insn.imm) {
+        (ebpf::LE, 16) => (dst as u16).to_le() as u64,
+        (ebpf::LE, 32) => (dst as u32).to_le() as u64,
+        (ebpf::LE, 64) => dst.to_le(),
+        (ebpf::BE, 16) => (dst as u16).to_be() as u64,
+        (ebpf::BE, 32) => (dst as u32).to_be() as u64,
+        (ebpf::BE, 64) => dst.to_be(),
+        _ => return None,
+    })
+}
+
+/// Whether the conditional branch `opc` in the immediate form is taken
+fn condition(opc: u8, dst: u64, src: u64) -> Option<bool> {
+    Some(match opc {
+        ebpf::JEQ_IMM => dst == src,
+        ebpf::JGT_IMM => dst > src,
+        ebpf::JGE_IMM => dst >= src,
+        ebpf::JLT_IMM => dst < src,
+        ebpf::JLE_IMM => dst <= src,
+        ebpf::JSET_IMM => dst & src != 0,
+        ebpf::JNE_IMM => dst != src,
+        ebpf::JSGT_IMM => (dst as i64) > src as i64,
+        ebpf::JSGE_IMM => (dst as i64) >= src as i64,
+        ebpf::JSLT_IMM => (dst as i64) < (src as i64),
+        ebpf::JSLE_IMM => (dst as i64) <= src as i64,
+        _ => return None,
+    })
+}
+
+/// Immediate which has the same effect in `opc` as a source register holding `value`
+fn immediate_operand(opc: u8, value: u64) -> Option<i64> {
+    let is_jump = opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP;
+    let is_64 = opc & ebpf::BPF_CLS_MASK != ebpf::BPF_ALU;
+    let is_known = if is_jump {
+        condition(opc, 0, 0).is_some()
+    } else {
+        evaluate(opc, Some(0), Some(1)).is_some()
+    };
+    if !is_known {
+        return None;
+    }
+    match opc & ebpf::BPF_ALU_OP_MASK {
+        _ if is_jump => {}
+        ebpf::BPF_LSH | ebpf::BPF_RSH | ebpf::BPF_ARSH if is_64 => {
+            return Some((value & 63) as i64)
+        }
+        // The machine code of 32 bit shifts by zero does not clear the upper half
+        ebpf::BPF_LSH | ebpf::BPF_RSH | ebpf::BPF_ARSH if value & 31 != 0 => {
+            return Some((value & 31) as i64)
+        }
+        ebpf::BPF_LSH | ebpf::BPF_RSH | ebpf::BPF_ARSH => return None,
+        // Only the register forms check for a division by zero
+        ebpf::BPF_DIV | ebpf::BPF_SDIV | ebpf::BPF_MOD if value as u32 == 0 => return None,
+        _ => {}
+    }
+    if is_64 && value as i32 as i64 as u64 != value {
+        return None;
+    }
+    Some(value as i32 as i64)
+}
+
+/// Whether an instruction only changes registers and can not fail or validate the meter
+fn is_register_only(insn: &ebpf::Insn) -> bool {
+    match insn.opc & ebpf::BPF_CLS_MASK {
+        ebpf::BPF_ALU | ebpf::BPF_ALU64 => !matches!(
+            insn.opc & ebpf::BPF_ALU_OP_MASK,
+            ebpf::BPF_DIV | ebpf::BPF_SDIV | ebpf::BPF_MOD
+        ),
+        _ => false,
+    }
+}
+
+/// Whether a register only instruction reads `reg`
+fn reads(insn: &ebpf::Insn, reg: u8) -> bool {
+    let reads_dst = !matches!(insn.opc & ebpf::BPF_ALU_OP_MASK, ebpf::BPF_MOV);
+    let reads_src = insn.opc & ebpf::BPF_X != 0 && !matches!(insn.opc, ebpf::BE);
+    (reads_dst && insn.dst == reg) || (reads_src && insn.src == reg)
+}
+
+/// Decides how to emit each instruction in `pcs`, see [Optimization]
+pub(crate) fn optimize<V: Verifier, C: ContextObject>(
+    executable: &Executable<V, C>,
+    pcs: Range<usize>,
+) -> Vec<Optimization> {
+    let config = executable.get_config();
+    let mut optimizations = vec![Optimization::None; pcs.len()];
+    if !config.optimize_jit_code {
+        return optimizations;
+    }
+    let (_program_vm_addr, program) = executable.get_text_bytes();
+    let mut instructions = Vec::new();
+    let mut is_leader = vec![!config.static_syscalls; pcs.len() + 1];
+    is_leader[0] = true;
+    for (pc, _name) in executable.get_function_registry().values() {
+        if pcs.contains(pc) {
+            is_leader[pc - pcs.start] = true;
+        }
+    }
+    let mut pc = pcs.start;
+    while pc < pcs.end {
+        let mut insn = ebpf::get_insn_unchecked(program, pc);
+        let next_pc = if insn.opc == ebpf::LD_DW_IMM {
+            ebpf::augment_lddw_unchecked(program, &mut insn);
+            pc + 2
+        } else {
+            pc + 1
+        };
+        if insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP {
+            let target_pc = pc as isize + insn.off as isize + 1;
+            if !matches!(insn.opc, ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT)
+                && target_pc >= pcs.start as isize
+                && (target_pc as usize) < pcs.end
+            {
+                is_leader[target_pc as usize - pcs.start] = true;
+            }
+            is_leader[(next_pc - pcs.start).min(pcs.len())] = true;
+        }
+        instructions.push(insn);
+        pc = next_pc;
+    }
+
+    let mut block = BlockState::default();
+    for insn in instructions.iter() {
+        if is_leader[insn.ptr - pcs.start] {
+            block = BlockState::default();
+        }
+        optimizations[insn.ptr - pcs.start] = block.step(insn);
+    }
+
+    // A trace shows the registers before every instruction
+    if config.enable_instruction_tracing {
+        return optimizations;
+    }
+    for (index, insn) in instructions.iter().enumerate() {
+        if insn.opc & ebpf::BPF_ALU_OP_MASK != ebpf::BPF_MOV
+            || !is_register_only(insn)
+            || insn.dst as usize >= ebpf::FRAME_PTR_REG
+            || optimizations[insn.ptr - pcs.start] == Optimization::Redundant
+        {
+            continue;
+        }
+        for next in instructions[index + 1..].iter() {
+            if !is_register_only(next)
+                || reads(next, insn.dst)
+                || optimizations[next.ptr - pcs.start] == Optimization::Redundant
+            {
+                break;
+            }
+            if next.dst == insn.dst {
+                optimizations[insn.ptr - pcs.start] = Optimization::DeadMove(next.ptr);
+                break;
+            }
+        }
+    }
+    optimizations
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::{BuiltInProgram, Config, TestContextObject};
+    use std::sync::Arc;
+
+    /// The optimizations are off by default
+    fn optimizing_config() -> Config {
+        Config {
+            optimize_jit_code: true,
+            ..Config::default()
+        }
+    }
+
+    fn optimized(src: &str, config: Config) -> Vec<Optimization> {
+        let executable = crate::assembler::assemble::<TestContextObject>(
+            src,
+            Arc::new(BuiltInProgram::new_loader(config)),
+        )
+        .unwrap();
+        let (_program_vm_addr, program) = executable.get_text_bytes();
+        optimize(&executable, 0..program.len() / ebpf::INSN_SIZE)
+    }
+
+    #[test]
+    fn test_constant_propagation() {
+        let src = "
+            mov64 r1, 2
+            add64 r1, 3
+            mov64 r2, r1
+            add64 r3, r2
+            mov64 r2, 5
+            lsh32 r3, r2
+            exit";
+        assert_eq!(
+            optimized(src, optimizing_config()),
+            vec![
+                Optimization::None,
+                Optimization::Constant(5),
+                Optimization::Constant(5),
+                Optimization::ImmediateSource(5),
+                Optimization::Redundant,
+                Optimization::ImmediateSource(5),
+                Optimization::None,
+            ]
+        );
+        let config = Config {
+            static_syscalls: false,
+            ..optimizing_config()
+        };
+        assert!(optimized(src, config)
+            .iter()
+            .all(|optimization| *optimization == Optimization::None));
+        let config = Config {
+            optimize_jit_code: false,
+            ..optimizing_config()
+        };
+        assert!(optimized(src, config)
+            .iter()
+            .all(|optimization| *optimization == Optimization::None));
+    }
+
+    #[test]
+    fn test_static_branch() {
+        assert_eq!(
+            optimized(
+                "
+                mov64 r1, 1
+                jgt r1, 4, +1
+                jeq r1, 1, +0
+                add64 r1, 1
+                exit",
+                optimizing_config()
+            ),
+            vec![
+                Optimization::None,
+                Optimization::StaticBranch(false),
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+            ]
+        );
+        assert_eq!(
+            optimized(
+                "
+                mov64 r1, 1
+                mov64 r2, 4
+                jgt r1, r2, +0
+                exit",
+                optimizing_config()
+            ),
+            vec![
+                Optimization::None,
+                Optimization::None,
+                Optimization::StaticBranch(false),
+                Optimization::None,
+            ]
+        );
+        assert_eq!(
+            optimized(
+                "
+                mov64 r2, 4
+                jgt r3, r2, +0
+                exit",
+                optimizing_config()
+            ),
+            vec![
+                Optimization::None,
+                Optimization::ImmediateSource(4),
+                Optimization::None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memory_forwarding() {
+        assert_eq!(
+            optimized(
+                "
+                ldxdw r2, [r1+8]
+                ldxdw r3, [r1+8]
+                ldxw r4, [r1+8]
+                stxw [r1+16], r3
+                ldxdw r5, [r1+8]
+                ldxw r4, [r1+16]
+                mov64 r5, 0x1000
+                ldxb r6, [r5+1]
+                stb [r1+2], 7
+                ldxb r0, [r1+2]
+                ldxb r0, [r1+2]
+                exit",
+                optimizing_config()
+            ),
+            vec![
+                Optimization::None,
+                Optimization::ForwardLoad(2),
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+                Optimization::ForwardLoad(3),
+                Optimization::None,
+                Optimization::ConstantAddress(0x1001),
+                Optimization::None,
+                Optimization::Constant(7),
+                Optimization::Redundant,
+                Optimization::None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dead_move() {
+        let src = "
+            mov64 r2, r1
+            add64 r3, 1
+            mov64 r2, 7
+            mov32 r4, r1
+            add64 r3, r4
+            mov64 r4, r1
+            div64 r3, 2
+            mov64 r4, r3
+            exit";
+        assert_eq!(
+            optimized(src, optimizing_config()),
+            vec![
+                Optimization::DeadMove(2),
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+                Optimization::None,
+            ]
+        );
+        let config = Config {
+            enable_instruction_tracing: true,
+            ..optimizing_config()
+        };
+        assert!(optimized(src, config)
+            .iter()
+            .all(|optimization| *optimization == Optimization::None));
+    }
+}
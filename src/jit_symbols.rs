@@ -0,0 +1,579 @@
+#![allow(clippy::integer_arithmetic)]
+//! Symbols of JIT compiled programs for native profilers and debuggers
+//!
+//! Functions are appended to a perf map, which `perf report` reads from `/tmp/perf-<pid>.map`.
+//! An ELF object with the same symbols and a DWARF line table is registered through the
+//! GDB JIT compilation interface, see <https://sourceware.org/gdb/onlinedocs/gdb/JIT-Interface.html>.
+//! Debuggers only find the registered objects if the `gdb-jit-interface` feature exports the
+//! symbols of the interface, which other JIT compilers in the same process may define as well.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::{
+    collections::BTreeMap, fmt::Write as _, fs::OpenOptions, io::Write as _, path::Path, ptr,
+    sync::Mutex,
+};
+
+/// Function in the machine code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JitSymbol {
+    pub name: String,
+    /// Host address of the first byte
+    pub start: usize,
+    /// Host address after the last byte
+    pub end: usize,
+}
+
+/// Appends the symbols to the perf map at `path`
+pub(crate) fn write_perf_map(path: &Path, symbols: &[JitSymbol]) -> std::io::Result<()> {
+    let mut lines = String::new();
+    for symbol in symbols.iter() {
+        let _ = writeln!(
+            lines,
+            "{:x} {:x} {}",
+            symbol.start,
+            symbol.end - symbol.start,
+            symbol.name
+        );
+    }
+    // A single write, so that lines of other threads do not end up in between
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(lines.as_bytes())
+}
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const ELF_HEADER_SIZE: usize = 64;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+
+const DW_TAG_COMPILE_UNIT: u64 = 0x11;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_STMT_LIST: u64 = 0x10;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_PRODUCER: u64 = 0x25;
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+/// Number of operands of the standard opcodes 1 to 12 of the line number program
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+fn write_uleb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_sleb128(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(string.as_bytes());
+    bytes.push(0);
+}
+
+/// Prefixes a DWARF unit with its 32 bit length
+fn dwarf_unit(contents: Vec<u8>) -> Vec<u8> {
+    let mut unit = Vec::with_capacity(4 + contents.len());
+    unit.write_u32::<LittleEndian>(contents.len() as u32)
+        .unwrap();
+    unit.extend_from_slice(&contents);
+    unit
+}
+
+/// DWARF 2 line number program attributing every host address in `lines` to its file and line
+///
+/// `lines` has to be sorted by host address.
+fn debug_line(text: (usize, usize), lines: &[(usize, &str, usize)]) -> Vec<u8> {
+    let mut files = BTreeMap::<&str, u64>::new();
+    for (_address, file, _line) in lines.iter() {
+        let index = files.len() as u64 + 1;
+        files.entry(file).or_insert(index);
+    }
+    let mut header = vec![
+        1,                                       // minimum_instruction_length
+        1,                                       // default_is_stmt
+        -5i8 as u8,                              // line_base
+        14,                                      // line_range
+        STANDARD_OPCODE_LENGTHS.len() as u8 + 1, // opcode_base
+    ];
+    header.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+    header.push(0); // No include_directories
+    let mut files_by_index = files.iter().collect::<Vec<_>>();
+    files_by_index.sort_unstable_by_key(|(_file, index)| **index);
+    for (file, _index) in files_by_index {
+        write_string(&mut header, file);
+        write_uleb128(&mut header, 0); // Directory
+        write_uleb128(&mut header, 0); // Modification time
+        write_uleb128(&mut header, 0); // Length
+    }
+    header.push(0);
+
+    let mut program = vec![0, 9, DW_LNE_SET_ADDRESS];
+    program.write_u64::<LittleEndian>(text.0 as u64).unwrap();
+    let (mut address, mut file, mut line) = (text.0, 1, 1);
+    for (row_address, row_file, row_line) in lines.iter() {
+        let row_file = files[row_file];
+        if row_file != file {
+            program.push(DW_LNS_SET_FILE);
+            write_uleb128(&mut program, row_file);
+            file = row_file;
+        }
+        if *row_line != line {
+            program.push(DW_LNS_ADVANCE_LINE);
+            write_sleb128(&mut program, *row_line as i64 - line as i64);
+            line = *row_line;
+        }
+        if *row_address != address {
+            program.push(DW_LNS_ADVANCE_PC);
+            write_uleb128(&mut program, (*row_address - address) as u64);
+            address = *row_address;
+        }
+        program.push(DW_LNS_COPY);
+    }
+    program.push(DW_LNS_ADVANCE_PC);
+    write_uleb128(&mut program, (text.1 - address) as u64);
+    program.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+
+    let mut contents = Vec::new();
+    contents.write_u16::<LittleEndian>(2).unwrap(); // Version
+    contents
+        .write_u32::<LittleEndian>(header.len() as u32)
+        .unwrap();
+    contents.extend_from_slice(&header);
+    contents.extend_from_slice(&program);
+    dwarf_unit(contents)
+}
+
+/// Abbreviations and DWARF 2 compile unit with a subprogram per symbol
+fn debug_info(text: (usize, usize), symbols: &[JitSymbol], name: &str) -> (Vec<u8>, Vec<u8>) {
+    let mut abbrev = Vec::new();
+    for value in [
+        1,
+        DW_TAG_COMPILE_UNIT,
+        1, // Has children
+        DW_AT_NAME,
+        DW_FORM_STRING,
+        DW_AT_PRODUCER,
+        DW_FORM_STRING,
+        DW_AT_STMT_LIST,
+        DW_FORM_DATA4,
+        DW_AT_LOW_PC,
+        DW_FORM_ADDR,
+        DW_AT_HIGH_PC,
+        DW_FORM_ADDR,
+        0,
+        0,
+        2,
+        DW_TAG_SUBPROGRAM,
+        0, // Has no children
+        DW_AT_NAME,
+        DW_FORM_STRING,
+        DW_AT_LOW_PC,
+        DW_FORM_ADDR,
+        DW_AT_HIGH_PC,
+        DW_FORM_ADDR,
+        0,
+        0,
+        0,
+    ] {
+        write_uleb128(&mut abbrev, value);
+    }
+
+    let mut contents = Vec::new();
+    contents.write_u16::<LittleEndian>(2).unwrap(); // Version
+    contents.write_u32::<LittleEndian>(0).unwrap(); // Offset into .debug_abbrev
+    contents.push(8); // Address size
+    write_uleb128(&mut contents, 1);
+    write_string(&mut contents, name);
+    write_string(
+        &mut contents,
+        concat!("solana_rbpf ", env!("CARGO_PKG_VERSION")),
+    );
+    contents.write_u32::<LittleEndian>(0).unwrap(); // Offset into .debug_line
+    contents.write_u64::<LittleEndian>(text.0 as u64).unwrap();
+    contents.write_u64::<LittleEndian>(text.1 as u64).unwrap();
+    for symbol in symbols.iter() {
+        write_uleb128(&mut contents, 2);
+        write_string(&mut contents, &symbol.name);
+        contents
+            .write_u64::<LittleEndian>(symbol.start as u64)
+            .unwrap();
+        contents
+            .write_u64::<LittleEndian>(symbol.end as u64)
+            .unwrap();
+    }
+    contents.push(0); // End of the children
+    (abbrev, dwarf_unit(contents))
+}
+
+/// Builds an ELF object describing the machine code in the host address range `text`
+///
+/// The .text section occupies no space in the file, it only tells the debugger where the
+/// machine code is. `lines` are the host address, file and line of every instruction,
+/// sorted by host address.
+pub(crate) fn elf_object(
+    text: (usize, usize),
+    symbols: &[JitSymbol],
+    lines: &[(usize, &str, usize)],
+) -> Vec<u8> {
+    let name = lines
+        .first()
+        .map(|(_address, file, _line)| *file)
+        .unwrap_or("program");
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; SYMBOL_SIZE];
+    for symbol in symbols.iter() {
+        symtab
+            .write_u32::<LittleEndian>(strtab.len() as u32)
+            .unwrap();
+        write_string(&mut strtab, &symbol.name);
+        symtab.push(0x12); // STB_GLOBAL, STT_FUNC
+        symtab.push(0); // STV_DEFAULT
+        symtab.write_u16::<LittleEndian>(1).unwrap(); // .text
+        symtab
+            .write_u64::<LittleEndian>(symbol.start as u64)
+            .unwrap();
+        symtab
+            .write_u64::<LittleEndian>((symbol.end - symbol.start) as u64)
+            .unwrap();
+    }
+    let (debug_abbrev, debug_info) = debug_info(text, symbols, name);
+    let debug_line = debug_line(text, lines);
+
+    // Name, type, flags, address, contents, link, info, entry size
+    #[allow(clippy::type_complexity)]
+    let sections: [(&str, u32, u64, usize, Option<&[u8]>, u32, u32, u64); 7] = [
+        (
+            ".text",
+            SHT_NOBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            text.0,
+            None,
+            0,
+            0,
+            0,
+        ),
+        (
+            ".symtab",
+            SHT_SYMTAB,
+            0,
+            0,
+            Some(&symtab),
+            3,
+            1,
+            SYMBOL_SIZE as u64,
+        ),
+        (".strtab", SHT_STRTAB, 0, 0, Some(&strtab), 0, 0, 0),
+        (
+            ".debug_abbrev",
+            SHT_PROGBITS,
+            0,
+            0,
+            Some(&debug_abbrev),
+            0,
+            0,
+            0,
+        ),
+        (
+            ".debug_info",
+            SHT_PROGBITS,
+            0,
+            0,
+            Some(&debug_info),
+            0,
+            0,
+            0,
+        ),
+        (
+            ".debug_line",
+            SHT_PROGBITS,
+            0,
+            0,
+            Some(&debug_line),
+            0,
+            0,
+            0,
+        ),
+        (".shstrtab", SHT_STRTAB, 0, 0, None, 0, 0, 0),
+    ];
+    let mut shstrtab = vec![0];
+    let mut section_names = Vec::with_capacity(sections.len());
+    for section in sections.iter() {
+        section_names.push(shstrtab.len() as u32);
+        write_string(&mut shstrtab, section.0);
+    }
+
+    let mut contents = Vec::new();
+    let mut section_headers = vec![0; SECTION_HEADER_SIZE];
+    for (section, name) in sections.iter().zip(section_names) {
+        let (_name, kind, flags, address, data, link, info, entry_size) = *section;
+        let data = if kind == SHT_NOBITS {
+            &[][..]
+        } else {
+            data.unwrap_or(&shstrtab)
+        };
+        while contents.len() % 8 != 0 {
+            contents.push(0);
+        }
+        let offset = ELF_HEADER_SIZE + contents.len();
+        let size = if kind == SHT_NOBITS {
+            text.1 - text.0
+        } else {
+            data.len()
+        };
+        contents.extend_from_slice(data);
+        section_headers.write_u32::<LittleEndian>(name).unwrap();
+        section_headers.write_u32::<LittleEndian>(kind).unwrap();
+        section_headers.write_u64::<LittleEndian>(flags).unwrap();
+        section_headers
+            .write_u64::<LittleEndian>(address as u64)
+            .unwrap();
+        section_headers
+            .write_u64::<LittleEndian>(offset as u64)
+            .unwrap();
+        section_headers
+            .write_u64::<LittleEndian>(size as u64)
+            .unwrap();
+        section_headers.write_u32::<LittleEndian>(link).unwrap();
+        section_headers.write_u32::<LittleEndian>(info).unwrap();
+        section_headers
+            .write_u64::<LittleEndian>(if kind == SHT_NOBITS { 16 } else { 1 })
+            .unwrap();
+        section_headers
+            .write_u64::<LittleEndian>(entry_size)
+            .unwrap();
+    }
+    while contents.len() % 8 != 0 {
+        contents.push(0);
+    }
+
+    let mut object = Vec::with_capacity(ELF_HEADER_SIZE + contents.len() + section_headers.len());
+    object.extend_from_slice(b"\x7fELF");
+    object.extend_from_slice(&[2, 1, 1, 0]); // 64 bit, little endian, version 1, System V ABI
+    object.extend_from_slice(&[0; 8]);
+    object.write_u16::<LittleEndian>(1).unwrap(); // ET_REL
+    object.write_u16::<LittleEndian>(62).unwrap(); // EM_X86_64
+    object.write_u32::<LittleEndian>(1).unwrap(); // Version
+    object.write_u64::<LittleEndian>(0).unwrap(); // Entry point
+    object.write_u64::<LittleEndian>(0).unwrap(); // Program header offset
+    object
+        .write_u64::<LittleEndian>((ELF_HEADER_SIZE + contents.len()) as u64)
+        .unwrap();
+    object.write_u32::<LittleEndian>(0).unwrap(); // Flags
+    object
+        .write_u16::<LittleEndian>(ELF_HEADER_SIZE as u16)
+        .unwrap();
+    object.write_u16::<LittleEndian>(0).unwrap(); // Program header entry size
+    object.write_u16::<LittleEndian>(0).unwrap(); // Program header count
+    object
+        .write_u16::<LittleEndian>(SECTION_HEADER_SIZE as u16)
+        .unwrap();
+    object
+        .write_u16::<LittleEndian>(sections.len() as u16 + 1)
+        .unwrap();
+    object
+        .write_u16::<LittleEndian>(sections.len() as u16)
+        .unwrap(); // .shstrtab
+    object.extend_from_slice(&contents);
+    object.extend_from_slice(&section_headers);
+    object
+}
+
+const JIT_NOACTION: u32 = 0;
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+
+#[repr(C)]
+struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+#[repr(C)]
+struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+/// The list of registered objects, which the debugger reads
+///
+/// Unit tests link this crate twice, so the symbols are only exported in regular builds.
+#[cfg_attr(all(feature = "gdb-jit-interface", not(test)), no_mangle)]
+#[allow(non_upper_case_globals)]
+static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: JIT_NOACTION,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+/// The debugger sets a breakpoint here to be notified of changes to the list
+#[cfg_attr(all(feature = "gdb-jit-interface", not(test)), no_mangle)]
+#[inline(never)]
+extern "C" fn __jit_debug_register_code() {
+    // Prevents the call from being optimized away
+    unsafe { std::arch::asm!("", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Serializes changes to __jit_debug_descriptor
+static GDB_JIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// ELF object registered through the GDB JIT compilation interface until dropped
+pub(crate) struct GdbJitRegistration {
+    entry: Box<JitCodeEntry>,
+    object: Vec<u8>,
+}
+
+// Safety: The entry is only touched while GDB_JIT_LOCK is held
+unsafe impl Send for GdbJitRegistration {}
+unsafe impl Sync for GdbJitRegistration {}
+
+impl GdbJitRegistration {
+    /// Adds the object to the front of the list and notifies the debugger
+    pub(crate) fn new(object: Vec<u8>) -> Self {
+        let mut entry = Box::new(JitCodeEntry {
+            next_entry: ptr::null_mut(),
+            prev_entry: ptr::null_mut(),
+            symfile_addr: object.as_ptr(),
+            symfile_size: object.len() as u64,
+        });
+        let _guard = GDB_JIT_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            let descriptor = ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry_ptr: *mut JitCodeEntry = &mut *entry;
+            entry.next_entry = (*descriptor).first_entry;
+            if !entry.next_entry.is_null() {
+                (*entry.next_entry).prev_entry = entry_ptr;
+            }
+            (*descriptor).first_entry = entry_ptr;
+            (*descriptor).relevant_entry = entry_ptr;
+            (*descriptor).action_flag = JIT_REGISTER_FN;
+            __jit_debug_register_code();
+            (*descriptor).action_flag = JIT_NOACTION;
+            (*descriptor).relevant_entry = ptr::null_mut();
+        }
+        Self { entry, object }
+    }
+
+    /// The registered ELF object
+    pub(crate) fn object(&self) -> &[u8] {
+        &self.object
+    }
+}
+
+impl Drop for GdbJitRegistration {
+    /// Removes the object from the list and notifies the debugger
+    fn drop(&mut self) {
+        let _guard = GDB_JIT_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            let descriptor = ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry_ptr: *mut JitCodeEntry = &mut *self.entry;
+            if self.entry.prev_entry.is_null() {
+                (*descriptor).first_entry = self.entry.next_entry;
+            } else {
+                (*self.entry.prev_entry).next_entry = self.entry.next_entry;
+            }
+            if !self.entry.next_entry.is_null() {
+                (*self.entry.next_entry).prev_entry = self.entry.prev_entry;
+            }
+            (*descriptor).relevant_entry = entry_ptr;
+            (*descriptor).action_flag = JIT_UNREGISTER_FN;
+            __jit_debug_register_code();
+            (*descriptor).action_flag = JIT_NOACTION;
+            (*descriptor).relevant_entry = ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_registered(object: *const u8) -> bool {
+        let _guard = GDB_JIT_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            let descriptor = ptr::addr_of!(__jit_debug_descriptor);
+            assert_eq!((*descriptor).action_flag, JIT_NOACTION);
+            let mut entry = (*descriptor).first_entry;
+            while !entry.is_null() {
+                if (*entry).symfile_addr == object {
+                    return true;
+                }
+                entry = (*entry).next_entry;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_leb128() {
+        let mut bytes = Vec::new();
+        write_uleb128(&mut bytes, 2);
+        write_uleb128(&mut bytes, 127);
+        write_uleb128(&mut bytes, 128);
+        write_uleb128(&mut bytes, 624485);
+        assert_eq!(bytes, [2, 0x7f, 0x80, 0x01, 0xe5, 0x8e, 0x26]);
+        bytes.clear();
+        write_sleb128(&mut bytes, 2);
+        write_sleb128(&mut bytes, -2);
+        write_sleb128(&mut bytes, 127);
+        write_sleb128(&mut bytes, -128);
+        assert_eq!(bytes, [2, 0x7e, 0xff, 0x00, 0x80, 0x7f]);
+    }
+
+    #[test]
+    fn test_gdb_jit_registration() {
+        let first = GdbJitRegistration::new(vec![1]);
+        let second = GdbJitRegistration::new(vec![2]);
+        let third = GdbJitRegistration::new(vec![3]);
+        let objects = [
+            first.object().as_ptr(),
+            second.object().as_ptr(),
+            third.object().as_ptr(),
+        ];
+        assert!(objects.iter().all(|object| is_registered(*object)));
+        // Unlink from the middle, the front and the back of the list
+        drop(second);
+        assert!(
+            is_registered(objects[0]) && !is_registered(objects[1]) && is_registered(objects[2])
+        );
+        drop(third);
+        assert!(is_registered(objects[0]) && !is_registered(objects[2]));
+        drop(first);
+        assert!(!is_registered(objects[0]));
+    }
+}
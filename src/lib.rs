@@ -24,9 +24,17 @@ extern crate log;
 extern crate rand;
 extern crate thiserror;
 
+pub mod abstract_interpretation;
 pub mod aligned_memory;
+pub mod allocator;
 mod asm_parser;
 pub mod assembler;
+pub mod cost_analysis;
+pub mod coverage;
+#[cfg(feature = "cranelift")]
+pub mod cranelift;
+#[cfg(feature = "debug-info")]
+pub mod debug_info;
 #[cfg(feature = "debugger")]
 pub mod debugger;
 pub mod disassembler;
@@ -34,17 +42,26 @@ pub mod ebpf;
 pub mod elf;
 pub mod elf_parser;
 pub mod elf_parser_glue;
+pub mod elf_report;
 pub mod error;
 pub mod fuzz;
 pub mod insn_builder;
 pub mod interpreter;
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
 mod jit;
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+mod jit_optimizer;
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+mod jit_symbols;
 #[cfg(feature = "jit")]
 mod memory_management;
 pub mod memory_region;
+pub mod profiler;
+pub mod snapshot;
+pub mod stack_analysis;
 pub mod static_analysis;
 pub mod syscalls;
+pub mod trace;
 pub mod verifier;
 pub mod vm;
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
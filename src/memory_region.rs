@@ -8,7 +8,7 @@ use crate::{
 };
 use std::{
     array,
-    cell::{Cell, UnsafeCell},
+    cell::{Cell, RefCell, UnsafeCell},
     fmt, mem,
     ops::Range,
     ptr::{self, copy_nonoverlapping},
@@ -188,6 +188,8 @@ pub struct UnalignedMemoryMapping<'a> {
     config: &'a Config,
     /// CoW callback
     cow_cb: Option<MemoryCowCallback>,
+    /// Address ranges written while recording, see `MemoryMapping::record_stores()`
+    recorded_stores: RefCell<Option<Vec<Range<u64>>>>,
 }
 
 impl<'a> fmt::Debug for UnalignedMemoryMapping<'a> {
@@ -205,6 +207,7 @@ impl<'a> fmt::Debug for UnalignedMemoryMapping<'a> {
                     .map(|cb| format!("Some({:p})", &cb))
                     .unwrap_or_else(|| "None".to_string()),
             )
+            .field("recorded_stores", &self.recorded_stores)
             .finish()
     }
 }
@@ -256,6 +259,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
             cache: UnsafeCell::new(MappingCache::new()),
             config,
             cow_cb,
+            recorded_stores: RefCell::new(None),
         };
         result.construct_eytzinger_order(&mut regions, 0, 0);
         Ok(result)
@@ -519,6 +523,8 @@ pub struct AlignedMemoryMapping<'a> {
     config: &'a Config,
     /// CoW callback
     cow_cb: Option<MemoryCowCallback>,
+    /// Address ranges written while recording, see `MemoryMapping::record_stores()`
+    recorded_stores: RefCell<Option<Vec<Range<u64>>>>,
 }
 
 impl<'a> fmt::Debug for AlignedMemoryMapping<'a> {
@@ -534,6 +540,7 @@ impl<'a> fmt::Debug for AlignedMemoryMapping<'a> {
                     .map(|cb| format!("Some({:p})", &cb))
                     .unwrap_or_else(|| "None".to_string()),
             )
+            .field("recorded_stores", &self.recorded_stores)
             .finish()
     }
 }
@@ -557,6 +564,7 @@ impl<'a> AlignedMemoryMapping<'a> {
             }
         }
         Ok(Self {
+            recorded_stores: RefCell::new(None),
             regions: regions.into_boxed_slice(),
             config,
             cow_cb,
@@ -688,6 +696,8 @@ pub enum MemoryMapping<'a> {
     Aligned(AlignedMemoryMapping<'a>),
     /// Memory mapping that allows mapping unaligned memory regions.
     Unaligned(UnalignedMemoryMapping<'a>),
+    /// Aligned or unaligned memory mapping checked by the memory sanitizer.
+    Sanitizing(Box<SanitizingMemoryMapping<'a>>),
 }
 
 impl<'a> MemoryMapping<'a> {
@@ -705,6 +715,7 @@ impl<'a> MemoryMapping<'a> {
         } else {
             UnalignedMemoryMapping::new(regions, config).map(MemoryMapping::Unaligned)
         }
+        .map(|mapping| mapping.sanitize_if_enabled(config))
     }
 
     /// Creates a new memory mapping.
@@ -722,15 +733,35 @@ impl<'a> MemoryMapping<'a> {
             UnalignedMemoryMapping::new_with_cow(regions, cow_cb, config)
                 .map(MemoryMapping::Unaligned)
         }
+        .map(|mapping| mapping.sanitize_if_enabled(config))
+    }
+
+    /// Wraps the mapping into a SanitizingMemoryMapping if config.enable_memory_sanitizer=true
+    ///
+    /// This way the other mappings don't check the sanitizer on every access.
+    fn sanitize_if_enabled(self, config: &Config) -> Self {
+        if config.enable_memory_sanitizer {
+            MemoryMapping::Sanitizing(Box::new(SanitizingMemoryMapping::new(self)))
+        } else {
+            self
+        }
     }
 
     /// Map virtual memory to host memory.
+    ///
+    /// A successful `AccessType::Store` marks the bytes as initialized for the memory sanitizer
+    /// and is recorded if `record_stores()` was called.
     pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64, pc: usize) -> ProgramResult {
-        match self {
+        let result = match self {
             MemoryMapping::Identity => ProgramResult::Ok(vm_addr),
             MemoryMapping::Aligned(m) => m.map(access_type, vm_addr, len, pc),
             MemoryMapping::Unaligned(m) => m.map(access_type, vm_addr, len, pc),
+            MemoryMapping::Sanitizing(m) => return m.map(access_type, vm_addr, len, pc),
+        };
+        if let (AccessType::Store, ProgramResult::Ok(_)) = (access_type, &result) {
+            self.record_store(vm_addr, len);
         }
+        result
     }
 
     /// Loads `size_of::<T>()` bytes from the given address.
@@ -744,6 +775,7 @@ impl<'a> MemoryMapping<'a> {
             },
             MemoryMapping::Aligned(m) => m.load::<T>(vm_addr, pc),
             MemoryMapping::Unaligned(m) => m.load::<T>(vm_addr, pc),
+            MemoryMapping::Sanitizing(m) => m.load::<T>(vm_addr, pc),
         }
     }
 
@@ -752,14 +784,60 @@ impl<'a> MemoryMapping<'a> {
     /// Works across memory region boundaries if `len` does not fit within a single region.
     #[inline]
     pub fn store<T: Pod>(&self, value: T, vm_addr: u64, pc: usize) -> ProgramResult {
-        match self {
+        let result = match self {
             MemoryMapping::Identity => unsafe {
                 ptr::write_unaligned(vm_addr as *mut T, value);
                 ProgramResult::Ok(0)
             },
             MemoryMapping::Aligned(m) => m.store(value, vm_addr, pc),
             MemoryMapping::Unaligned(m) => m.store(value, vm_addr, pc),
+            MemoryMapping::Sanitizing(m) => return m.store(value, vm_addr, pc),
+        };
+        if let ProgramResult::Ok(_) = result {
+            self.record_store(vm_addr, mem::size_of::<T>() as u64);
         }
+        result
+    }
+
+    /// Marks host memory written without going through `map()` or `store()` as initialized
+    pub(crate) fn mark_initialized(&self, host_addr: u64, len: u64) {
+        if let MemoryMapping::Sanitizing(m) = self {
+            m.shadow.mark_initialized(host_addr, len);
+        }
+    }
+
+    fn recorded_stores(&self) -> Option<&RefCell<Option<Vec<Range<u64>>>>> {
+        match self {
+            MemoryMapping::Identity => None,
+            MemoryMapping::Aligned(m) => Some(&m.recorded_stores),
+            MemoryMapping::Unaligned(m) => Some(&m.recorded_stores),
+            MemoryMapping::Sanitizing(m) => m.mapping.recorded_stores(),
+        }
+    }
+
+    #[inline]
+    fn record_store(&self, vm_addr: u64, len: u64) {
+        if let Some(recorded_stores) = self.recorded_stores() {
+            if let Some(recorded_stores) = recorded_stores.borrow_mut().as_mut() {
+                recorded_stores.push(vm_addr..vm_addr.saturating_add(len));
+            }
+        }
+    }
+
+    /// Starts recording the address ranges written through `map()` and `store()`
+    ///
+    /// Lets the trace find the bytes a syscall wrote without comparing the memory regions.
+    pub(crate) fn record_stores(&self) {
+        if let Some(recorded_stores) = self.recorded_stores() {
+            *recorded_stores.borrow_mut() = Some(Vec::new());
+        }
+    }
+
+    /// Stops recording and returns the address ranges written since `record_stores()`
+    pub(crate) fn take_recorded_stores(&self) -> Vec<Range<u64>> {
+        self.recorded_stores()
+            .and_then(|recorded_stores| recorded_stores.borrow_mut().take())
+            .unwrap_or_default()
     }
 
     /// Returns the `MemoryRegion` corresponding to the given address.
@@ -772,6 +850,7 @@ impl<'a> MemoryMapping<'a> {
             MemoryMapping::Identity => Err(Box::new(EbpfError::InvalidMemoryRegion(0))),
             MemoryMapping::Aligned(m) => m.region(access_type, vm_addr),
             MemoryMapping::Unaligned(m) => m.region(access_type, vm_addr),
+            MemoryMapping::Sanitizing(m) => m.mapping.region(access_type, vm_addr),
         }
     }
 
@@ -781,6 +860,7 @@ impl<'a> MemoryMapping<'a> {
             MemoryMapping::Identity => &[],
             MemoryMapping::Aligned(m) => m.get_regions(),
             MemoryMapping::Unaligned(m) => m.get_regions(),
+            MemoryMapping::Sanitizing(m) => m.mapping.get_regions(),
         }
     }
 
@@ -790,7 +870,125 @@ impl<'a> MemoryMapping<'a> {
             MemoryMapping::Identity => Err(EbpfError::InvalidMemoryRegion(index)),
             MemoryMapping::Aligned(m) => m.replace_region(index, region),
             MemoryMapping::Unaligned(m) => m.replace_region(index, region),
+            MemoryMapping::Sanitizing(m) => m.replace_region(index, region),
+        }
+    }
+}
+
+/// Memory mapping which checks the loads of another mapping with the memory sanitizer
+///
+/// Only used if config.enable_memory_sanitizer=true, see `MemoryMapping::Sanitizing`.
+#[derive(Debug)]
+pub struct SanitizingMemoryMapping<'a> {
+    /// The aligned or unaligned mapping which translates the addresses
+    mapping: MemoryMapping<'a>,
+    /// Initialized bytes of the writable stack and heap regions
+    shadow: ShadowMemory,
+}
+
+impl<'a> SanitizingMemoryMapping<'a> {
+    fn new(mapping: MemoryMapping<'a>) -> Self {
+        let mut shadow = ShadowMemory::default();
+        for region in mapping.get_regions() {
+            shadow.track(region);
+        }
+        Self { mapping, shadow }
+    }
+
+    /// Map virtual memory to host memory.
+    ///
+    /// See [MemoryMapping::map].
+    pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64, pc: usize) -> ProgramResult {
+        let result = self.mapping.map(access_type, vm_addr, len, pc);
+        if let (AccessType::Store, ProgramResult::Ok(host_addr)) = (access_type, &result) {
+            self.shadow.mark_initialized(*host_addr, len);
+        }
+        result
+    }
+
+    /// Loads `size_of::<T>()` bytes from the given address.
+    ///
+    /// See [MemoryMapping::load].
+    #[inline]
+    pub fn load<T: Pod + Into<u64>>(&self, vm_addr: u64, pc: usize) -> ProgramResult {
+        match self.mapping.load::<T>(vm_addr, pc) {
+            ProgramResult::Ok(value) => {
+                match self.sanitize(AccessType::Load, vm_addr, mem::size_of::<T>() as u64, pc) {
+                    ProgramResult::Ok(_) => ProgramResult::Ok(value),
+                    err => err,
+                }
+            }
+            err => err,
+        }
+    }
+
+    /// Store `value` at the given address.
+    ///
+    /// See [MemoryMapping::store].
+    #[inline]
+    pub fn store<T: Pod>(&self, value: T, vm_addr: u64, pc: usize) -> ProgramResult {
+        match self.mapping.store(value, vm_addr, pc) {
+            ProgramResult::Ok(host_addr) => {
+                self.sanitize(AccessType::Store, vm_addr, mem::size_of::<T>() as u64, pc);
+                ProgramResult::Ok(host_addr)
+            }
+            err => err,
+        }
+    }
+
+    /// Marks the bytes of a successful store as initialized or checks those of a successful load
+    ///
+    /// Translates one range per memory region, as the access may span multiple of them.
+    fn sanitize(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+        pc: usize,
+    ) -> ProgramResult {
+        let end = vm_addr.saturating_add(len);
+        let mut chunk_vm_addr = vm_addr;
+        while chunk_vm_addr < end {
+            let region = match self.mapping.region(AccessType::Load, chunk_vm_addr) {
+                Ok(region) => region,
+                Err(_) => break,
+            };
+            let chunk_len = end.min(region.vm_addr_end).saturating_sub(chunk_vm_addr);
+            let host_addr = match region.vm_to_host(chunk_vm_addr, chunk_len) {
+                ProgramResult::Ok(host_addr) => host_addr,
+                ProgramResult::Err(_) => break,
+            };
+            match access_type {
+                AccessType::Store => self.shadow.mark_initialized(host_addr, chunk_len),
+                AccessType::Load => {
+                    if !self.shadow.is_initialized(host_addr, chunk_len) {
+                        return ProgramResult::Err(Box::new(EbpfError::UninitializedRead(
+                            pc,
+                            vm_addr,
+                            len,
+                            region_name(chunk_vm_addr),
+                        )));
+                    }
+                }
+            }
+            chunk_vm_addr = chunk_vm_addr.saturating_add(chunk_len);
+        }
+        ProgramResult::Ok(0)
+    }
+
+    /// Replaces the `MemoryRegion` at the given index
+    pub fn replace_region(&mut self, index: usize, region: MemoryRegion) -> Result<(), EbpfError> {
+        let old_host_addr = self
+            .mapping
+            .get_regions()
+            .get(index)
+            .map(|old_region| old_region.host_addr.get());
+        self.mapping.replace_region(index, region)?;
+        if let Some(old_host_addr) = old_host_addr {
+            self.shadow.untrack(old_host_addr);
         }
+        self.shadow.track(&self.mapping.get_regions()[index]);
+        Ok(())
     }
 }
 
@@ -835,23 +1033,107 @@ fn generate_access_violation(
             stack_frame,
         )))
     } else {
-        let region_name = match vm_addr & (!ebpf::MM_PROGRAM_START.saturating_sub(1)) {
-            ebpf::MM_PROGRAM_START => "program",
-            ebpf::MM_STACK_START => "stack",
-            ebpf::MM_HEAP_START => "heap",
-            ebpf::MM_INPUT_START => "input",
-            _ => "unknown",
-        };
         ProgramResult::Err(Box::new(EbpfError::AccessViolation(
             pc,
             access_type,
             vm_addr,
             len,
-            region_name,
+            region_name(vm_addr),
         )))
     }
 }
 
+/// Name of the section whose address space contains `vm_addr`
+fn region_name(vm_addr: u64) -> &'static str {
+    match vm_addr & (!ebpf::MM_PROGRAM_START.saturating_sub(1)) {
+        ebpf::MM_PROGRAM_START => "program",
+        ebpf::MM_STACK_START => "stack",
+        ebpf::MM_HEAP_START => "heap",
+        ebpf::MM_INPUT_START => "input",
+        _ => "unknown",
+    }
+}
+
+/// Host memory of a region tracked by the memory sanitizer
+#[derive(Debug)]
+struct ShadowRegion {
+    host_addr: u64,
+    len: u64,
+    /// One bit per byte, set once the byte was written
+    initialized: Box<[Cell<u64>]>,
+}
+
+/// Shadow state of the memory sanitizer
+///
+/// Tracks which bytes of the writable stack and heap regions were written,
+/// either by the program or by a syscall through `MemoryMapping::map()`.
+#[derive(Debug, Default)]
+struct ShadowMemory {
+    regions: Vec<ShadowRegion>,
+}
+
+impl ShadowMemory {
+    /// Starts tracking `region` if it is a writable stack or heap region
+    fn track(&mut self, region: &MemoryRegion) {
+        let is_tracked = matches!(
+            region.vm_addr & (!ebpf::MM_PROGRAM_START.saturating_sub(1)),
+            ebpf::MM_STACK_START | ebpf::MM_HEAP_START
+        );
+        if !is_tracked || region.state.get() != MemoryState::Writable || region.len == 0 {
+            return;
+        }
+        let words = region.len.saturating_add(63).checked_shr(6).unwrap_or(0);
+        self.regions.push(ShadowRegion {
+            host_addr: region.host_addr.get(),
+            len: region.len,
+            initialized: (0..words).map(|_| Cell::new(0)).collect(),
+        });
+    }
+
+    fn untrack(&mut self, host_addr: u64) {
+        self.regions
+            .retain(|shadow_region| shadow_region.host_addr != host_addr);
+    }
+
+    fn mark_initialized(&self, host_addr: u64, len: u64) {
+        for shadow_region in self.regions.iter() {
+            for offset in shadow_region.offsets(host_addr, len) {
+                let (word, bit) = shadow_region.bit(offset);
+                word.set(word.get() | bit);
+            }
+        }
+    }
+
+    /// Untracked bytes count as initialized
+    fn is_initialized(&self, host_addr: u64, len: u64) -> bool {
+        self.regions.iter().all(|shadow_region| {
+            shadow_region.offsets(host_addr, len).all(|offset| {
+                let (word, bit) = shadow_region.bit(offset);
+                word.get() & bit != 0
+            })
+        })
+    }
+}
+
+impl ShadowRegion {
+    /// Offsets into this region of the bytes in `host_addr..host_addr + len`
+    fn offsets(&self, host_addr: u64, len: u64) -> Range<u64> {
+        let start = host_addr.max(self.host_addr);
+        let end = host_addr
+            .saturating_add(len)
+            .min(self.host_addr.saturating_add(self.len));
+        start.saturating_sub(self.host_addr)..end.saturating_sub(self.host_addr)
+    }
+
+    /// Returns the word and bit of the byte at `offset`
+    fn bit(&self, offset: u64) -> (&Cell<u64>, u64) {
+        (
+            &self.initialized[offset.checked_shr(6).unwrap_or(0) as usize],
+            1u64.checked_shl((offset & 63) as u32).unwrap_or(0),
+        )
+    }
+}
+
 /// Fast, small linear cache used to speed up unaligned memory mapping.
 #[derive(Debug)]
 struct MappingCache {
@@ -1735,4 +2017,84 @@ mod test {
 
         m.store(33u8, ebpf::MM_PROGRAM_START, 0).unwrap();
     }
+
+    #[test]
+    fn test_memory_sanitizer() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                enable_memory_sanitizer: true,
+                ..Config::default()
+            };
+            let program = [0u8; 8];
+            let mut stack = [0u8; 8];
+            let mut heap = [0u8; 8];
+            let input = [0u8; 8];
+            let m = MemoryMapping::new(
+                vec![
+                    MemoryRegion::new_readonly(&program, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable(&mut stack, ebpf::MM_STACK_START),
+                    MemoryRegion::new_writable(&mut heap, ebpf::MM_HEAP_START),
+                    MemoryRegion::new_readonly(&input, ebpf::MM_INPUT_START),
+                ],
+                &config,
+            )
+            .unwrap();
+
+            assert_error!(
+                m.load::<u16>(ebpf::MM_HEAP_START + 3, 10),
+                "UninitializedRead(10, {}, 2, \"heap\")",
+                ebpf::MM_HEAP_START + 3
+            );
+            m.store(0x11u8, ebpf::MM_HEAP_START + 3, 0).unwrap();
+            assert_error!(
+                m.load::<u16>(ebpf::MM_HEAP_START + 3, 10),
+                "UninitializedRead(10, {}, 2, \"heap\")",
+                ebpf::MM_HEAP_START + 3
+            );
+            m.map(AccessType::Store, ebpf::MM_HEAP_START + 4, 4, 0)
+                .unwrap();
+            assert_eq!(m.load::<u16>(ebpf::MM_HEAP_START + 3, 10).unwrap(), 0x11);
+            assert_error!(
+                m.load::<u64>(ebpf::MM_HEAP_START, 10),
+                "UninitializedRead(10, {}, 8, \"heap\")",
+                ebpf::MM_HEAP_START
+            );
+            assert_eq!(m.load::<u64>(ebpf::MM_PROGRAM_START, 10).unwrap(), 0);
+            assert_eq!(m.load::<u64>(ebpf::MM_INPUT_START, 10).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_memory_sanitizer_across_regions() {
+        let config = Config {
+            aligned_memory_mapping: false,
+            enable_memory_sanitizer: true,
+            ..Config::default()
+        };
+        let mut heap_a = [0u8; 8];
+        let mut heap_b = [0u8; 8];
+        let m = MemoryMapping::new(
+            vec![
+                MemoryRegion::new_writable(&mut heap_a, ebpf::MM_HEAP_START),
+                MemoryRegion::new_writable(&mut heap_b, ebpf::MM_HEAP_START + 8),
+            ],
+            &config,
+        )
+        .unwrap();
+
+        m.store(0x1122u16, ebpf::MM_HEAP_START + 7, 0).unwrap();
+        assert_eq!(m.load::<u16>(ebpf::MM_HEAP_START + 7, 10).unwrap(), 0x1122);
+        m.store(0x33u8, ebpf::MM_HEAP_START + 6, 0).unwrap();
+        assert_error!(
+            m.load::<u32>(ebpf::MM_HEAP_START + 6, 10),
+            "UninitializedRead(10, {}, 4, \"heap\")",
+            ebpf::MM_HEAP_START + 6
+        );
+        m.store(0x44u8, ebpf::MM_HEAP_START + 9, 0).unwrap();
+        assert_eq!(
+            m.load::<u32>(ebpf::MM_HEAP_START + 6, 10).unwrap(),
+            0x44112233
+        );
+    }
 }
@@ -0,0 +1,263 @@
+//! Per-function instruction count profiles which follow the call stack
+
+use crate::{
+    coverage::increment,
+    ebpf,
+    static_analysis::{Analysis, TraceLogEntry},
+};
+use std::{collections::BTreeMap, io::Write};
+
+/// Instruction counts of a function
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// Demangled name
+    pub name: String,
+    /// Instructions executed in the function itself
+    pub exclusive: usize,
+    /// Instructions executed in the function and everything it called
+    ///
+    /// Recursive calls are only counted once.
+    pub inclusive: usize,
+    /// Instructions executed in the function itself, keyed by pc
+    pub instructions: BTreeMap<usize, usize>,
+}
+
+/// Calls from one call site to a function
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallProfile {
+    /// How often the call was made
+    pub count: usize,
+    /// Instructions executed in the callee and everything it called
+    pub inclusive: usize,
+}
+
+/// Profile of one or more runs
+///
+/// Functions are identified by the pc of their first instruction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Instruction counts of every function which was executed
+    pub functions: BTreeMap<usize, FunctionProfile>,
+    /// Call stacks starting at the root function and the instructions executed in them
+    pub stacks: BTreeMap<Vec<usize>, usize>,
+    /// Caller function, pc of the call instruction, callee function and the calls made there
+    pub calls: BTreeMap<(usize, usize, usize), CallProfile>,
+}
+
+/// A function on the call stack while a trace log is replayed
+struct Frame {
+    function: usize,
+    call_site: usize,
+    /// Index of the first trace log entry executed in this frame
+    start: usize,
+}
+
+impl Profile {
+    /// Accumulates a trace log as recorded by `ContextObject::trace()`
+    ///
+    /// The call stack is reconstructed from the `call` and `exit` instructions in the trace.
+    pub fn add_trace_log(&mut self, analysis: &Analysis, trace_log: &[TraceLogEntry]) {
+        let function_starts = analysis.functions.keys().copied().collect::<Vec<_>>();
+        let function_of = |pc: usize| {
+            function_starts
+                .partition_point(|function_pc| *function_pc <= pc)
+                .checked_sub(1)
+                .map(|index| function_starts[index])
+                .unwrap_or(0)
+        };
+        let mut frames = Vec::<Frame>::new();
+        let mut stack = Vec::<usize>::new();
+        for (index, entry) in trace_log.iter().enumerate() {
+            let pc = entry[11] as usize;
+            if frames.is_empty() {
+                // Either the start of the trace or the root function returned
+                let function = function_of(pc);
+                frames.push(Frame {
+                    function,
+                    call_site: usize::MAX,
+                    start: index,
+                });
+                stack.push(function);
+            }
+            let function = stack[stack.len().saturating_sub(1)];
+            let function_profile = self.function_profile(analysis, function);
+            increment(&mut function_profile.exclusive, 1);
+            increment(function_profile.instructions.entry(pc).or_insert(0), 1);
+            match self.stacks.get_mut(&stack) {
+                Some(counter) => increment(counter, 1),
+                None => {
+                    self.stacks.insert(stack.clone(), 1);
+                }
+            }
+            let insn = match analysis
+                .instructions
+                .binary_search_by_key(&pc, |insn| insn.ptr)
+            {
+                Ok(insn_index) => &analysis.instructions[insn_index],
+                Err(_) => continue,
+            };
+            match insn.opc {
+                ebpf::CALL_IMM | ebpf::CALL_REG if !analysis.is_syscall(insn) => {
+                    // Only a call which was executed has a successor in the trace
+                    if let Some(next_entry) = trace_log.get(index.saturating_add(1)) {
+                        let callee = next_entry[11] as usize;
+                        frames.push(Frame {
+                            function: callee,
+                            call_site: pc,
+                            start: index.saturating_add(1),
+                        });
+                        stack.push(callee);
+                    }
+                }
+                ebpf::EXIT => {
+                    self.pop_frame(analysis, &mut frames, &mut stack, index.saturating_add(1));
+                }
+                _ => {}
+            }
+        }
+        // Frames which did not return because the program was aborted
+        while !frames.is_empty() {
+            self.pop_frame(analysis, &mut frames, &mut stack, trace_log.len());
+        }
+    }
+
+    /// Adds the counters of another profile
+    pub fn merge(&mut self, other: &Self) {
+        for (function, other_profile) in other.functions.iter() {
+            let function_profile =
+                self.functions
+                    .entry(*function)
+                    .or_insert_with(|| FunctionProfile {
+                        name: other_profile.name.clone(),
+                        ..FunctionProfile::default()
+                    });
+            increment(&mut function_profile.exclusive, other_profile.exclusive);
+            increment(&mut function_profile.inclusive, other_profile.inclusive);
+            for (pc, counter) in other_profile.instructions.iter() {
+                increment(
+                    function_profile.instructions.entry(*pc).or_insert(0),
+                    *counter,
+                );
+            }
+        }
+        for (stack, counter) in other.stacks.iter() {
+            increment(self.stacks.entry(stack.clone()).or_insert(0), *counter);
+        }
+        for (call, other_profile) in other.calls.iter() {
+            let call_profile = self.calls.entry(*call).or_default();
+            increment(&mut call_profile.count, other_profile.count);
+            increment(&mut call_profile.inclusive, other_profile.inclusive);
+        }
+    }
+
+    fn function_profile(&mut self, analysis: &Analysis, function: usize) -> &mut FunctionProfile {
+        self.functions
+            .entry(function)
+            .or_insert_with(|| FunctionProfile {
+                name: analysis
+                    .cfg_nodes
+                    .get(&function)
+                    .map(|cfg_node| cfg_node.label.clone())
+                    .unwrap_or_else(|| format!("function_{function}")),
+                ..FunctionProfile::default()
+            })
+    }
+
+    /// Removes the innermost frame, `end` is the index of the first trace log entry after it
+    fn pop_frame(
+        &mut self,
+        analysis: &Analysis,
+        frames: &mut Vec<Frame>,
+        stack: &mut Vec<usize>,
+        end: usize,
+    ) {
+        let frame = match frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        stack.pop();
+        let inclusive = end.saturating_sub(frame.start);
+        if !stack.contains(&frame.function) {
+            increment(
+                &mut self.function_profile(analysis, frame.function).inclusive,
+                inclusive,
+            );
+        }
+        if let Some(caller) = frames.last() {
+            let call_profile = self
+                .calls
+                .entry((caller.function, frame.call_site, frame.function))
+                .or_default();
+            increment(&mut call_profile.count, 1);
+            increment(&mut call_profile.inclusive, inclusive);
+        }
+    }
+
+    fn function_name(&self, function: usize) -> &str {
+        self.functions
+            .get(&function)
+            .map(|function_profile| function_profile.name.as_str())
+            .unwrap_or("")
+    }
+
+    /// Writes the call stacks in the folded format of flamegraph tools
+    pub fn write_folded<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        for (stack, counter) in self.stacks.iter() {
+            let names = stack
+                .iter()
+                .map(|function| self.function_name(*function))
+                .collect::<Vec<_>>();
+            writeln!(output, "{} {counter}", names.join(";"))?;
+        }
+        Ok(())
+    }
+
+    /// Writes a callgrind file for KCachegrind, positions are the pcs of the instructions
+    pub fn write_callgrind<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let total = self
+            .functions
+            .values()
+            .fold(0usize, |total, function_profile| {
+                total.saturating_add(function_profile.exclusive)
+            });
+        writeln!(output, "# callgrind format")?;
+        writeln!(output, "version: 1")?;
+        writeln!(output, "creator: solana_rbpf {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(output, "positions: instr")?;
+        writeln!(output, "events: Instructions")?;
+        writeln!(output, "summary: {total}")?;
+        // Names are compressed into ids, the first use of an id also defines the name
+        let ids = self
+            .functions
+            .keys()
+            .enumerate()
+            .map(|(index, function)| (*function, index.saturating_add(1)))
+            .collect::<BTreeMap<_, _>>();
+        let mut defined = vec![false; ids.len()];
+        let mut function_reference = |function: usize| {
+            let id = ids[&function];
+            let first_use = !std::mem::replace(&mut defined[id.saturating_sub(1)], true);
+            if first_use {
+                format!("({id}) {}", self.function_name(function))
+            } else {
+                format!("({id})")
+            }
+        };
+        for (function, function_profile) in self.functions.iter() {
+            writeln!(output)?;
+            writeln!(output, "fn={}", function_reference(*function))?;
+            for (pc, counter) in function_profile.instructions.iter() {
+                writeln!(output, "{pc} {counter}")?;
+            }
+            for ((_caller, call_site, callee), call_profile) in self
+                .calls
+                .range((*function, 0, 0)..=(*function, usize::MAX, usize::MAX))
+            {
+                writeln!(output, "cfn={}", function_reference(*callee))?;
+                writeln!(output, "calls={} {callee}", call_profile.count)?;
+                writeln!(output, "{call_site} {}", call_profile.inclusive)?;
+            }
+        }
+        Ok(())
+    }
+}
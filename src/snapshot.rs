@@ -0,0 +1,230 @@
+//! Snapshots of suspended or failed programs including their memory
+
+use crate::{
+    ebpf,
+    error::EbpfError,
+    memory_region::{AccessType, MemoryState},
+    verifier::Verifier,
+    vm::{CallFrame, ContextObject, EbpfVm, SuspendedState},
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read};
+
+/// Identifies the serialized form of a Snapshot
+const MAGIC: &[u8; 8] = b"RBPFSNAP";
+
+/// Version of the serialized form of a Snapshot
+const VERSION: u32 = 1;
+
+/// Contents of a writable MemoryRegion
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegionSnapshot {
+    /// Start virtual address
+    pub vm_addr: u64,
+    /// Size of regular gaps as bit shift (63 means the region is continuous)
+    pub vm_gap_shift: u8,
+    /// The bytes backing the region on the host
+    pub data: Vec<u8>,
+}
+
+/// Complete state of a suspended or failed VM
+///
+/// Consists of the SuspendedState and the contents of every writable MemoryRegion.
+/// Readonly regions are not part of it, they have to be provided again by the host.
+/// A program which was suspended can be captured if config.enable_suspension=true,
+/// a program which failed if config.enable_backtrace=true.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Registers, call frames and stack pointer
+    pub state: SuspendedState,
+    /// Writable memory regions, ordered by vm_addr
+    pub regions: Vec<RegionSnapshot>,
+}
+
+impl Snapshot {
+    /// Serializes the snapshot into a byte blob
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.write_u32::<LittleEndian>(VERSION).unwrap();
+        for register in self.state.registers.iter() {
+            bytes.write_u64::<LittleEndian>(*register).unwrap();
+        }
+        bytes
+            .write_u64::<LittleEndian>(self.state.call_depth)
+            .unwrap();
+        bytes
+            .write_u64::<LittleEndian>(self.state.stack_pointer)
+            .unwrap();
+        bytes
+            .write_u64::<LittleEndian>(self.state.overdue_insn_count)
+            .unwrap();
+        bytes
+            .write_u64::<LittleEndian>(self.state.call_frames.len() as u64)
+            .unwrap();
+        for frame in self.state.call_frames.iter() {
+            for register in frame.caller_saved_registers.iter() {
+                bytes.write_u64::<LittleEndian>(*register).unwrap();
+            }
+            bytes
+                .write_u64::<LittleEndian>(frame.frame_pointer)
+                .unwrap();
+            bytes
+                .write_u64::<LittleEndian>(frame.target_pc as u64)
+                .unwrap();
+        }
+        bytes
+            .write_u64::<LittleEndian>(self.regions.len() as u64)
+            .unwrap();
+        for region in self.regions.iter() {
+            bytes.write_u64::<LittleEndian>(region.vm_addr).unwrap();
+            bytes.write_u8(region.vm_gap_shift).unwrap();
+            bytes
+                .write_u64::<LittleEndian>(region.data.len() as u64)
+                .unwrap();
+            bytes.extend_from_slice(&region.data);
+        }
+        bytes
+    }
+
+    /// Deserializes a snapshot from a byte blob created by `to_bytes()`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EbpfError> {
+        Self::read(&mut Cursor::new(bytes)).map_err(|_| EbpfError::InvalidSnapshot)
+    }
+
+    fn read(reader: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let invalid_data = || std::io::Error::from(std::io::ErrorKind::InvalidData);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || reader.read_u32::<LittleEndian>()? != VERSION {
+            return Err(invalid_data());
+        }
+        let mut state = SuspendedState::default();
+        for register in state.registers.iter_mut() {
+            *register = reader.read_u64::<LittleEndian>()?;
+        }
+        state.call_depth = reader.read_u64::<LittleEndian>()?;
+        state.stack_pointer = reader.read_u64::<LittleEndian>()?;
+        state.overdue_insn_count = reader.read_u64::<LittleEndian>()?;
+        let call_frame_count = reader.read_u64::<LittleEndian>()?;
+        for _ in 0..call_frame_count {
+            let mut frame = CallFrame::default();
+            for register in frame.caller_saved_registers.iter_mut() {
+                *register = reader.read_u64::<LittleEndian>()?;
+            }
+            frame.frame_pointer = reader.read_u64::<LittleEndian>()?;
+            frame.target_pc = reader.read_u64::<LittleEndian>()? as usize;
+            state.call_frames.push(frame);
+        }
+        let region_count = reader.read_u64::<LittleEndian>()?;
+        let mut regions = Vec::new();
+        for _ in 0..region_count {
+            let vm_addr = reader.read_u64::<LittleEndian>()?;
+            let vm_gap_shift = reader.read_u8()?;
+            let len = reader.read_u64::<LittleEndian>()?;
+            // Don't trust the length before the bytes are actually there
+            let remaining = reader.get_ref().len() as u64;
+            if len > remaining.saturating_sub(reader.position()) {
+                return Err(invalid_data());
+            }
+            let mut data = vec![0u8; len as usize];
+            reader.read_exact(&mut data)?;
+            regions.push(RegionSnapshot {
+                vm_addr,
+                vm_gap_shift,
+                data,
+            });
+        }
+        if reader.position() != reader.get_ref().len() as u64 {
+            return Err(invalid_data());
+        }
+        Ok(Self { state, regions })
+    }
+}
+
+impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
+    /// Captures the SuspendedState of the last execution and the contents of all writable regions
+    ///
+    /// If the last execution failed, the state is the one at the instruction which failed,
+    /// taken from its Backtrace. Resuming from it runs that instruction again.
+    /// Fails with `EbpfError::NotSuspended` unless the last call to `execute_program()` or
+    /// `resume_program()` suspended the program or captured a Backtrace, because the registers
+    /// and call frames of a running program are only captured at these points.
+    pub fn snapshot(&self) -> Result<Snapshot, EbpfError> {
+        let state = match (&self.env.suspended_state, &self.env.backtrace) {
+            (Some(state), _) => state.clone(),
+            (None, Some(backtrace)) => SuspendedState {
+                registers: backtrace.registers,
+                call_depth: backtrace.call_frames.len() as u64,
+                // Fixed frames keep the stack pointer at the frame pointer, which a backtrace
+                // restores for a call which failed after pushing its frame
+                stack_pointer: if self.executable.get_config().dynamic_stack_frames {
+                    self.env.stack_pointer
+                } else {
+                    backtrace.registers[ebpf::FRAME_PTR_REG]
+                },
+                call_frames: backtrace.call_frames.clone(),
+                overdue_insn_count: 0,
+            },
+            (None, None) => return Err(EbpfError::NotSuspended),
+        };
+        let regions = self
+            .env
+            .memory_mapping
+            .get_regions()
+            .iter()
+            .filter(|region| region.len > 0 && region.state.get() == MemoryState::Writable)
+            .map(|region| RegionSnapshot {
+                vm_addr: region.vm_addr,
+                vm_gap_shift: region.vm_gap_shift,
+                data: unsafe {
+                    std::slice::from_raw_parts(
+                        region.host_addr.get() as *const u8,
+                        region.len as usize,
+                    )
+                }
+                .to_vec(),
+            })
+            .collect();
+        Ok(Snapshot { state, regions })
+    }
+
+    /// Writes the memory contents of a snapshot back into the memory regions of this VM
+    ///
+    /// The VM has to be created with the same layout of memory regions as the one the
+    /// snapshot was taken from. Copy on write regions are copied before being overwritten.
+    /// The memory sanitizer considers all restored bytes initialized.
+    /// Returns the SuspendedState which can then be passed to `resume_program()`,
+    /// which also validates it.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<SuspendedState, EbpfError> {
+        let mut destinations = Vec::with_capacity(snapshot.regions.len());
+        for region_snapshot in snapshot.regions.iter() {
+            let region = self
+                .env
+                .memory_mapping
+                .region(AccessType::Store, region_snapshot.vm_addr)
+                .map_err(|_| EbpfError::InvalidSnapshot)?;
+            if region.vm_addr != region_snapshot.vm_addr
+                || region.vm_gap_shift != region_snapshot.vm_gap_shift
+                || region.len != region_snapshot.data.len() as u64
+            {
+                return Err(EbpfError::InvalidSnapshot);
+            }
+            destinations.push(region.host_addr.get() as *mut u8);
+        }
+        // Only write once all regions are known to match
+        for (region_snapshot, destination) in snapshot.regions.iter().zip(destinations) {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    region_snapshot.data.as_ptr(),
+                    destination,
+                    region_snapshot.data.len(),
+                );
+            }
+            self.env
+                .memory_mapping
+                .mark_initialized(destination as u64, region_snapshot.data.len() as u64);
+        }
+        Ok(snapshot.state.clone())
+    }
+}
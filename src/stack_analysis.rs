@@ -0,0 +1,245 @@
+//! Static worst-case stack usage across the call graph
+//!
+//! With fixed frames every call occupies `Config::stack_frame_size` bytes. With
+//! `Config::dynamic_stack_frames` a function reserves stack space by lowering the stack pointer
+//! r11 and its callees start their frames below that, so the usage of a call chain is the sum of
+//! how far each function lowered r11 when it made the call.
+
+use crate::{ebpf, static_analysis::Analysis, vm::Config};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How often the stack pointer offset of a basic block may grow before it is deemed unbounded
+const STACK_POINTER_VISIT_LIMIT: usize = 8;
+
+/// Worst-case stack usage of a function including everything it calls
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StackUsage {
+    /// Bytes of the stack region used
+    pub bytes: u64,
+    /// Number of call frames, including the one of the function itself
+    pub frames: usize,
+    /// Functions of the call chain which uses the most bytes, starting with this one
+    pub largest_call_chain: Vec<usize>,
+    /// Functions of the call chain with the most frames, starting with this one
+    pub deepest_call_chain: Vec<usize>,
+}
+
+/// Reasons why a program can run out of stack
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StackProblem {
+    /// Functions which call each other in a cycle, starting with the lowest pc
+    Recursion(Vec<usize>),
+    /// Function whose stack pointer (r11) adjustments have no lower bound
+    UnboundedStackPointer(usize),
+    /// Call chain with more frames than `Config::max_call_depth` allows
+    CallDepthExceeded(Vec<usize>),
+    /// Call chain which uses more bytes than the stack region has
+    StackOverflow(Vec<usize>, u64),
+}
+
+/// Calls and stack pointer adjustments of a single function
+struct FunctionFrame {
+    /// Bytes the function lowers the stack pointer at most
+    bytes: u64,
+    /// Callees and how far the stack pointer was lowered when they were called
+    calls: Vec<(usize, u64)>,
+}
+
+/// Static stack usage of all functions
+#[derive(Debug, Default)]
+pub struct StackAnalysis {
+    /// Usage of every function by the pc of its first instruction
+    ///
+    /// `None` if the function can recurse or adjusts the stack pointer without bound,
+    /// directly or through its callees. Calls through `callx` are not followed.
+    pub functions: BTreeMap<usize, Option<StackUsage>>,
+    /// Problems found in call chains starting at functions which are not called by others
+    pub problems: BTreeSet<StackProblem>,
+}
+
+impl StackAnalysis {
+    /// Analyzes every function of the analysis against the limits of the config
+    pub fn new(analysis: &Analysis, config: &Config) -> Self {
+        let mut result = Self::default();
+        let function_by_key = analysis
+            .functions
+            .iter()
+            .map(|(pc, (key, _name))| (*key, *pc))
+            .collect::<BTreeMap<u32, usize>>();
+        let frames = analysis
+            .functions
+            .keys()
+            .map(|function| {
+                let frame = function_frame(analysis, &function_by_key, *function);
+                if frame.is_none() {
+                    result
+                        .problems
+                        .insert(StackProblem::UnboundedStackPointer(*function));
+                }
+                (*function, frame)
+            })
+            .collect::<BTreeMap<usize, Option<FunctionFrame>>>();
+        for function in analysis.functions.keys() {
+            result.usage(config, &frames, *function, &mut Vec::new());
+        }
+        let callees = frames
+            .values()
+            .flatten()
+            .flat_map(|frame| frame.calls.iter().map(|(callee, _bytes)| *callee))
+            .collect::<BTreeSet<usize>>();
+        for function in analysis.functions.keys() {
+            if callees.contains(function) && *function != analysis.entrypoint {
+                continue;
+            }
+            if let Some(Some(usage)) = result.functions.get(function) {
+                if usage.frames > config.max_call_depth {
+                    result.problems.insert(StackProblem::CallDepthExceeded(
+                        usage.deepest_call_chain.clone(),
+                    ));
+                }
+                if usage.bytes > config.stack_size() as u64 {
+                    result.problems.insert(StackProblem::StackOverflow(
+                        usage.largest_call_chain.clone(),
+                        usage.bytes,
+                    ));
+                }
+            }
+        }
+        result
+    }
+
+    /// Usage of the program as a whole, `None` if it is unbounded
+    pub fn entrypoint<'a>(&'a self, analysis: &Analysis) -> Option<&'a StackUsage> {
+        self.functions.get(&analysis.entrypoint)?.as_ref()
+    }
+
+    fn usage(
+        &mut self,
+        config: &Config,
+        frames: &BTreeMap<usize, Option<FunctionFrame>>,
+        function: usize,
+        call_stack: &mut Vec<usize>,
+    ) -> Option<StackUsage> {
+        if let Some(usage) = self.functions.get(&function) {
+            return usage.clone();
+        }
+        if let Some(index) = call_stack.iter().position(|caller| *caller == function) {
+            let mut cycle = call_stack[index..].to_vec();
+            let lowest = cycle
+                .iter()
+                .enumerate()
+                .min_by_key(|(_index, function)| **function)
+                .map(|(index, _function)| index)
+                .unwrap_or(0);
+            cycle.rotate_left(lowest);
+            self.problems.insert(StackProblem::Recursion(cycle));
+            return None;
+        }
+        let frame = frames.get(&function)?.as_ref()?;
+        call_stack.push(function);
+        let mut usage = Some(StackUsage {
+            bytes: if config.dynamic_stack_frames {
+                frame.bytes
+            } else {
+                config.stack_frame_size as u64
+            },
+            frames: 1,
+            largest_call_chain: vec![function],
+            deepest_call_chain: vec![function],
+        });
+        for (callee, bytes_at_call) in frame.calls.iter() {
+            // Keep going after a failure to find all recursion cycles
+            let callee_usage = self.usage(config, frames, *callee, call_stack);
+            if let (Some(usage), Some(callee_usage)) = (usage.as_mut(), callee_usage) {
+                let bytes = if config.dynamic_stack_frames {
+                    bytes_at_call.saturating_add(callee_usage.bytes)
+                } else {
+                    (config.stack_frame_size as u64).saturating_add(callee_usage.bytes)
+                };
+                if bytes > usage.bytes {
+                    usage.bytes = bytes;
+                    usage.largest_call_chain =
+                        [vec![function], callee_usage.largest_call_chain].concat();
+                }
+                let frames = callee_usage.frames.saturating_add(1);
+                if frames > usage.frames {
+                    usage.frames = frames;
+                    usage.deepest_call_chain =
+                        [vec![function], callee_usage.deepest_call_chain].concat();
+                }
+            } else {
+                usage = None;
+            }
+        }
+        call_stack.pop();
+        self.functions.insert(function, usage.clone());
+        usage
+    }
+}
+
+/// Follows the stack pointer through the control-flow graph of a function
+///
+/// Returns `None` if the stack pointer can be lowered without bound, e.g. in a loop.
+fn function_frame(
+    analysis: &Analysis,
+    function_by_key: &BTreeMap<u32, usize>,
+    function: usize,
+) -> Option<FunctionFrame> {
+    let function_range = analysis
+        .iter_cfg_by_function()
+        .find(|(function_range, _cfg_node_start, _cfg_node)| function_range.start == function)
+        .map(|(function_range, _cfg_node_start, _cfg_node)| function_range)?;
+    let mut frame = FunctionFrame {
+        bytes: 0,
+        calls: Vec::new(),
+    };
+    // Bytes the stack pointer is lowered at the start of every basic block
+    let mut offsets = BTreeMap::<usize, (i64, usize)>::from([(function, (0, 0))]);
+    let mut worklist = vec![function];
+    while let Some(cfg_node_start) = worklist.pop() {
+        let cfg_node = &analysis.cfg_nodes[&cfg_node_start];
+        let mut offset = offsets[&cfg_node_start].0;
+        for insn in analysis.instructions[cfg_node.instructions.clone()].iter() {
+            match insn.opc {
+                ebpf::SUB64_IMM if insn.dst as usize == ebpf::STACK_PTR_REG => {
+                    offset = offset.saturating_add(insn.imm);
+                }
+                ebpf::ADD64_IMM if insn.dst as usize == ebpf::STACK_PTR_REG => {
+                    offset = offset.saturating_sub(insn.imm);
+                }
+                ebpf::CALL_IMM if !analysis.is_syscall(insn) => {
+                    if let Some(callee) = function_by_key.get(&(insn.imm as u32)) {
+                        frame.calls.push((*callee, offset.max(0) as u64));
+                    }
+                }
+                _ => {}
+            }
+            frame.bytes = frame.bytes.max(offset.max(0) as u64);
+        }
+        for destination in cfg_node.destinations.iter() {
+            if !function_range.contains(destination) {
+                continue;
+            }
+            match offsets.get_mut(destination) {
+                Some((destination_offset, visits)) if *destination_offset < offset => {
+                    *visits = visits.saturating_add(1);
+                    if *visits > STACK_POINTER_VISIT_LIMIT {
+                        return None;
+                    }
+                    *destination_offset = offset;
+                }
+                Some(_) => continue,
+                None => {
+                    offsets.insert(*destination, (offset, 0));
+                }
+            }
+            worklist.push(*destination);
+        }
+    }
+    // Calls are found once per visit of their basic block
+    frame
+        .calls
+        .sort_unstable_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    frame.calls.dedup_by_key(|(callee, _bytes)| *callee);
+    Some(frame)
+}
@@ -1,6 +1,8 @@
 #![allow(clippy::integer_arithmetic)]
 //! Static Byte Code Analysis
 
+#[cfg(feature = "debug-info")]
+use crate::debug_info::SourceLocation;
 use crate::disassembler::disassemble_instruction;
 use crate::{
     ebpf,
@@ -47,6 +49,91 @@ impl PartialOrd for TopologicalIndex {
     }
 }
 
+/// Tarjan's algorithm on a graph whose nodes are the indices of `successors`
+///
+/// Returns the topological index of every node. The components are numbered in reverse
+/// topological order and the discovery index is the position of a node in its component.
+pub(crate) fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<TopologicalIndex> {
+    if successors.is_empty() {
+        return Vec::new();
+    }
+    struct NodeState {
+        discovery: usize,
+        lowlink: usize,
+        scc_id: usize,
+        is_on_scc_stack: bool,
+    }
+    let mut nodes = successors
+        .iter()
+        .map(|_| NodeState {
+            discovery: usize::MAX,
+            lowlink: usize::MAX,
+            scc_id: usize::MAX,
+            is_on_scc_stack: false,
+        })
+        .collect::<Vec<NodeState>>();
+    let mut scc_id = 0;
+    let mut scc_stack = Vec::new();
+    let mut discovered = 0;
+    let mut next_v = 1;
+    let mut recursion_stack = vec![(0, 0)];
+    'dfs: while let Some((v, edge_index)) = recursion_stack.pop() {
+        let node = &mut nodes[v];
+        if edge_index == 0 {
+            node.discovery = discovered;
+            node.lowlink = discovered;
+            node.is_on_scc_stack = true;
+            scc_stack.push(v);
+            discovered += 1;
+        }
+        for (j, w) in successors[v].iter().copied().enumerate().skip(edge_index) {
+            if nodes[w].discovery == usize::MAX {
+                recursion_stack.push((v, j + 1));
+                recursion_stack.push((w, 0));
+                continue 'dfs;
+            } else if nodes[w].is_on_scc_stack {
+                nodes[v].lowlink = nodes[v].lowlink.min(nodes[w].discovery);
+            }
+        }
+        if nodes[v].discovery == nodes[v].lowlink {
+            let mut index_in_scc = 0;
+            while let Some(w) = scc_stack.pop() {
+                let node = &mut nodes[w];
+                node.is_on_scc_stack = false;
+                node.scc_id = scc_id;
+                node.discovery = index_in_scc;
+                index_in_scc += 1;
+                if w == v {
+                    break;
+                }
+            }
+            scc_id += 1;
+        }
+        if let Some((w, _)) = recursion_stack.last() {
+            nodes[*w].lowlink = nodes[*w].lowlink.min(nodes[v].lowlink);
+        } else {
+            loop {
+                if next_v == nodes.len() {
+                    break 'dfs;
+                }
+                if nodes[next_v].discovery == usize::MAX {
+                    break;
+                }
+                next_v += 1;
+            }
+            recursion_stack.push((next_v, 0));
+            next_v += 1;
+        }
+    }
+    nodes
+        .iter()
+        .map(|node| TopologicalIndex {
+            scc_id: node.scc_id,
+            discovery: node.discovery,
+        })
+        .collect()
+}
+
 /// A node of the control-flow graph
 #[derive(Debug)]
 pub struct CfgNode {
@@ -346,7 +433,7 @@ impl<'a> Analysis<'a> {
             let mut instruction_index = 0;
             let mut cfg_node_iter = self.cfg_nodes.iter_mut().peekable();
             let mut cfg_edge_iter = cfg_edges.iter_mut().peekable();
-            while let Some((cfg_node_start, cfg_node)) = cfg_node_iter.next() {
+            while let Some((_cfg_node_start, cfg_node)) = cfg_node_iter.next() {
                 let cfg_node_end = if let Some(next_cfg_node) = cfg_node_iter.peek() {
                     *next_cfg_node.0 - 1
                 } else {
@@ -369,7 +456,7 @@ impl<'a> Analysis<'a> {
                     }
                 }
                 if let Some(next_cfg_node) = cfg_node_iter.peek() {
-                    if !self.functions.contains_key(cfg_node_start) {
+                    if !self.functions.contains_key(next_cfg_node.0) {
                         cfg_node.destinations.push(*next_cfg_node.0);
                     }
                 }
@@ -447,6 +534,16 @@ impl<'a> Analysis<'a> {
         Ok(())
     }
 
+    /// Whether an instruction is a `call imm` which resolves to a syscall
+    pub(crate) fn is_syscall(&self, insn: &ebpf::Insn) -> bool {
+        self.executable.is_syscall(insn)
+    }
+
+    /// Cost the instruction meter charges for an instruction
+    pub(crate) fn instruction_cost(&self, insn: &ebpf::Insn) -> u64 {
+        self.executable.get_instruction_cost(insn)
+    }
+
     /// Generates assembler code for a single instruction
     pub fn disassemble_instruction(&self, insn: &ebpf::Insn) -> String {
         disassemble_instruction(
@@ -457,9 +554,40 @@ impl<'a> Analysis<'a> {
         )
     }
 
+    /// Source location of the instruction at `pc`, if the executable has debug information
+    #[cfg(feature = "debug-info")]
+    pub fn source_location(&self, pc: usize) -> Option<SourceLocation<'a>> {
+        self.executable
+            .get_debug_info()
+            .and_then(|debug_info| debug_info.lookup(pc))
+    }
+
+    /// Writes the source location of the instruction at `pc` as a comment if it changed
+    #[cfg(feature = "debug-info")]
+    pub fn disassemble_source_location<W: std::io::Write>(
+        &self,
+        output: &mut W,
+        pc: usize,
+        last_source_location: &mut Option<SourceLocation<'a>>,
+    ) -> std::io::Result<()> {
+        let source_location = self.source_location(pc);
+        if let Some(location) = source_location {
+            if source_location != *last_source_location {
+                writeln!(output, "    ; {location}")?;
+            }
+        }
+        *last_source_location = source_location;
+        Ok(())
+    }
+
     /// Generates assembler code for the analyzed executable
+    ///
+    /// If the executable has debug information, the instructions of every
+    /// source line are preceded by a `; file:line` comment.
     pub fn disassemble<W: std::io::Write>(&self, output: &mut W) -> std::io::Result<()> {
         let mut last_basic_block = usize::MAX;
+        #[cfg(feature = "debug-info")]
+        let mut last_source_location = None;
         for insn in self.instructions.iter() {
             self.disassemble_label(
                 output,
@@ -467,6 +595,8 @@ impl<'a> Analysis<'a> {
                 insn.ptr,
                 &mut last_basic_block,
             )?;
+            #[cfg(feature = "debug-info")]
+            self.disassemble_source_location(output, insn.ptr, &mut last_source_location)?;
             writeln!(output, "    {}", self.disassemble_instruction(insn))?;
         }
         Ok(())
@@ -492,7 +622,7 @@ impl<'a> Analysis<'a> {
         for (index, entry) in trace_log.iter().enumerate() {
             let pc = entry[11] as usize;
             let insn = &self.instructions[pc_to_insn_index[pc]];
-            writeln!(
+            write!(
                 output,
                 "{:5?} {:016X?} {:5?}: {}",
                 index,
@@ -500,6 +630,11 @@ impl<'a> Analysis<'a> {
                 pc + ebpf::ELF_INSN_DUMP_OFFSET,
                 self.disassemble_instruction(insn),
             )?;
+            #[cfg(feature = "debug-info")]
+            if let Some(location) = self.source_location(pc) {
+                write!(output, " ; {location}")?;
+            }
+            writeln!(output)?;
         }
         Ok(())
     }
@@ -717,94 +852,24 @@ impl<'a> Analysis<'a> {
         if self.cfg_nodes.is_empty() {
             return;
         }
-        struct NodeState {
-            cfg_node: usize,
-            discovery: usize,
-            lowlink: usize,
-            scc_id: usize,
-            is_on_scc_stack: bool,
-        }
-        let mut nodes = self
+        let cfg_node_starts = self.cfg_nodes.keys().copied().collect::<Vec<usize>>();
+        let successors = self
             .cfg_nodes
-            .iter_mut()
-            .enumerate()
-            .map(|(v, (key, cfg_node))| {
-                cfg_node.topo_index.scc_id = v;
-                NodeState {
-                    cfg_node: *key,
-                    discovery: usize::MAX,
-                    lowlink: usize::MAX,
-                    scc_id: usize::MAX,
-                    is_on_scc_stack: false,
-                }
+            .values()
+            .map(|cfg_node| {
+                cfg_node
+                    .destinations
+                    .iter()
+                    .map(|destination| cfg_node_starts.binary_search(destination).unwrap())
+                    .collect()
             })
-            .collect::<Vec<NodeState>>();
-        let mut scc_id = 0;
-        let mut scc_stack = Vec::new();
-        let mut discovered = 0;
-        let mut next_v = 1;
-        let mut recursion_stack = vec![(0, 0)];
-        'dfs: while let Some((v, edge_index)) = recursion_stack.pop() {
-            let node = &mut nodes[v];
-            if edge_index == 0 {
-                node.discovery = discovered;
-                node.lowlink = discovered;
-                node.is_on_scc_stack = true;
-                scc_stack.push(v);
-                discovered += 1;
-            }
-            let cfg_node = self.cfg_nodes.get(&node.cfg_node).unwrap();
-            for j in edge_index..cfg_node.destinations.len() {
-                let w = self
-                    .cfg_nodes
-                    .get(&cfg_node.destinations[j])
-                    .unwrap()
-                    .topo_index
-                    .scc_id;
-                if nodes[w].discovery == usize::MAX {
-                    recursion_stack.push((v, j + 1));
-                    recursion_stack.push((w, 0));
-                    continue 'dfs;
-                } else if nodes[w].is_on_scc_stack {
-                    nodes[v].lowlink = nodes[v].lowlink.min(nodes[w].discovery);
-                }
-            }
-            if nodes[v].discovery == nodes[v].lowlink {
-                let mut index_in_scc = 0;
-                while let Some(w) = scc_stack.pop() {
-                    let node = &mut nodes[w];
-                    node.is_on_scc_stack = false;
-                    node.scc_id = scc_id;
-                    node.discovery = index_in_scc;
-                    index_in_scc += 1;
-                    if w == v {
-                        break;
-                    }
-                }
-                scc_id += 1;
-            }
-            if let Some((w, _)) = recursion_stack.last() {
-                nodes[*w].lowlink = nodes[*w].lowlink.min(nodes[v].lowlink);
-            } else {
-                loop {
-                    if next_v == nodes.len() {
-                        break 'dfs;
-                    }
-                    if nodes[next_v].discovery == usize::MAX {
-                        break;
-                    }
-                    next_v += 1;
-                }
-                recursion_stack.push((next_v, 0));
-                next_v += 1;
-            }
-        }
-        for node in &nodes {
-            let cfg_node = self.cfg_nodes.get_mut(&node.cfg_node).unwrap();
-            cfg_node.topo_index = TopologicalIndex {
-                scc_id: node.scc_id,
-                discovery: node.discovery,
-            };
+            .collect::<Vec<Vec<usize>>>();
+        for (cfg_node, topo_index) in self
+            .cfg_nodes
+            .values_mut()
+            .zip(strongly_connected_components(&successors))
+        {
+            cfg_node.topo_index = topo_index;
         }
         let mut topological_order = self.cfg_nodes.keys().cloned().collect::<Vec<_>>();
         topological_order.sort_by(|a, b| {
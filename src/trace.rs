@@ -0,0 +1,721 @@
+//! Recording of executions into a binary trace and their deterministic replay
+
+use crate::{
+    ebpf,
+    error::EbpfError,
+    interpreter::Interpreter,
+    memory_region::{AccessType, MemoryMapping},
+    static_analysis::TraceLogEntry,
+    verifier::Verifier,
+    vm::{Config, ContextObject, EbpfVm, ProgramResult},
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    io::{Read, Write},
+    ops::Range,
+};
+
+/// Identifies the serialized form of a trace
+const MAGIC: &[u8; 8] = b"RBPFTRCE";
+
+/// Version of the serialized form of a trace
+const VERSION: u32 = 1;
+
+const TAG_INSTRUCTION: u8 = 0;
+const TAG_SYSCALL: u8 = 1;
+const TAG_RESULT: u8 = 2;
+
+/// Bytes a syscall wrote to a MemoryRegion
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryDelta {
+    /// Start virtual address of the region
+    pub vm_addr: u64,
+    /// Offset of the first written byte in the host bytes of the region
+    pub offset: u64,
+    /// The new contents
+    pub data: Vec<u8>,
+}
+
+/// Inputs and effects of a syscall
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyscallRecord {
+    /// Key of the syscall in the loader, the immediate of the `call imm`
+    pub function: u32,
+    /// Registers r1 to r5
+    pub arguments: [u64; 5],
+    /// Instructions the syscall itself consumed from the meter
+    pub consumed: u64,
+    /// Value returned in r0 or the message of the error the syscall threw
+    pub result: Result<u64, String>,
+    /// Writes of the syscall to memory
+    pub memory_deltas: Vec<MemoryDelta>,
+}
+
+/// Entry of a trace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceRecord {
+    /// Registers r0 to r10 and the pc before an instruction is executed
+    Instruction(TraceLogEntry),
+    /// Follows the Instruction record of the `call imm` which invoked the syscall
+    Syscall(SyscallRecord),
+    /// Return value or error message of the execution, always the last record
+    Result(Result<u64, String>),
+}
+
+/// Streams TraceRecords into a `Write`
+///
+/// Instruction records only store the registers which changed since the previous one.
+#[derive(Debug)]
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    previous_state: TraceLogEntry,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Writes the header of the trace
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_u32::<LittleEndian>(VERSION)?;
+        Ok(Self {
+            writer,
+            previous_state: [0; 12],
+        })
+    }
+
+    /// Appends a record to the trace
+    pub fn write(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        match record {
+            TraceRecord::Instruction(state) => {
+                let changed = state
+                    .iter()
+                    .zip(self.previous_state.iter())
+                    .enumerate()
+                    .filter(|(_index, (value, previous))| value != previous)
+                    .fold(0u16, |mask, (index, _)| mask | 1 << index);
+                self.writer.write_u8(TAG_INSTRUCTION)?;
+                self.writer.write_u16::<LittleEndian>(changed)?;
+                for (index, value) in state.iter().enumerate() {
+                    if changed & 1 << index != 0 {
+                        self.writer.write_u64::<LittleEndian>(*value)?;
+                    }
+                }
+                self.previous_state = *state;
+            }
+            TraceRecord::Syscall(syscall) => {
+                self.writer.write_u8(TAG_SYSCALL)?;
+                self.writer.write_u32::<LittleEndian>(syscall.function)?;
+                for argument in syscall.arguments.iter() {
+                    self.writer.write_u64::<LittleEndian>(*argument)?;
+                }
+                self.writer.write_u64::<LittleEndian>(syscall.consumed)?;
+                write_result(&mut self.writer, &syscall.result)?;
+                self.writer
+                    .write_u64::<LittleEndian>(syscall.memory_deltas.len() as u64)?;
+                for delta in syscall.memory_deltas.iter() {
+                    self.writer.write_u64::<LittleEndian>(delta.vm_addr)?;
+                    self.writer.write_u64::<LittleEndian>(delta.offset)?;
+                    write_bytes(&mut self.writer, &delta.data)?;
+                }
+            }
+            TraceRecord::Result(result) => {
+                self.writer.write_u8(TAG_RESULT)?;
+                write_result(&mut self.writer, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the underlying writer
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads TraceRecords from a `Read`
+#[derive(Debug)]
+pub struct TraceReader<R: Read> {
+    reader: R,
+    previous_state: TraceLogEntry,
+    record_count: usize,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Reads and checks the header of the trace
+    pub fn new(mut reader: R) -> Result<Self, EbpfError> {
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| EbpfError::InvalidTrace)?;
+        let version = reader
+            .read_u32::<LittleEndian>()
+            .map_err(|_| EbpfError::InvalidTrace)?;
+        if &magic != MAGIC || version != VERSION {
+            return Err(EbpfError::InvalidTrace);
+        }
+        Ok(Self {
+            reader,
+            previous_state: [0; 12],
+            record_count: 0,
+        })
+    }
+
+    /// Reads the next record, `None` marks the end of the trace
+    pub fn read(&mut self) -> Result<Option<TraceRecord>, EbpfError> {
+        let record = self.read_record().map_err(|_| EbpfError::InvalidTrace)?;
+        if record.is_some() {
+            self.record_count = self.record_count.saturating_add(1);
+        }
+        Ok(record)
+    }
+
+    /// Collects the states of all remaining Instruction records
+    ///
+    /// The result can be passed to `Analysis::disassemble_trace_log()`.
+    pub fn trace_log(&mut self) -> Result<Vec<TraceLogEntry>, EbpfError> {
+        let mut trace_log = Vec::new();
+        while let Some(record) = self.read()? {
+            if let TraceRecord::Instruction(state) = record {
+                trace_log.push(state);
+            }
+        }
+        Ok(trace_log)
+    }
+
+    /// Index of the record returned by the last call to `read()`
+    fn last_record_index(&self) -> usize {
+        self.record_count.saturating_sub(1)
+    }
+
+    fn read_record(&mut self) -> std::io::Result<Option<TraceRecord>> {
+        let tag = match self.reader.read_u8() {
+            Ok(tag) => tag,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let record = match tag {
+            TAG_INSTRUCTION => {
+                let changed = self.reader.read_u16::<LittleEndian>()?;
+                if changed >> self.previous_state.len() != 0 {
+                    return Err(invalid_data());
+                }
+                for (index, value) in self.previous_state.iter_mut().enumerate() {
+                    if changed & 1 << index != 0 {
+                        *value = self.reader.read_u64::<LittleEndian>()?;
+                    }
+                }
+                TraceRecord::Instruction(self.previous_state)
+            }
+            TAG_SYSCALL => {
+                let function = self.reader.read_u32::<LittleEndian>()?;
+                let mut arguments = [0u64; 5];
+                for argument in arguments.iter_mut() {
+                    *argument = self.reader.read_u64::<LittleEndian>()?;
+                }
+                let consumed = self.reader.read_u64::<LittleEndian>()?;
+                let result = read_result(&mut self.reader)?;
+                let delta_count = self.reader.read_u64::<LittleEndian>()?;
+                let mut memory_deltas = Vec::new();
+                for _ in 0..delta_count {
+                    let vm_addr = self.reader.read_u64::<LittleEndian>()?;
+                    let offset = self.reader.read_u64::<LittleEndian>()?;
+                    let data = read_bytes(&mut self.reader)?;
+                    memory_deltas.push(MemoryDelta {
+                        vm_addr,
+                        offset,
+                        data,
+                    });
+                }
+                TraceRecord::Syscall(SyscallRecord {
+                    function,
+                    arguments,
+                    consumed,
+                    result,
+                    memory_deltas,
+                })
+            }
+            TAG_RESULT => TraceRecord::Result(read_result(&mut self.reader)?),
+            _ => return Err(invalid_data()),
+        };
+        Ok(Some(record))
+    }
+}
+
+fn invalid_data() -> std::io::Error {
+    std::io::Error::from(std::io::ErrorKind::InvalidData)
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    writer.write_u64::<LittleEndian>(bytes.len() as u64)?;
+    writer.write_all(bytes)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let len = reader.read_u64::<LittleEndian>()?;
+    // Don't trust the length before the bytes are actually there
+    let mut bytes = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+    }
+    Ok(bytes)
+}
+
+fn write_result<W: Write>(writer: &mut W, result: &Result<u64, String>) -> std::io::Result<()> {
+    match result {
+        Ok(value) => {
+            writer.write_u8(0)?;
+            writer.write_u64::<LittleEndian>(*value)
+        }
+        Err(message) => {
+            writer.write_u8(1)?;
+            write_bytes(writer, message.as_bytes())
+        }
+    }
+}
+
+fn read_result<R: Read>(reader: &mut R) -> std::io::Result<Result<u64, String>> {
+    match reader.read_u8()? {
+        0 => Ok(Ok(reader.read_u64::<LittleEndian>()?)),
+        1 => String::from_utf8(read_bytes(reader)?)
+            .map(Err)
+            .map_err(|_| invalid_data()),
+        _ => Err(invalid_data()),
+    }
+}
+
+/// How a ProgramResult is stored in a trace
+fn recorded_result(result: &ProgramResult) -> Result<u64, String> {
+    match result {
+        ProgramResult::Ok(value) => Ok(*value),
+        ProgramResult::Err(err) => match err.downcast_ref::<EbpfError>() {
+            Some(EbpfError::ReplayedSyscallError(message)) => Err(message.clone()),
+            _ => Err(err.to_string()),
+        },
+    }
+}
+
+fn throw_error<V: Verifier, C: ContextObject>(
+    interpreter: &mut Interpreter<V, C>,
+    err: Box<dyn std::error::Error>,
+) -> bool {
+    interpreter.vm.env.program_result = ProgramResult::Err(err);
+    false
+}
+
+fn interpreter_state<V: Verifier, C: ContextObject>(
+    interpreter: &Interpreter<V, C>,
+) -> TraceLogEntry {
+    let mut state = [0u64; 12];
+    state[0..11].copy_from_slice(&interpreter.reg);
+    state[11] = interpreter.pc as u64;
+    state
+}
+
+/// Key of the syscall if the next instruction calls one
+fn syscall_at<V: Verifier, C: ContextObject>(interpreter: &Interpreter<V, C>) -> Option<u32> {
+    let offset = interpreter.pc.checked_mul(ebpf::INSN_SIZE)?;
+    interpreter
+        .program
+        .get(offset..offset.checked_add(ebpf::INSN_SIZE)?)?;
+    let insn = ebpf::get_insn_unchecked(interpreter.program, interpreter.pc);
+    interpreter
+        .vm
+        .executable
+        .is_syscall(&insn)
+        .then_some(insn.imm as u32)
+}
+
+/// Copies the bytes in the address ranges `MemoryMapping::take_recorded_stores()` returned
+fn memory_deltas(memory_mapping: &MemoryMapping, mut stores: Vec<Range<u64>>) -> Vec<MemoryDelta> {
+    stores.sort_unstable_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::new();
+    for range in stores {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    let mut memory_deltas = Vec::new();
+    for range in merged {
+        let mut vm_addr = range.start;
+        while vm_addr < range.end {
+            let region = match memory_mapping.region(AccessType::Load, vm_addr) {
+                Ok(region) => region,
+                Err(_) => break,
+            };
+            let len = range.end.min(region.vm_addr_end).saturating_sub(vm_addr);
+            if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
+                memory_deltas.push(MemoryDelta {
+                    vm_addr: region.vm_addr,
+                    offset: host_addr.saturating_sub(region.host_addr.get()),
+                    data: unsafe {
+                        std::slice::from_raw_parts(host_addr as *const u8, len as usize).to_vec()
+                    },
+                });
+            }
+            vm_addr = vm_addr.saturating_add(len);
+        }
+    }
+    memory_deltas
+}
+
+/// How the result of a syscall is stored in a trace
+fn syscall_result(result: &ProgramResult, r0: u64) -> Result<u64, String> {
+    match result {
+        // The meter can run out after the syscall returned
+        ProgramResult::Err(err)
+            if !matches!(
+                err.downcast_ref::<EbpfError>(),
+                Some(EbpfError::ExceededMaxInstructions(_))
+            ) =>
+        {
+            Err(err.to_string())
+        }
+        _ => Ok(r0),
+    }
+}
+
+/// Executes one instruction and writes its records
+fn record_step<V: Verifier, C: ContextObject, W: Write>(
+    interpreter: &mut Interpreter<V, C>,
+    writer: &mut TraceWriter<W>,
+) -> bool {
+    let state = interpreter_state(interpreter);
+    if let Err(err) = writer.write(&TraceRecord::Instruction(state)) {
+        return throw_error(interpreter, Box::new(err));
+    }
+    let function = match syscall_at(interpreter) {
+        Some(function) => function,
+        None => return interpreter.step(),
+    };
+    interpreter.vm.env.memory_mapping.record_stores();
+    let remaining_before = interpreter.vm.env.context_object_pointer.get_remaining();
+    let due_before = interpreter.due_insn_count;
+    let running = interpreter.step();
+    let config = interpreter.vm.executable.get_config();
+    let consumed = if config.enable_instruction_meter {
+        // Everything which was not charged by the interpreter itself
+        remaining_before
+            .saturating_sub(interpreter.vm.env.context_object_pointer.get_remaining())
+            .saturating_sub(due_before)
+            .saturating_sub(config.instruction_costs.get(ebpf::CALL_IMM, true))
+    } else {
+        0
+    };
+    let stores = interpreter.vm.env.memory_mapping.take_recorded_stores();
+    let record = SyscallRecord {
+        function,
+        arguments: [state[1], state[2], state[3], state[4], state[5]],
+        consumed,
+        result: syscall_result(&interpreter.vm.env.program_result, interpreter.reg[0]),
+        memory_deltas: memory_deltas(&interpreter.vm.env.memory_mapping, stores),
+    };
+    if let Err(err) = writer.write(&TraceRecord::Syscall(record)) {
+        return throw_error(interpreter, Box::new(err));
+    }
+    running
+}
+
+/// Records a JIT execution, the compiled program calls into it when it traces instructions
+pub(crate) struct Recording<'a> {
+    /// Type-erased `TraceWriter`, which outlives the execution
+    writer: *mut (),
+    write: unsafe fn(*mut (), &TraceRecord) -> std::io::Result<()>,
+    program: &'a [u8],
+    config: &'a Config,
+    state: TraceLogEntry,
+    /// Remaining instructions when the meter was last read, like `previous_instruction_meter`
+    remaining: u64,
+    /// Cost of the instructions since the meter was last read
+    due_insn_count: u64,
+    /// The interpreter would have thrown ExceededMaxInstructions by now
+    ///
+    /// The JIT only checks the meter at the end of basic blocks, so it can trace
+    /// instructions which the interpreter does not execute anymore.
+    exceeded_meter: bool,
+    /// First error of the writer, no further records are written after it
+    error: Option<std::io::Error>,
+}
+
+unsafe fn write_record<W: Write>(writer: *mut (), record: &TraceRecord) -> std::io::Result<()> {
+    (*(writer as *mut TraceWriter<W>)).write(record)
+}
+
+impl<'a> Recording<'a> {
+    fn new<W: Write>(
+        writer: &mut TraceWriter<W>,
+        program: &'a [u8],
+        config: &'a Config,
+        remaining: u64,
+    ) -> Self {
+        Self {
+            writer: writer as *mut TraceWriter<W> as *mut (),
+            write: write_record::<W>,
+            program,
+            config,
+            state: [0; 12],
+            remaining,
+            due_insn_count: 0,
+            exceeded_meter: false,
+            error: None,
+        }
+    }
+
+    fn write(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            if let Err(err) = unsafe { (self.write)(self.writer, record) } {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    /// Registers r0 to r10 and the pc before an instruction is executed
+    pub(crate) fn instruction(&mut self, state: TraceLogEntry) {
+        if self.config.enable_instruction_meter && !self.exceeded_meter {
+            // Same check as the interpreter does at the end of the previous instruction
+            self.exceeded_meter = self.due_insn_count >= self.remaining;
+            let opc = ebpf::get_insn(self.program, state[11] as usize).opc;
+            self.due_insn_count = self
+                .due_insn_count
+                .saturating_add(self.config.instruction_costs.get(opc, false));
+        }
+        if self.exceeded_meter {
+            return;
+        }
+        self.state = state;
+        self.write(&TraceRecord::Instruction(state));
+    }
+
+    /// After the instruction meter was charged for the `call imm` of a syscall
+    pub(crate) fn syscall_entry(&mut self, remaining: u64, memory_mapping: &MemoryMapping) {
+        self.remaining = remaining;
+        memory_mapping.record_stores();
+    }
+
+    /// Right after the syscall returned
+    pub(crate) fn syscall_exit(
+        &mut self,
+        remaining: u64,
+        result: &ProgramResult,
+        memory_mapping: &MemoryMapping,
+    ) {
+        let stores = memory_mapping.take_recorded_stores();
+        if self.exceeded_meter {
+            return;
+        }
+        let consumed = if self.config.enable_instruction_meter {
+            self.remaining.saturating_sub(remaining)
+        } else {
+            0
+        };
+        self.remaining = remaining;
+        self.due_insn_count = 0;
+        let state = self.state;
+        let record = SyscallRecord {
+            function: ebpf::get_insn(self.program, state[11] as usize).imm as u32,
+            arguments: [state[1], state[2], state[3], state[4], state[5]],
+            consumed,
+            result: match result {
+                ProgramResult::Ok(value) => syscall_result(result, *value),
+                ProgramResult::Err(_) => syscall_result(result, 0),
+            },
+            memory_deltas: memory_deltas(memory_mapping, stores),
+        };
+        self.write(&TraceRecord::Syscall(record));
+    }
+}
+
+/// Checks the next instruction against the trace and executes it
+///
+/// Syscalls are not called, their recorded effects are applied instead.
+fn replay_step<V: Verifier, C: ContextObject, R: Read>(
+    interpreter: &mut Interpreter<V, C>,
+    reader: &mut TraceReader<R>,
+) -> bool {
+    let state = interpreter_state(interpreter);
+    match reader.read() {
+        Ok(Some(TraceRecord::Instruction(recorded_state))) if recorded_state == state => {}
+        Ok(_) => {
+            let record_index = reader.last_record_index();
+            return throw_error(
+                interpreter,
+                Box::new(EbpfError::TraceDivergence(record_index)),
+            );
+        }
+        Err(err) => return throw_error(interpreter, Box::new(err)),
+    }
+    let function = match syscall_at(interpreter) {
+        Some(function) => function,
+        None => return interpreter.step(),
+    };
+    let syscall = match reader.read() {
+        Ok(Some(TraceRecord::Syscall(syscall))) if syscall.function == function => syscall,
+        Ok(_) => {
+            let record_index = reader.last_record_index();
+            return throw_error(
+                interpreter,
+                Box::new(EbpfError::TraceDivergence(record_index)),
+            );
+        }
+        Err(err) => return throw_error(interpreter, Box::new(err)),
+    };
+    let config = interpreter.vm.executable.get_config();
+    if config.enable_instruction_tracing {
+        interpreter.vm.env.context_object_pointer.trace(state);
+    }
+    if config.enable_instruction_meter {
+        interpreter.vm.env.context_object_pointer.consume(
+            interpreter
+                .due_insn_count
+                .saturating_add(config.instruction_costs.get(ebpf::CALL_IMM, true))
+                .saturating_add(syscall.consumed),
+        );
+    }
+    interpreter.due_insn_count = 0;
+    for delta in syscall.memory_deltas.iter() {
+        let destination = interpreter
+            .vm
+            .env
+            .memory_mapping
+            .region(AccessType::Store, delta.vm_addr)
+            .ok()
+            .filter(|region| {
+                region.vm_addr == delta.vm_addr
+                    && delta
+                        .offset
+                        .checked_add(delta.data.len() as u64)
+                        .filter(|end| *end <= region.len)
+                        .is_some()
+            })
+            .map(|region| region.host_addr.get().saturating_add(delta.offset) as *mut u8);
+        match destination {
+            Some(destination) => unsafe {
+                std::ptr::copy_nonoverlapping(delta.data.as_ptr(), destination, delta.data.len());
+            },
+            None => {
+                let record_index = reader.last_record_index();
+                return throw_error(
+                    interpreter,
+                    Box::new(EbpfError::TraceDivergence(record_index)),
+                );
+            }
+        }
+    }
+    interpreter.pc = interpreter.pc.saturating_add(1);
+    match syscall.result {
+        Ok(value) => {
+            interpreter.reg[0] = value;
+            interpreter.vm.env.program_result = ProgramResult::Ok(value);
+        }
+        Err(message) => {
+            return throw_error(
+                interpreter,
+                Box::new(EbpfError::ReplayedSyscallError(message)),
+            )
+        }
+    }
+    if config.enable_instruction_meter {
+        interpreter.vm.env.previous_instruction_meter =
+            interpreter.vm.env.context_object_pointer.get_remaining();
+        if interpreter.vm.env.previous_instruction_meter == 0 {
+            return throw_error(
+                interpreter,
+                Box::new(EbpfError::ExceededMaxInstructions(
+                    interpreter.pc.saturating_add(ebpf::ELF_INSN_DUMP_OFFSET),
+                )),
+            );
+        }
+    }
+    true
+}
+
+impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
+    /// Runs the program while streaming a trace into `writer`
+    ///
+    /// Every instruction is recorded, and every syscall together with the bytes it wrote
+    /// to memory, so that `replay_program()` does not need to call it.
+    /// The JIT needs `config.enable_instruction_tracing` to record.
+    /// Suspension is not supported while recording.
+    pub fn execute_program_recorded<W: Write>(
+        &mut self,
+        interpreted: bool,
+        writer: &mut TraceWriter<W>,
+    ) -> (u64, ProgramResult) {
+        let registers = self.entrypoint_registers();
+        let (instruction_count, result) = if interpreted {
+            self.execute(true, registers, false, |interpreter| {
+                record_step(interpreter, writer)
+            })
+        } else {
+            let config = self.executable.get_config();
+            if !config.enable_instruction_tracing {
+                return (
+                    0,
+                    ProgramResult::Err(Box::new(EbpfError::UnsupportedRecording)),
+                );
+            }
+            self.env.recording = Some(Recording::new(
+                writer,
+                self.executable.get_text_bytes().1,
+                config,
+                self.env.context_object_pointer.get_remaining(),
+            ));
+            let (instruction_count, result) = self.execute(false, registers, false, |_| false);
+            match self
+                .env
+                .recording
+                .take()
+                .and_then(|recording| recording.error)
+            {
+                Some(err) => return (instruction_count, ProgramResult::Err(Box::new(err))),
+                None => (instruction_count, result),
+            }
+        };
+        if let Err(err) = writer
+            .write(&TraceRecord::Result(recorded_result(&result)))
+            .and_then(|_| writer.flush())
+        {
+            return (instruction_count, ProgramResult::Err(Box::new(err)));
+        }
+        (instruction_count, result)
+    }
+
+    /// Runs the program in the interpreter following a trace of `execute_program_recorded()`
+    ///
+    /// The VM has to be created from the same executable, with the same memory regions and
+    /// instruction meter budget as the recording. Syscalls are not called, so the loader only
+    /// needs to know their keys. A ContextObject with tracing enabled sees every instruction.
+    /// Fails with TraceDivergence as soon as the execution does not match the trace.
+    pub fn replay_program<R: Read>(&mut self, reader: &mut TraceReader<R>) -> (u64, ProgramResult) {
+        let registers = self.entrypoint_registers();
+        let (instruction_count, result) = self.execute(true, registers, false, |interpreter| {
+            replay_step(interpreter, reader)
+        });
+        if let ProgramResult::Err(err) = &result {
+            if matches!(
+                err.downcast_ref::<EbpfError>(),
+                Some(EbpfError::InvalidTrace | EbpfError::TraceDivergence(_))
+            ) {
+                return (instruction_count, result);
+            }
+        }
+        match reader.read() {
+            Ok(Some(TraceRecord::Result(recorded))) if recorded == recorded_result(&result) => {
+                (instruction_count, result)
+            }
+            Ok(_) => (
+                instruction_count,
+                ProgramResult::Err(Box::new(EbpfError::TraceDivergence(
+                    reader.last_record_index(),
+                ))),
+            ),
+            Err(err) => (instruction_count, ProgramResult::Err(Box::new(err))),
+        }
+    }
+}
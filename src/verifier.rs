@@ -25,7 +25,9 @@
 
 use crate::{
     ebpf,
-    vm::{Config, FunctionRegistry},
+    elf::Executable,
+    error::EbpfError,
+    vm::{Config, ContextObject, FunctionRegistry},
 };
 use thiserror::Error;
 
@@ -83,6 +85,12 @@ pub enum VerifierError {
     /// Invalid function
     #[error("Invalid function at instruction {0}")]
     InvalidFunction(usize),
+    /// Memory access through a pointer which can leave its region
+    #[error("cannot prove that the {1} access is in bounds (insn #{0})")]
+    MemoryAccessOutOfBounds(usize, &'static str),
+    /// Memory access which faults whenever it is executed
+    #[error("{1} access always faults (insn #{0})")]
+    AlwaysFaultingMemoryAccess(usize, &'static str),
 }
 
 /// eBPF Verifier
@@ -100,6 +108,21 @@ pub trait Verifier {
         config: &Config,
         function_registry: &FunctionRegistry,
     ) -> Result<(), VerifierError>;
+
+    /// Verifies an executable, used by `Executable::verified()`
+    ///
+    /// Defaults to `verify()` on its text section. Verifiers which need the loader, e.g. to
+    /// tell syscalls apart, can analyze the executable instead.
+    fn verify_executable<C: ContextObject>(
+        executable: &Executable<TautologyVerifier, C>,
+    ) -> Result<(), EbpfError> {
+        Self::verify(
+            executable.get_text_bytes().1,
+            executable.get_config(),
+            executable.get_function_registry(),
+        )?;
+        Ok(())
+    }
 }
 
 fn adj_insn_ptr(insn_ptr: usize) -> usize {
@@ -19,6 +19,7 @@ use crate::{
     interpreter::Interpreter,
     memory_region::MemoryMapping,
     static_analysis::{Analysis, TraceLogEntry},
+    trace::Recording,
     verifier::{TautologyVerifier, Verifier},
 };
 use rand::Rng;
@@ -142,6 +143,13 @@ impl<C: ContextObject> BuiltInProgram<C> {
         self.functions.get(&key).cloned()
     }
 
+    /// Keys and names of all registered functions, in no particular order
+    pub(crate) fn iter_functions(&self) -> impl Iterator<Item = (u32, &'static [u8])> + '_ {
+        self.functions
+            .iter()
+            .map(|(key, (name, _function))| (*key, *name))
+    }
+
     /// Calculate memory size
     pub fn mem_size(&self) -> usize {
         mem::size_of::<Self>()
@@ -192,6 +200,87 @@ impl<C: ContextObject> PartialEq for BuiltInProgram<C> {
 /// 3 bits for 8 Byte alignment, and 1 bit to have encoding space for the RuntimeEnvironment.
 pub const PROGRAM_ENVIRONMENT_KEY_SHIFT: u32 = 4;
 
+/// Costs charged by the instruction meter per class of instruction
+///
+/// A cost of 0 is charged as 1, so that loops can not escape the instruction meter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionCosts {
+    /// ALU instructions other than multiplication and division, and lddw
+    pub alu: u32,
+    /// Multiplication
+    pub mul: u32,
+    /// Division, signed division and remainder
+    pub div: u32,
+    /// Loads and stores
+    pub memory: u32,
+    /// Unconditional and conditional jumps
+    pub jump: u32,
+    /// Calls to BPF functions, callx and exit
+    pub call: u32,
+    /// Calls to syscalls, not including what the syscall consumes itself
+    pub syscall: u32,
+}
+
+impl Default for InstructionCosts {
+    fn default() -> Self {
+        Self {
+            alu: 1,
+            mul: 1,
+            div: 1,
+            memory: 1,
+            jump: 1,
+            call: 1,
+            syscall: 1,
+        }
+    }
+}
+
+impl InstructionCosts {
+    /// Returns true if every instruction costs exactly one unit
+    pub fn is_uniform(&self) -> bool {
+        [
+            self.alu,
+            self.mul,
+            self.div,
+            self.memory,
+            self.jump,
+            self.call,
+            self.syscall,
+        ]
+        .iter()
+        .all(|cost| *cost <= 1)
+    }
+
+    /// Returns the cost of an instruction with the opcode `opc`
+    ///
+    /// `syscall` tells if a `call imm` resolves to a syscall rather than a BPF function.
+    pub fn get(&self, opc: u8, syscall: bool) -> u64 {
+        let cost = match (opc & 0x07, opc & 0xf0) {
+            (ebpf::BPF_LD, _) if opc == ebpf::LD_DW_IMM => self.alu,
+            (ebpf::BPF_LD, _) | (ebpf::BPF_LDX, _) | (ebpf::BPF_ST, _) | (ebpf::BPF_STX, _) => {
+                self.memory
+            }
+            (ebpf::BPF_ALU, ebpf::BPF_MUL) | (ebpf::BPF_ALU64, ebpf::BPF_MUL) => self.mul,
+            (ebpf::BPF_ALU, ebpf::BPF_DIV)
+            | (ebpf::BPF_ALU64, ebpf::BPF_DIV)
+            | (ebpf::BPF_ALU, ebpf::BPF_SDIV)
+            | (ebpf::BPF_ALU64, ebpf::BPF_SDIV)
+            | (ebpf::BPF_ALU, ebpf::BPF_MOD)
+            | (ebpf::BPF_ALU64, ebpf::BPF_MOD) => self.div,
+            (ebpf::BPF_JMP, ebpf::BPF_CALL) if syscall && opc == ebpf::CALL_IMM => self.syscall,
+            (ebpf::BPF_JMP, ebpf::BPF_CALL) | (ebpf::BPF_JMP, ebpf::BPF_EXIT) => self.call,
+            (ebpf::BPF_JMP, _) => self.jump,
+            _ => self.alu,
+        };
+        cost.max(1) as u64
+    }
+
+    /// Returns the cost of running past the end of the program, which is charged like a jump
+    pub fn get_execution_overrun(&self) -> u64 {
+        self.get(ebpf::JA, false)
+    }
+}
+
 /// VM configuration settings
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Config {
@@ -207,8 +296,18 @@ pub struct Config {
     pub instruction_meter_checkpoint_distance: usize,
     /// Enable instruction meter and limiting
     pub enable_instruction_meter: bool,
+    /// Costs charged by the instruction meter
+    pub instruction_costs: InstructionCosts,
     /// Enable instruction tracing
     pub enable_instruction_tracing: bool,
+    /// Capture a SuspendedState when the instruction meter runs out or a syscall yields
+    pub enable_suspension: bool,
+    /// Capture a Backtrace when the program fails
+    pub enable_backtrace: bool,
+    /// Report loads from stack and heap bytes which were never written before
+    pub enable_memory_sanitizer: bool,
+    /// Calls and back-edges of a function after which `EbpfVm::execute_program_tiered()` switches to the JIT
+    pub tiering_threshold: u64,
     /// Enable dynamic string allocation for labels
     pub enable_symbol_and_section_labels: bool,
     /// Reject ELF files containing issues that the verifier did not catch before (up to v0.2.21)
@@ -217,6 +316,12 @@ pub struct Config {
     pub noop_instruction_rate: u32,
     /// Enable disinfection of immediate values and offsets provided by the user in JIT
     pub sanitize_user_provided_values: bool,
+    /// Optimize across the instructions of basic blocks in JIT
+    ///
+    /// Opt-in, because the pass changes the machine code of every program and so far only the
+    /// differential fuzzers (smart_jit_diff, smarter_jit_diff) compare it to the interpreter.
+    /// It also adds an analysis of each unit to the compile time.
+    pub optimize_jit_code: bool,
     /// Encrypt the runtime environment in JIT
     ///
     /// Use 0 to disable encryption. Otherwise only leave PROGRAM_ENVIRONMENT_KEY_SHIFT MSBs 0.
@@ -261,11 +366,17 @@ impl Default for Config {
             enable_stack_frame_gaps: true,
             instruction_meter_checkpoint_distance: 10000,
             enable_instruction_meter: true,
+            instruction_costs: InstructionCosts::default(),
             enable_instruction_tracing: false,
+            enable_suspension: false,
+            enable_backtrace: false,
+            enable_memory_sanitizer: false,
+            tiering_threshold: 1_000,
             enable_symbol_and_section_labels: false,
             reject_broken_elfs: false,
             noop_instruction_rate: 256,
             sanitize_user_provided_values: true,
+            optimize_jit_code: false,
             runtime_environment_key: rand::thread_rng().gen::<i32>()
                 >> PROGRAM_ENVIRONMENT_KEY_SHIFT,
             external_internal_function_hash_collision: true,
@@ -289,6 +400,15 @@ impl<C: ContextObject> Executable<TautologyVerifier, C> {
         let executable = Executable::load(elf_bytes, loader)?;
         Ok(executable)
     }
+
+    /// Creates one executable per program section of a relocatable object file
+    pub fn from_relocatable(
+        object_bytes: &[u8],
+        loader: Arc<BuiltInProgram<C>>,
+    ) -> Result<Vec<(String, Self)>, EbpfError> {
+        let executables = Executable::load_relocatable(object_bytes, loader)?;
+        Ok(executables)
+    }
     /// Creates an executable from machine code
     pub fn from_text_bytes(
         text_bytes: &[u8],
@@ -390,7 +510,7 @@ impl DynamicAnalysis {
 }
 
 /// A call frame used for function calls inside the Interpreter
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct CallFrame {
     /// The caller saved registers
     pub caller_saved_registers: [u64; ebpf::SCRATCH_REGS],
@@ -400,6 +520,87 @@ pub struct CallFrame {
     pub target_pc: usize,
 }
 
+/// Execution state of a suspended program
+///
+/// Captured if config.enable_suspension=true and the instruction meter runs
+/// out or a syscall returns EbpfError::SyscallYield. Pass it to
+/// `EbpfVm::resume_program()` to continue the execution where it stopped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SuspendedState {
+    /// Registers r0 to r10 followed by the pc of the next instruction to execute
+    ///
+    /// After a syscall yielded r0 still holds its value from before the call,
+    /// so the host may place the return value of the syscall here.
+    pub registers: [u64; 12],
+    /// The call depth
+    pub call_depth: u64,
+    /// Guest stack pointer (r11)
+    pub stack_pointer: u64,
+    /// The active call frames, one per level of call depth
+    pub call_frames: Vec<CallFrame>,
+    /// Cost of the executed instructions which exceeded the budget, charged on resumption
+    pub overdue_insn_count: u64,
+}
+
+/// Call stack and registers of a program at the instruction which failed
+///
+/// Captured if config.enable_backtrace=true and the program returns an error
+/// without being suspended.
+/// A call which exceeds the max call depth or leaves the text segment is reported
+/// at the call instruction with the registers and call frames of the caller.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Backtrace {
+    /// Registers r0 to r10 followed by the pc of the instruction which failed
+    pub registers: [u64; 12],
+    /// The active call frames, one per level of call depth
+    pub call_frames: Vec<CallFrame>,
+    /// The call stack resolved to functions, innermost first
+    pub frames: Vec<BacktraceFrame>,
+}
+
+/// One level of the call stack in a Backtrace
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    /// The pc of the instruction which failed in the innermost frame, the pc returned to otherwise
+    pub pc: usize,
+    /// The pc of the registered function this frame is in
+    pub function_pc: Option<usize>,
+    /// The name of that function, empty if the executable does not keep symbol names
+    pub function_name: String,
+    /// Source file and line of the instruction, if the executable has debug information
+    pub source_location: Option<(String, usize)>,
+}
+
+impl std::fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (depth, frame) in self.frames.iter().enumerate() {
+            write!(
+                f,
+                "#{} BPF instruction #{}",
+                depth,
+                frame.pc + ebpf::ELF_INSN_DUMP_OFFSET
+            )?;
+            if let Some(function_pc) = frame.function_pc {
+                write!(
+                    f,
+                    " in {} at #{}",
+                    if frame.function_name.is_empty() {
+                        "function"
+                    } else {
+                        frame.function_name.as_str()
+                    },
+                    function_pc + ebpf::ELF_INSN_DUMP_OFFSET
+                )?;
+            }
+            if let Some((file, line)) = &frame.source_location {
+                write!(f, " ({file}:{line})")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
 /// Runtime state
 // Keep changes here in sync with RuntimeEnvironmentSlot
 #[repr(C)]
@@ -426,12 +627,22 @@ pub struct RuntimeEnvironment<'a, C: ContextObject> {
     pub stopwatch_numerator: u64,
     /// Number of times the stop watch was used
     pub stopwatch_denominator: u64,
+    /// Executable of the running invocation, set by `JitProgram::invoke()`
+    ///
+    /// Compiling functions lazily needs it. Type-erased, as the environment does not know the Verifier.
+    pub(crate) executable: *const (),
     /// ProgramResult inlined
     pub program_result: ProgramResult,
     /// MemoryMapping inlined
     pub memory_mapping: MemoryMapping<'a>,
     /// Stack of CallFrames used by the Interpreter
     pub call_frames: Vec<CallFrame>,
+    /// State captured by the last execution if it was suspended
+    pub suspended_state: Option<SuspendedState>,
+    /// Backtrace captured by the last execution if it failed
+    pub backtrace: Option<Backtrace>,
+    /// Trace recorded by the JIT, see `EbpfVm::execute_program_recorded()`
+    pub(crate) recording: Option<Recording<'a>>,
 }
 
 /// A virtual machine to run eBPF programs.
@@ -529,9 +740,13 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
                 previous_instruction_meter: 0,
                 stopwatch_numerator: 0,
                 stopwatch_denominator: 0,
+                executable: std::ptr::null(),
                 program_result: ProgramResult::Ok(0),
                 memory_mapping,
                 call_frames: vec![CallFrame::default(); config.max_call_depth],
+                suspended_state: None,
+                backtrace: None,
+                recording: None,
             },
         }
     }
@@ -540,11 +755,157 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
     ///
     /// If interpreted = `false` then the JIT compiled executable is used.
     pub fn execute_program(&mut self, interpreted: bool) -> (u64, ProgramResult) {
+        let registers = self.entrypoint_registers();
+        self.execute(interpreted, registers, false, |interpreter| {
+            interpreter.run()
+        })
+    }
+
+    /// Execute the program in the interpreter until a function gets hot, then in the JIT
+    ///
+    /// Counts the calls and back-edges of each function, and the invocations of the entrypoint,
+    /// across all executions of the executable. Once a function was counted
+    /// `config.tiering_threshold` times, it is JIT compiled and the JIT takes over, including
+    /// the call frames of the interpreter. Requires `Executable::jit_compile_lazily()`, a
+    /// program compiled up front runs in the JIT right away.
+    ///
+    /// Tiering is whole-program: after the switch the rest of the execution stays in the JIT,
+    /// which also compiles the cold functions on their first call. Only hot functions start
+    /// the JIT, but later executions run interpreted again until one of them is reached.
+    ///
+    /// Produces the same results, instruction counts and errors as the interpreter.
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn execute_program_tiered(&mut self) -> (u64, ProgramResult) {
+        let executable = self.executable;
+        let compiled_program = match executable.get_compiled_program() {
+            Some(compiled_program) => compiled_program,
+            None => return (0, ProgramResult::Err(Box::new(EbpfError::JitNotCompiled))),
+        };
+        let threshold = executable.get_config().tiering_threshold;
+        let mut registers = self.entrypoint_registers();
+        if compiled_program.count_call_or_back_edge(executable, registers[11] as usize, threshold) {
+            return self.execute(false, registers, false, |interpreter| interpreter.step());
+        }
+        let mut promoted = false;
+        let (instruction_count, result) = self.execute(true, registers, false, |interpreter| {
+            let (pc, call_depth) = (interpreter.pc, interpreter.vm.env.call_depth);
+            if !interpreter.step() {
+                return false;
+            }
+            let is_call = interpreter.vm.env.call_depth > call_depth;
+            let is_back_edge = interpreter.vm.env.call_depth == call_depth && interpreter.pc <= pc;
+            if (is_call || is_back_edge)
+                && compiled_program.count_call_or_back_edge(executable, interpreter.pc, threshold)
+            {
+                registers[0..11].copy_from_slice(&interpreter.reg);
+                registers[11] = interpreter.pc as u64;
+                promoted = true;
+                return false;
+            }
+            true
+        });
+        if !promoted {
+            return (instruction_count, result);
+        }
+        // The call frames, call depth and stack pointer are left in the environment
+        let (jit_instruction_count, result) =
+            self.execute(false, registers, true, |interpreter| interpreter.step());
+        (
+            instruction_count.saturating_add(jit_instruction_count),
+            result,
+        )
+    }
+
+    /// Execute the program compiled by `Executable::cranelift_compile()`
+    ///
+    /// Produces the same results, instruction counts, traces, errors, suspended states and
+    /// backtraces as the interpreter.
+    #[cfg(feature = "cranelift")]
+    pub fn execute_program_cranelift(&mut self) -> (u64, ProgramResult) {
+        let compiled_program = match self.executable.get_cranelift_program() {
+            Some(compiled_program) => compiled_program,
+            None => return (0, ProgramResult::Err(Box::new(EbpfError::JitNotCompiled))),
+        };
+        let registers = self.entrypoint_registers();
+        // The interpreter only holds the state of the execution
+        self.execute(true, registers, false, |interpreter| {
+            compiled_program.execute(interpreter)
+        })
+    }
+
+    /// Registers at the start of the program
+    pub(crate) fn entrypoint_registers(&self) -> [u64; 12] {
         let mut registers = [0u64; 12];
         // R1 points to beginning of input memory, R10 to the stack of the first frame, R11 is the pc (hidden)
         registers[1] = ebpf::MM_INPUT_START;
         registers[ebpf::FRAME_PTR_REG] = self.env.stack_pointer;
         registers[11] = self.executable.get_entrypoint_instruction_offset() as u64;
+        registers
+    }
+
+    /// Continue the execution of a program which was suspended
+    ///
+    /// The state can come from either the interpreter or the JIT, independently
+    /// of which one is used to resume it.
+    pub fn resume_program(
+        &mut self,
+        interpreted: bool,
+        state: SuspendedState,
+    ) -> (u64, ProgramResult) {
+        let config = self.executable.get_config();
+        let (_program_vm_addr, program) = self.executable.get_text_bytes();
+        // The second slot of an lddw is not an instruction execution can continue at
+        let is_instruction = |pc: usize| {
+            pc.checked_add(1)
+                .and_then(|end_pc| end_pc.checked_mul(ebpf::INSN_SIZE))
+                .filter(|end| *end <= program.len())
+                .is_some()
+                && !matches!(
+                    pc.checked_sub(1)
+                        .map(|previous_pc| ebpf::get_insn(program, previous_pc).opc),
+                    Some(ebpf::LD_DW_IMM)
+                )
+        };
+        if !config.enable_suspension
+            || state.call_depth as usize != state.call_frames.len()
+            || state.call_frames.len() >= config.max_call_depth
+            || !is_instruction(state.registers[11] as usize)
+            || state
+                .call_frames
+                .iter()
+                .any(|frame| !is_instruction(frame.target_pc))
+        {
+            return (
+                0,
+                ProgramResult::Err(Box::new(EbpfError::InvalidSuspendedState)),
+            );
+        }
+        self.env.call_depth = state.call_depth;
+        self.env.stack_pointer = state.stack_pointer;
+        self.env.call_frames[0..state.call_frames.len()].clone_from_slice(&state.call_frames);
+        let overdue_insn_count = if config.enable_instruction_meter {
+            self.env
+                .context_object_pointer
+                .consume(state.overdue_insn_count);
+            state.overdue_insn_count
+        } else {
+            0
+        };
+        let (instruction_count, result) =
+            self.execute(interpreted, state.registers, true, |interpreter| {
+                interpreter.run()
+            });
+        (instruction_count.saturating_add(overdue_insn_count), result)
+    }
+
+    /// Runs the program, `step` advances the interpreter and returns false once it is done
+    pub(crate) fn execute<F: FnMut(&mut Interpreter<'_, 'a, V, C>) -> bool>(
+        &mut self,
+        interpreted: bool,
+        registers: [u64; 12],
+        resume: bool,
+        mut step: F,
+    ) -> (u64, ProgramResult) {
         let config = self.executable.get_config();
         let initial_insn_count = if config.enable_instruction_meter {
             self.env.context_object_pointer.get_remaining()
@@ -553,6 +914,8 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
         };
         self.env.previous_instruction_meter = initial_insn_count;
         self.env.program_result = ProgramResult::Ok(0);
+        self.env.suspended_state = None;
+        self.env.backtrace = None;
         let due_insn_count = if interpreted {
             #[cfg(feature = "debugger")]
             let debug_port = self.debug_port.clone();
@@ -561,10 +924,10 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
             if let Some(debug_port) = debug_port {
                 crate::debugger::execute(&mut interpreter, debug_port);
             } else {
-                while interpreter.step() {}
+                while step(&mut interpreter) {}
             }
             #[cfg(not(feature = "debugger"))]
-            while interpreter.step() {}
+            while step(&mut interpreter) {}
             interpreter.due_insn_count
         } else {
             #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
@@ -577,29 +940,107 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
                     Ok(compiled_program) => compiled_program,
                     Err(error) => return (0, ProgramResult::Err(error)),
                 };
-                let instruction_meter_final = compiled_program
-                    .invoke(config, &mut self.env, registers)
-                    .max(0) as u64;
-                self.env
-                    .context_object_pointer
-                    .get_remaining()
-                    .saturating_sub(instruction_meter_final)
+                let host_call_frames = if resume {
+                    match compiled_program.host_call_frames(
+                        self.executable,
+                        &self.env.call_frames[0..self.env.call_depth as usize],
+                    ) {
+                        Some(host_call_frames) => Some(host_call_frames),
+                        None => {
+                            return (
+                                0,
+                                ProgramResult::Err(Box::new(EbpfError::InvalidSuspendedState)),
+                            )
+                        }
+                    }
+                } else {
+                    None
+                };
+                let instruction_meter_final = compiled_program.invoke(
+                    self.executable,
+                    &mut self.env,
+                    registers,
+                    host_call_frames.as_deref(),
+                );
+                if let Some(suspended_state) = self.env.suspended_state.as_mut() {
+                    compiled_program.resolve_call_frames(&mut suspended_state.call_frames);
+                }
+                if let Some(backtrace) = self.env.backtrace.as_mut() {
+                    compiled_program.resolve_call_frames(&mut backtrace.call_frames);
+                }
+                // A negative instruction meter means the last slice overshot its budget
+                let remaining = self.env.context_object_pointer.get_remaining();
+                if instruction_meter_final < 0 {
+                    remaining.saturating_add(instruction_meter_final.unsigned_abs())
+                } else {
+                    remaining.saturating_sub(instruction_meter_final as u64)
+                }
             }
             #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
             {
+                let _ = resume;
                 return (0, ProgramResult::Err(Box::new(EbpfError::JitNotCompiled)));
             }
         };
         let instruction_count = if config.enable_instruction_meter {
+            let remaining = self.env.context_object_pointer.get_remaining();
+            if let Some(suspended_state) = self.env.suspended_state.as_mut() {
+                suspended_state.overdue_insn_count = due_insn_count.saturating_sub(remaining);
+            }
             self.env.context_object_pointer.consume(due_insn_count);
             initial_insn_count.saturating_sub(self.env.context_object_pointer.get_remaining())
         } else {
             0
         };
+        if let Some(backtrace) = self.env.backtrace.as_mut() {
+            backtrace.frames = Self::resolve_backtrace_frames(
+                self.executable,
+                &backtrace.registers,
+                &backtrace.call_frames,
+            );
+        }
         let mut result = ProgramResult::Ok(0);
         std::mem::swap(&mut result, &mut self.env.program_result);
         (instruction_count, result)
     }
+
+    /// Finds the registered function and the source location of the failing pc and of every call site
+    fn resolve_backtrace_frames(
+        executable: &Executable<V, C>,
+        registers: &[u64; 12],
+        call_frames: &[CallFrame],
+    ) -> Vec<BacktraceFrame> {
+        let mut functions = executable
+            .get_function_registry()
+            .values()
+            .collect::<Vec<_>>();
+        functions.sort_unstable_by_key(|(pc, _name)| *pc);
+        let frame = |pc: usize, lookup_pc: usize| {
+            let function = functions
+                .partition_point(|(function_pc, _name)| *function_pc <= lookup_pc)
+                .checked_sub(1)
+                .map(|index| functions[index]);
+            BacktraceFrame {
+                pc,
+                function_pc: function.map(|(function_pc, _name)| *function_pc),
+                function_name: function.map(|(_pc, name)| name.clone()).unwrap_or_default(),
+                #[cfg(feature = "debug-info")]
+                source_location: executable
+                    .get_debug_info()
+                    .and_then(|debug_info| debug_info.lookup(lookup_pc))
+                    .map(|location| (location.file.to_string(), location.line)),
+                #[cfg(not(feature = "debug-info"))]
+                source_location: None,
+            }
+        };
+        let pc = registers[11] as usize;
+        std::iter::once(frame(pc, pc))
+            // The call instruction belongs to the caller, even if it is the last one of its function
+            .chain(call_frames.iter().rev().map(|call_frame| {
+                frame(call_frame.target_pc, call_frame.target_pc.saturating_sub(1))
+            }))
+            .collect()
+    }
 }
 
 #[cfg(test)]
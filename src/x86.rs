@@ -1,4 +1,6 @@
 #![allow(clippy::integer_arithmetic)]
+use std::convert::TryInto;
+
 use crate::{
     jit::{JitCompiler, OperandSize},
     verifier::Verifier,
@@ -503,6 +505,22 @@ impl X86Instruction {
         }
     }
 
+    /// Load destination from a 64 bit host address
+    ///
+    /// Always uses the 10 byte encoding, with the address in the last 8 bytes, so that it can be relocated.
+    #[inline]
+    pub const fn load_immediate_address(destination: u8, address: i64) -> Self {
+        Self {
+            size: OperandSize::S64,
+            opcode: 0xb8 | (destination & 0b111),
+            modrm: false,
+            second_operand: destination,
+            immediate_size: OperandSize::S64,
+            immediate: address,
+            ..Self::DEFAULT
+        }
+    }
+
     /// Store sign-extended immediate in destination
     #[inline]
     pub const fn store_immediate(
@@ -615,6 +633,19 @@ impl X86Instruction {
         }
     }
 
+    /// Jump to absolute destination
+    #[inline]
+    pub const fn jump_reg(destination: u8, indirect: Option<X86IndirectAccess>) -> Self {
+        Self {
+            size: OperandSize::S64,
+            opcode: 0xff,
+            first_operand: 4,
+            second_operand: destination,
+            indirect,
+            ..Self::DEFAULT
+        }
+    }
+
     /// Push RIP and jump to relative destination
     #[inline]
     pub const fn call_immediate(relative_destination: i32) -> Self {
@@ -712,3 +743,636 @@ impl X86Instruction {
         }
     }
 }
+
+const REGISTER_NAMES_64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REGISTER_NAMES_32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REGISTER_NAMES_16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const REGISTER_NAMES_8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+/// Without a REX prefix the byte registers 4 to 7 are the high bytes of the first four
+const REGISTER_NAMES_8_LEGACY: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+const CONDITION_CODES: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+/// Opcodes 0x00 to 0x3F, indexed by bits 3 to 5, and the extensions of 0x80, 0x81 and 0x83
+const ARITHMETIC_MNEMONICS: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+/// Extensions of 0xC1, 0xD1 and 0xD3
+const SHIFT_MNEMONICS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+/// Extensions of 0xF6 and 0xF7, test takes an immediate
+const UNARY_MNEMONICS: [&str; 8] = ["test", "test", "not", "neg", "mul", "imul", "div", "idiv"];
+
+/// A host instruction decoded by `disassemble_instruction()`
+#[derive(Debug, PartialEq, Eq)]
+pub struct X86DisassembledInstruction {
+    /// Number of bytes the instruction occupies
+    pub length: usize,
+    /// Intel syntax
+    pub text: String,
+    /// Destination of a relative jump or call
+    pub target: Option<usize>,
+}
+
+/// Second operand of a ModRM byte
+enum X86RegisterOrMemory {
+    Register(u8),
+    Memory {
+        base: u8,
+        index: Option<(u8, u8)>,
+        displacement: i32,
+    },
+}
+
+struct X86Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    rex: Option<u8>,
+}
+
+impl<'a> X86Decoder<'a> {
+    fn read<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.position..self.position + N)?;
+        self.position += N;
+        bytes.try_into().ok()
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        self.read::<1>().map(|bytes| bytes[0])
+    }
+
+    /// Reads a little endian immediate of the given size and sign extends it
+    fn read_immediate(&mut self, size: OperandSize) -> Option<i64> {
+        Some(match size {
+            OperandSize::S8 => i8::from_le_bytes(self.read()?) as i64,
+            OperandSize::S16 => i16::from_le_bytes(self.read()?) as i64,
+            OperandSize::S32 => i32::from_le_bytes(self.read()?) as i64,
+            OperandSize::S64 => i64::from_le_bytes(self.read()?),
+            OperandSize::S0 => 0,
+        })
+    }
+
+    fn rex_bit(&self, bit: u8) -> u8 {
+        (self.rex.unwrap_or(0) >> bit) & 1
+    }
+
+    /// Returns the register operand, extended by REX.R, and the other operand
+    ///
+    /// RIP relative and absolute addressing are never emitted and thus not supported.
+    fn read_modrm(&mut self) -> Option<(u8, X86RegisterOrMemory)> {
+        let modrm = self.read_u8()?;
+        let mode = modrm >> 6;
+        let register = ((modrm >> 3) & 0b111) | (self.rex_bit(2) << 3);
+        let mut base = modrm & 0b111;
+        if mode == 3 {
+            return Some((
+                register,
+                X86RegisterOrMemory::Register(base | (self.rex_bit(0) << 3)),
+            ));
+        }
+        let mut index = None;
+        if base == RSP {
+            let sib = self.read_u8()?;
+            let index_register = ((sib >> 3) & 0b111) | (self.rex_bit(1) << 3);
+            if index_register != RSP {
+                index = Some((index_register, 1 << (sib >> 6)));
+            }
+            base = sib & 0b111;
+        }
+        if mode == 0 && base == RBP {
+            return None;
+        }
+        let displacement = match mode {
+            0 => 0,
+            1 => self.read_immediate(OperandSize::S8)? as i32,
+            _ => self.read_immediate(OperandSize::S32)? as i32,
+        };
+        Some((
+            register,
+            X86RegisterOrMemory::Memory {
+                base: base | (self.rex_bit(0) << 3),
+                index,
+                displacement,
+            },
+        ))
+    }
+
+    fn register(&self, size: OperandSize, register: u8) -> String {
+        match size {
+            OperandSize::S8 if self.rex.is_none() => {
+                REGISTER_NAMES_8_LEGACY[register as usize & 0b111]
+            }
+            OperandSize::S8 => REGISTER_NAMES_8[register as usize],
+            OperandSize::S16 => REGISTER_NAMES_16[register as usize],
+            OperandSize::S32 => REGISTER_NAMES_32[register as usize],
+            _ => REGISTER_NAMES_64[register as usize],
+        }
+        .to_string()
+    }
+
+    /// Formats the operand, `OperandSize::S0` omits the size of memory operands
+    fn register_or_memory(&self, size: OperandSize, operand: &X86RegisterOrMemory) -> String {
+        match operand {
+            X86RegisterOrMemory::Register(register) => self.register(size, *register),
+            X86RegisterOrMemory::Memory {
+                base,
+                index,
+                displacement,
+            } => {
+                let mut text = match size {
+                    OperandSize::S0 => String::new(),
+                    OperandSize::S8 => "byte ptr ".to_string(),
+                    OperandSize::S16 => "word ptr ".to_string(),
+                    OperandSize::S32 => "dword ptr ".to_string(),
+                    OperandSize::S64 => "qword ptr ".to_string(),
+                };
+                text.push('[');
+                text.push_str(REGISTER_NAMES_64[*base as usize]);
+                match index {
+                    Some((index, 1)) => {
+                        text.push_str(&format!(" + {}", REGISTER_NAMES_64[*index as usize]))
+                    }
+                    Some((index, scale)) => text.push_str(&format!(
+                        " + {}*{}",
+                        scale, REGISTER_NAMES_64[*index as usize]
+                    )),
+                    None => {}
+                }
+                match displacement {
+                    0 => {}
+                    displacement if *displacement < 0 => {
+                        text.push_str(&format!(" - {:#x}", displacement.unsigned_abs()))
+                    }
+                    displacement => text.push_str(&format!(" + {:#x}", displacement)),
+                }
+                text.push(']');
+                text
+            }
+        }
+    }
+}
+
+fn format_immediate(immediate: i64) -> String {
+    if immediate < 0 {
+        format!("-{:#x}", immediate.unsigned_abs())
+    } else {
+        format!("{:#x}", immediate)
+    }
+}
+
+/// Decodes the host instruction at the beginning of `bytes`
+///
+/// `address` is where the instruction is located, relative jumps and calls are resolved
+/// against it. Covers every instruction form `X86Instruction` can emit and returns `None`
+/// for anything else.
+pub fn disassemble_instruction(bytes: &[u8], address: usize) -> Option<X86DisassembledInstruction> {
+    let mut decoder = X86Decoder {
+        bytes,
+        position: 0,
+        rex: None,
+    };
+    let mut opcode = decoder.read_u8()?;
+    let operand_size_override = opcode == 0x66;
+    if operand_size_override {
+        opcode = decoder.read_u8()?;
+    }
+    if opcode & 0xf0 == 0x40 {
+        decoder.rex = Some(opcode & 0x0f);
+        opcode = decoder.read_u8()?;
+    }
+    let size = if decoder.rex_bit(3) != 0 {
+        OperandSize::S64
+    } else if operand_size_override {
+        OperandSize::S16
+    } else {
+        OperandSize::S32
+    };
+    // Immediates are at most 32 bit, except for movabs
+    let immediate_size = match size {
+        OperandSize::S16 => OperandSize::S16,
+        _ => OperandSize::S32,
+    };
+    let mut target = None;
+    let mut relative_target = |decoder: &mut X86Decoder, size: OperandSize| {
+        let relative = decoder.read_immediate(size)?;
+        let destination = (address as i64 + decoder.position as i64 + relative) as usize;
+        target = Some(destination);
+        Some(vec![format!("{:#x}", destination)])
+    };
+    let (mnemonic, operands): (String, Vec<String>) = match opcode {
+        0x00..=0x3f if opcode & 0b111 <= 3 => {
+            let size = if opcode & 1 == 0 {
+                OperandSize::S8
+            } else {
+                size
+            };
+            let (register, operand) = decoder.read_modrm()?;
+            let register = decoder.register(size, register);
+            let operand = decoder.register_or_memory(size, &operand);
+            (
+                ARITHMETIC_MNEMONICS[opcode as usize >> 3].to_string(),
+                if opcode & 0b10 == 0 {
+                    vec![operand, register]
+                } else {
+                    vec![register, operand]
+                },
+            )
+        }
+        0x50..=0x5f => {
+            let register = (opcode & 0b111) | (decoder.rex_bit(0) << 3);
+            (
+                if opcode < 0x58 { "push" } else { "pop" }.to_string(),
+                vec![decoder.register(OperandSize::S64, register)],
+            )
+        }
+        0x63 => {
+            let (register, operand) = decoder.read_modrm()?;
+            (
+                "movsxd".to_string(),
+                vec![
+                    decoder.register(size, register),
+                    decoder.register_or_memory(OperandSize::S32, &operand),
+                ],
+            )
+        }
+        0x68 | 0x6a => {
+            let immediate = decoder.read_immediate(if opcode == 0x6a {
+                OperandSize::S8
+            } else {
+                immediate_size
+            })?;
+            ("push".to_string(), vec![format_immediate(immediate)])
+        }
+        0x80 | 0x81 | 0x83 | 0xc0 | 0xc1 | 0xc6 | 0xc7 | 0xf6 | 0xf7 => {
+            let size = if opcode & 1 == 0 {
+                OperandSize::S8
+            } else {
+                size
+            };
+            let (extension, operand) = decoder.read_modrm()?;
+            let extension = extension as usize & 0b111;
+            let mnemonic = match opcode {
+                0x80 | 0x81 | 0x83 => ARITHMETIC_MNEMONICS[extension],
+                0xc0 | 0xc1 => SHIFT_MNEMONICS[extension],
+                0xc6 | 0xc7 if extension == 0 => "mov",
+                0xf6 | 0xf7 => UNARY_MNEMONICS[extension],
+                _ => return None,
+            };
+            let mut operands = vec![decoder.register_or_memory(size, &operand)];
+            let immediate_size = match opcode {
+                0x80 | 0x83 | 0xc0 | 0xc1 | 0xc6 | 0xf6 => Some(OperandSize::S8),
+                0xf7 if extension > 1 => None,
+                _ => Some(immediate_size),
+            };
+            if let Some(immediate_size) = immediate_size {
+                let immediate = decoder.read_immediate(immediate_size)?;
+                operands.push(if opcode == 0xc0 || opcode == 0xc1 {
+                    // Shift counts are unsigned
+                    format!("{:#x}", immediate as u8)
+                } else {
+                    format_immediate(immediate)
+                });
+            }
+            (mnemonic.to_string(), operands)
+        }
+        0x84..=0x89 => {
+            let size = if opcode & 1 == 0 {
+                OperandSize::S8
+            } else {
+                size
+            };
+            let (register, operand) = decoder.read_modrm()?;
+            (
+                match opcode {
+                    0x84 | 0x85 => "test",
+                    0x86 | 0x87 => "xchg",
+                    _ => "mov",
+                }
+                .to_string(),
+                vec![
+                    decoder.register_or_memory(size, &operand),
+                    decoder.register(size, register),
+                ],
+            )
+        }
+        0x8a | 0x8b | 0x8d => {
+            let (register, operand) = decoder.read_modrm()?;
+            (
+                if opcode == 0x8d { "lea" } else { "mov" }.to_string(),
+                vec![
+                    decoder.register(
+                        if opcode == 0x8a {
+                            OperandSize::S8
+                        } else {
+                            size
+                        },
+                        register,
+                    ),
+                    decoder.register_or_memory(
+                        match opcode {
+                            0x8a => OperandSize::S8,
+                            0x8d => OperandSize::S0,
+                            _ => size,
+                        },
+                        &operand,
+                    ),
+                ],
+            )
+        }
+        0x90 if decoder.rex_bit(0) == 0 => ("nop".to_string(), Vec::new()),
+        0x99 => (
+            match size {
+                OperandSize::S16 => "cwd",
+                OperandSize::S64 => "cqo",
+                _ => "cdq",
+            }
+            .to_string(),
+            Vec::new(),
+        ),
+        0xb8..=0xbf => {
+            let register = (opcode & 0b111) | (decoder.rex_bit(0) << 3);
+            let immediate = decoder.read_immediate(size)?;
+            if let OperandSize::S64 = size {
+                (
+                    "movabs".to_string(),
+                    vec![
+                        decoder.register(size, register),
+                        format!("{:#x}", immediate as u64),
+                    ],
+                )
+            } else {
+                (
+                    "mov".to_string(),
+                    vec![
+                        decoder.register(size, register),
+                        format_immediate(immediate),
+                    ],
+                )
+            }
+        }
+        0xc3 => ("ret".to_string(), Vec::new()),
+        0xcc => ("int3".to_string(), Vec::new()),
+        0xcd => (
+            "int".to_string(),
+            vec![format!("{:#x}", decoder.read_u8()?)],
+        ),
+        0xd1 | 0xd3 => {
+            let (extension, operand) = decoder.read_modrm()?;
+            (
+                SHIFT_MNEMONICS[extension as usize & 0b111].to_string(),
+                vec![
+                    decoder.register_or_memory(size, &operand),
+                    if opcode == 0xd1 { "1" } else { "cl" }.to_string(),
+                ],
+            )
+        }
+        0xe8 => (
+            "call".to_string(),
+            relative_target(&mut decoder, OperandSize::S32)?,
+        ),
+        0xe9 => (
+            "jmp".to_string(),
+            relative_target(&mut decoder, OperandSize::S32)?,
+        ),
+        0xeb => (
+            "jmp".to_string(),
+            relative_target(&mut decoder, OperandSize::S8)?,
+        ),
+        0xff => {
+            let (extension, operand) = decoder.read_modrm()?;
+            let (mnemonic, size) = match extension & 0b111 {
+                0 => ("inc", size),
+                1 => ("dec", size),
+                2 => ("call", OperandSize::S64),
+                4 => ("jmp", OperandSize::S64),
+                6 => ("push", OperandSize::S64),
+                _ => return None,
+            };
+            (
+                mnemonic.to_string(),
+                vec![decoder.register_or_memory(size, &operand)],
+            )
+        }
+        0x0f => {
+            let opcode = decoder.read_u8()?;
+            match opcode {
+                0x31 => ("rdtsc".to_string(), Vec::new()),
+                0x40..=0x4f => {
+                    let (register, operand) = decoder.read_modrm()?;
+                    (
+                        format!("cmov{}", CONDITION_CODES[opcode as usize & 0xf]),
+                        vec![
+                            decoder.register(size, register),
+                            decoder.register_or_memory(size, &operand),
+                        ],
+                    )
+                }
+                0x80..=0x8f => (
+                    format!("j{}", CONDITION_CODES[opcode as usize & 0xf]),
+                    relative_target(&mut decoder, OperandSize::S32)?,
+                ),
+                0xae => {
+                    let (extension, operand) = decoder.read_modrm()?;
+                    if !matches!(operand, X86RegisterOrMemory::Register(_)) {
+                        return None;
+                    }
+                    (
+                        match extension & 0b111 {
+                            5 => "lfence",
+                            6 => "mfence",
+                            7 => "sfence",
+                            _ => return None,
+                        }
+                        .to_string(),
+                        Vec::new(),
+                    )
+                }
+                0xaf => {
+                    let (register, operand) = decoder.read_modrm()?;
+                    (
+                        "imul".to_string(),
+                        vec![
+                            decoder.register(size, register),
+                            decoder.register_or_memory(size, &operand),
+                        ],
+                    )
+                }
+                0xb6 | 0xb7 => {
+                    let (register, operand) = decoder.read_modrm()?;
+                    (
+                        "movzx".to_string(),
+                        vec![
+                            decoder.register(size, register),
+                            decoder.register_or_memory(
+                                if opcode == 0xb6 {
+                                    OperandSize::S8
+                                } else {
+                                    OperandSize::S16
+                                },
+                                &operand,
+                            ),
+                        ],
+                    )
+                }
+                0xc8..=0xcf => {
+                    let register = (opcode & 0b111) | (decoder.rex_bit(0) << 3);
+                    ("bswap".to_string(), vec![decoder.register(size, register)])
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some(X86DisassembledInstruction {
+        length: decoder.position,
+        text: if operands.is_empty() {
+            mnemonic
+        } else {
+            format!("{} {}", mnemonic, operands.join(", "))
+        },
+        target,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble(bytes: &[u8], address: usize) -> Option<(usize, String, Option<usize>)> {
+        disassemble_instruction(bytes, address)
+            .map(|instruction| (instruction.length, instruction.text, instruction.target))
+    }
+
+    #[test]
+    fn test_disassemble_instruction() {
+        for (bytes, text) in [
+            (&[0x4c, 0x01, 0xd8][..], "add rax, r11"),
+            (&[0x4c, 0x03, 0x7d, 0xf0], "add r15, qword ptr [rbp - 0x10]"),
+            (
+                &[0x49, 0x81, 0xc3, 0x78, 0x56, 0x34, 0x12],
+                "add r11, 0x12345678",
+            ),
+            (
+                &[0x41, 0x81, 0xe3, 0xff, 0xff, 0x00, 0x00],
+                "and r11d, 0xffff",
+            ),
+            (&[0x48, 0x83, 0xec, 0x08], "sub rsp, 0x8"),
+            (&[0x4c, 0x39, 0xdf], "cmp rdi, r11"),
+            (&[0x48, 0x81, 0xff, 0xff, 0xff, 0xff, 0xff], "cmp rdi, -0x1"),
+            (&[0x48, 0xc1, 0xe8, 0xf0], "shr rax, 0xf0"),
+            (&[0x49, 0xd3, 0xe3], "shl r11, cl"),
+            (&[0x49, 0xc1, 0xcb, 0x20], "ror r11, 0x20"),
+            (&[0x66, 0xc1, 0xc0, 0x08], "rol ax, 0x8"),
+            (&[0x48, 0x63, 0xc0], "movsxd rax, eax"),
+            (&[0x48, 0xf7, 0xd8], "neg rax"),
+            (&[0x41, 0xf7, 0xf3], "div r11d"),
+            (&[0x49, 0xf7, 0xfb], "idiv r11"),
+            (&[0x48, 0x99], "cqo"),
+            (&[0x99], "cdq"),
+            (&[0x4c, 0x89, 0xd8], "mov rax, r11"),
+            (&[0x4c, 0x8b, 0x5d, 0x08], "mov r11, qword ptr [rbp + 0x8]"),
+            (
+                &[0x4e, 0x8b, 0x94, 0xdd, 0x08, 0x00, 0x00, 0x00],
+                "mov r10, qword ptr [rbp + 8*r11 + 0x8]",
+            ),
+            (
+                &[0x0f, 0xb6, 0x45, 0x10],
+                "movzx eax, byte ptr [rbp + 0x10]",
+            ),
+            (
+                &[0x44, 0x0f, 0xb7, 0x5d, 0xf8],
+                "movzx r11d, word ptr [rbp - 0x8]",
+            ),
+            (&[0x88, 0x65, 0x00], "mov byte ptr [rbp], ah"),
+            (&[0x40, 0x88, 0x75, 0x00], "mov byte ptr [rbp], sil"),
+            (&[0x66, 0x89, 0x45, 0x02], "mov word ptr [rbp + 0x2], ax"),
+            (
+                &[0x66, 0xc7, 0x45, 0x02, 0x34, 0x12],
+                "mov word ptr [rbp + 0x2], 0x1234",
+            ),
+            (&[0x41, 0xc6, 0x03, 0x7f], "mov byte ptr [r11], 0x7f"),
+            (&[0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff], "mov rax, -0x1"),
+            (
+                &[0x41, 0xbb, 0x78, 0x56, 0x34, 0x12],
+                "mov r11d, 0x12345678",
+            ),
+            (
+                &[0x49, 0xbb, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+                "movabs r11, 0x1122334455667788",
+            ),
+            (&[0x48, 0x8d, 0x65, 0xf0], "lea rsp, [rbp - 0x10]"),
+            (
+                &[0x4c, 0x87, 0x9c, 0x24, 0x00, 0x00, 0x00, 0x00],
+                "xchg qword ptr [rsp], r11",
+            ),
+            (&[0x48, 0x85, 0xc0], "test rax, rax"),
+            (&[0xf6, 0xc4, 0x01], "test ah, 0x1"),
+            (&[0x41, 0xf6, 0xc3, 0x80], "test r11b, -0x80"),
+            (&[0x4d, 0x0f, 0x45, 0xdd], "cmovne r11, r13"),
+            (&[0x0f, 0xc8], "bswap eax"),
+            (&[0x49, 0x0f, 0xcb], "bswap r11"),
+            (&[0x41, 0x53], "push r11"),
+            (&[0x41, 0x5b], "pop r11"),
+            (&[0xff, 0x75, 0x08], "push qword ptr [rbp + 0x8]"),
+            (&[0x48, 0x68, 0xff, 0xff, 0xff, 0xff], "push -0x1"),
+            (&[0x41, 0xff, 0xd3], "call r11"),
+            (&[0xc3], "ret"),
+            (&[0x90], "nop"),
+            (&[0xcc], "int3"),
+            (&[0xcd, 0x80], "int 0x80"),
+            (&[0x0f, 0x31], "rdtsc"),
+            (&[0x0f, 0xae, 0xe8], "lfence"),
+            (&[0x0f, 0xae, 0xf8], "sfence"),
+        ] {
+            assert_eq!(
+                disassemble(bytes, 0),
+                Some((bytes.len(), text.to_string(), None)),
+            );
+        }
+    }
+
+    #[test]
+    fn test_disassemble_relative_target() {
+        assert_eq!(
+            disassemble(&[0xe8, 0xfb, 0xff, 0xff, 0xff], 0x10),
+            Some((5, "call 0x10".to_string(), Some(0x10))),
+        );
+        assert_eq!(
+            disassemble(&[0xe9, 0x00, 0x01, 0x00, 0x00], 0),
+            Some((5, "jmp 0x105".to_string(), Some(0x105))),
+        );
+        assert_eq!(
+            disassemble(&[0x0f, 0x84, 0xf0, 0xff, 0xff, 0xff, 0xcc], 0x20),
+            Some((6, "je 0x16".to_string(), Some(0x16))),
+        );
+    }
+
+    #[test]
+    fn test_disassemble_unsupported() {
+        for bytes in [
+            // Empty and truncated
+            &[][..],
+            &[0x48, 0x8b],
+            &[0x49, 0xbb, 0x88, 0x77],
+            // ud2
+            &[0x0f, 0x0b],
+            // add eax, imm32
+            &[0x05, 0x01, 0x00, 0x00, 0x00],
+            // RIP relative
+            &[0x8b, 0x05, 0x00, 0x00, 0x00, 0x00],
+            // xchg r8, rax
+            &[0x49, 0x90],
+        ] {
+            assert_eq!(disassemble(bytes, 0), None);
+        }
+    }
+}
@@ -163,6 +163,7 @@ pub fn create_memory_mapping<'a, V: Verifier, C: ContextObject>(
     executable: &'a Executable<V, C>,
     stack: &'a mut AlignedMemory<{ HOST_ALIGN }>,
     heap: &'a mut AlignedMemory<{ HOST_ALIGN }>,
+    data: &'a mut AlignedMemory<{ HOST_ALIGN }>,
     additional_regions: Vec<MemoryRegion>,
     cow_cb: Option<MemoryCowCallback>,
 ) -> Result<MemoryMapping<'a>, EbpfError> {
@@ -182,6 +183,7 @@ pub fn create_memory_mapping<'a, V: Verifier, C: ContextObject>(
     ]
     .into_iter()
     .chain(additional_regions.into_iter())
+    .chain(executable.get_data_region(data))
     .collect();
 
     Ok(if let Some(cow_cb) = cow_cb {
@@ -198,11 +200,13 @@ macro_rules! create_vm {
             $verified_executable.get_config().stack_size(),
         );
         let mut $heap = solana_rbpf::aligned_memory::AlignedMemory::with_capacity(0);
+        let mut data = solana_rbpf::aligned_memory::AlignedMemory::with_capacity(0);
         let stack_len = $stack.len();
         let memory_mapping = test_utils::create_memory_mapping(
             $verified_executable,
             &mut $stack,
             &mut $heap,
+            &mut data,
             $additional_regions,
             $cow_cb,
         )
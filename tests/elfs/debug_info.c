@@ -0,0 +1,12 @@
+/**
+ * @brief test program with DWARF debug information
+ */
+
+static __attribute__((noinline)) unsigned long triple(unsigned long x) {
+  return x * 3;
+}
+
+__attribute__((section("xdp"))) unsigned long prog(const unsigned char *input) {
+  unsigned long x = *input;
+  return triple(x) + 1;
+}
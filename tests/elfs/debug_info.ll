@@ -0,0 +1,63 @@
+; debug_info.c with debug metadata, see elfs.sh
+source_filename = "debug_info.c"
+target datalayout = "e-m:e-p:64:64-i64:64-i128:128-n32:64-S128"
+target triple = "bpf"
+
+define dso_local i64 @prog(i8* nocapture readonly %input) local_unnamed_addr #0 section "xdp" !dbg !10 {
+entry:
+  call void @llvm.dbg.value(metadata i8* %input, metadata !17, metadata !DIExpression()), !dbg !19
+  %0 = load i8, i8* %input, align 1, !dbg !20
+  %conv = zext i8 %0 to i64, !dbg !20
+  call void @llvm.dbg.value(metadata i64 %conv, metadata !18, metadata !DIExpression()), !dbg !19
+  %call = call fastcc i64 @triple(i64 %conv), !dbg !21
+  %add = add i64 %call, 1, !dbg !22
+  ret i64 %add, !dbg !23
+}
+
+define internal fastcc i64 @triple(i64 %x) unnamed_addr #1 !dbg !24 {
+entry:
+  call void @llvm.dbg.value(metadata i64 %x, metadata !28, metadata !DIExpression()), !dbg !29
+  %mul = mul i64 %x, 3, !dbg !30
+  ret i64 %mul, !dbg !31
+}
+
+declare void @llvm.dbg.value(metadata, metadata, metadata) #2
+
+attributes #0 = { nofree norecurse nounwind readonly willreturn }
+attributes #1 = { noinline nofree norecurse nosync nounwind readnone willreturn }
+attributes #2 = { nofree nosync nounwind readnone speculatable willreturn }
+
+!llvm.dbg.cu = !{!0}
+!llvm.module.flags = !{!2, !3, !4, !5}
+!llvm.ident = !{!6}
+
+!0 = distinct !DICompileUnit(language: DW_LANG_C99, file: !1, producer: "clang version 14.0.0", isOptimized: true, runtimeVersion: 0, emissionKind: FullDebug, splitDebugInlining: false, nameTableKind: None)
+!1 = !DIFile(filename: "debug_info.c", directory: ".")
+!2 = !{i32 7, !"Dwarf Version", i32 5}
+!3 = !{i32 2, !"Debug Info Version", i32 3}
+!4 = !{i32 1, !"wchar_size", i32 4}
+!5 = !{i32 7, !"frame-pointer", i32 2}
+!6 = !{!"clang version 14.0.0"}
+!10 = distinct !DISubprogram(name: "prog", scope: !1, file: !1, line: 9, type: !11, scopeLine: 9, flags: DIFlagPrototyped | DIFlagAllCallsDescribed, spFlags: DISPFlagDefinition | DISPFlagOptimized, unit: !0, retainedNodes: !16)
+!11 = !DISubroutineType(types: !12)
+!12 = !{!13, !14}
+!13 = !DIBasicType(name: "unsigned long", size: 64, encoding: DW_ATE_unsigned)
+!14 = !DIDerivedType(tag: DW_TAG_pointer_type, baseType: !15, size: 64)
+!15 = !DIDerivedType(tag: DW_TAG_const_type, baseType: !32)
+!16 = !{!17, !18}
+!17 = !DILocalVariable(name: "input", arg: 1, scope: !10, file: !1, line: 9, type: !14)
+!18 = !DILocalVariable(name: "x", scope: !10, file: !1, line: 10, type: !13)
+!19 = !DILocation(line: 0, scope: !10)
+!20 = !DILocation(line: 10, column: 21, scope: !10)
+!21 = !DILocation(line: 11, column: 10, scope: !10)
+!22 = !DILocation(line: 11, column: 20, scope: !10)
+!23 = !DILocation(line: 11, column: 3, scope: !10)
+!24 = distinct !DISubprogram(name: "triple", scope: !1, file: !1, line: 5, type: !25, scopeLine: 5, flags: DIFlagPrototyped | DIFlagAllCallsDescribed, spFlags: DISPFlagLocalToUnit | DISPFlagDefinition | DISPFlagOptimized, unit: !0, retainedNodes: !27)
+!25 = !DISubroutineType(types: !26)
+!26 = !{!13, !13}
+!27 = !{!28}
+!28 = !DILocalVariable(name: "x", arg: 1, scope: !24, file: !1, line: 5, type: !13)
+!29 = !DILocation(line: 0, scope: !24)
+!30 = !DILocation(line: 6, column: 12, scope: !24)
+!31 = !DILocation(line: 6, column: 3, scope: !24)
+!32 = !DIBasicType(name: "unsigned char", size: 8, encoding: DW_ATE_unsigned_char)
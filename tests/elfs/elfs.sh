@@ -33,6 +33,10 @@ rm multiple_file.o
 "$LLVM_DIR"ld.lld $LD_FLAGS -o relative_call.so relative_call.o
 rm relative_call.o
 
+"$LLVM_DIR"clang $CC_FLAGS -ffunction-sections -o function_sections.o -c relative_call.c
+"$LLVM_DIR"ld.lld $LD_FLAGS -o function_sections.so function_sections.o
+rm function_sections.o
+
 "$LLVM_DIR"clang $CC_FLAGS -o reloc_64_64.o -c reloc_64_64.c
 "$LLVM_DIR"ld.lld $LD_FLAGS -o reloc_64_64.so reloc_64_64.o
 rm reloc_64_64.o
@@ -104,3 +108,9 @@ rm scratch_registers_debug.o
 "$LLVM_DIR"clang $CC_FLAGS -o struct_func_pointer.o -c struct_func_pointer.c
 "$LLVM_DIR"ld.lld $LD_FLAGS -o struct_func_pointer.so struct_func_pointer.o
 rm struct_func_pointer.o
+
+# Kernel style relocatable object with one program per section, not linked
+"$LLVM_DIR"llvm-mc -triple bpfel -filetype=obj -o relocatable.o relocatable.s
+
+# debug_info.ll is debug_info.c in LLVM IR with debug metadata
+"$LLVM_DIR"llc -march=bpfel -O2 -filetype=obj -o debug_info.o debug_info.ll
@@ -0,0 +1,50 @@
+# Two programs in the xdp and .classifier sections sharing the subprograms
+# in .text, as emitted by clang -target bpf -c
+	.text
+	.globl	double_it
+	.type	double_it,@function
+double_it:
+	r0 = r1
+	r0 += r1
+	exit
+
+	.type	add_one,@function
+add_one:
+	r0 = r1
+	r0 += 1
+	exit
+
+	.section	xdp,"ax",@progbits
+	.globl	prog_xdp
+	.type	prog_xdp,@function
+prog_xdp:
+	r1 = .Lvalues ll
+	r1 = *(u64 *)(r1 + 0)
+	call double_it
+	exit
+
+	.section	.classifier,"ax",@progbits
+	.globl	prog_classifier
+	.type	prog_classifier,@function
+prog_classifier:
+	r1 = counter ll
+	r2 = *(u64 *)(r1 + 0)
+	r2 += 1
+	*(u64 *)(r1 + 0) = r2
+	r1 = r2
+	call add_one
+	r1 = r0
+	call double_it
+	exit
+
+	.section	.rodata,"a",@progbits
+	.p2align	3
+	.quad	10
+.Lvalues:
+	.quad	20
+
+	.data
+	.globl	counter
+	.p2align	3
+counter:
+	.quad	5
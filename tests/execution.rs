@@ -16,21 +16,35 @@ use byteorder::{ByteOrder, LittleEndian};
 #[cfg(all(not(windows), target_arch = "x86_64"))]
 use rand::{rngs::SmallRng, RngCore, SeedableRng};
 use solana_rbpf::{
+    aligned_memory::AlignedMemory,
+    allocator::{
+        register_allocator, AllocatorError, AllocatorMode, HeapAllocator,
+        HeapAllocatorContextObject,
+    },
     assembler::assemble,
+    cost_analysis::{CostAnalysis, CostBound},
+    coverage::{disassembly_line_table, Coverage},
     ebpf,
     elf::Executable,
+    elf_report::{ElfReport, RelocationTarget},
     error::EbpfError,
     memory_region::{AccessType, MemoryMapping, MemoryRegion},
+    profiler::{CallProfile, Profile},
+    snapshot::Snapshot,
+    stack_analysis::{StackAnalysis, StackProblem, StackUsage},
     static_analysis::Analysis,
     syscalls,
+    trace::{TraceReader, TraceWriter},
     verifier::{RequisiteVerifier, TautologyVerifier},
     vm::{
-        BuiltInProgram, Config, ContextObject, FunctionRegistry, ProgramResult, TestContextObject,
+        BuiltInProgram, CallFrame, Config, ContextObject, EbpfVm, FunctionRegistry,
+        InstructionCosts, ProgramResult, TestContextObject,
     },
 };
 use std::{fs::File, io::Read, sync::Arc};
 use test_utils::{
-    assert_error, create_vm, PROG_TCP_PORT_80, TCP_SACK_ASM, TCP_SACK_MATCH, TCP_SACK_NOMATCH,
+    assert_error, create_memory_mapping, create_vm, PROG_TCP_PORT_80, TCP_SACK_ASM, TCP_SACK_MATCH,
+    TCP_SACK_NOMATCH,
 };
 
 const INSTRUCTION_METER_BUDGET: u64 = 1024;
@@ -78,12 +92,39 @@ macro_rules! test_interpreter_and_jit {
                 vm.env.context_object_pointer.clone(),
             )
         };
+        #[cfg(feature = "cranelift")]
+        {
+            verified_executable.cranelift_compile().unwrap();
+            let mut mem = $mem;
+            let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            let mut context_object = context_object.clone();
+            create_vm!(
+                vm,
+                &verified_executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![mem_region],
+                None
+            );
+            let (instruction_count_cranelift, result) = vm.execute_program_cranelift();
+            assert_eq!(format!("{:?}", result), expected_result);
+            assert_eq!(
+                _tracer_interpreter.trace_log, vm.env.context_object_pointer.trace_log,
+                "Interpreter and Cranelift traces diverged",
+            );
+            assert_eq!(
+                instruction_count_interpreter, instruction_count_cranelift,
+                "Interpreter and Cranelift instruction meter diverged",
+            );
+        }
         #[cfg(all(not(windows), target_arch = "x86_64"))]
         {
             #[allow(unused_mut)]
             let compilation_result = verified_executable.jit_compile();
             let mut mem = $mem;
             let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            let mut context_object = context_object.clone();
             create_vm!(
                 vm,
                 &verified_executable,
@@ -120,6 +161,34 @@ macro_rules! test_interpreter_and_jit {
                 }
             }
         }
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        if verified_executable.get_compiled_program().is_some() {
+            verified_executable.jit_compile_lazily().unwrap();
+            let mut mem = $mem;
+            let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            create_vm!(
+                vm,
+                &verified_executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![mem_region],
+                None
+            );
+            let (instruction_count_lazy, result) = vm.execute_program(false);
+            assert_eq!(format!("{:?}", result), expected_result);
+            assert!(
+                TestContextObject::compare_trace_log(
+                    &_tracer_interpreter,
+                    &vm.env.context_object_pointer,
+                ),
+                "Interpreter and lazily compiled JIT traces diverged",
+            );
+            assert_eq!(
+                instruction_count_interpreter, instruction_count_lazy,
+                "Interpreter and lazily compiled JIT instruction meter diverged",
+            );
+        }
         if verified_executable.get_config().enable_instruction_meter {
             assert_eq!(instruction_count_interpreter, expected_instruction_count);
         }
@@ -2608,6 +2677,19 @@ fn test_relative_call() {
     );
 }
 
+#[test]
+fn test_relative_call_across_function_sections() {
+    test_interpreter_and_jit_elf!(
+        "tests/elfs/function_sections.so",
+        [1],
+        (
+            "log" => syscalls::bpf_syscall_string,
+        ),
+        TestContextObject::new(14),
+        ProgramResult::Ok(2),
+    );
+}
+
 #[test]
 fn test_bpf_to_bpf_scratch_registers() {
     test_interpreter_and_jit_elf!(
@@ -3133,6 +3215,290 @@ fn test_load_elf_rodata_high_vaddr() {
     );
 }
 
+#[test]
+fn test_load_elf_writable_data() {
+    test_interpreter_and_jit_elf!(
+        "tests/elfs/writable_data_section.so",
+        [],
+        (),
+        TestContextObject::new(5),
+        ProgramResult::Ok(0),
+    );
+}
+
+#[test]
+fn test_load_elf_bss() {
+    test_interpreter_and_jit_elf!(
+        "tests/elfs/bss_section.so",
+        [],
+        (),
+        TestContextObject::new(5),
+        ProgramResult::Ok(0),
+    );
+}
+
+#[test]
+fn test_load_elf_data_region_per_invocation() {
+    let elf = std::fs::read("tests/elfs/writable_data_section.so").unwrap();
+    let loader = Arc::new(BuiltInProgram::new_loader(Config::default()));
+    let executable =
+        Executable::<TautologyVerifier, TestContextObject>::from_elf(&elf, loader).unwrap();
+    #[allow(unused_mut)]
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    executable.jit_compile().unwrap();
+    let mut data = AlignedMemory::with_capacity(0);
+    for interpreted in [true, false] {
+        if !interpreted && !cfg!(all(not(windows), target_arch = "x86_64")) {
+            continue;
+        }
+        let mut context_object = TestContextObject::new(5);
+        let mut stack = AlignedMemory::zero_filled(executable.get_config().stack_size());
+        let mut heap = AlignedMemory::with_capacity(0);
+        let mut mem = [];
+        let stack_len = stack.len();
+        let memory_mapping = create_memory_mapping(
+            &executable,
+            &mut stack,
+            &mut heap,
+            &mut data,
+            vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+            None,
+        )
+        .unwrap();
+        let mut vm = EbpfVm::new(&executable, &mut context_object, memory_mapping, stack_len);
+        let (_instruction_count, result) = vm.execute_program(interpreted);
+        assert_eq!(result.unwrap(), 0);
+        // The store went to this invocation's copy, not to the template
+        assert_eq!(data.as_slice(), &43u32.to_le_bytes());
+        assert_eq!(executable.get_data_section(), &42u32.to_le_bytes());
+    }
+}
+
+#[test]
+fn test_load_relocatable_object() {
+    let object = std::fs::read("tests/elfs/relocatable.o").unwrap();
+    let loader = Arc::new(BuiltInProgram::new_loader(Config::default()));
+    let mut executables =
+        Executable::<TautologyVerifier, TestContextObject>::from_relocatable(&object, loader)
+            .unwrap()
+            .into_iter();
+    let (name, executable) = executables.next().unwrap();
+    assert_eq!(name, "xdp");
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(7),
+        ProgramResult::Ok(40),
+    );
+    // .data is reset for every invocation, so the interpreter and the JIT agree
+    let (name, executable) = executables.next().unwrap();
+    assert_eq!(name, ".classifier");
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(15),
+        ProgramResult::Ok(14),
+    );
+    assert!(executables.next().is_none());
+}
+
+#[cfg(feature = "debug-info")]
+#[test]
+fn test_load_debug_info() {
+    let object = std::fs::read("tests/elfs/debug_info.o").unwrap();
+    let config = Config {
+        enable_instruction_tracing: true,
+        enable_symbol_and_section_labels: true,
+        enable_backtrace: true,
+        ..Config::default()
+    };
+    let loader = Arc::new(BuiltInProgram::new_loader(config));
+    let (name, executable) =
+        Executable::<TautologyVerifier, TestContextObject>::from_relocatable(&object, loader)
+            .unwrap()
+            .pop()
+            .unwrap();
+    assert_eq!(name, "xdp");
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let mut disassembly = Vec::new();
+    analysis.disassemble(&mut disassembly).unwrap();
+    let disassembly = String::from_utf8(disassembly).unwrap();
+    assert!(disassembly.contains("    ; debug_info.c:11\n    call function_4\n"));
+    assert!(disassembly.contains("    ; debug_info.c:6\n    mul64 r0, 3\n"));
+
+    let mut context_object = TestContextObject::new(7);
+    let mut mem = [5u8];
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program(true);
+    assert_eq!(result.unwrap(), 16);
+    let mut trace = Vec::new();
+    analysis
+        .disassemble_trace_log(&mut trace, &vm.env.context_object_pointer.trace_log)
+        .unwrap();
+    let trace = String::from_utf8(trace).unwrap();
+    assert_eq!(trace.lines().count(), 7);
+    assert!(trace.lines().all(|line| line.ends_with("debug_info.c:10")
+        || line.ends_with("debug_info.c:11")
+        || line.ends_with("debug_info.c:5")
+        || line.ends_with("debug_info.c:6")));
+
+    // Errors point at the source line of the failing instruction
+    let mut context_object = TestContextObject::new(7);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program(true);
+    assert!(result.is_err());
+    assert!(vm
+        .env
+        .backtrace
+        .unwrap()
+        .to_string()
+        .ends_with(" (debug_info.c:10)\n"));
+}
+
+// ELF report
+
+#[test]
+fn test_elf_report() {
+    let elf = std::fs::read("tests/elfs/relative_call.so").unwrap();
+    let loader = Arc::new(BuiltInProgram::<TestContextObject>::new_loader(Config {
+        enable_symbol_and_section_labels: true,
+        ..Config::default()
+    }));
+    let report = ElfReport::new(&elf, loader).unwrap();
+    assert!(report
+        .sections
+        .iter()
+        .any(|section| section.name == ".text"));
+    assert!(report
+        .dynamic_symbols
+        .iter()
+        .any(|symbol| symbol.name == "function_foo"));
+    let targets = report
+        .relocations
+        .iter()
+        .map(|relocation| (relocation.type_name(), relocation.target.clone().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        targets,
+        vec![
+            (
+                "R_BPF_64_RELATIVE".to_string(),
+                RelocationTarget::Address(ebpf::MM_PROGRAM_START + 0x168)
+            ),
+            (
+                "R_BPF_64_RELATIVE".to_string(),
+                RelocationTarget::Address(ebpf::MM_PROGRAM_START + 0x175)
+            ),
+            (
+                "R_BPF_64_32".to_string(),
+                RelocationTarget::Function(0, "function_foo".to_string())
+            ),
+        ]
+    );
+    assert_eq!(
+        report.ro_region.as_ref().unwrap().vm_addr,
+        ebpf::MM_PROGRAM_START + 0xe8
+    );
+    assert_eq!(
+        report.ro_region.as_ref().unwrap().file_range,
+        Some(0xe8..0x180)
+    );
+    let function_registry = report.function_registry.as_ref().unwrap();
+    assert_eq!(
+        function_registry.values().collect::<Vec<_>>(),
+        vec![
+            &(0, "function_foo".to_string()),
+            &(8, "entrypoint".to_string())
+        ]
+    );
+
+    #[cfg(feature = "json")]
+    {
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json = json::parse(std::str::from_utf8(&json).unwrap()).unwrap();
+        assert_eq!(json["sections"].len(), report.sections.len());
+        assert_eq!(json["relocations"][2]["target"]["function"], "function_foo");
+        assert_eq!(json["function_registry"][1]["name"], "entrypoint");
+    }
+
+    // Syscalls resolve to their name if the loader knows them
+    let elf = std::fs::read("tests/elfs/unresolved_syscall.so").unwrap();
+    let mut loader = BuiltInProgram::new_loader(Config::default());
+    loader
+        .register_function(b"log", syscalls::bpf_syscall_string)
+        .unwrap();
+    let report = ElfReport::new(&elf, Arc::new(loader)).unwrap();
+    let targets = report
+        .relocations
+        .iter()
+        .filter_map(|relocation| relocation.target.clone())
+        .filter(|target| matches!(target, RelocationTarget::Syscall(..)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        targets,
+        vec![
+            RelocationTarget::Syscall(ebpf::hash_symbol_name(b"log"), Some("log".to_string())),
+            RelocationTarget::Syscall(ebpf::hash_symbol_name(b"log_64"), None),
+        ]
+    );
+}
+
+#[test]
+fn test_elf_report_load_error() {
+    let elf = std::fs::read("tests/elfs/unresolved_syscall.so").unwrap();
+    let loader = Arc::new(BuiltInProgram::<TestContextObject>::new_loader(Config {
+        reject_broken_elfs: true,
+        ..Config::default()
+    }));
+    let report = ElfReport::new(&elf, loader).unwrap();
+    assert_error!(report.function_registry, "UnresolvedSymbol(\"log\"");
+    // Everything the loader saw before it gave up is still reported
+    assert!(report
+        .relocations
+        .iter()
+        .any(|relocation| relocation.symbol.as_deref() == Some("log")
+            && relocation.type_name() == "R_BPF_64_32"));
+    assert!(report
+        .relocations
+        .iter()
+        .all(|relocation| relocation.target.is_none()));
+    assert!(report.ro_region.is_ok());
+
+    let mut text = Vec::new();
+    report.write_text(&mut text).unwrap();
+    assert!(String::from_utf8(text)
+        .unwrap()
+        .contains("Function Registry:\n  Error: Unresolved symbol (log)"));
+    #[cfg(feature = "json")]
+    {
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json = json::parse(std::str::from_utf8(&json).unwrap()).unwrap();
+        assert!(json["function_registry"]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unresolved symbol (log)"));
+    }
+}
+
 // Instruction Meter Limit
 
 #[test]
@@ -3384,6 +3750,2817 @@ fn test_err_exit_capped() {
     );
 }
 
+fn weighted_config() -> Config {
+    Config {
+        enable_instruction_tracing: true,
+        instruction_costs: InstructionCosts {
+            alu: 1,
+            mul: 3,
+            div: 5,
+            memory: 2,
+            jump: 2,
+            call: 4,
+            syscall: 10,
+        },
+        ..Config::default()
+    }
+}
+
+#[test]
+fn test_weighted_instruction_costs() {
+    let config = weighted_config();
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0
+        mov64 r1, 3
+        stxdw [r10-8], r1
+        ldxdw r2, [r10-8]
+        mul64 r2, 2
+        add64 r0, r2
+        sub64 r1, 1
+        jne r1, 0, -6
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(39),
+        ProgramResult::Ok(12),
+    );
+    let config = weighted_config();
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r2, 0x5
+        syscall bpf_syscall_string
+        lddw r0, 0x10
+        mov64 r1, 0x1
+        lsh64 r1, 0x20
+        or64 r1, 0x48
+        callx r1
+        exit
+        function_foo:
+        div64 r0, 2
+        exit",
+        config,
+        [72, 101, 108, 108, 111],
+        (
+            "bpf_syscall_string" => syscalls::bpf_syscall_string,
+        ),
+        TestContextObject::new(32),
+        ProgramResult::Ok(8),
+    );
+}
+
+#[test]
+fn test_err_weighted_instruction_costs_capped() {
+    for (budget, pc) in [(13, 37), (14, 32), (20, 34)] {
+        let config = weighted_config();
+        test_interpreter_and_jit_asm!(
+            "
+            mov64 r0, 0
+            mov64 r1, 3
+            stxdw [r10-8], r1
+            ldxdw r2, [r10-8]
+            mul64 r2, 2
+            add64 r0, r2
+            sub64 r1, 1
+            jne r1, 0, -6
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(budget),
+            ProgramResult::Err(Box::new(EbpfError::ExceededMaxInstructions(pc))),
+        );
+    }
+    for (budget, pc) in [(11, 31), (12, 33), (20, 39)] {
+        let config = weighted_config();
+        test_interpreter_and_jit_asm!(
+            "
+            mov64 r2, 0x5
+            syscall bpf_syscall_string
+            lddw r0, 0x10
+            mov64 r1, 0x1
+            lsh64 r1, 0x20
+            or64 r1, 0x48
+            callx r1
+            exit
+            function_foo:
+            div64 r0, 2
+            exit",
+            config,
+            [72, 101, 108, 108, 111],
+            (
+                "bpf_syscall_string" => syscalls::bpf_syscall_string,
+            ),
+            TestContextObject::new(budget),
+            ProgramResult::Err(Box::new(EbpfError::ExceededMaxInstructions(pc))),
+        );
+    }
+}
+
+#[test]
+fn test_weighted_instruction_costs_lddw() {
+    // The second slot of an lddw costs nothing, even if opcode 0 is more expensive than the lddw
+    let config = Config {
+        enable_instruction_tracing: true,
+        instruction_costs: InstructionCosts {
+            alu: 3,
+            memory: 7,
+            ..InstructionCosts::default()
+        },
+        ..Config::default()
+    };
+    let source = "
+        mov64 r0, 1
+        lddw r0, 0x100000002
+        add64 r0, 1
+        exit";
+    test_interpreter_and_jit_asm!(
+        source,
+        config,
+        [],
+        (),
+        TestContextObject::new(10),
+        ProgramResult::Ok(0x100000003),
+    );
+    for (budget, pc) in [(4, 32), (6, 32), (7, 33), (9, 33)] {
+        test_interpreter_and_jit_asm!(
+            source,
+            config,
+            [],
+            (),
+            TestContextObject::new(budget),
+            ProgramResult::Err(Box::new(EbpfError::ExceededMaxInstructions(pc))),
+        );
+    }
+}
+
+// Suspension
+
+/// Runs the program in slices of `budget` instructions, resuming it with the backend chosen by `interpreted`
+fn execute_in_slices(
+    executable: &Executable<RequisiteVerifier, TestContextObject>,
+    budget: u64,
+    interpreted: fn(usize) -> bool,
+) -> (u64, ProgramResult, usize) {
+    let mut context_object = TestContextObject::new(budget);
+    create_vm!(
+        vm,
+        executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    let mut instruction_count = 0;
+    let mut suspensions = 0;
+    let (mut slice_instruction_count, mut result) = vm.execute_program(interpreted(0));
+    loop {
+        instruction_count += slice_instruction_count;
+        match vm.env.suspended_state.take() {
+            Some(state) => {
+                assert_error!(result, "ExceededMaxInstructions");
+                suspensions += 1;
+                vm.env.context_object_pointer.remaining = budget;
+                (slice_instruction_count, result) =
+                    vm.resume_program(interpreted(suspensions), state);
+            }
+            None => return (instruction_count, result, suspensions),
+        }
+    }
+}
+
+#[test]
+fn test_suspend_and_resume() {
+    // A slice has to afford more than the most expensive instruction, otherwise it makes no progress
+    for (instruction_costs, minimum_budget) in [
+        (InstructionCosts::default(), 2),
+        (weighted_config().instruction_costs, 5),
+    ] {
+        let config = Config {
+            enable_suspension: true,
+            instruction_costs,
+            ..Config::default()
+        };
+        let executable = assemble::<TestContextObject>(
+            "
+            mov64 r0, 0
+            mov64 r6, 0
+            mov64 r1, r6
+            call function_foo
+            add64 r6, 1
+            jlt r6, 10, -4
+            exit
+            function_foo:
+            stxdw [r10-8], r1
+            mov64 r2, r1
+            call function_bar
+            ldxdw r1, [r10-8]
+            add64 r0, r1
+            exit
+            function_bar:
+            mul64 r2, r2
+            add64 r0, r2
+            exit",
+            Arc::new(BuiltInProgram::new_loader(config)),
+        )
+        .unwrap();
+        let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+        executable.jit_compile().unwrap();
+        let (expected_instruction_count, expected_result, suspensions) =
+            execute_in_slices(&executable, INSTRUCTION_METER_BUDGET, |_| true);
+        assert_eq!(format!("{expected_result:?}"), "Ok(330)");
+        assert_eq!(suspensions, 0);
+
+        for budget in minimum_budget..12 {
+            let (instruction_count, result, suspensions) =
+                execute_in_slices(&executable, budget, |_| true);
+            assert_eq!(format!("{result:?}"), format!("{expected_result:?}"));
+            // What an instruction costs beyond the budget of its slice is charged on resumption
+            assert_eq!(instruction_count, expected_instruction_count);
+            assert!((suspensions as u64 + 1) * budget >= instruction_count);
+
+            // The JIT suspends at branches only, where it can overshoot the budget of a slice
+            for interpreted in [|_| false, |suspensions| suspensions % 2 == 0] {
+                let (instruction_count, result, suspensions) =
+                    execute_in_slices(&executable, budget, interpreted);
+                assert_eq!(format!("{result:?}"), format!("{expected_result:?}"));
+                assert_eq!(instruction_count, expected_instruction_count);
+                assert!(suspensions > 0);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn yield_syscall(
+    _context_object: &mut TestContextObject,
+    _arg1: u64,
+    _arg2: u64,
+    _arg3: u64,
+    _arg4: u64,
+    _arg5: u64,
+    _memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult,
+) {
+    *result = ProgramResult::Err(Box::new(EbpfError::SyscallYield));
+}
+
+#[test]
+fn test_syscall_yield() {
+    for enable_suspension in [false, true] {
+        let config = Config {
+            enable_suspension,
+            ..Config::default()
+        };
+        let mut loader = BuiltInProgram::new_loader(config);
+        loader
+            .register_function(b"yield_syscall", yield_syscall)
+            .unwrap();
+        let executable = assemble::<TestContextObject>(
+            "
+            mov64 r6, 0x11
+            mov64 r0, 0
+            syscall yield_syscall
+            add64 r0, r6
+            exit",
+            Arc::new(loader),
+        )
+        .unwrap();
+        let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+        executable.jit_compile().unwrap();
+        for interpreted in [true, false] {
+            let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![],
+                None
+            );
+            let (instruction_count, result) = vm.execute_program(interpreted);
+            assert_eq!(instruction_count, 3);
+            assert_error!(result, "SyscallYield");
+            if !enable_suspension {
+                assert!(vm.env.suspended_state.is_none());
+                continue;
+            }
+            let mut state = vm.env.suspended_state.take().unwrap();
+            assert_eq!(state.registers[11], 3);
+            assert_eq!(state.registers[6], 0x11);
+            assert_eq!(state.call_depth, 0);
+
+            let mut invalid_state = state.clone();
+            invalid_state.call_depth = 1;
+            let (_instruction_count, result) = vm.resume_program(interpreted, invalid_state);
+            assert_error!(result, "InvalidSuspendedState");
+
+            // Provide the return value of the syscall
+            state.registers[0] = 0x20;
+            let (instruction_count, result) = vm.resume_program(!interpreted, state);
+            assert_eq!(instruction_count, 2);
+            assert_eq!(result.unwrap(), 0x31);
+        }
+    }
+}
+
+#[test]
+fn test_resume_at_lddw_second_slot() {
+    let config = Config {
+        enable_suspension: true,
+        ..Config::default()
+    };
+    let mut loader = BuiltInProgram::new_loader(config);
+    loader
+        .register_function(b"yield_syscall", yield_syscall)
+        .unwrap();
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0
+        syscall yield_syscall
+        lddw r0, 0x100000002
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+    for interpreted in [true, false] {
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![],
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(interpreted);
+        assert_error!(result, "SyscallYield");
+        let state = vm.env.suspended_state.take().unwrap();
+        assert_eq!(state.registers[11], 2);
+
+        // Neither the pc nor the return address of a call frame may point into an lddw
+        let mut invalid_state = state.clone();
+        invalid_state.registers[11] = 3;
+        let (_instruction_count, result) = vm.resume_program(interpreted, invalid_state);
+        assert_error!(result, "InvalidSuspendedState");
+        let mut invalid_state = state.clone();
+        invalid_state.call_depth = 1;
+        invalid_state.call_frames = vec![CallFrame {
+            target_pc: 3,
+            ..CallFrame::default()
+        }];
+        let (_instruction_count, result) = vm.resume_program(interpreted, invalid_state);
+        assert_error!(result, "InvalidSuspendedState");
+
+        let (_instruction_count, result) = vm.resume_program(interpreted, state);
+        assert_eq!(result.unwrap(), 0x100000002);
+    }
+}
+
+#[test]
+fn test_snapshot_and_restore() {
+    let config = Config {
+        enable_suspension: true,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r6, 0
+        loop:
+        ldxdw r2, [r10-8]
+        add64 r2, r6
+        stxdw [r10-8], r2
+        ldxdw r3, [r1]
+        call function_foo
+        add64 r6, 1
+        jlt r6, 8, loop
+        ldxdw r0, [r10-8]
+        ldxdw r3, [r1]
+        add64 r0, r3
+        exit
+        function_foo:
+        add64 r3, r6
+        stxdw [r1], r3
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+
+    for interpreted in [true, false] {
+        let mut mem = [0u8; 8];
+        let mut context_object = TestContextObject::new(7);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(interpreted);
+        assert_error!(result, "ExceededMaxInstructions");
+        let snapshot = vm.snapshot().unwrap();
+        assert_eq!(snapshot.state.call_depth, 1);
+        let bytes = snapshot.to_bytes();
+        assert_error!(
+            Snapshot::from_bytes(&bytes[0..bytes.len() - 1]),
+            "InvalidSnapshot"
+        );
+
+        // Restore into a VM with fresh memory and continue with the other backend
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+        let mut mem = [0u8; 8];
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            restored_vm,
+            &executable,
+            &mut context_object,
+            restored_stack,
+            restored_heap,
+            vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+            None
+        );
+        let state = restored_vm.restore(&snapshot).unwrap();
+        let (_instruction_count, result) = restored_vm.resume_program(!interpreted, state);
+        assert_eq!(result.unwrap(), 56);
+        assert_eq!(LittleEndian::read_u64(&mem), 28);
+        assert_error!(restored_vm.snapshot(), "NotSuspended");
+
+        // The input region is missing
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            other_vm,
+            &executable,
+            &mut context_object,
+            other_stack,
+            other_heap,
+            vec![],
+            None
+        );
+        assert_error!(other_vm.restore(&snapshot), "InvalidSnapshot");
+    }
+}
+
+#[test]
+fn test_snapshot_of_failed_program() {
+    for (fault, expected_result) in [
+        (
+            "
+            ldxdw r0, [r0+0]",
+            "AccessViolation(35, Load, 0, 8, \"unknown\")",
+        ),
+        (
+            "
+            callx r0",
+            "CallOutsideTextSegment(35, 0)",
+        ),
+    ] {
+        for (enable_backtrace, dynamic_stack_frames) in
+            [(false, false), (true, false), (true, true)]
+        {
+            let config = Config {
+                enable_suspension: true,
+                enable_backtrace,
+                dynamic_stack_frames,
+                ..Config::default()
+            };
+            let source = format!(
+                "
+                mov64 r6, 0x2A
+                call function_foo
+                exit
+                function_foo:
+                stxdw [r10-8], r6
+                stxdw [r1], r6
+                mov64 r0, 0{fault}
+                exit"
+            );
+            let executable = assemble::<TestContextObject>(
+                &source,
+                Arc::new(BuiltInProgram::new_loader(config)),
+            )
+            .unwrap();
+            let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+            executable.jit_compile().unwrap();
+            let mut snapshots = Vec::new();
+            for interpreted in [true, false] {
+                let mut mem = [0u8; 8];
+                let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+                create_vm!(
+                    vm,
+                    &executable,
+                    &mut context_object,
+                    stack,
+                    heap,
+                    vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+                    None
+                );
+                let (_instruction_count, result) = vm.execute_program(interpreted);
+                assert_eq!(format!("{result:?}"), format!("Err({expected_result})"));
+                if !enable_backtrace {
+                    assert_error!(vm.snapshot(), "NotSuspended");
+                    continue;
+                }
+                let snapshot = vm.snapshot().unwrap();
+                assert_eq!(snapshot.state.registers[6], 0x2A);
+                assert_eq!(snapshot.state.registers[11], 6);
+                assert_eq!(snapshot.state.call_depth, 1);
+                assert_eq!(
+                    snapshot.state.stack_pointer,
+                    snapshot.state.registers[ebpf::FRAME_PTR_REG]
+                );
+                snapshots.push(snapshot);
+
+                // Restoring it runs into the same error again
+                let mut mem = [0u8; 8];
+                let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+                create_vm!(
+                    restored_vm,
+                    &executable,
+                    &mut context_object,
+                    restored_stack,
+                    restored_heap,
+                    vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+                    None
+                );
+                let state = restored_vm.restore(&snapshots[0]).unwrap();
+                assert_eq!(LittleEndian::read_u64(&mem), 0x2A);
+                let (_instruction_count, result) = restored_vm.resume_program(interpreted, state);
+                assert_eq!(format!("{result:?}"), format!("Err({expected_result})"));
+            }
+            if enable_backtrace {
+                assert_eq!(snapshots[0], snapshots[1]);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_snapshot_and_restore_memory_sanitizer() {
+    let config = Config {
+        enable_suspension: true,
+        enable_memory_sanitizer: true,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        stdw [r10-8], 0x1234
+        ldxdw r0, [r10-8]
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+    let mut context_object = TestContextObject::new(1);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program(true);
+    assert_error!(result, "ExceededMaxInstructions");
+    let snapshot = vm.snapshot().unwrap();
+
+    // The restored stack counts as initialized
+    for interpreted in [true, false] {
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            restored_vm,
+            &executable,
+            &mut context_object,
+            restored_stack,
+            restored_heap,
+            vec![],
+            None
+        );
+        let state = restored_vm.restore(&snapshot).unwrap();
+        let (_instruction_count, result) = restored_vm.resume_program(interpreted, state);
+        assert_eq!(result.unwrap(), 0x1234);
+    }
+}
+
+// Backtrace
+
+#[test]
+fn test_backtrace() {
+    for (source, expected_result) in [
+        (
+            "
+            div64 r1, r2",
+            "DivideByZero(36)",
+        ),
+        (
+            "
+            ldxdw r2, [r2+0]",
+            "AccessViolation(36, Load, 0, 8, \"unknown\")",
+        ),
+    ] {
+        let config = Config {
+            enable_backtrace: true,
+            enable_symbol_and_section_labels: true,
+            ..Config::default()
+        };
+        let source = format!(
+            "
+            mov64 r6, 7
+            call function_foo
+            exit
+            function_foo:
+            mov64 r7, 8
+            call function_bar
+            exit
+            function_bar:
+            mov64 r1, 1{source}
+            exit"
+        );
+        let executable =
+            assemble::<TestContextObject>(&source, Arc::new(BuiltInProgram::new_loader(config)))
+                .unwrap();
+        let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+        executable.jit_compile().unwrap();
+
+        let mut backtraces = Vec::new();
+        for interpreted in [true, false] {
+            let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![],
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(interpreted);
+            assert_eq!(format!("{result:?}"), format!("Err({expected_result})"));
+            backtraces.push(vm.env.backtrace.take().unwrap());
+        }
+        assert_eq!(backtraces[0], backtraces[1]);
+        let backtrace = &backtraces[0];
+        assert_eq!(backtrace.registers[6], 7);
+        assert_eq!(backtrace.registers[7], 8);
+        assert_eq!(backtrace.registers[11], 7);
+        assert_eq!(backtrace.call_frames.len(), 2);
+        assert_eq!(
+            backtrace.to_string(),
+            "#0 BPF instruction #36 in function_bar at #35\n\
+             #1 BPF instruction #34 in function_foo at #32\n\
+             #2 BPF instruction #31 in entrypoint at #29\n"
+        );
+    }
+}
+
+#[test]
+fn test_backtrace_call_depth_exceeded() {
+    let config = Config {
+        enable_backtrace: true,
+        max_call_depth: 3,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        call function_foo
+        exit
+        function_foo:
+        call function_foo
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+    for interpreted in [true, false] {
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![],
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(interpreted);
+        assert_error!(result, "CallDepthExceeded(31, 3)");
+        let backtrace = vm.env.backtrace.take().unwrap();
+        let frames = backtrace
+            .frames
+            .iter()
+            .map(|frame| (frame.pc, frame.function_pc))
+            .collect::<Vec<_>>();
+        // Names are not kept without enable_symbol_and_section_labels
+        assert_eq!(frames, vec![(2, Some(2)), (3, Some(2)), (1, Some(0))]);
+        assert!(backtrace.frames[1].function_name.is_empty());
+    }
+}
+
+#[test]
+fn test_backtrace_failing_call() {
+    for (source, budget, expected_result) in [
+        (
+            "
+            call function_foo",
+            INSTRUCTION_METER_BUDGET,
+            "CallDepthExceeded(41, 3)",
+        ),
+        (
+            "
+            mov64 r8, 0
+            callx r8",
+            INSTRUCTION_METER_BUDGET,
+            "CallOutsideTextSegment(42, 0)",
+        ),
+        (
+            "
+            lddw r8, 0x100000030
+            callx r8",
+            INSTRUCTION_METER_BUDGET,
+            "UnsupportedInstruction(35)",
+        ),
+        (
+            "
+            call function_foo",
+            22,
+            "ExceededMaxInstructions(41)",
+        ),
+    ] {
+        for dynamic_stack_frames in [false, true] {
+            let config = Config {
+                enable_backtrace: true,
+                max_call_depth: 3,
+                dynamic_stack_frames,
+                ..Config::default()
+            };
+            let source = format!(
+                "
+                call function_foo
+                exit
+                function_foo:
+                mov64 r0, 1
+                mov64 r1, 2
+                mov64 r2, 3
+                mov64 r3, 4
+                mov64 r4, 5
+                mov64 r5, 6
+                mov64 r6, 7
+                mov64 r7, 8
+                mov64 r8, 9
+                mov64 r9, 10{source}
+                exit"
+            );
+            let executable = assemble::<TestContextObject>(
+                &source,
+                Arc::new(BuiltInProgram::new_loader(config)),
+            )
+            .unwrap();
+            let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+            executable.jit_compile().unwrap();
+            let mut backtraces = Vec::new();
+            for interpreted in [true, false] {
+                let mut context_object = TestContextObject::new(budget);
+                create_vm!(
+                    vm,
+                    &executable,
+                    &mut context_object,
+                    stack,
+                    heap,
+                    vec![],
+                    None
+                );
+                let (_instruction_count, result) = vm.execute_program(interpreted);
+                assert_eq!(format!("{result:?}"), format!("Err({expected_result})"));
+                backtraces.push(vm.env.backtrace.take().unwrap());
+            }
+            assert_eq!(backtraces[0], backtraces[1]);
+            // The registers are those of the caller, whichever engine ran the call
+            assert_eq!(backtraces[0].registers[0..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        }
+    }
+}
+
+#[test]
+fn test_no_backtrace() {
+    for enable_backtrace in [false, true] {
+        let config = Config {
+            enable_backtrace,
+            ..Config::default()
+        };
+        let executable = assemble::<TestContextObject>(
+            "
+            mov64 r0, 1
+            exit",
+            Arc::new(BuiltInProgram::new_loader(config)),
+        )
+        .unwrap();
+        let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+        executable.jit_compile().unwrap();
+        for (interpreted, budget) in [(true, 1), (false, 1), (true, 2), (false, 2)] {
+            let mut context_object = TestContextObject::new(budget);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![],
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(interpreted);
+            assert_eq!(result.is_err(), budget == 1);
+            assert_eq!(
+                vm.env.backtrace.is_some(),
+                enable_backtrace && result.is_err()
+            );
+        }
+    }
+}
+
+// Memory sanitizer
+
+#[test]
+fn test_memory_sanitizer() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        enable_memory_sanitizer: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        stxb [r10-7], r1
+        ldxh r0, [r10-8]
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(2),
+        ProgramResult::Err(Box::new(EbpfError::UninitializedRead(
+            30,
+            ebpf::MM_STACK_START + config.stack_size() as u64 - 8,
+            2,
+            "stack"
+        ))),
+    );
+    test_interpreter_and_jit_asm!(
+        "
+        stdw [r10-8], 0x1234
+        ldxdw r0, [r10-8]
+        ldxb r1, [r1]
+        exit",
+        config,
+        [0xff],
+        (),
+        TestContextObject::new(4),
+        ProgramResult::Ok(0x1234),
+    );
+}
+
+#[test]
+fn test_memory_sanitizer_syscall() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        enable_memory_sanitizer: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov r1, r10
+        add r1, -8
+        mov r2, 8
+        syscall bpf_mem_frob
+        ldxdw r0, [r10-8]
+        exit",
+        config,
+        [],
+        (
+            "bpf_mem_frob" => syscalls::bpf_mem_frob,
+        ),
+        TestContextObject::new(6),
+        ProgramResult::Ok(0x2a2a2a2a2a2a2a2a),
+    );
+}
+
+#[test]
+fn test_memory_sanitizer_disabled() {
+    test_interpreter_and_jit_asm!(
+        "
+        ldxdw r0, [r10-8]
+        exit",
+        [],
+        (),
+        TestContextObject::new(2),
+        ProgramResult::Ok(0),
+    );
+}
+
+// Heap allocator
+
+#[derive(Debug)]
+struct AllocatorContextObject {
+    meter: TestContextObject,
+    heap_allocator: HeapAllocator,
+}
+
+impl ContextObject for AllocatorContextObject {
+    fn trace(&mut self, state: [u64; 12]) {
+        self.meter.trace(state);
+    }
+
+    fn consume(&mut self, amount: u64) {
+        self.meter.consume(amount);
+    }
+
+    fn get_remaining(&self) -> u64 {
+        self.meter.get_remaining()
+    }
+}
+
+impl HeapAllocatorContextObject for AllocatorContextObject {
+    fn heap_allocator(&mut self) -> &mut HeapAllocator {
+        &mut self.heap_allocator
+    }
+}
+
+/// Runs the program in the interpreter and the JIT, returns the result and that of finish()
+fn run_with_allocator(source: &str, mode: AllocatorMode) -> (String, String) {
+    const HEAP_SIZE: usize = 1024;
+    let mut loader = BuiltInProgram::new_loader(Config::default());
+    register_allocator(&mut loader).unwrap();
+    let executable = assemble::<AllocatorContextObject>(source, Arc::new(loader)).unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+    let mut results = Vec::new();
+    for interpreted in [true, false] {
+        let mut context_object = AllocatorContextObject {
+            meter: TestContextObject::new(INSTRUCTION_METER_BUDGET),
+            heap_allocator: HeapAllocator::new(mode, HEAP_SIZE as u64),
+        };
+        let mut stack = AlignedMemory::zero_filled(executable.get_config().stack_size());
+        let mut heap = AlignedMemory::zero_filled(HEAP_SIZE);
+        let mut data = AlignedMemory::with_capacity(0);
+        let stack_len = stack.len();
+        let memory_mapping =
+            create_memory_mapping(&executable, &mut stack, &mut heap, &mut data, vec![], None)
+                .unwrap();
+        let mut vm = EbpfVm::new(&executable, &mut context_object, memory_mapping, stack_len);
+        let (_instruction_count, result) = vm.execute_program(interpreted);
+        let finished = vm
+            .env
+            .context_object_pointer
+            .heap_allocator
+            .finish(&vm.env.memory_mapping);
+        results.push((format!("{result:?}"), format!("{finished:?}")));
+    }
+    assert_eq!(results[0], results[1]);
+    results.pop().unwrap()
+}
+
+#[test]
+fn test_heap_allocator() {
+    let source = "
+        mov r1, 12
+        mov r2, 8
+        syscall bpf_alloc
+        mov r6, r0
+        stdw [r6], 0x1234
+        mov r1, r6
+        mov r2, 12
+        mov r3, 8
+        mov r4, 32
+        syscall bpf_realloc
+        mov r6, r0
+        ldxdw r7, [r6]
+        mov r1, r6
+        mov r2, 32
+        mov r3, 8
+        syscall bpf_free
+        mov r0, r6
+        lsh r0, 16
+        or r0, r7
+        exit";
+    // Without red zones the reallocation starts right after the 12 bytes rounded up to 16
+    assert_eq!(
+        run_with_allocator(source, AllocatorMode::Bump),
+        (
+            format!("Ok({})", ((ebpf::MM_HEAP_START + 16) << 16) | 0x1234),
+            "Ok(())".to_string(),
+        )
+    );
+    // The first allocation occupies 16 + 16 (12 rounded up) + 16 bytes
+    assert_eq!(
+        run_with_allocator(source, AllocatorMode::Debug),
+        (
+            format!("Ok({})", ((ebpf::MM_HEAP_START + 64) << 16) | 0x1234),
+            "Ok(())".to_string(),
+        )
+    );
+}
+
+#[test]
+fn test_heap_allocator_errors() {
+    // Double free
+    let (result, _finished) = run_with_allocator(
+        "
+        mov r1, 8
+        mov r2, 8
+        syscall bpf_alloc
+        mov r6, r0
+        mov r1, r6
+        mov r2, 8
+        syscall bpf_free
+        mov r1, r6
+        mov r2, 8
+        syscall bpf_free
+        exit",
+        AllocatorMode::Debug,
+    );
+    assert_eq!(
+        result,
+        format!(
+            "Err({:?})",
+            AllocatorError::DoubleFree(ebpf::MM_HEAP_START + 16)
+        )
+    );
+
+    // Out-of-bounds write detected by free
+    let (result, _finished) = run_with_allocator(
+        "
+        mov r1, 8
+        mov r2, 8
+        syscall bpf_alloc
+        stb [r0+8], 1
+        mov r1, r0
+        mov r2, 8
+        syscall bpf_free
+        exit",
+        AllocatorMode::Debug,
+    );
+    assert_eq!(
+        result,
+        format!(
+            "Err({:?})",
+            AllocatorError::OutOfBoundsWrite(ebpf::MM_HEAP_START + 16, 8)
+        )
+    );
+
+    // Free with the wrong size
+    let (result, _finished) = run_with_allocator(
+        "
+        mov r1, 8
+        mov r2, 8
+        syscall bpf_alloc
+        mov r1, r0
+        mov r2, 16
+        syscall bpf_free
+        exit",
+        AllocatorMode::Debug,
+    );
+    assert_eq!(
+        result,
+        format!(
+            "Err({:?})",
+            AllocatorError::InvalidFree(ebpf::MM_HEAP_START + 16, 16)
+        )
+    );
+
+    // Realloc of a range which was not allocated, or which overlaps the new allocation
+    for (source, expected) in [
+        (
+            "
+            lddw r1, 0x300000000
+            mov r2, 8
+            mov r3, 8
+            mov r4, 16
+            syscall bpf_realloc
+            exit",
+            AllocatorError::InvalidRealloc(ebpf::MM_HEAP_START, 8),
+        ),
+        (
+            "
+            mov r1, 8
+            mov r2, 8
+            syscall bpf_alloc
+            mov r1, r0
+            mov r2, 64
+            mov r3, 8
+            mov r4, 64
+            syscall bpf_realloc
+            exit",
+            AllocatorError::InvalidRealloc(ebpf::MM_HEAP_START, 64),
+        ),
+    ] {
+        let (result, _finished) = run_with_allocator(source, AllocatorMode::Bump);
+        assert_eq!(result, format!("Err({expected:?})"));
+    }
+
+    // Leak and out-of-bounds write reported by finish
+    for (source, expected) in [
+        (
+            "
+            mov r1, 5
+            mov r2, 1
+            syscall bpf_alloc
+            mov r1, 3
+            mov r2, 1
+            syscall bpf_alloc
+            exit",
+            format!("Err({:?})", AllocatorError::MemoryLeak(2, 8)),
+        ),
+        (
+            "
+            mov r1, 5
+            mov r2, 1
+            syscall bpf_alloc
+            stb [r0-1], 1
+            exit",
+            format!(
+                "Err({:?})",
+                AllocatorError::OutOfBoundsWrite(ebpf::MM_HEAP_START + 16, 5)
+            ),
+        ),
+    ] {
+        let (result, finished) = run_with_allocator(source, AllocatorMode::Debug);
+        assert!(result.starts_with("Ok("));
+        assert_eq!(finished, expected);
+    }
+
+    // Exhausted heap and invalid alignment
+    let (result, _finished) = run_with_allocator(
+        "
+        mov r1, 1024
+        mov r2, 8
+        syscall bpf_alloc
+        exit",
+        AllocatorMode::Debug,
+    );
+    assert_eq!(result, "Ok(0)");
+    let (result, _finished) = run_with_allocator(
+        "
+        mov r1, 8
+        mov r2, 3
+        syscall bpf_alloc
+        exit",
+        AllocatorMode::Bump,
+    );
+    assert_eq!(
+        result,
+        format!("Err({:?})", AllocatorError::InvalidAlignment(3))
+    );
+}
+
+// Record and replay
+
+fn replay_loader(config: Config, syscalls: bool) -> Arc<BuiltInProgram<TestContextObject>> {
+    let mut loader = BuiltInProgram::new_loader(config);
+    if syscalls {
+        loader
+            .register_function(b"bpf_mem_frob", syscalls::bpf_mem_frob)
+            .unwrap();
+        loader
+            .register_function(b"bpf_gather_bytes", syscalls::bpf_gather_bytes)
+            .unwrap();
+    } else {
+        // Replay must not call the syscalls, only their keys are needed
+        for name in [&b"bpf_mem_frob"[..], &b"bpf_gather_bytes"[..]] {
+            loader
+                .register_function(name, |_, _, _, _, _, _, _, _| unreachable!())
+                .unwrap();
+        }
+    }
+    Arc::new(loader)
+}
+
+fn record(
+    source: &str,
+    config: Config,
+    budget: u64,
+    interpreted: bool,
+) -> (Vec<u8>, TestContextObject, u64, String) {
+    let executable = assemble::<TestContextObject>(source, replay_loader(config, true)).unwrap();
+    #[allow(unused_mut)]
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    if !interpreted {
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+    }
+    let mut context_object = TestContextObject::new(budget);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    let mut writer = TraceWriter::new(Vec::new()).unwrap();
+    let (instruction_count, result) = vm.execute_program_recorded(interpreted, &mut writer);
+    (
+        writer.into_inner().unwrap(),
+        context_object,
+        instruction_count,
+        format!("{result:?}"),
+    )
+}
+
+fn replay(
+    source: &str,
+    config: Config,
+    budget: u64,
+    trace: &[u8],
+) -> (TestContextObject, u64, String) {
+    let executable = assemble::<TestContextObject>(source, replay_loader(config, false)).unwrap();
+    let executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    let mut context_object = TestContextObject::new(budget);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    let mut reader = TraceReader::new(trace).unwrap();
+    let (instruction_count, result) = vm.replay_program(&mut reader);
+    (context_object, instruction_count, format!("{result:?}"))
+}
+
+#[test]
+fn test_record_and_replay() {
+    let source = "
+        stb [r10-4], 0x01
+        stb [r10-3], 0x02
+        stb [r10-2], 0x03
+        stb [r10-1], 0x04
+        mov r1, r10
+        mov r2, 0x4
+        sub r1, r2
+        syscall bpf_mem_frob
+        mov r1, 0
+        ldxb r2, [r10-4]
+        ldxb r3, [r10-3]
+        ldxb r4, [r10-2]
+        ldxb r5, [r10-1]
+        syscall bpf_gather_bytes
+        xor r0, 0x2a2a2a2a
+        exit";
+    let config = Config {
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    for (budget, expected_result) in [
+        (INSTRUCTION_METER_BUDGET, "Ok(16909060)"),
+        (10, "Err(ExceededMaxInstructions(39))"),
+    ] {
+        let (trace, recorded_context_object, recorded_instruction_count, recorded_result) =
+            record(source, config, budget, true);
+        assert_eq!(recorded_result, expected_result);
+        let (replayed_context_object, replayed_instruction_count, replayed_result) =
+            replay(source, config, budget, &trace);
+        assert_eq!(replayed_result, recorded_result);
+        assert_eq!(replayed_instruction_count, recorded_instruction_count);
+        assert_eq!(
+            replayed_context_object.trace_log,
+            recorded_context_object.trace_log
+        );
+
+        // The trace file alone is enough to disassemble the execution
+        let trace_log = TraceReader::new(&trace[..]).unwrap().trace_log().unwrap();
+        assert_eq!(trace_log, recorded_context_object.trace_log);
+        let executable =
+            assemble::<TestContextObject>(source, replay_loader(config, false)).unwrap();
+        let analysis = Analysis::from_executable(&executable).unwrap();
+        let mut disassembly = Vec::new();
+        analysis
+            .disassemble_trace_log(&mut disassembly, &trace_log)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(disassembly).unwrap().lines().count(),
+            trace_log.len()
+        );
+
+        // The JIT records the same trace
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        {
+            let (jit_trace, _context_object, jit_instruction_count, jit_result) =
+                record(source, config, budget, false);
+            assert_eq!(jit_result, recorded_result);
+            assert_eq!(jit_instruction_count, recorded_instruction_count);
+            assert_eq!(jit_trace, trace);
+        }
+    }
+
+    // The JIT can only record if it traces instructions
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    {
+        let (_trace, _context_object, _instruction_count, result) =
+            record(source, Config::default(), INSTRUCTION_METER_BUDGET, false);
+        assert_eq!(result, "Err(UnsupportedRecording)");
+    }
+}
+
+#[test]
+fn test_replay_syscall_error() {
+    let source = "
+        mov r1, 0
+        mov r2, 8
+        syscall bpf_mem_frob
+        exit";
+    let (trace, _context_object, _instruction_count, recorded_result) =
+        record(source, Config::default(), INSTRUCTION_METER_BUDGET, true);
+    assert_eq!(
+        recorded_result,
+        "Err(AccessViolation(0, Store, 0, 8, \"unknown\"))"
+    );
+    let (_context_object, _instruction_count, replayed_result) =
+        replay(source, Config::default(), INSTRUCTION_METER_BUDGET, &trace);
+    assert_eq!(
+        replayed_result,
+        "Err(ReplayedSyscallError(\"Access violation in unknown section at address 0x0 of size 8 at BPF instruction #0\"))"
+    );
+}
+
+#[test]
+fn test_replay_divergence() {
+    let source = "
+        mov r0, 1
+        add r0, 2
+        exit";
+    let config = Config::default();
+    let (trace, _context_object, _instruction_count, recorded_result) =
+        record(source, config, INSTRUCTION_METER_BUDGET, true);
+    assert_eq!(recorded_result, "Ok(3)");
+
+    // A different program
+    let (_context_object, _instruction_count, replayed_result) = replay(
+        "
+        mov r0, 1
+        add r0, 3
+        exit",
+        config,
+        INSTRUCTION_METER_BUDGET,
+        &trace,
+    );
+    assert_eq!(replayed_result, "Err(TraceDivergence(2))");
+
+    // A budget which runs out before the recording ended
+    let (_context_object, _instruction_count, replayed_result) = replay(source, config, 2, &trace);
+    assert_eq!(replayed_result, "Err(TraceDivergence(2))");
+
+    // The trace ends early or is damaged
+    let (_context_object, _instruction_count, replayed_result) = replay(
+        source,
+        config,
+        INSTRUCTION_METER_BUDGET,
+        &trace[0..trace.len() - 10],
+    );
+    assert_eq!(replayed_result, "Err(TraceDivergence(2))");
+    let (_context_object, _instruction_count, replayed_result) = replay(
+        source,
+        config,
+        INSTRUCTION_METER_BUDGET,
+        &trace[0..trace.len() - 1],
+    );
+    assert_eq!(replayed_result, "Err(InvalidTrace)");
+    assert_error!(TraceReader::new(&trace[1..]), "InvalidTrace");
+}
+
+// Coverage
+
+#[test]
+fn test_coverage() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        enable_symbol_and_section_labels: true,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxb r2, [r1]
+        jeq r2, 0, skip
+        call function_foo
+        skip:
+        exit
+        function_foo:
+        mov r0, 1
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let line_table = disassembly_line_table(&analysis, "program.s");
+
+    // Both runs together take every branch
+    let mut coverage = Coverage::default();
+    for (interpreted, input) in [(true, 0u8), (false, 1u8)] {
+        let mut mem = [input];
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+            None
+        );
+        vm.execute_program(interpreted).1.unwrap();
+        let mut run_coverage = Coverage::default();
+        run_coverage.add_trace_log(&analysis, &vm.env.context_object_pointer.trace_log);
+        assert_eq!(
+            run_coverage.branches[&1]
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![if input == 0 { 3 } else { 2 }]
+        );
+        coverage.merge(&run_coverage);
+    }
+    assert_eq!(
+        coverage.instructions.iter().collect::<Vec<_>>(),
+        vec![(&0, &2), (&1, &2), (&2, &1), (&3, &2), (&4, &1), (&5, &1)]
+    );
+    assert_eq!(
+        coverage.branches[&1].iter().collect::<Vec<_>>(),
+        vec![(&2, &1), (&3, &1)]
+    );
+
+    let mut lcov = Vec::new();
+    coverage
+        .write_lcov(&mut lcov, &analysis, &line_table)
+        .unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert_eq!(
+        lcov,
+        "TN:
+SF:program.s
+FN:2,entrypoint
+FN:9,function_foo
+FNDA:2,entrypoint
+FNDA:1,function_foo
+FNF:2
+FNH:2
+BRDA:3,1,0,1
+BRDA:3,1,1,1
+BRF:2
+BRH:2
+DA:2,2
+DA:3,2
+DA:4,1
+DA:6,2
+DA:9,1
+DA:10,1
+LF:6
+LH:6
+end_of_record
+"
+    );
+
+    let mut cobertura = Vec::new();
+    Coverage::default()
+        .write_cobertura(&mut cobertura, &analysis, &line_table)
+        .unwrap();
+    let cobertura = String::from_utf8(cobertura).unwrap();
+    assert!(cobertura.contains(
+        "lines-covered=\"0\" lines-valid=\"6\" branches-covered=\"0\" branches-valid=\"2\""
+    ));
+    assert!(cobertura.contains("<method name=\"function_foo\""));
+    assert!(cobertura.contains(
+        "<line number=\"3\" hits=\"0\" branch=\"true\" condition-coverage=\"0% (0/2)\"/>"
+    ));
+}
+
+// Profiler
+
+#[test]
+fn test_profile() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        enable_symbol_and_section_labels: true,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 2
+        call function_foo
+        call function_bar
+        exit
+        function_foo:
+        jeq r1, 0, done
+        sub64 r1, 1
+        call function_foo
+        done:
+        exit
+        function_bar:
+        mov64 r0, 0
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+
+    let mut profiles = Vec::new();
+    for interpreted in [true, false] {
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![],
+            None
+        );
+        vm.execute_program(interpreted).1.unwrap();
+        let mut profile = Profile::default();
+        profile.add_trace_log(&analysis, &vm.env.context_object_pointer.trace_log);
+        profiles.push(profile);
+    }
+    assert_eq!(profiles[0], profiles[1]);
+    let profile = &profiles[0];
+    let counters = profile
+        .functions
+        .iter()
+        .map(|(pc, function)| {
+            (
+                *pc,
+                function.name.as_str(),
+                function.exclusive,
+                function.inclusive,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counters,
+        vec![
+            (0, "entrypoint", 4, 16),
+            (4, "function_foo", 10, 10),
+            (8, "function_bar", 2, 2),
+        ]
+    );
+    assert_eq!(
+        profile.calls.iter().collect::<Vec<_>>(),
+        vec![
+            (
+                &(0, 1, 4),
+                &CallProfile {
+                    count: 1,
+                    inclusive: 10
+                }
+            ),
+            (
+                &(0, 2, 8),
+                &CallProfile {
+                    count: 1,
+                    inclusive: 2
+                }
+            ),
+            (
+                &(4, 6, 4),
+                &CallProfile {
+                    count: 2,
+                    inclusive: 8
+                }
+            ),
+        ]
+    );
+
+    let mut folded = Vec::new();
+    profile.write_folded(&mut folded).unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "entrypoint 4
+entrypoint;function_foo 4
+entrypoint;function_foo;function_foo 4
+entrypoint;function_foo;function_foo;function_foo 2
+entrypoint;function_bar 2
+"
+    );
+    let mut callgrind = Vec::new();
+    profile.write_callgrind(&mut callgrind).unwrap();
+    let callgrind = String::from_utf8(callgrind).unwrap();
+    assert!(callgrind.contains("summary: 16\n"));
+    assert!(callgrind.contains("fn=(1) entrypoint\n0 1\n1 1\n2 1\n3 1\ncfn=(2) function_foo\ncalls=1 4\n1 10\ncfn=(3) function_bar\ncalls=1 8\n2 2\n"));
+    assert!(callgrind.contains("fn=(2)\n4 3\n5 2\n6 2\n7 3\ncfn=(2)\ncalls=2 4\n6 8\n"));
+
+    // Merging counts both runs, frames of an aborted run end with the trace
+    let mut merged = profiles[0].clone();
+    merged.merge(&profiles[1]);
+    assert_eq!(merged.functions[&4].inclusive, 20);
+    let mut aborted = Profile::default();
+    let trace_log = [0, 1, 4].map(|pc| {
+        let mut entry = [0u64; 12];
+        entry[11] = pc;
+        entry
+    });
+    aborted.add_trace_log(&analysis, &trace_log);
+    assert_eq!(aborted.functions[&4].inclusive, 1);
+    assert_eq!(aborted.functions[&0].inclusive, 3);
+    assert_eq!(aborted.calls[&(0, 1, 4)].count, 1);
+}
+
+// Control flow graph
+
+#[test]
+fn test_cfg_fall_through() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0
+        add64 r0, 1
+        jlt r0, 3, -2
+        call function_foo
+        exit
+        function_foo:
+        call function_bar
+        mov64 r0, 1
+        function_bar:
+        mov64 r0, 2
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    // A function entry falls through into the next basic block of the same function
+    assert_eq!(analysis.cfg_nodes[&0].destinations, vec![1]);
+    assert_eq!(analysis.cfg_nodes[&5].destinations, vec![6]);
+    // But the last basic block of a function never falls through into the next function
+    assert!(analysis.cfg_nodes[&6].destinations.is_empty());
+    assert!(!analysis.cfg_nodes[&7].sources.contains(&6));
+}
+
+// Cost analysis
+
+#[test]
+fn test_cost_analysis() {
+    // Without loops the bound is the cost of the most expensive path, including callees
+    let config = Config {
+        instruction_costs: InstructionCosts {
+            mul: 5,
+            ..InstructionCosts::default()
+        },
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0
+        jeq r1, 0, +2
+        call function_foo
+        mul64 r0, 3
+        exit
+        function_foo:
+        mov64 r0, 1
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let cost_analysis = CostAnalysis::new(&analysis);
+    assert_eq!(cost_analysis.functions[&5], Some(CostBound::constant(2)));
+    let bound = cost_analysis.entrypoint(&analysis).unwrap();
+    assert_eq!(bound.as_constant(), Some(11));
+    let mut context_object = TestContextObject::new(100);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    vm.execute_program(true).1.unwrap();
+    assert_eq!(100 - context_object.remaining, 11);
+
+    // Loops multiply the cost of their body with their iteration count
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 0
+        mov64 r2, 0
+        add64 r2, 1
+        jlt r2, 3, -2
+        add64 r1, 1
+        jlt r1, 4, -5
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    let executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let cost_analysis = CostAnalysis::new(&analysis);
+    let bound = cost_analysis.entrypoint(&analysis).unwrap();
+    assert_eq!(bound.to_string(), "2 + 3*n1 + 2*n1*n2");
+    assert_eq!(bound.as_constant(), None);
+    assert_eq!(bound.loops().into_iter().collect::<Vec<_>>(), vec![1, 2]);
+    let expected_instruction_count = bound.evaluate(|header| if header == 1 { 4 } else { 3 });
+    assert_eq!(expected_instruction_count, 38);
+    let mut context_object = TestContextObject::new(100);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    assert_eq!(vm.execute_program(true).0, expected_instruction_count);
+}
+
+#[test]
+fn test_cost_analysis_unbounded() {
+    for source in [
+        "
+        call function_foo
+        exit
+        function_foo:
+        jeq r1, 0, +1
+        call function_foo
+        exit",
+        "
+        callx r1
+        exit",
+    ] {
+        let executable = assemble::<TestContextObject>(
+            source,
+            Arc::new(BuiltInProgram::new_loader(Config::default())),
+        )
+        .unwrap();
+        let analysis = Analysis::from_executable(&executable).unwrap();
+        let cost_analysis = CostAnalysis::new(&analysis);
+        assert_eq!(cost_analysis.entrypoint(&analysis), None);
+        assert_eq!(cost_analysis.functions[&0], None);
+    }
+}
+
+// Stack analysis
+
+#[test]
+fn test_stack_analysis() {
+    let source = "
+        sub r11, 64
+        call function_foo
+        add r11, 64
+        exit
+        function_foo:
+        sub r11, 32
+        call function_bar
+        add r11, 32
+        exit
+        function_bar:
+        sub r11, 16
+        add r11, 16
+        exit";
+    let executable = assemble::<TestContextObject>(
+        source,
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let stack_analysis = StackAnalysis::new(&analysis, &Config::default());
+    assert_eq!(
+        stack_analysis.entrypoint(&analysis),
+        Some(&StackUsage {
+            bytes: 112,
+            frames: 3,
+            largest_call_chain: vec![0, 4, 8],
+            deepest_call_chain: vec![0, 4, 8],
+        })
+    );
+    assert_eq!(stack_analysis.functions[&8].as_ref().unwrap().bytes, 16);
+    assert!(stack_analysis.problems.is_empty());
+
+    // The predicted problems happen at runtime
+    let config = Config {
+        max_call_depth: 2,
+        stack_frame_size: 32,
+        ..Config::default()
+    };
+    let stack_analysis = StackAnalysis::new(&analysis, &config);
+    assert_eq!(
+        stack_analysis.problems.into_iter().collect::<Vec<_>>(),
+        vec![
+            StackProblem::CallDepthExceeded(vec![0, 4, 8]),
+            StackProblem::StackOverflow(vec![0, 4, 8], 112),
+        ]
+    );
+    let executable =
+        assemble::<TestContextObject>(source, Arc::new(BuiltInProgram::new_loader(config)))
+            .unwrap();
+    let mut context_object = TestContextObject::new(100);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    assert_eq!(
+        format!("{:?}", vm.execute_program(true).1),
+        format!(
+            "{:?}",
+            ProgramResult::Err(Box::new(EbpfError::CallDepthExceeded(34, 2)))
+        )
+    );
+
+    // Fixed frames use a whole frame per call
+    let config = Config {
+        dynamic_stack_frames: false,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        call function_foo
+        exit
+        function_foo:
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let stack_analysis = StackAnalysis::new(&analysis, &config);
+    let usage = stack_analysis.entrypoint(&analysis).unwrap();
+    assert_eq!(usage.bytes, 2 * config.stack_frame_size as u64);
+    assert_eq!(usage.frames, 2);
+}
+
+#[test]
+fn test_stack_analysis_unbounded() {
+    let executable = assemble::<TestContextObject>(
+        "
+        call function_foo
+        exit
+        function_foo:
+        call function_bar
+        exit
+        function_bar:
+        jeq r1, 0, +1
+        call function_foo
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let stack_analysis = StackAnalysis::new(&analysis, &Config::default());
+    assert_eq!(stack_analysis.entrypoint(&analysis), None);
+    assert_eq!(
+        stack_analysis.problems.into_iter().collect::<Vec<_>>(),
+        vec![StackProblem::Recursion(vec![2, 4])]
+    );
+
+    let executable = assemble::<TestContextObject>(
+        "
+        sub r11, 8
+        jne r1, 0, -2
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let stack_analysis = StackAnalysis::new(&analysis, &Config::default());
+    assert_eq!(stack_analysis.entrypoint(&analysis), None);
+    assert_eq!(
+        stack_analysis.problems.into_iter().collect::<Vec<_>>(),
+        vec![StackProblem::UnboundedStackPointer(0)]
+    );
+}
+
+// JIT cache
+
+#[test]
+fn test_jit_cache() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        instruction_costs: InstructionCosts {
+            memory: 2,
+            call: 3,
+            ..InstructionCosts::default()
+        },
+        ..Config::default()
+    };
+    let load = |path: &str, config: Config, syscalls: &[&'static [u8]]| {
+        let mut file = File::open(path).unwrap();
+        let mut elf = Vec::new();
+        file.read_to_end(&mut elf).unwrap();
+        let mut loader = BuiltInProgram::new_loader(config);
+        for name in syscalls {
+            loader
+                .register_function(name, syscalls::bpf_syscall_string)
+                .unwrap();
+        }
+        Executable::<TautologyVerifier, TestContextObject>::from_elf(&elf, Arc::new(loader))
+            .unwrap()
+    };
+    let run = |executable: &Executable<TautologyVerifier, TestContextObject>| {
+        let mut mem = [1u8];
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            vm,
+            executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+            None
+        );
+        let (instruction_count, result) = vm.execute_program(false);
+        (instruction_count, result.unwrap(), context_object.trace_log)
+    };
+    let mut executable = load("tests/elfs/relative_call.so", config, &[b"log"]);
+    executable.jit_compile().unwrap();
+    let key = b"secret of the embedder";
+    let cache = executable.serialize_compiled_program(key).unwrap();
+
+    // Behaves exactly like the compiled program and serializes to the same bytes again
+    let mut cached_executable = load("tests/elfs/relative_call.so", config, &[b"log"]);
+    cached_executable
+        .load_compiled_program(&cache, key)
+        .unwrap();
+    assert_eq!(
+        cached_executable.serialize_compiled_program(key).unwrap(),
+        cache
+    );
+    let expected = run(&executable);
+    assert_eq!(expected.1, 2);
+    assert_eq!(run(&cached_executable), expected);
+
+    // Tampered with, truncated or authenticated with another key
+    let mut tampered = cache.clone();
+    let index = tampered.len() / 2;
+    tampered[index] ^= 0xcc;
+    assert_error!(
+        cached_executable.load_compiled_program(&tampered, key),
+        "InvalidJitCache"
+    );
+    assert_error!(
+        cached_executable.load_compiled_program(&cache[0..cache.len() - 1], key),
+        "InvalidJitCache"
+    );
+    assert_error!(
+        cached_executable.load_compiled_program(&cache, b"guessed key"),
+        "InvalidJitCache"
+    );
+    let forged = executable
+        .serialize_compiled_program(b"guessed key")
+        .unwrap();
+    assert_error!(
+        cached_executable.load_compiled_program(&forged, key),
+        "InvalidJitCache"
+    );
+
+    // Stale because of a different Config, different syscalls or different ELF bytes
+    let other_config = Config {
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    let mut stale_executable = load("tests/elfs/relative_call.so", other_config, &[b"log"]);
+    assert_error!(
+        stale_executable.load_compiled_program(&cache, key),
+        "InvalidJitCache"
+    );
+    let mut stale_executable = load("tests/elfs/relative_call.so", config, &[b"log", b"other"]);
+    assert_error!(
+        stale_executable.load_compiled_program(&cache, key),
+        "InvalidJitCache"
+    );
+    let mut stale_executable = load("tests/elfs/syscall_static.so", config, &[b"log"]);
+    assert_error!(
+        stale_executable.load_compiled_program(&cache, key),
+        "InvalidJitCache"
+    );
+
+    // Falls back to compiling
+    assert!(!stale_executable
+        .jit_compile_cached(Some(&cache), key)
+        .unwrap());
+    assert!(stale_executable.get_compiled_program().is_some());
+    assert!(!cached_executable.jit_compile_cached(None, key).unwrap());
+    assert!(cached_executable
+        .jit_compile_cached(Some(&cache), key)
+        .unwrap());
+    assert_eq!(run(&cached_executable), expected);
+}
+
+// Lazy JIT compilation
+
+#[test]
+fn test_lazy_jit_compilation() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x38
+        callx r8
+        call function_bar
+        exit
+        function_foo:
+        add64 r0, 0x2A
+        exit
+        function_bar:
+        add64 r0, 0x1
+        exit
+        function_unused:
+        mov64 r0, 0x0
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    let run = |executable: &Executable<RequisiteVerifier, TestContextObject>| {
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            vm,
+            executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![],
+            None
+        );
+        let (instruction_count, result) = vm.execute_program(false);
+        (instruction_count, result.unwrap())
+    };
+    executable.jit_compile().unwrap();
+    let eager_machine_code_length = executable
+        .get_compiled_program()
+        .unwrap()
+        .machine_code_length();
+    executable.jit_compile_lazily().unwrap();
+    let compiled_program = executable.get_compiled_program().unwrap();
+    assert!((0..13).all(|pc| !compiled_program.is_compiled(pc)));
+    assert!(executable.serialize_compiled_program(b"key").is_none());
+
+    // Concurrent invocations wait for the unit another one is compiling
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| assert_eq!(run(&executable), (11, 43)));
+        }
+    });
+    assert_eq!(run(&executable), (11, 43));
+
+    // Only the called functions are compiled
+    let compiled_program = executable.get_compiled_program().unwrap();
+    for pc in [0, 6, 7, 8, 9, 10] {
+        assert!(compiled_program.is_compiled(pc));
+    }
+    for pc in [11, 12] {
+        assert!(!compiled_program.is_compiled(pc));
+    }
+    assert!(compiled_program.machine_code_length() < eager_machine_code_length);
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let mut listing = Vec::new();
+    compiled_program
+        .disassemble(&analysis, &mut listing)
+        .unwrap();
+    let listing = String::from_utf8(listing).unwrap();
+    assert!(listing.contains("lazy_compile:"));
+}
+
+#[test]
+fn test_lazy_jit_compilation_suspend_and_resume() {
+    let config = Config {
+        enable_suspension: true,
+        ..Config::default()
+    };
+    let load = || {
+        let executable = assemble::<TestContextObject>(
+            "
+            mov64 r0, 0
+            mov64 r6, 0
+            mov64 r1, r6
+            call function_foo
+            add64 r6, 1
+            jlt r6, 10, -4
+            exit
+            function_foo:
+            stxdw [r10-8], r1
+            mov64 r2, r1
+            call function_bar
+            ldxdw r1, [r10-8]
+            add64 r0, r1
+            exit
+            function_bar:
+            mul64 r2, r2
+            add64 r0, r2
+            exit",
+            Arc::new(BuiltInProgram::new_loader(config)),
+        )
+        .unwrap();
+        let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+        executable.jit_compile_lazily().unwrap();
+        executable
+    };
+    let (expected_instruction_count, expected_result, _suspensions) =
+        execute_in_slices(&load(), INSTRUCTION_METER_BUDGET, |_| true);
+    for budget in 2..12 {
+        // Resumes in call frames of functions which the interpreter ran before
+        for interpreted in [|_| false, |suspensions| suspensions % 2 == 0] {
+            let (instruction_count, result, suspensions) =
+                execute_in_slices(&load(), budget, interpreted);
+            assert_eq!(format!("{result:?}"), format!("{expected_result:?}"));
+            assert_eq!(instruction_count, expected_instruction_count);
+            assert!(suspensions > 0);
+        }
+    }
+}
+
+// Tiered execution
+
+fn tiered_executable(config: Config) -> Executable<RequisiteVerifier, TestContextObject> {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0
+        mov64 r6, 0
+        mov64 r1, r6
+        call function_foo
+        add64 r6, 1
+        jlt r6, 10, -4
+        exit
+        function_foo:
+        stxdw [r10-8], r1
+        mov64 r2, r1
+        call function_bar
+        ldxdw r1, [r10-8]
+        add64 r0, r1
+        exit
+        function_bar:
+        mul64 r2, r2
+        add64 r0, r2
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile_lazily().unwrap();
+    executable
+}
+
+#[test]
+fn test_tiered_execution() {
+    for config in [
+        Config {
+            enable_instruction_tracing: true,
+            ..Config::default()
+        },
+        weighted_config(),
+    ] {
+        for budget in [INSTRUCTION_METER_BUDGET, 60] {
+            let executable = tiered_executable(config);
+            let mut expected_context_object = TestContextObject::new(budget);
+            create_vm!(
+                vm,
+                &executable,
+                &mut expected_context_object,
+                stack,
+                heap,
+                vec![],
+                None
+            );
+            let (expected_instruction_count, expected_result) = vm.execute_program(true);
+            let expected_result = format!("{expected_result:?}");
+            if budget == INSTRUCTION_METER_BUDGET {
+                assert_eq!(expected_result, "Ok(330)");
+            } else {
+                assert!(expected_result.contains("ExceededMaxInstructions"));
+            }
+            // Hands over at every call and back-edge, in and across call frames
+            for tiering_threshold in 0..25 {
+                let executable = tiered_executable(Config {
+                    tiering_threshold,
+                    ..config
+                });
+                let mut context_object = TestContextObject::new(budget);
+                create_vm!(
+                    vm,
+                    &executable,
+                    &mut context_object,
+                    stack,
+                    heap,
+                    vec![],
+                    None
+                );
+                let (instruction_count, result) = vm.execute_program_tiered();
+                assert_eq!(format!("{result:?}"), expected_result);
+                assert_eq!(instruction_count, expected_instruction_count);
+                assert_eq!(context_object.remaining, expected_context_object.remaining);
+                assert!(TestContextObject::compare_trace_log(
+                    &expected_context_object,
+                    &context_object,
+                ));
+                if budget == INSTRUCTION_METER_BUDGET {
+                    // Each function is counted ten times
+                    let compiled_program = executable.get_compiled_program().unwrap();
+                    assert_eq!(compiled_program.is_compiled(0), tiering_threshold <= 10);
+                    // Once the JIT took over, it also runs function_bar
+                    assert_eq!(compiled_program.is_compiled(13), tiering_threshold <= 10);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_tiered_execution_of_hot_program() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0x2A
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config {
+            tiering_threshold: 3,
+            ..Config::default()
+        })),
+    )
+    .unwrap();
+    let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+    let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![],
+        None
+    );
+    assert_error!(vm.execute_program_tiered().1, "JitNotCompiled");
+    executable.jit_compile_lazily().unwrap();
+    // Invocations count across VMs, the third one promotes the entrypoint
+    for invocation in 1..=4 {
+        let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![],
+            None
+        );
+        let (instruction_count, result) = vm.execute_program_tiered();
+        assert_eq!((instruction_count, result.unwrap()), (2, 0x2A));
+        let compiled_program = executable.get_compiled_program().unwrap();
+        assert_eq!(compiled_program.is_compiled(0), invocation >= 3);
+    }
+}
+
+// JIT optimizations
+
+#[test]
+fn test_jit_optimizations_chaos() {
+    let opcodes = [
+        ebpf::MOV64_IMM,
+        ebpf::MOV64_IMM,
+        ebpf::MOV32_IMM,
+        ebpf::MOV64_REG,
+        ebpf::MOV32_REG,
+        ebpf::ADD64_REG,
+        ebpf::ADD32_IMM,
+        ebpf::SUB64_REG,
+        ebpf::MUL64_REG,
+        ebpf::DIV64_REG,
+        ebpf::SDIV32_REG,
+        ebpf::MOD32_REG,
+        ebpf::LSH32_REG,
+        ebpf::RSH64_REG,
+        ebpf::ARSH32_REG,
+        ebpf::AND64_REG,
+        ebpf::OR32_REG,
+        ebpf::XOR64_IMM,
+        ebpf::NEG64,
+        ebpf::LE,
+        ebpf::BE,
+        ebpf::LD_B_REG,
+        ebpf::LD_H_REG,
+        ebpf::LD_W_REG,
+        ebpf::LD_DW_REG,
+        ebpf::LD_DW_REG,
+        ebpf::ST_B_IMM,
+        ebpf::ST_W_IMM,
+        ebpf::ST_H_REG,
+        ebpf::ST_DW_REG,
+        ebpf::ST_DW_REG,
+        ebpf::JA,
+        ebpf::JEQ_REG,
+        ebpf::JGT_IMM,
+        ebpf::JSET_REG,
+        ebpf::JSLT_REG,
+        ebpf::JNE_IMM,
+    ];
+    let instruction_count = 16;
+    let mut prng = SmallRng::seed_from_u64(0x9713_1230_FF5E);
+    for config in [
+        Config {
+            enable_instruction_tracing: true,
+            ..Config::default()
+        },
+        Config {
+            enable_suspension: true,
+            enable_backtrace: true,
+            instruction_meter_checkpoint_distance: 3,
+            ..Config::default()
+        },
+        Config {
+            enable_backtrace: true,
+            sanitize_user_provided_values: false,
+            ..Config::default()
+        },
+    ] {
+        let mut program = vec![0; instruction_count * ebpf::INSN_SIZE];
+        for _ in 0..2000 {
+            for insn in program.chunks_exact_mut(ebpf::INSN_SIZE) {
+                let opc = opcodes[prng.next_u32() as usize % opcodes.len()];
+                let (mut dst, mut src) = (prng.next_u32() % 10, prng.next_u32() % 10);
+                // Most memory accesses go to the stack or the input
+                let base = [1, 10, 10][prng.next_u32() as usize % 3];
+                let off = match opc & ebpf::BPF_CLS_MASK {
+                    ebpf::BPF_LDX | ebpf::BPF_ST | ebpf::BPF_STX if base == 10 => {
+                        -8 * (1 + prng.next_u32() % 3) as i16
+                    }
+                    ebpf::BPF_LDX | ebpf::BPF_ST | ebpf::BPF_STX => {
+                        8 * (prng.next_u32() % 3) as i16
+                    }
+                    _ => (prng.next_u32() % 8) as i16 - 3,
+                };
+                match opc & ebpf::BPF_CLS_MASK {
+                    ebpf::BPF_LDX => src = base,
+                    ebpf::BPF_ST | ebpf::BPF_STX => dst = base,
+                    _ => {}
+                }
+                let imm = match opc {
+                    ebpf::LE | ebpf::BE => [16, 32, 64][prng.next_u32() as usize % 3],
+                    _ if prng.next_u32() % 4 == 0 => prng.next_u32() as i32,
+                    _ => (prng.next_u32() % 8) as i32 - 2,
+                };
+                insn[0] = opc;
+                insn[1] = (src << 4 | dst) as u8;
+                LittleEndian::write_i16(&mut insn[2..4], off);
+                LittleEndian::write_i32(&mut insn[4..8], imm);
+            }
+            program[ebpf::INSN_SIZE * (instruction_count - 1)..].copy_from_slice(&[
+                ebpf::EXIT,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ]);
+            let load = |optimize_jit_code| {
+                let loader = Arc::new(BuiltInProgram::new_loader(Config {
+                    optimize_jit_code,
+                    ..config
+                }));
+                let mut executable =
+                    Executable::<TautologyVerifier, TestContextObject>::from_text_bytes(
+                        &program,
+                        loader,
+                        FunctionRegistry::default(),
+                    )
+                    .and_then(Executable::<RequisiteVerifier, TestContextObject>::verified)
+                    .ok()?;
+                executable.jit_compile().unwrap();
+                Some(executable)
+            };
+            let executables = match (load(false), load(true)) {
+                (Some(unoptimized), Some(optimized)) => [unoptimized, optimized],
+                _ => continue,
+            };
+            let budget = 1 + prng.next_u64() % 48;
+            let run = |executable: &Executable<RequisiteVerifier, TestContextObject>,
+                       interpreted: bool| {
+                let mut mem = (0..32u8).collect::<Vec<_>>();
+                let mut context_object = TestContextObject::new(budget);
+                let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+                create_vm!(
+                    vm,
+                    executable,
+                    &mut context_object,
+                    stack,
+                    heap,
+                    vec![mem_region],
+                    None
+                );
+                let (instruction_count, result) = vm.execute_program(interpreted);
+                let state = (
+                    instruction_count,
+                    format!("{result:?}"),
+                    format!("{:?}", vm.env.suspended_state),
+                    format!("{:?}", vm.env.backtrace),
+                    vm.env.context_object_pointer.trace_log.clone(),
+                );
+                (state, mem)
+            };
+            let interpreter = run(&executables[1], true);
+            let unoptimized = run(&executables[0], false);
+            let optimized = run(&executables[1], false);
+            // Where the JIT already deviates from the interpreter on faults and suspensions,
+            // the optimizations must not change anything observable either
+            assert_eq!(unoptimized, optimized, "{program:?}");
+            if optimized.0 .1.starts_with("Ok") {
+                assert_eq!(interpreter, optimized, "{program:?}");
+            }
+        }
+    }
+}
+
+// GDB JIT interface
+
+#[cfg(feature = "gdb-jit-interface")]
+#[test]
+fn test_gdb_jit_interface() {
+    #[repr(C)]
+    struct JitCodeEntry {
+        next_entry: *const JitCodeEntry,
+        prev_entry: *const JitCodeEntry,
+        symfile_addr: *const u8,
+        symfile_size: u64,
+    }
+    #[repr(C)]
+    struct JitDescriptor {
+        version: u32,
+        action_flag: u32,
+        relevant_entry: *const JitCodeEntry,
+        first_entry: *const JitCodeEntry,
+    }
+    extern "C" {
+        static __jit_debug_descriptor: JitDescriptor;
+        fn __jit_debug_register_code();
+    }
+    // No other test of this binary registers objects, so the list is not modified concurrently
+    fn registered_objects() -> Vec<(usize, usize)> {
+        let mut objects = Vec::new();
+        unsafe {
+            let descriptor = std::ptr::addr_of!(__jit_debug_descriptor);
+            assert_eq!((*descriptor).version, 1);
+            let mut entry = (*descriptor).first_entry;
+            while !entry.is_null() {
+                objects.push((
+                    (*entry).symfile_addr as usize,
+                    (*entry).symfile_size as usize,
+                ));
+                entry = (*entry).next_entry;
+            }
+        }
+        objects
+    }
+
+    let executable = assemble::<TestContextObject>(
+        "
+        call function_foo
+        exit
+        function_foo:
+        mov r0, 1
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config {
+            enable_symbol_and_section_labels: true,
+            ..Config::default()
+        })),
+    )
+    .unwrap();
+    let mut executable = Executable::<TautologyVerifier, _>::verified(executable).unwrap();
+    executable.jit_compile().unwrap();
+    executable
+        .register_jit_symbols(
+            &disassembly_line_table(
+                &Analysis::from_executable(&executable).unwrap(),
+                "program.s",
+            ),
+            None,
+        )
+        .unwrap();
+    let object = executable
+        .get_compiled_program()
+        .unwrap()
+        .gdb_jit_object()
+        .unwrap();
+    let registration = (object.as_ptr() as usize, object.len());
+    assert!(registered_objects().contains(&registration));
+    // The debugger would break here; calling it must be harmless
+    unsafe { __jit_debug_register_code() };
+    drop(executable);
+    assert!(!registered_objects().contains(&registration));
+}
+
+// Cranelift
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_not_compiled() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 1
+        exit",
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    let mut context_object = TestContextObject::new(2);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program_cranelift();
+    assert_error!(result, "JitNotCompiled");
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_without_tracing() {
+    let config = Config {
+        enable_instruction_tracing: false,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov r1, 0
+        stxdw [r10-8], r1
+        ldxdw r2, [r10-8]
+        add r2, 3
+        stxdw [r10-8], r2
+        add r1, 1
+        jlt r1, 100, -5
+        ldxdw r0, [r10-8]
+        div r0, r1
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(505),
+        ProgramResult::Ok(3),
+    );
+    test_interpreter_and_jit_asm!(
+        "
+        mov r1, 0
+        add r1, 1
+        ja -2
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(100),
+        ProgramResult::Err(Box::new(EbpfError::ExceededMaxInstructions(31))),
+    );
+    test_interpreter_and_jit_asm!(
+        "
+        mov r1, 8
+        lsh r1, 32
+        ldxb r0, [r1]
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(3),
+        ProgramResult::Err(Box::new(EbpfError::AccessViolation(
+            31,
+            AccessType::Load,
+            0x800000000,
+            1,
+            "unknown"
+        ))),
+    );
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_suspension_and_backtrace() {
+    let mut loader = BuiltInProgram::new_loader(Config {
+        enable_suspension: true,
+        enable_backtrace: true,
+        max_call_depth: 5,
+        ..weighted_config()
+    });
+    loader
+        .register_function(b"yield_syscall", yield_syscall)
+        .unwrap();
+    let loader = Arc::new(loader);
+    for source in [
+        // Nested calls in a loop, suspended at the syscall
+        "
+        mov64 r6, 0
+        mov64 r1, r6
+        call function_foo
+        add64 r6, 1
+        jlt r6, 3, -4
+        syscall yield_syscall
+        exit
+        function_foo:
+        stxdw [r10-8], r1
+        mov64 r2, r1
+        call function_bar
+        ldxdw r1, [r10-8]
+        add64 r0, r1
+        exit
+        function_bar:
+        mul64 r2, r2
+        add64 r0, r2
+        exit",
+        // Fault inside a function called through a register
+        "
+        mov64 r6, 7
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x30
+        callx r8
+        exit
+        function_foo:
+        mov64 r7, 8
+        ldxdw r2, [r2+0]
+        exit",
+        // Call depth exceeded
+        "
+        call function_foo
+        exit
+        function_foo:
+        add64 r0, 1
+        call function_foo
+        exit",
+        // Call to the second half of an lddw, which is not a function
+        "
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x30
+        callx r8
+        exit
+        lddw r0, 0x1122334455667788
+        exit",
+    ] {
+        let executable = assemble::<TestContextObject>(source, loader.clone()).unwrap();
+        let mut executable = Executable::<RequisiteVerifier, _>::verified(executable).unwrap();
+        executable.cranelift_compile().unwrap();
+        for budget in 1..100 {
+            let results = [true, false].map(|interpreted| {
+                let mut context_object = TestContextObject::new(budget);
+                create_vm!(
+                    vm,
+                    &executable,
+                    &mut context_object,
+                    stack,
+                    heap,
+                    vec![],
+                    None
+                );
+                let (instruction_count, result) = if interpreted {
+                    vm.execute_program(true)
+                } else {
+                    vm.execute_program_cranelift()
+                };
+                (
+                    instruction_count,
+                    format!("{result:?}"),
+                    vm.env.suspended_state.take(),
+                    vm.env.backtrace.take(),
+                    vm.env.context_object_pointer.trace_log.clone(),
+                )
+            });
+            assert_eq!(results[0], results[1], "{source} with budget {budget}");
+        }
+    }
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_chaos() {
+    let opcodes = [
+        ebpf::LD_B_REG,
+        ebpf::LD_DW_REG,
+        ebpf::ST_W_IMM,
+        ebpf::ST_H_REG,
+        ebpf::ADD32_IMM,
+        ebpf::SUB32_REG,
+        ebpf::MUL32_REG,
+        ebpf::DIV32_REG,
+        ebpf::SDIV32_REG,
+        ebpf::MOD32_IMM,
+        ebpf::LSH32_REG,
+        ebpf::ARSH32_IMM,
+        ebpf::NEG32,
+        ebpf::MOV32_REG,
+        ebpf::LE,
+        ebpf::BE,
+        ebpf::ADD64_REG,
+        ebpf::MUL64_IMM,
+        ebpf::DIV64_REG,
+        ebpf::SDIV64_REG,
+        ebpf::MOD64_REG,
+        ebpf::OR64_IMM,
+        ebpf::XOR64_REG,
+        ebpf::RSH64_REG,
+        ebpf::ARSH64_REG,
+        ebpf::MOV64_IMM,
+        ebpf::JA,
+        ebpf::JEQ_IMM,
+        ebpf::JGT_REG,
+        ebpf::JSET_IMM,
+        ebpf::JSLT_REG,
+        ebpf::JSGE_IMM,
+        ebpf::CALL_REG,
+        ebpf::EXIT,
+    ];
+    let instruction_count = 8;
+    let loader = Arc::new(BuiltInProgram::new_loader(Config {
+        enable_instruction_tracing: true,
+        enable_suspension: true,
+        enable_backtrace: true,
+        static_syscalls: false,
+        ..Config::default()
+    }));
+    let mut prng = SmallRng::seed_from_u64(0x5EED0F0C4A05);
+    let mut program = vec![0; instruction_count * ebpf::INSN_SIZE];
+    for _ in 0..1000 {
+        for insn in program.chunks_exact_mut(ebpf::INSN_SIZE) {
+            let opc = opcodes[prng.next_u32() as usize % opcodes.len()];
+            let dst = prng.next_u32() % 10;
+            let src = prng.next_u32() % 11;
+            let off = (prng.next_u32() % 8) as i16 - 3;
+            let imm = match opc {
+                ebpf::LE | ebpf::BE => [16, 32, 64][prng.next_u32() as usize % 3],
+                ebpf::CALL_REG => (prng.next_u32() % 10) as i32,
+                _ => prng.next_u32() as i32 >> (prng.next_u32() % 32),
+            };
+            insn[0] = opc;
+            insn[1] = (src << 4 | dst) as u8;
+            LittleEndian::write_i16(&mut insn[2..4], off);
+            LittleEndian::write_i32(&mut insn[4..8], imm);
+        }
+        program[ebpf::INSN_SIZE * (instruction_count - 1)..].copy_from_slice(&[
+            ebpf::EXIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        let verified_executable =
+            Executable::<TautologyVerifier, TestContextObject>::from_text_bytes(
+                &program,
+                loader.clone(),
+                FunctionRegistry::default(),
+            )
+            .and_then(Executable::<RequisiteVerifier, TestContextObject>::verified);
+        let mut verified_executable = match verified_executable {
+            Ok(verified_executable) => verified_executable,
+            Err(_) => continue,
+        };
+        verified_executable.cranelift_compile().unwrap();
+        let budget = 1 + prng.next_u64() % 64;
+        let results = [true, false].map(|interpreted| {
+            let mut mem = vec![0u8; 1024];
+            let mut context_object = TestContextObject::new(budget);
+            let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            create_vm!(
+                vm,
+                &verified_executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![mem_region],
+                None
+            );
+            let (instruction_count, result) = if interpreted {
+                vm.execute_program(true)
+            } else {
+                vm.execute_program_cranelift()
+            };
+            (
+                instruction_count,
+                format!("{result:?}"),
+                vm.env.suspended_state.take(),
+                vm.env.backtrace.take(),
+                vm.env.context_object_pointer.trace_log.clone(),
+            )
+        });
+        assert_eq!(results[0], results[1], "{program:?}");
+    }
+}
+
 // Symbols and Relocation
 
 #[test]
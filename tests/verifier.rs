@@ -23,9 +23,11 @@ extern crate solana_rbpf;
 extern crate thiserror;
 
 use solana_rbpf::{
+    abstract_interpretation::{AbstractInterpretation, AbstractInterpretationVerifier},
     assembler::assemble,
     ebpf,
     elf::Executable,
+    static_analysis::Analysis,
     verifier::{RequisiteVerifier, TautologyVerifier, Verifier, VerifierError},
     vm::{BuiltInProgram, Config, FunctionRegistry, TestContextObject},
 };
@@ -394,3 +396,167 @@ fn test_sdiv_disabled() {
         }
     }
 }
+
+fn verify_abstract_interpretation(source: &str) -> Result<(), String> {
+    let executable = assemble::<TestContextObject>(
+        source,
+        Arc::new(BuiltInProgram::new_loader(Config::default())),
+    )
+    .unwrap();
+    Executable::<AbstractInterpretationVerifier, TestContextObject>::verified(executable)
+        .map(|_| ())
+        .map_err(|err| format!("{err:?}"))
+}
+
+#[test]
+fn test_abstract_interpretation_success() {
+    // Stack slots written in a bounded loop, input accesses with positive offsets
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            mov64 r3, 0
+            jge r3, 16, +7
+            mov64 r2, r10
+            mov64 r4, r3
+            lsh64 r4, 3
+            sub64 r2, r4
+            stxdw [r2-8], r3
+            add64 r3, 1
+            ja -8
+            ldxdw r0, [r10-128]
+            ldxb r0, [r1+8]
+            exit",
+        ),
+        Ok(())
+    );
+    // Spilled pointers keep their provenance
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            mov64 r2, r10
+            add64 r2, -16
+            stxdw [r10-8], r2
+            ldxdw r3, [r10-8]
+            stxdw [r3+0], r1
+            mov64 r0, 0
+            exit",
+        ),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_abstract_interpretation_err_out_of_bounds() {
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            stxdw [r10-4], r1
+            exit",
+        ),
+        Err("VerifierError(MemoryAccessOutOfBounds(29, \"stack\"))".to_string())
+    );
+    // The loop writes one slot past the frame pointer
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            mov64 r3, 0
+            jgt r3, 16, +6
+            mov64 r2, r10
+            mov64 r4, r3
+            lsh64 r4, 3
+            sub64 r2, r4
+            stxdw [r2+0], r3
+            ja +1
+            exit
+            add64 r3, 1
+            ja -10",
+        ),
+        Err("VerifierError(MemoryAccessOutOfBounds(35, \"stack\"))".to_string())
+    );
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            ldxb r0, [r1-1]
+            exit",
+        ),
+        Err("VerifierError(MemoryAccessOutOfBounds(29, \"input\"))".to_string())
+    );
+}
+
+#[test]
+fn test_abstract_interpretation_input_len() {
+    let config = Config::default();
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r0, [r1+8]
+        exit",
+        Arc::new(BuiltInProgram::new_loader(config)),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let abstract_interpretation = AbstractInterpretation::new(&analysis, &config);
+    // Without the length of the input only its start is checked
+    assert!(abstract_interpretation
+        .check(&analysis, &config, None)
+        .is_ok());
+    assert!(abstract_interpretation
+        .check(&analysis, &config, Some(16))
+        .is_ok());
+    assert!(matches!(
+        abstract_interpretation.check(&analysis, &config, Some(15)),
+        Err(VerifierError::MemoryAccessOutOfBounds(29, "input"))
+    ));
+}
+
+#[test]
+fn test_abstract_interpretation_byte_swap_width() {
+    // The width of be64 is its immediate, not r0, so the fallthrough is reachable
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            mov64 r0, 16
+            ldxb r2, [r10-1]
+            be64 r2
+            jle r2, 0xffff, +2
+            mov64 r1, 0
+            ldxdw r0, [r1+0]
+            exit",
+        ),
+        Err("VerifierError(AlwaysFaultingMemoryAccess(34, \"unknown\"))".to_string())
+    );
+}
+
+#[test]
+fn test_abstract_interpretation_err_always_faulting() {
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            mov64 r1, 0
+            jeq r2, 0, +1
+            mov64 r1, 8
+            ldxdw r0, [r1+0]
+            exit",
+        ),
+        Err("VerifierError(AlwaysFaultingMemoryAccess(32, \"unknown\"))".to_string())
+    );
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            lddw r1, 0x100000000
+            stw [r1+0], 1
+            exit",
+        ),
+        Err("VerifierError(AlwaysFaultingMemoryAccess(31, \"program\"))".to_string())
+    );
+    assert_eq!(
+        verify_abstract_interpretation(
+            "
+            mov64 r0, 1
+            mov64 r1, r2
+            and64 r1, 0
+            div64 r0, r1
+            exit",
+        ),
+        Err("VerifierError(DivisionByZero(32))".to_string())
+    );
+}